@@ -2,11 +2,16 @@ use serde_derive::{Deserialize, Serialize};
 use leds::demo;
 use leds::chan_spec::ChanSpec;
 use leds::coord::Coord;
-use leds::mux;
+use leds::pointer::PointerSpec;
+use leds::audio::AudioSpec;
+use leds::msg_handler::MsgHandler;
+use leds::dev::Dev;
 use crate::actions;
+use crate::config::Config;
 
 use std::time::Duration;
 use std::net::IpAddr;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,21 +22,95 @@ pub enum ActionSpec {
     SrvV3 { listen_ip: Option<IpAddr>, listen_port: Option<u16> },
     Set(ChanSpec),
     Web { listen_addr: Option<String> },
-    Space { location: Coord, radius: f32, brightness: f32 },
+    /// Light around a pointer, `pointer` or the config one or the tablet
+    Space {
+        location: Coord,
+        radius: f32,
+        brightness: f32,
+        pointer: Option<PointerSpec>,
+    },
     TestSeq,
     Glitch,
-    Hello,
+    /// Disco, `harder` for the faster and deeper profile, `params` tune it
+    Hello { harder: bool, params: Vec<String> },
     Fade,
     Whoosh,
+    /// Oscillator stacks from `path` or the config, `seed` overrides theirs
+    Schroom { path: Option<PathBuf>, seed: Option<u64> },
+    /// Audio levels onto chans by tag, from `input` or the config one
+    Audio { input: Option<AudioSpec> },
+    /// Chans set by `selector=expression` rules, or the config ones
+    Expr { rules: Vec<(String, String)> },
+    /// Record everything the inner action outputs to a file
+    Record { path: PathBuf, action: Box<ActionSpec> },
+    Play { path: PathBuf, speed: f32, looped: bool },
+    /// Fade to a scene from the config by its name or id
+    Scene(String),
+    SceneSrv { listen_ip: Option<IpAddr>, listen_port: Option<u16> },
+    /// Run a sequence of cues from the config
+    Sequence(String),
+    /// Run a script file, reloading it when it changes
+    Script(PathBuf),
+    /// Play a keyframe timeline file from the start or from a marker or
+    /// a number of seconds
+    Timeline { path: PathBuf, from: Option<String> },
+    /// Run scheduled actions from the config or list them
+    Schedule { list_only: bool },
+    /// Save current values of chans with any of `tags` (or all) as a scene
+    Snapshot { name: String, tags: Vec<String> },
+    /// Print today's dawn, sunrise, sunset and dusk at the config location
+    Sun,
+    /// Set chans with any of `tags` (or all) to `day` when the sun is up
+    /// and to `night` when it's down
+    SunFollow { tags: Vec<String>, day: f32, night: f32 },
+    /// Follow the warm / cool white curve over the day
+    Circadian,
+    /// List effects with their params
+    ListEffects,
+    /// Run an effect from the registry with `key=value` params
+    Effect { name: String, params: Vec<String> },
 }
 
 impl ActionSpec {
-    pub fn run(&self, config: &mux::Config) -> Result<(), String> {
-        let mux = leds::mux::Mux::init_from_config(config)?;
+    pub fn run(&self, config: &Config) -> Result<(), String> {
+        match self {
+            ActionSpec::Sun =>
+                return actions::sun::print_today(config.location.as_ref()),
+            ActionSpec::ListEffects => {
+                actions::effect::print_effects();
+                return Ok(());
+            },
+            _ => {},
+        }
+
+        let mux = leds::mux::Mux::init_from_config(&config.mux)?;
 
+        match self {
+            ActionSpec::Record { path, action } => {
+                use leds::recording::Recorder;
+
+                let recorder = Arc::new(Mutex::new(Recorder::create(path, mux)?));
+                {
+                    // don't lose the buffered frames on Ctrl-C
+                    let recorder = recorder.clone();
+                    ctrlc::set_handler(move || {
+                        recorder.lock().unwrap().flush();
+                        std::process::exit(130);
+                    }).map_err(|e| format!("{:?}", e))?;
+                }
+                action.run_with(recorder, config)
+            },
+            other => other.run_with(mux, config),
+        }
+    }
+
+    fn run_with<T: 'static + MsgHandler + Dev>(
+        &self,
+        mux: Arc<Mutex<T>>,
+        config: &Config,
+    ) -> Result<(), String> {
         match self {
             ActionSpec::ListChans => {
-                use leds::chan_description::HasChanDescriptions;
                 let mux = mux.lock().unwrap();
                 for descr in mux.chan_descriptions() {
                     let mut tags = String::new();
@@ -69,29 +148,225 @@ impl ActionSpec {
             ActionSpec::TestSeq => demo::test_seq::run(mux),
             ActionSpec::Glitch => demo::glitch::run(mux),
             ActionSpec::Whoosh => demo::whoosh::run(mux),
-            ActionSpec::Hello => demo::hello::run(mux),
+            ActionSpec::Hello { harder, params } => {
+                use demo::hello::{self, DiscoChanConfig};
+                use std::sync::mpsc;
+
+                let (_sender, receiver) = mpsc::channel();
+                if *harder {
+                    let config = config.disco_harder.clone()
+                        .unwrap_or_else(DiscoChanConfig::harder)
+                        .tune(params)?;
+                    hello::run_harder(mux, config, receiver)
+                } else {
+                    let config = DiscoChanConfig::default().tune(params)?;
+                    hello::run_with_config(mux, config, receiver)
+                }
+            },
+            ActionSpec::Schroom { path, seed } => {
+                use demo::schroom::{self, SchroomConfig};
+                use std::sync::mpsc;
+
+                let mut schroom_config = match path {
+                    Some(path) => SchroomConfig::load(path)?,
+                    None => config.schroom.clone().unwrap_or_default(),
+                };
+                if seed.is_some() {
+                    schroom_config.seed = *seed;
+                }
+                let (_sender, receiver) = mpsc::channel();
+                schroom::run_with_config(mux, schroom_config, receiver)
+            },
             ActionSpec::Fade => {
                 use leds::runner::Runner;
                 use leds::task::TaskMsg;
                 use std::sync::mpsc;
                 use leds::demo::fade::{Fade, FadeSpec};
+                use leds::chan_description::HasChanDescriptions;
+                use leds::dev::DevWrite;
+                use leds::frame::Frame;
 
                 let (_sender, receiver) = mpsc::channel::<TaskMsg>();
 
                 let fade_cfg = FadeSpec {
                     frame_duration: Duration::from_secs_f32(1.0 / 60.0),
                     fade_duration: Duration::from_secs_f32(1.0),
+                    ..FadeSpec::default()
                 };
-                let fade = Fade::new(mux, fade_cfg);
+                let mut fade = Fade::new(mux, fade_cfg);
+                let mut all_on = Frame::<f32>::empty();
+                for chan in fade.chan_descriptions() {
+                    all_on.set(chan.chan_id, 1.0);
+                }
+                fade.set_frame(&all_on)?;
                 Fade::run(Arc::new(Mutex::new(fade)), receiver)
             },
-            ActionSpec::Space { location, radius, brightness } => {
+            ActionSpec::Space { location, radius, brightness, pointer } => {
+                let pointer = pointer.as_ref()
+                    .or(config.pointer.as_ref())
+                    .cloned()
+                    .unwrap_or_default()
+                    .open()?;
                 demo::space::run(
                     mux,
                     demo::space::Config {
                         location:   *location,
                         radius:     *radius,
-                        brightness: *brightness })
+                        brightness: *brightness,
+                        falloff:    demo::space::DEFAULT_FALLOFF },
+                    pointer)
+            },
+            ActionSpec::Audio { input } => {
+                use std::sync::mpsc;
+
+                let mut audio_config = config.audio.clone().unwrap_or_default();
+                if let Some(input) = input {
+                    audio_config.input = input.clone();
+                }
+                let (_sender, receiver) = mpsc::channel();
+                leds::audio::run(mux, audio_config, receiver)
+            },
+            ActionSpec::Expr { rules } => {
+                use leds::expr::ExprEffect;
+                use leds::task::TaskMsg;
+                use std::sync::mpsc;
+
+                let mut expr_config = config.expr.clone().unwrap_or_default();
+                if !rules.is_empty() {
+                    expr_config.chans = rules.clone();
+                }
+                if expr_config.chans.is_empty() {
+                    return Err("expr requires SELECTOR=EXPR rules or `expr` \
+                                in the config".to_string());
+                }
+                let mut effect = ExprEffect::new(expr_config.program()?);
+                let _meter_task = match &expr_config.audio {
+                    Some(audio_config) => {
+                        let (levels, task) = audio_config.start_meter()?;
+                        effect = effect.with_levels(levels);
+                        Some(task)
+                    },
+                    None => None,
+                };
+                let _tempo_tasks =
+                    config.tempo.clone().unwrap_or_default().start()?;
+                let (_tx, rx) = mpsc::channel::<TaskMsg>();
+                leds::effect::run(mux, Box::new(effect), rx)
+            },
+            ActionSpec::Record { .. } => {
+                Err("record can't record another record".to_string())
+            },
+            ActionSpec::Play { path, speed, looped } => {
+                use leds::runner::Runner;
+                use leds::task::TaskMsg;
+                use std::sync::mpsc;
+                use leds::recording::{Player, PlaySpec};
+
+                let (_sender, receiver) = mpsc::channel::<TaskMsg>();
+
+                let player = Player::new(mux, PlaySpec {
+                    path: path.clone(),
+                    speed: *speed,
+                    looped: *looped,
+                })?;
+                Player::run(Arc::new(Mutex::new(player)), receiver)
+            },
+            ActionSpec::Scene(name) => {
+                let scenes = config.scenes.as_deref().unwrap_or(&[]);
+                let scene = leds::scene::find(scenes, name)
+                    .ok_or_else(|| format!("scene '{}' not found", name))?;
+                actions::scene::run(scene, mux)
+            },
+            ActionSpec::SceneSrv { listen_ip, listen_port } => {
+                use leds::scene::SceneSrv;
+                let scenes = config.scenes.clone().unwrap_or_default();
+                let mut srv =
+                    SceneSrv::new(*listen_ip, *listen_port, mux, scenes)?;
+                srv.run()
+            },
+            ActionSpec::Sequence(name) => {
+                let spec = config.sequences.iter().flatten()
+                    .find(|s| &s.name == name)
+                    .ok_or_else(|| format!("sequence '{}' not found", name))?;
+                let scenes = config.scenes.as_deref().unwrap_or(&[]);
+                actions::sequence::run(spec, scenes, mux)
+            },
+            ActionSpec::Script(path) => {
+                let scenes = config.scenes.as_deref().unwrap_or(&[]);
+                let _tempo_tasks =
+                    config.tempo.clone().unwrap_or_default().start()?;
+                actions::script::run(path, config.script.clone()
+                                     .unwrap_or_default(), scenes, mux)
+            },
+            ActionSpec::Timeline { path, from } =>
+                actions::timeline::play(path, from.as_deref(), mux),
+            ActionSpec::Schedule { list_only } => {
+                use actions::schedule::{self, Executor};
+                use leds::schedule::Scheduler;
+                use std::sync::mpsc;
+
+                let entries = config.schedule.as_deref().unwrap_or(&[]);
+                if *list_only {
+                    let (tx, _rx) = mpsc::channel();
+                    let scheduler = Scheduler::new(
+                        entries, config.location.as_ref(), tx)?;
+                    schedule::print_upcoming(&scheduler);
+                    return Ok(());
+                }
+
+                let executor = Executor::new(
+                    mux,
+                    config.scenes.clone().unwrap_or_default(),
+                    config.sequences.clone().unwrap_or_default(),
+                    config.schroom.clone().unwrap_or_default());
+                schedule::run(entries, config.location.as_ref(), executor)
+            },
+            ActionSpec::Snapshot { name, tags } => {
+                let scene = {
+                    let mux = mux.lock().unwrap();
+                    leds::scene::snapshot(name, &*mux, tags)?
+                };
+                let path = config.scenes_path();
+                leds::scene::save_to_file(&path, scene)?;
+                println!("saved scene '{}' to {:?}", name, path);
+                Ok(())
+            },
+            ActionSpec::Circadian => actions::circadian::run(
+                config.circadian.clone().unwrap_or_default(), mux),
+            ActionSpec::ListEffects => {
+                actions::effect::print_effects();
+                Ok(())
+            },
+            ActionSpec::Effect { name, params } => {
+                use leds::task::TaskMsg;
+                use std::sync::mpsc;
+
+                let effect = leds::effect::find(name)?
+                    .create_with_args(params)?;
+                let _tempo_tasks =
+                    config.tempo.clone().unwrap_or_default().start()?;
+                let (_tx, rx) = mpsc::channel::<TaskMsg>();
+                leds::effect::run(mux, effect, rx)
+            },
+            ActionSpec::Sun => actions::sun::print_today(
+                config.location.as_ref()),
+            ActionSpec::SunFollow { tags, day, night } => {
+                use leds::runner::Runner;
+                use leds::sun::{SunFollow, SunFollowSpec};
+                use std::sync::mpsc;
+
+                let location = config.location.ok_or_else(
+                    || "sun_follow needs `location` in the config"
+                    .to_string())?;
+                let spec = SunFollowSpec {
+                    location,
+                    tags: tags.clone(),
+                    day: *day,
+                    night: *night,
+                };
+                let (_tx, rx) = mpsc::channel();
+                SunFollow::run(
+                    Arc::new(Mutex::new(SunFollow::new(mux, spec))), rx)
             },
         }
     }
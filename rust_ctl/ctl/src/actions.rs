@@ -1 +1,50 @@
+pub mod circadian;
+pub mod effect;
+pub mod scene;
+pub mod script;
+pub mod schedule;
+pub mod sequence;
 pub mod set;
+pub mod sun;
+pub mod timeline;
+
+use std::io::{self, BufRead};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// What a command on stdin does, given the rest of its line
+pub type Command<T> = fn(&mut T, &str) -> Result<(), String>;
+
+/// Runs commands from stdin on `target`, picked by the first word of the
+/// line. `""` is for empty lines and `"*"` for lines nothing else matches,
+/// it gets the whole line. Nobody joins the thread, it's blocked on stdin
+/// when the task is done
+pub fn read_commands<T: 'static + Send + ?Sized>(
+    target: Arc<Mutex<T>>,
+    commands: Vec<(&'static str, Command<T>)>,
+    usage: &'static str,
+) {
+    let find = move |word: &str| commands.iter()
+        .find(|(name, _)| *name == word)
+        .map(|(_, command)| *command);
+
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { return };
+            let line = line.trim();
+            let (word, rest) = line.split_once(char::is_whitespace)
+                .map(|(word, rest)| (word, rest.trim()))
+                .unwrap_or((line, ""));
+            let res = match (find(word), find("*")) {
+                (Some(command), _) => command(&mut target.lock().unwrap(), rest),
+                (None, Some(command)) =>
+                    command(&mut target.lock().unwrap(), line),
+                (None, None) => Err(format!("unknown command '{}', expected {}",
+                                            word, usage)),
+            };
+            if let Err(e) = res {
+                eprintln!("{}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,74 @@
+use crate::actions;
+
+use leds::chan_description::HasChanDescriptions;
+use leds::circadian::{Circadian, CircadianSpec};
+use leds::dev::Dev;
+use leds::runner::Runner;
+use leds::task::TaskMsg;
+
+use std::fmt;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Runs until killed, `hold` on stdin keeps the current values,
+/// `override [MINUTES]` holds them for a while, `resume` fades back
+pub fn run<T: 'static + Dev + HasChanDescriptions + fmt::Debug>(
+    spec: CircadianSpec, output: Arc<Mutex<T>>
+) -> Result<(), String> {
+    let circadian = Arc::new(Mutex::new(
+            Circadian::new(output, spec)?));
+    print_status(&circadian.lock().unwrap());
+
+    let (_tx, rx) = mpsc::channel::<TaskMsg>();
+    let join_handle = {
+        let circadian = circadian.clone();
+        thread::spawn(move || Runner::run(circadian, rx))
+    };
+
+    actions::read_commands(circadian, vec![
+        ("hold", |circadian, _| {
+            circadian.hold();
+            print_status(circadian);
+            Ok(())
+        }),
+        ("override", override_for),
+        ("resume", |circadian, _| {
+            circadian.resume();
+            print_status(circadian);
+            Ok(())
+        }),
+        ("", |circadian, _| {
+            print_status(circadian);
+            Ok(())
+        }),
+    ], "hold, override [MINUTES] or resume");
+
+    join_handle.join().map_err(|e| format!("{:?}", e))?
+}
+
+fn override_for<T: HasChanDescriptions + Dev + ?Sized>(
+    circadian: &mut Circadian<T>, minutes: &str
+) -> Result<(), String> {
+    match minutes {
+        "" => circadian.override_default()?,
+        minutes => {
+            let duration = minutes.parse::<f32>().ok()
+                .and_then(|minutes| Duration::try_from_secs_f32(minutes * 60.0)
+                          .ok())
+                .ok_or_else(|| format!("invalid minutes '{}'", minutes))?;
+            circadian.override_for(duration);
+        },
+    }
+    print_status(circadian);
+    Ok(())
+}
+
+fn print_status<T: HasChanDescriptions + Dev + ?Sized>(
+    circadian: &Circadian<T>
+) {
+    let (kelvin, brightness) = circadian.current();
+    println!("{:.0}K at {:.0}%{}", kelvin, brightness * 100.0,
+             if circadian.is_overridden() { " (overridden)" } else { "" });
+}
@@ -0,0 +1,11 @@
+use leds::effect::EFFECTS;
+
+/// Prints every effect in the registry with its params
+pub fn print_effects() {
+    for info in EFFECTS {
+        println!("{} -- {}", info.name, info.description);
+        for param in (info.params)() {
+            println!("    {}", param);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use leds::demo::Fade;
+use leds::chan_description::HasChanDescriptions;
+use leds::dev::Dev;
+use leds::runner::Runner;
+use leds::scene::Scene;
+use leds::task::TaskMsg;
+
+use std::fmt;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Fades to `scene` and returns when the fade is done
+pub fn run<T: 'static + Dev + HasChanDescriptions + fmt::Debug>(
+    scene: &Scene, output: Arc<Mutex<T>>
+) -> Result<(), String> {
+    let frame_duration = Duration::from_secs_f32(1.0 / 60.0);
+    let fader = Arc::new(Mutex::new(
+            Fade::new(output, scene.fade_spec(frame_duration))));
+    let done = scene.fade_with(&mut fader.lock().unwrap())?;
+
+    let (tx, rx) = mpsc::channel::<TaskMsg>();
+    let join_handle = {
+        let fader = fader.clone();
+        thread::spawn(move || Runner::run(fader, rx))
+    };
+
+    // the fader has written the final values when it reports the end
+    done.recv().map_err(|e| format!("{:?}", e))?;
+
+    tx.send(TaskMsg::Stop).map_err(|e| format!("{:?}", e))?;
+    join_handle.join().map_err(|e| format!("{:?}", e))?
+}
@@ -0,0 +1,155 @@
+use crate::actions;
+
+use leds::chan_description::HasChanDescriptions;
+use leds::demo::{self, Fade};
+use leds::demo::schroom::SchroomConfig;
+use leds::dev::Dev;
+use leds::runner::Runner;
+use leds::scene::{self, Scene};
+use leds::schedule::{ScheduleAction, ScheduleEntry, Scheduler};
+use leds::sun::Location;
+use leds::sequence::{Sequence, SequenceSpec};
+use leds::task::{Task, TaskMsg};
+
+use std::fmt;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Runs scheduled actions one at a time, a new one stops the previous one
+pub struct Executor<T: fmt::Debug> {
+    output: Arc<Mutex<T>>,
+    scenes: Vec<Scene>,
+    sequences: Vec<SequenceSpec>,
+    schroom: SchroomConfig,
+    task: Option<Task>,
+}
+
+impl<T: 'static + Dev + HasChanDescriptions + fmt::Debug> Executor<T> {
+    pub fn new(
+        output: Arc<Mutex<T>>,
+        scenes: Vec<Scene>,
+        sequences: Vec<SequenceSpec>,
+        schroom: SchroomConfig,
+    ) -> Self {
+        Executor { output, scenes, sequences, schroom, task: None }
+    }
+
+    fn spawn<R: 'static + Runner + Send>(
+        &mut self, name: String, runner: Arc<Mutex<R>>
+    ) {
+        let (tx, rx) = mpsc::channel::<TaskMsg>();
+        let join_handle = thread::spawn(move || Runner::run(runner, rx));
+        self.task = Some(Task { name, chan: tx, join_handle });
+    }
+
+    pub fn perform(&mut self, action: &ScheduleAction) -> Result<(), String> {
+        if let Some(task) = self.task.take() {
+            task.stop();
+        }
+
+        let scene = match action {
+            ScheduleAction::Scene(name) => Some(
+                scene::find(&self.scenes, name)
+                    .ok_or_else(|| format!("scene '{}' not found", name))?
+                    .clone()),
+            other => other.as_scene(),
+        };
+
+        if let Some(scene) = scene {
+            let frame_duration = Duration::from_secs_f32(1.0 / 60.0);
+            let mut fader =
+                Fade::new(self.output.clone(), scene.fade_spec(frame_duration));
+            // nobody waits for scheduled fades to end
+            let _ = scene.fade_with(&mut fader)?;
+            self.spawn(format!("Scheduled {}", scene.name),
+                       Arc::new(Mutex::new(fader)));
+            return Ok(());
+        }
+
+        match action {
+            ScheduleAction::Sequence(name) => {
+                let spec = self.sequences.iter().find(|s| &s.name == name)
+                    .ok_or_else(|| format!("sequence '{}' not found", name))?;
+                let seq = Sequence::new(self.output.clone(), spec,
+                                        &self.scenes)?;
+                self.spawn(format!("Scheduled sequence {}", name),
+                           Arc::new(Mutex::new(seq)));
+                Ok(())
+            },
+            ScheduleAction::Demo(name) => {
+                let (tx, rx) = mpsc::channel::<TaskMsg>();
+                let output = self.output.clone();
+                let join_handle = match name.as_ref() {
+                    "disco" => thread::spawn(move ||
+                        demo::hello::run_with_channel(output, rx)),
+                    "schroom" => {
+                        let config = self.schroom.clone();
+                        thread::spawn(move ||
+                            demo::schroom::run_with_config(output, config, rx))
+                    },
+                    name => {
+                        let effect = leds::effect::find(name)?
+                            .create_with_args(Vec::<String>::new())?;
+                        thread::spawn(move ||
+                            leds::effect::run(output, effect, rx))
+                    },
+                };
+                self.task = Some(Task {
+                    name: format!("Scheduled demo {}", name),
+                    chan: tx,
+                    join_handle,
+                });
+                Ok(())
+            },
+            _ => unreachable!("scenes are handled above"),
+        }
+    }
+}
+
+/// Runs the schedule until killed, `ls` on stdin lists upcoming entries,
+/// `skip NAME` skips the next occurrence of NAME
+pub fn run<T: 'static + Dev + HasChanDescriptions + fmt::Debug>(
+    entries: &[ScheduleEntry],
+    location: Option<&Location>,
+    mut executor: Executor<T>,
+) -> Result<(), String> {
+    let (events_tx, events) = mpsc::channel::<ScheduleAction>();
+    let scheduler = Arc::new(Mutex::new(
+            Scheduler::new(entries, location, events_tx)?));
+    print_upcoming(&scheduler.lock().unwrap());
+
+    let (_tx, rx) = mpsc::channel::<TaskMsg>();
+    {
+        let scheduler = scheduler.clone();
+        thread::spawn(move || Runner::run(scheduler, rx));
+    }
+
+    actions::read_commands(scheduler, vec![
+        ("skip", |scheduler, name| {
+            let time = scheduler.skip_next(name)?;
+            println!("skipping {} at {}", name, time);
+            Ok(())
+        }),
+        ("*", |scheduler, _| {
+            print_upcoming(scheduler);
+            Ok(())
+        }),
+    ], "ls or skip NAME");
+
+    for action in events.iter() {
+        println!("running scheduled {}", action);
+        if let Err(e) = executor.perform(&action) {
+            eprintln!("scheduled {}: {}", action, e);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn print_upcoming(scheduler: &Scheduler) {
+    for upcoming in scheduler.upcoming() {
+        println!("{}", upcoming);
+    }
+}
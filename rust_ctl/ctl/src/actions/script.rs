@@ -0,0 +1,40 @@
+use crate::actions;
+
+use leds::chan_description::HasChanDescriptions;
+use leds::dev::Dev;
+use leds::runner::Runner;
+use leds::scene::Scene;
+use leds::script::{Script, ScriptConfig};
+use leds::task::TaskMsg;
+
+use std::fmt;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Runs the script until it's done, every line on stdin is an event for
+/// its `wait`s
+pub fn run<T: 'static + Dev + HasChanDescriptions + fmt::Debug>(
+    path: &Path, config: ScriptConfig, scenes: &[Scene], output: Arc<Mutex<T>>
+) -> Result<(), String> {
+    let script = Script::load(output, path)?
+        .with_config(config)
+        .with_scenes(scenes.to_vec());
+    let script = Arc::new(Mutex::new(script));
+
+    let (_tx, rx) = mpsc::channel::<TaskMsg>();
+    let join_handle = {
+        let script = script.clone();
+        thread::spawn(move || Runner::run(script, rx))
+    };
+
+    actions::read_commands(script, vec![
+        ("*", |script, line| {
+            script.event(line);
+            Ok(())
+        }),
+    ], "an event");
+
+    join_handle.join().map_err(|e| format!("{:?}", e))?
+}
@@ -0,0 +1,59 @@
+use crate::actions;
+
+use leds::chan_description::HasChanDescriptions;
+use leds::dev::Dev;
+use leds::runner::Runner;
+use leds::scene::Scene;
+use leds::sequence::{Sequence, SequenceSpec};
+use leds::task::TaskMsg;
+
+use std::fmt;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Runs the sequence until it's done, reading controls from stdin
+pub fn run<T: 'static + Dev + HasChanDescriptions + fmt::Debug>(
+    spec: &SequenceSpec, scenes: &[Scene], output: Arc<Mutex<T>>
+) -> Result<(), String> {
+    let seq = Arc::new(Mutex::new(Sequence::new(output, spec, scenes)?));
+
+    let (_tx, rx) = mpsc::channel::<TaskMsg>();
+    let join_handle = {
+        let seq = seq.clone();
+        thread::spawn(move || Runner::run(seq, rx))
+    };
+
+    actions::read_commands(seq, vec![
+        ("", go), ("go", go),
+        ("b", back), ("back", back),
+        ("p", pause), ("pause", pause),
+    ], "go, back or pause");
+
+    join_handle.join().map_err(|e| format!("{:?}", e))?
+}
+
+fn go<T: HasChanDescriptions + Dev>(seq: &mut Sequence<T>, _: &str)
+        -> Result<(), String> {
+    seq.go();
+    print_state(seq)
+}
+
+fn back<T: HasChanDescriptions + Dev>(seq: &mut Sequence<T>, _: &str)
+        -> Result<(), String> {
+    seq.back();
+    print_state(seq)
+}
+
+fn pause<T: HasChanDescriptions + Dev>(seq: &mut Sequence<T>, _: &str)
+        -> Result<(), String> {
+    seq.toggle_pause();
+    print_state(seq)
+}
+
+fn print_state<T: HasChanDescriptions + Dev>(seq: &Sequence<T>)
+        -> Result<(), String> {
+    println!("{:?}{}", seq.state(),
+             if seq.is_paused() { " (paused)" } else { "" });
+    Ok(())
+}
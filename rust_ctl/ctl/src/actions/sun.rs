@@ -0,0 +1,21 @@
+use leds::clock;
+use leds::sun::{self, Location, SunEvent};
+
+/// Prints today's sun events in local time
+pub fn print_today(location: Option<&Location>) -> Result<(), String> {
+    let location = location
+        .ok_or_else(|| "`location` is missing in the config".to_string())?;
+    let clock = clock::real();
+    let today = clock.local_now().date();
+
+    for event in [SunEvent::Dawn, SunEvent::Sunrise,
+                  SunEvent::Sunset, SunEvent::Dusk] {
+        match sun::sun_event(today, location, event) {
+            Some(utc) => println!("{:8} {}", event.to_string(),
+                                  clock.local_from_utc(utc)
+                                  .format("%H:%M")),
+            None => println!("{:8} none today", event.to_string()),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use crate::actions;
+
+use leds::chan_description::HasChanDescriptions;
+use leds::dev::Dev;
+use leds::runner::Runner;
+use leds::task::TaskMsg;
+use leds::timeline::{Player, Timeline};
+
+use std::fmt;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Plays the timeline until it ends, from `from` (a marker or seconds) if
+/// given, `pause` on stdin pauses and resumes and `seek POS` jumps
+pub fn play<T: 'static + Dev + HasChanDescriptions + fmt::Debug>(
+    path: &Path, from: Option<&str>, output: Arc<Mutex<T>>
+) -> Result<(), String> {
+    let mut timeline = Timeline::load(path)?;
+    timeline.frame_duration = Duration::from_secs_f32(1.0 / 60.0);
+    let mut player = Player::new(output, timeline);
+    if let Some(from) = from {
+        player.seek(from)?;
+    }
+    let player = Arc::new(Mutex::new(player));
+
+    let (_tx, rx) = mpsc::channel::<TaskMsg>();
+    let join_handle = {
+        let player = player.clone();
+        thread::spawn(move || Runner::run(player, rx))
+    };
+
+    actions::read_commands(player, vec![
+        ("pause", |player, _| {
+            player.toggle_pause();
+            Ok(())
+        }),
+        ("seek", |player, to| player.seek(to)),
+        ("", |_, _| Ok(())),
+    ], "pause or seek POS");
+
+    join_handle.join().map_err(|e| format!("{:?}", e))?
+}
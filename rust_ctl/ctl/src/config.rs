@@ -8,13 +8,25 @@ use serde_derive::{Deserialize, Serialize};
 use crate::action_spec::ActionSpec;
 use leds::chan_spec::ChanSpec;
 use leds::coord::Coord;
-use leds::template::Template;
+use leds::pointer::PointerSpec;
+use leds::audio::{AudioConfig, AudioSpec};
+use leds::expr::ExprConfig;
+use leds::script::ScriptConfig;
+use leds::tempo::TempoConfig;
+use leds::scene::Scene;
+use leds::sequence::SequenceSpec;
+use leds::circadian::CircadianSpec;
+use leds::demo::hello::DiscoChanConfig;
+use leds::demo::schroom::SchroomConfig;
+use leds::schedule::ScheduleEntry;
+use leds::sun::Location;
 use leds::mux;
 use leds::parse_ip_port::parse_ip_port;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 const DEFAULT_CONFIG_PATH: &str = "/etc/led_ctl.yaml";
+const DEFAULT_SCENES_FILENAME: &str = "scenes.yaml";
 
 fn print_help() {
     println!("
@@ -62,22 +74,161 @@ Actions:
     test_seq -- Sequentially fade each LED in and out. Useful for testing
     glitch   -- Glitchy demo that only works in some configurations
                 (don't remember which) but is kinda cool
-    hello    -- AKA Disco in web UI. Sine all LEDs at random-ish frequencies at
-                high brightness
+    hello [harder] [KEY=VALUE ...]
+             -- AKA Disco in web UI. Sine all LEDs at random-ish frequencies at
+                high brightness, chans' `disco_config` overrides the defaults.
+                `harder` is faster and deeper for every chan (`disco_harder`
+                in the config). Tune with freq_min, freq_max, min, max and
+                adjustment, i.e. `demo hello harder freq_max=1.5`
     fade     -- Probably fade out everything, I don't remember. Not very useful.
     whoosh   -- Quick fade across all LEDs sequentially (I think, I also don't
                 remember clearly what it does)
+    schroom [FILE.yaml] [seed=N]
+             -- Stacks of added / multiplied oscillators per chan, from FILE
+                or `schroom` in the config, same seed plays the same show
 
 Other
-    space    -- does something cool or not
-
-", default_config_path=DEFAULT_CONFIG_PATH);
+    space x,y,z RADIUS BRIGHTNESS [POINTER]
+             -- light chans with a cuboid around a pointer, POINTER is
+                wacom[:PATH] (default {wacom}), evdev:/dev/input/eventN,
+                udp[:ADDR:PORT] (OSC or `x,y,z` text, default {udp}) or
+                lissajous, `pointer` in the config sets the calibration
+
+  Audio (from `audio` in the config):
+    audio [INPUT]               -- light chans tagged `bed` with the bass and
+                                   `ceiling` with the highs (`bands` and
+                                   `rms_tags` in the config), INPUT is
+                                   alsa[:DEVICE] (through arecord),
+                                   wav:FILE or stdin[:RATE] for raw
+                                   S16_LE mono
+
+  Expressions (from `expr` in the config):
+    expr [SELECTOR=EXPR ...]    -- set chans with a tag (or id, or all) to
+                                   EXPR every frame, first match wins, i.e.
+                                   `expr 'wall=0.5+0.4*sin(t*0.1+x)'`, EXPR
+                                   sees t, i, id, n, x, y, z (cuboid centre),
+                                   v (last value), chan(ID), tag_NAME, beat,
+                                   bar, rms and bands of `audio` in `expr`;
+                                   v and chan(ID) are what EXPRs gave last
+                                   frame, 0 for chans no EXPR sets
+
+  Scripts:
+    script FILE                 -- run a Rhai script that can call chans,
+                                   set, frame, scene, sleep and wait,
+                                   reloaded when FILE changes, every line on
+                                   stdin is an event for `wait(NAME)`, see
+                                   scripts/demo.rhai
+    timeline play FILE [POS]    -- play YAML or JSON keyframe tracks per tag
+                                   or chan, with markers and loops, from
+                                   marker or seconds POS, `pause` and
+                                   `seek POS` on stdin, see
+                                   timelines/demo.yaml
+
+  Effects:
+    effects                     -- list effects and their params
+    effect NAME [KEY=VALUE ...] -- run effect NAME, i.e.
+                                   `effect whoosh period=2.5 radius=0.3`
+                                   sweep, ripple, noise and spotlight light
+                                   chans by their `cuboid` in the room,
+                                   `whoosh beats=1` fires on every beat and
+                                   `hello bars=on` snaps to bars of `tempo`
+                                   in the config (bpm, midi clock device or
+                                   audio onsets), tap it in `web`,
+                                   candle, fire and lightning take
+                                   `chans=TAG`, fire goes up from the floor,
+                                   `chase order=x` lines chans up by their
+                                   cuboid, `order=config` by chans' `order`
+
+  Scenes (from `scenes` in the config):
+    scene NAME                  -- fade to scene NAME (or its id) and exit
+    scene_srv [ADDR[:PORT]]     -- fade to a scene whenever a UDP datagram
+                                   with its name or id arrives
+                                   (default 0.0.0.0:{scene_port})
+    sequence NAME               -- run sequence NAME from `sequences` in the
+                                   config, Enter (or `go`) goes to the next
+                                   cue, `b` goes back, `p` pauses/resumes
+
+  Schedule (from `schedule` in the config, `web` runs it too):
+    schedule                    -- run scheduled actions until killed,
+                                   type `ls` to list upcoming ones,
+                                   `skip NAME` to skip the next one
+    schedule ls                 -- list upcoming scheduled actions
+                                   (`sun: sunset-20m` entries need
+                                   `location: {{ lat: 51.5, lon: -0.13 }}`)
+    sun                         -- print today's dawn, sunrise, sunset and
+                                   dusk at the config location
+    sun_follow TAG[,TAG] DAY NIGHT
+                                -- set tagged chans (or `all`) to DAY while
+                                   the sun is up and to NIGHT when it's down,
+                                   i.e. `sun_follow window 0 0.6`
+
+    snapshot NAME [TAG,TAG]     -- save current values as scene NAME to
+                                   CONFIG.scenes.yaml next to the config,
+                                   only the chans with any of TAGs if given;
+                                   devices don't report their values back,
+                                   so a new `ctl` only sees what it starts
+                                   them with, use the snapshot in `web` to
+                                   save what another run has on the lights
+
+  Circadian (from `circadian` in the config):
+    circadian                   -- mix chans tagged `warm` and `cool` (and
+                                   `w` brightness) following the colour
+                                   temperature curve over the day,
+                                   type `hold`, `override [MINUTES]` or
+                                   `resume` to take over for a while
+
+  Recording:
+    record FILE ACTION          -- run ACTION and record everything it outputs
+                                   to FILE, i.e. `record party.rec srv`
+    play FILE [SPEED] [loop]    -- replay FILE with the original timing,
+                                   SPEED 2.0 plays twice as fast,
+                                   `loop` starts over when it's done
+
+", default_config_path=DEFAULT_CONFIG_PATH,
+             wacom=leds::pointer::DEFAULT_WACOM_PATH,
+             udp=leds::pointer::DEFAULT_UDP_ADDR,
+             scene_port=leds::scene::DEFAULT_PORT);
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
-    pub templates: Option<Vec<Template>>,
+    #[serde(alias = "templates")]
+    pub scenes: Option<Vec<Scene>>,
+    pub sequences: Option<Vec<SequenceSpec>>,
+    pub schedule: Option<Vec<ScheduleEntry>>,
+
+    /// For sunrise / sunset, i.e. `location: { lat: 51.5, lon: -0.13 }`
+    pub location: Option<Location>,
+
+    /// Curve for `circadian`, the default one when missing
+    pub circadian: Option<CircadianSpec>,
+
+    /// Every chan gets this in disco harder, i.e.
+    /// `disco_harder: { freq_max: 1.0, min: 0.1 }`
+    pub disco_harder: Option<DiscoChanConfig>,
+
+    /// Oscillator stacks for `demo schroom` and its web button
+    pub schroom: Option<SchroomConfig>,
+
+    /// Where `space` gets its position from, the Wacom tablet by default
+    pub pointer: Option<PointerSpec>,
+
+    /// Input and band to tag mapping for `audio`
+    pub audio: Option<AudioConfig>,
+
+    /// BPM and where the beat comes from for `web` and `effect`
+    pub tempo: Option<TempoConfig>,
+
+    /// Per chan expressions for `expr`
+    pub expr: Option<ExprConfig>,
+
+    /// Operation budget for `script`
+    pub script: Option<ScriptConfig>,
     pub mux: mux::Config,
+
+    /// Where we've read the config from
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
 }
 
 pub fn from_args(mut args: env::Args)
@@ -87,8 +238,10 @@ pub fn from_args(mut args: env::Args)
     let mut cfg: Option<Config> = None;
 
     args.next(); // remove the executable name from args
+    let mut args = args.peekable();
 
     let mut skip_default_config: bool = false;
+    let mut record_path: Option<PathBuf> = None;
 
     loop {
         let arg = args.next();
@@ -232,6 +385,8 @@ pub fn from_args(mut args: env::Args)
                     },
                     radius,
                     brightness,
+                    pointer: args.next().as_deref()
+                        .map(PointerSpec::parse).transpose()?,
                 })
             }
             "demo" => {
@@ -243,17 +398,206 @@ pub fn from_args(mut args: env::Args)
                 match demo_arg.as_ref() {
                     "test_seq" => action = Some(ActionSpec::TestSeq),
                     "glitch" => action = Some(ActionSpec::Glitch),
-                    "hello" => action = Some(ActionSpec::Hello),
+                    "hello" => {
+                        let harder = args.peek()
+                            .map(|a| a == "harder").unwrap_or(false);
+                        if harder {
+                            args.next();
+                        }
+                        let params: Vec<String> = args.by_ref().collect();
+                        // fail early on invalid params
+                        DiscoChanConfig::default().tune(&params)?;
+                        action = Some(ActionSpec::Hello { harder, params });
+                    },
                     "fade" => action = Some(ActionSpec::Fade),
                     "whoosh" => action = Some(ActionSpec::Whoosh),
+                    "schroom" => {
+                        let mut path: Option<PathBuf> = None;
+                        let mut seed: Option<u64> = None;
+                        for arg in args.by_ref() {
+                            match arg.strip_prefix("seed=") {
+                                Some(s) => seed = Some(s.parse().map_err(
+                                        |e| format!("seed: {:?}", e))?),
+                                None => path = Some(arg.into()),
+                            }
+                        }
+                        // fail early on a broken file
+                        if let Some(path) = &path {
+                            SchroomConfig::load(path)?;
+                        }
+                        action = Some(ActionSpec::Schroom { path, seed });
+                    },
                     other => return Err(
                         format!("demo \"{}\" does not exist", other)),
                 }
             }
+            "scene" => {
+                let name = args.next();
+                if name.is_none() {
+                    return Err("scene requires a scene name or id"
+                               .to_string());
+                }
+                action = Some(ActionSpec::Scene(name.unwrap()));
+            }
+            "scene_srv" => {
+                let listen_arg = args.next();
+                let (listen_ip, listen_port) = match listen_arg {
+                    Some(arg) => {
+                        let parts: Vec<&str> = arg.split(':').collect();
+                        let (ip, port) =
+                            parse_ip_port(&parts[0..2.min(parts.len())])?;
+                        (Some(ip), port)
+                    }
+                    None => (None, None),
+                };
+
+                action = Some(ActionSpec::SceneSrv {
+                    listen_ip,
+                    listen_port,
+                });
+            }
+            "sequence" => {
+                let name = args.next();
+                if name.is_none() {
+                    return Err("sequence requires a sequence name"
+                               .to_string());
+                }
+                action = Some(ActionSpec::Sequence(name.unwrap()));
+            }
+            "script" => {
+                let path = PathBuf::from(args.next()
+                    .ok_or("script requires a script file")?);
+                // fail early on syntax errors
+                leds::script::Program::load(&path)?;
+                action = Some(ActionSpec::Script(path));
+            }
+            "timeline" => {
+                match args.next().as_deref() {
+                    Some("play") => {},
+                    _ => return Err("expected timeline play FILE".to_string()),
+                }
+                let path = PathBuf::from(args.next()
+                    .ok_or("timeline play requires a timeline file")?);
+                // fail early on invalid timelines
+                leds::timeline::Timeline::load(&path)?;
+                action = Some(ActionSpec::Timeline { path, from: args.next() });
+            }
+            "schedule" => {
+                let list_only = match args.next().as_deref() {
+                    None => false,
+                    Some("ls") => true,
+                    Some(other) => return Err(format!(
+                            "unknown schedule argument '{}'", other)),
+                };
+                action = Some(ActionSpec::Schedule { list_only });
+            }
+            "snapshot" => {
+                let name = args.next();
+                if name.is_none() {
+                    return Err("snapshot requires a scene name".to_string());
+                }
+                let tags: Vec<String> = match args.next() {
+                    Some(tags) => tags.split(',')
+                        .filter(|t| !t.is_empty())
+                        .map(|t| t.to_string())
+                        .collect(),
+                    None => vec![],
+                };
+                action = Some(ActionSpec::Snapshot {
+                    name: name.unwrap(),
+                    tags,
+                });
+            }
+            "sun" => {
+                action = Some(ActionSpec::Sun);
+            }
+            "sun_follow" => {
+                let usage = "usage: sun_follow TAG[,TAG] DAY NIGHT";
+                let tags = args.next().ok_or(usage)?;
+                let tags: Vec<String> = tags.split(',')
+                    .filter(|t| !t.is_empty() && *t != "all")
+                    .map(|t| t.to_string())
+                    .collect();
+                let mut value = || -> Result<f32, String> {
+                    args.next().ok_or(usage)?.parse::<f32>()
+                        .map_err(|e| format!("{}: {:?}", usage, e))
+                };
+                let day = value()?;
+                let night = value()?;
+                action = Some(ActionSpec::SunFollow { tags, day, night });
+            }
+            "audio" => {
+                let input = args.next().as_deref()
+                    .map(AudioSpec::parse).transpose()?;
+                action = Some(ActionSpec::Audio { input });
+            }
+            "expr" => {
+                let rules = args.by_ref()
+                    .map(|rule| rule.split_once('=')
+                         .map(|(s, e)| (s.to_string(), e.to_string()))
+                         .ok_or_else(|| format!(
+                                 "expected SELECTOR=EXPR, got '{}'", rule)))
+                    .collect::<Result<Vec<_>, String>>()?;
+                action = Some(ActionSpec::Expr { rules });
+            }
+            "effects" => {
+                action = Some(ActionSpec::ListEffects);
+            }
+            "effect" => {
+                let name = args.next().ok_or(
+                    "effect requires an effect name, see `effects`")?;
+                let params: Vec<String> = args.by_ref().collect();
+                // fail early on unknown effects and invalid params
+                leds::effect::find(&name)?.create_with_args(&params)?;
+                action = Some(ActionSpec::Effect { name, params });
+            }
+            "circadian" => {
+                action = Some(ActionSpec::Circadian);
+            }
+            "record" => {
+                let path = args.next();
+                if path.is_none() {
+                    return Err("record requires a filename".to_string());
+                }
+                record_path = Some(path.unwrap().into());
+            }
+            "play" => {
+                let path = args.next();
+                if path.is_none() {
+                    return Err("play requires a filename".to_string());
+                }
+
+                let mut speed: f32 = 1.0;
+                if let Some(s) = args.peek().and_then(|a| a.parse().ok()) {
+                    speed = s;
+                    args.next();
+                }
+
+                let mut looped = false;
+                if args.peek().map(|a| a == "loop").unwrap_or(false) {
+                    looped = true;
+                    args.next();
+                }
+
+                action = Some(ActionSpec::Play {
+                    path: path.unwrap().into(),
+                    speed,
+                    looped,
+                });
+            }
             other => return Err(format!("Unknown arg \"{}\"", other)),
         }
     }
 
+    if let Some(path) = record_path {
+        let inner = match action {
+            Some(inner) => inner,
+            None => return Err("record requires an action to record"
+                               .to_string()),
+        };
+        action = Some(ActionSpec::Record { path, action: Box::new(inner) });
+    }
+
     if cfg.is_none() && !skip_default_config {
         match fs::metadata(DEFAULT_CONFIG_PATH) {
             Ok(_) => cfg = Some(Config::from_file(DEFAULT_CONFIG_PATH)?),
@@ -267,35 +611,73 @@ pub fn from_args(mut args: env::Args)
         }
     }
 
-    let cfg = match cfg {
+    let mut cfg = match cfg {
         Some(mut cfg) => {
             cfg.mux.devs.extend(mux_cfg.devs);
-            Config {
-                templates: cfg.templates,
-                mux: cfg.mux,
-            }
+            cfg
         }
         None => {
-            Config { mux: mux_cfg, templates: None }
+            Config {
+                mux: mux_cfg,
+                scenes: None,
+                sequences: None,
+                schedule: None,
+                location: None,
+                circadian: None,
+                disco_harder: None,
+                schroom: None,
+                pointer: None,
+                audio: None,
+                tempo: None,
+                expr: None,
+                script: None,
+                path: None,
+            }
         }
     };
 
+    let saved_scenes = leds::scene::load_file(cfg.scenes_path())?;
+    if !saved_scenes.is_empty() {
+        let scenes = cfg.scenes.get_or_insert_with(Vec::new);
+        for scene in saved_scenes {
+            leds::scene::merge(scenes, scene);
+        }
+    }
+
     Ok((action, cfg))
 }
 
 impl Config {
     fn from_file<T: AsRef<Path>>(filename: T) -> Result<Self, String> {
-        let mut file = File::open(filename).map_err(|e| format!("{:?}", e))?;
+        let mut file = File::open(&filename).map_err(|e| format!("{:?}", e))?;
         let mut buf = String::new();
         file.read_to_string(&mut buf)
             .map_err(|e| format!("{:?}", e))?;
 
-        let cfg: Config =
+        let mut cfg: Config =
             serde_yaml::from_str(buf.as_ref())
             .map_err(|e| format!("parsing config: {:?}", e))?;
+        if let Some(circadian) = &cfg.circadian {
+            circadian.validate()?;
+        }
+        cfg.path = Some(filename.as_ref().to_path_buf());
         Ok(cfg)
     }
 
+    /// Snapshots are saved next to the config, i.e. `led_ctl.yaml` keeps
+    /// them in `led_ctl.scenes.yaml`, or in the current dir without config
+    pub fn scenes_path(&self) -> PathBuf {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return PathBuf::from(DEFAULT_SCENES_FILENAME),
+        };
+
+        let stem = path.file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| "led_ctl".to_string());
+        path.with_file_name(format!("{}.scenes.yaml", stem))
+    }
+
 
 }
 
@@ -315,4 +697,19 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_scenes_path() {
+        let cfg = Config::from_file("../configs/test.yaml").unwrap();
+        assert_eq!(cfg.scenes_path(),
+                   PathBuf::from("../configs/test.scenes.yaml"));
+
+        let cfg = Config { scenes: None, sequences: None, schedule: None,
+                           location: None, circadian: None,
+                           disco_harder: None, schroom: None, pointer: None,
+                           audio: None, tempo: None, expr: None,
+                           script: None,
+                           mux: mux::Config::default(), path: None };
+        assert_eq!(cfg.scenes_path(), PathBuf::from("scenes.yaml"));
+    }
 }
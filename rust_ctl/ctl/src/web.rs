@@ -2,6 +2,7 @@ extern crate tiny_http;
 extern crate form_urlencoded;
 use url::Url;
 
+use std::collections::HashMap;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::fmt;
@@ -9,19 +10,31 @@ use std::fmt;
 use std::io::{Write, Cursor};
 
 use crate::actions;
+use crate::config::Config;
 
 use leds::chan_spec::{ChanSpec, ChanSpecGeneric};
 use leds::mux;
 use leds::msg_handler::{MsgHandler};
 use leds::chan_description::{ChanDescription, HasChanDescriptions};
-use leds::dev::Dev;
+use leds::dev::{Dev, DevRead};
 use leds::tag::Tag;
-use leds::demo::{self, Fade, FadeSpec};
+use leds::demo::{Fade, FadeSpec};
+use leds::demo::hello::{DiscoChanConfig, HelloEffect};
+use leds::demo::schroom::{SchroomConfig, SchroomEffect};
+use leds::circadian::{Circadian, CircadianSpec};
+use leds::compositor::{Base, BlendMode, Compositor, LayerInfo, LayerSpec};
+use leds::easing::Easing;
+use leds::effect::{self, EffectInfo, ParamKind, ParamSpec, Params};
+use leds::scene::{self, Scene};
+use leds::sequence::{Sequence, SequenceSpec};
+use leds::schedule::{ScheduleAction, Scheduler, Upcoming};
 use leds::task::{Task, TaskMsg};
+use leds::tempo;
 use leds::runner::{Runner};
 
 use askama::Template;
 
+use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
 
@@ -59,6 +72,15 @@ impl<'a> FlashMsg<'a> {
     }
 }
 
+/// Fields of the url-encoded form in the body of `req`
+fn read_form(req: &mut tiny_http::Request)
+        -> Result<HashMap<String, String>, String> {
+    let mut body: Vec<u8> = Vec::new();
+    req.as_reader().read_to_end(&mut body)
+        .map_err(|e| format!("reading request: {:?}", e))?;
+    Ok(form_urlencoded::parse(body.as_slice()).into_owned().collect())
+}
+
 #[derive(Template)]
 #[template(path = "chan.html", escape = "none")]
 struct ChanTemplate {
@@ -84,26 +106,153 @@ impl ChanTemplate {
     }
 }
 
+/// Form for starting an effect, generated from its params
+#[derive(Template)]
+#[template(path = "effect.html", escape = "none")]
+struct EffectTemplate {
+    name: &'static str,
+    description: &'static str,
+    params: Vec<ParamTemplate>,
+    blend_modes: &'static [&'static str],
+}
+
+struct ParamTemplate {
+    name: &'static str,
+    description: &'static str,
+    input_type: &'static str,
+    value: String,
+    min: String,
+    max: String,
+    step: &'static str,
+    options: &'static [&'static str],
+}
+
+impl ParamTemplate {
+    fn is_selected(&self, option: &str) -> bool {
+        self.value == option
+    }
+}
+
+impl From<&EffectInfo> for EffectTemplate {
+    fn from(info: &EffectInfo) -> Self {
+        EffectTemplate {
+            name: info.name,
+            description: info.description,
+            params: (info.params)().iter().map(ParamTemplate::from).collect(),
+            blend_modes: BlendMode::ALL,
+        }
+    }
+}
+
+impl From<&ParamSpec> for ParamTemplate {
+    fn from(spec: &ParamSpec) -> Self {
+        let (input_type, min, max, step, options) = match &spec.kind {
+            ParamKind::Float { min, max } =>
+                ("number", min.to_string(), max.to_string(), "any", &[][..]),
+            ParamKind::Int { min, max } =>
+                ("number", min.to_string(), max.to_string(), "1", &[][..]),
+            ParamKind::Bool =>
+                ("checkbox", String::new(), String::new(), "", &[][..]),
+            ParamKind::Choice(choices) =>
+                ("select", String::new(), String::new(), "", *choices),
+            ParamKind::Text =>
+                ("text", String::new(), String::new(), "", &[][..]),
+        };
+
+        ParamTemplate {
+            name: spec.name,
+            description: spec.description,
+            input_type,
+            value: spec.default.to_string(),
+            min,
+            max,
+            step,
+            options,
+        }
+    }
+}
+
 #[derive(Template)]
 #[template(path = "home.html")]
 struct HomeTemplate<'a> {
     msg: Option<FlashMsg<'a>>,
     chans: Vec<ChanTemplate>,
+    scenes: &'a [Scene],
+    sequences: &'a [SequenceSpec],
+
+    /// Description of the running sequence
+    sequence: Option<String>,
+
+    schedule: Vec<Upcoming>,
+
+    /// Current circadian values if it's running
+    circadian: Option<String>,
+
+    effects: Vec<EffectTemplate>,
+    layers: Vec<LayerInfo>,
+
+    /// Beats per minute of the shared tempo
+    bpm: f32,
 }
 
 const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:7373";
 
+/// Circadian mixing on top of the compositor base
+type SharedCircadian<T> = Arc<Mutex<Circadian<Base<T>>>>;
+
 struct WebState<T: fmt::Debug> {
     base_url: Url,
-    output: Arc<Mutex<T>>,
+
+    /// Base of the compositor, everything except effects writes here
+    output: Arc<Mutex<Base<T>>>,
+
+    /// Effects run as its layers on top of `output`
+    compositor: Arc<Mutex<Compositor<T>>>,
+
+    /// Keeps the compositor running
+    #[allow(dead_code)]
+    compositor_task: Task,
 
     #[allow(dead_code)]
     output_config: mux::Config,
 
+    scenes: Vec<Scene>,
+
+    /// Where snapshots are saved
+    scenes_path: PathBuf,
+
+    sequences: Vec<SequenceSpec>,
+
+    /// Sequence run by `task`, so we can control it
+    sequence: Option<Arc<Mutex<Sequence<Base<T>>>>>,
+
+    scheduler: Option<Arc<Mutex<Scheduler>>>,
+    schedule_events: mpsc::Receiver<ScheduleAction>,
+
+    /// Keeps the scheduler running
+    #[allow(dead_code)]
+    scheduler_task: Option<Task>,
+
+    circadian_spec: CircadianSpec,
+
+    /// Every chan gets this in "Disco harder!"
+    disco_harder: DiscoChanConfig,
+
+    schroom: SchroomConfig,
+
+    /// Runs in the background, manual changes override it for a while
+    circadian: Option<(SharedCircadian<T>, Task)>,
+
+    /// Keep following MIDI clock or beats in the audio
+    #[allow(dead_code)]
+    tempo_tasks: Vec<Task>,
+
     http: tiny_http::Server,
+
+    /// Sequence or a smooth set writing to the base
     task: Option<Task>,
 
-    fader: Arc<Mutex<Fade<T>>>,
+    fader: Arc<Mutex<Fade<Base<T>>>>,
     fade_task: Task,
 }
 
@@ -141,10 +290,16 @@ impl<T: 'static + Dev + HasChanDescriptions + fmt::Debug> WebState<T> {
     }
 
     fn stop_fade(&mut self) {
-        self.fade_task.ask_to_pause();
+        self.fader.lock().unwrap().cancel();
     }
 
     fn stop_task(&mut self) {
+        if let Some((circadian, _)) = &self.circadian {
+            if let Err(e) = circadian.lock().unwrap().override_default() {
+                eprintln!("{}", e);
+            }
+        }
+        self.sequence = None;
         let mut task: Option<Task> = self.running_task();
         if task.is_some() {
             let task: Option<Task> = task.take();
@@ -156,9 +311,18 @@ impl<T: 'static + Dev + HasChanDescriptions + fmt::Debug> WebState<T> {
 
     pub fn run(&mut self) {
         loop {
-            let res = self.http.recv();
+            while let Ok(action) = self.schedule_events.try_recv() {
+                println!("running scheduled {}", action);
+                if let Err(e) = self.perform_scheduled(&action) {
+                    eprintln!("scheduled {}: {}", action, e);
+                }
+            }
+
+            // wake up once in a while to check the schedule
+            let res = self.http.recv_timeout(Duration::from_millis(250));
             let req = match res {
-                Ok(req) => req,
+                Ok(Some(req)) => req,
+                Ok(None) => continue,
                 Err(e) => {
                     println!("http error: {:?}", e);
                     continue;
@@ -173,8 +337,10 @@ impl<T: 'static + Dev + HasChanDescriptions + fmt::Debug> WebState<T> {
         self.base_url.join(url)
     }
 
+    /// Everything on or off, effects fade out too
     fn fade_all_to(&mut self, val: f32, ok_msg: &str)
             -> tiny_http::Response<Cursor<Vec<u8>>> {
+        self.compositor.lock().unwrap().clear();
         self.fade_to(&ChanSpec::F32(ChanSpecGeneric::<f32>::SomeWithDefault(
             val,
             vec![],
@@ -186,47 +352,24 @@ impl<T: 'static + Dev + HasChanDescriptions + fmt::Debug> WebState<T> {
             -> tiny_http::Response<Cursor<Vec<u8>>> {
         // eprintln!("fade_to {chan_spec:?}");
         self.stop_task();
-        let (tx, rx) = mpsc::channel::<TaskMsg>();
-
-        let settings = FadeSpec {
-            frame_duration: Duration::from_millis(4),
-            fade_duration: fading.duration(),
-        };
-
-        let fader = Arc::new(Mutex::new(Fade::new(
-            self.output.clone(),
-            settings,
-        )));
-
-        let join_handle = {
-            let fader = fader.clone();
-            thread::spawn(move || {
-                Runner::run(fader, rx)
-            })
-        };
-
-        let mut msg = FlashMsg::Ok(ok_msg.as_ref());
-
-        let result = actions::set::run_dev(chan_spec, fader);
-        msg = msg.and_result(&result);
-
-        if let Err(e) = &result {
-            eprintln!("fade_all_to: action.perform error: {:?}", e);
-        }
-
-        self.task = Some(Task {
-            name: "Smooth set val".to_string(),
-            chan: tx,
-            join_handle,
-        });
 
         {
             let mut fader = self.fader.lock().unwrap();
             fader.settings.fade_duration = fading.duration();
+            fader.settings.easing = Easing::Linear;
         }
 
-        let result = actions::set::run_dev(chan_spec, self.fader.clone());
-        msg = msg.and_result(&result);
+        // the fader supersedes whatever it was fading these chans to, so
+        // quick successive sets continue from where the last one got
+        let result = if self.fade_task.is_running() {
+            actions::set::run_dev(chan_spec, self.fader.clone())
+        } else {
+            Err(format!("{} is not running", self.fade_task.name))
+        };
+        if let Err(e) = &result {
+            eprintln!("fade_to: action.perform error: {:?}", e);
+        }
+        let msg = FlashMsg::Ok(ok_msg.as_ref()).and_result(&result);
 
         self.home_with(Some(msg))
     }
@@ -239,6 +382,7 @@ impl<T: 'static + Dev + HasChanDescriptions + fmt::Debug> WebState<T> {
     }
 
     fn slow_fade_in(&mut self) -> tiny_http::Response<Cursor<Vec<u8>>> {
+        self.compositor.lock().unwrap().clear();
         let fade_type = FadingType::TensOfMinutes;
         let duration = fade_type.duration();
         self.fade_to(&ChanSpec::F32(ChanSpecGeneric::<f32>::SomeWithDefault(
@@ -249,6 +393,7 @@ impl<T: 'static + Dev + HasChanDescriptions + fmt::Debug> WebState<T> {
     }
 
     fn slow_fade_out(&mut self) -> tiny_http::Response<Cursor<Vec<u8>>> {
+        self.compositor.lock().unwrap().clear();
         let fade_type = FadingType::TensOfMinutes;
         let duration = fade_type.duration();
         self.fade_to(&ChanSpec::F32(ChanSpecGeneric::<f32>::SomeWithDefault(
@@ -265,65 +410,409 @@ impl<T: 'static + Dev + HasChanDescriptions + fmt::Debug> WebState<T> {
         )
     }
 
-    fn disco(&mut self) -> tiny_http::Response<Cursor<Vec<u8>>> {
-        self.stop_fade();
+    fn scene(&mut self, req: &mut tiny_http::Request)
+            -> tiny_http::Response<Cursor<Vec<u8>>> {
+        let form = match read_form(req) {
+            Ok(form) => form,
+            Err(err) => return self.home_with(Some(FlashMsg::Err(&err))),
+        };
+
+        let name = form.get("name").cloned().unwrap_or_default();
+
+        let result = match scene::find(&self.scenes, &name).cloned() {
+            Some(scene) => self.fade_to_scene(&scene),
+            None => Err(format!("scene '{}' not found", name)),
+        };
+
+        let ok_msg = format!("Fading to {}", name);
+        self.home_with(Some(FlashMsg::from_result(&result, &ok_msg)))
+    }
+
+    fn fade_to_scene(&mut self, scene: &Scene) -> Result<(), String> {
         self.stop_task();
-        let (tx, rx) = mpsc::channel::<TaskMsg>();
+        let mut fader = self.fader.lock().unwrap();
+        scene.fade_with(&mut fader).map(|_| ())
+    }
 
-        let join_handle = {
-            let output = self.output.clone();
+    fn start_sequence(&mut self, req: &mut tiny_http::Request)
+            -> tiny_http::Response<Cursor<Vec<u8>>> {
+        let form = match read_form(req) {
+            Ok(form) => form,
+            Err(err) => return self.home_with(Some(FlashMsg::Err(&err))),
+        };
 
-            let disco_chan_configs: Vec<demo::hello::DiscoChanConfig> =
-            {
-                let output = output.lock().unwrap();
-                output.chans().iter().map(|_chan| {
-                    demo::hello::DiscoChanConfig::default()
-                }).collect()
-            };
+        let name = form.get("name").cloned().unwrap_or_default();
+
+        let spec = match self.sequences.iter().find(|s| s.name == name) {
+            Some(spec) => spec.clone(),
+            None => {
+                let err = format!("sequence '{}' not found", name);
+                return self.home_with(Some(FlashMsg::Err(&err)));
+            }
+        };
 
-            thread::spawn(move || demo::hello::run_with_config(
-                    output, disco_chan_configs, rx))
+        let result = self.run_sequence(&spec);
+        let ok_msg = format!("Running sequence {}", name);
+        self.home_with(Some(FlashMsg::from_result(&result, &ok_msg)))
+    }
+
+    fn run_sequence(&mut self, spec: &SequenceSpec) -> Result<(), String> {
+        self.stop_fade();
+        self.stop_task();
+
+        let seq = Arc::new(Mutex::new(
+                Sequence::new(self.output.clone(), spec, &self.scenes)?));
+
+        let (tx, rx) = mpsc::channel::<TaskMsg>();
+        let join_handle = {
+            let seq = seq.clone();
+            thread::spawn(move || Runner::run(seq, rx))
         };
 
         self.task = Some(Task {
-            name: "Hello task from web test".to_string(),
+            name: format!("Sequence {}", spec.name),
             chan: tx,
             join_handle,
         });
+        self.sequence = Some(seq);
+        Ok(())
+    }
 
-        self.home_with(Some(FlashMsg::Ok("Wooooo111!!!")))
+    /// GO / back / pause for the running sequence
+    fn control_sequence(&mut self, control: Option<&str>)
+            -> tiny_http::Response<Cursor<Vec<u8>>> {
+        let seq = match &self.sequence {
+            Some(seq) => seq.clone(),
+            None => return self.home_with(
+                Some(FlashMsg::Err("no sequence is running"))),
+        };
+
+        {
+            let mut seq = seq.lock().unwrap();
+            match control {
+                Some("go") => seq.go(),
+                Some("back") => seq.back(),
+                Some("pause") => seq.toggle_pause(),
+                _ => return self.home_with(
+                    Some(FlashMsg::Err("unknown sequence control"))),
+            }
+        }
+
+        self.home()
     }
 
-    fn disco_harder(&mut self) -> tiny_http::Response<Cursor<Vec<u8>>> {
-        self.stop_fade();
-        self.stop_task();
-        let (tx, rx) = mpsc::channel::<TaskMsg>();
+    fn perform_scheduled(&mut self, action: &ScheduleAction)
+            -> Result<(), String> {
+        match action {
+            ScheduleAction::Scene(name) => {
+                let scene = scene::find(&self.scenes, name).cloned()
+                    .ok_or_else(|| format!("scene '{}' not found", name))?;
+                self.fade_to_scene(&scene)
+            },
+            ScheduleAction::Sequence(name) => {
+                let spec = self.sequences.iter().find(|s| &s.name == name)
+                    .cloned()
+                    .ok_or_else(|| format!("sequence '{}' not found", name))?;
+                self.run_sequence(&spec)
+            },
+            ScheduleAction::Demo(name) => match name.as_ref() {
+                "disco" => {
+                    self.start_disco();
+                    Ok(())
+                },
+                "schroom" => {
+                    self.start_schroom();
+                    Ok(())
+                },
+                name => self.run_effect(name, &HashMap::new()),
+            },
+            ScheduleAction::Fade { .. } | ScheduleAction::Off =>
+                self.fade_to_scene(&action.as_scene().unwrap()),
+        }
+    }
 
-        let join_handle = {
-            let output = self.output.clone();
+    /// Skips the next occurrence of schedule entry `name` from the form
+    fn skip_scheduled(&mut self, req: &mut tiny_http::Request)
+            -> tiny_http::Response<Cursor<Vec<u8>>> {
+        let form = match read_form(req) {
+            Ok(form) => form,
+            Err(err) => return self.home_with(Some(FlashMsg::Err(&err))),
+        };
 
-            let disco_chan_configs: Vec<demo::hello::DiscoChanConfig> =
-            {
-                let output = output.lock().unwrap();
+        let name = form.get("name").cloned().unwrap_or_default();
+
+        let result = match &self.scheduler {
+            Some(scheduler) => scheduler.lock().unwrap().skip_next(&name),
+            None => Err("nothing is scheduled".to_string()),
+        };
+
+        let ok_msg = match &result {
+            Ok(time) => format!("Skipping {} at {}", name, time),
+            Err(_) => String::new(),
+        };
+        self.home_with(Some(FlashMsg::from_result(&result, &ok_msg)))
+    }
+
+    /// Saves current values as a scene, form params: `name` and
+    /// optional comma-separated `tags`
+    fn snapshot(&mut self, req: &mut tiny_http::Request)
+            -> tiny_http::Response<Cursor<Vec<u8>>> {
+        let form = match read_form(req) {
+            Ok(form) => form,
+            Err(err) => return self.home_with(Some(FlashMsg::Err(&err))),
+        };
+
+        let mut name = String::new();
+        let mut tags: Vec<String> = Vec::new();
+        for (k, v) in form {
+            match k.as_ref() {
+                "name" => name = v.trim().to_string(),
+                "tags" => tags = v.split(',')
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty())
+                    .collect(),
+                other => {
+                    println!("unexpected form parameter {} with value '{}'",
+                             other, v);
+                }
+            }
+        }
 
-                output.chans().iter().map(|(_cid, _desc)| {
-                    demo::hello::DiscoChanConfig::default()
-                }).collect()
+        let result = if name.is_empty() {
+            Err("scene name is required".to_string())
+        } else {
+            // what's on the lights, not just the base below the effects
+            let output = self.compositor.lock().unwrap().output();
+            let scene = {
+                let output = output.lock().unwrap();
+                scene::snapshot(&name, &*output, &tags)
             };
+            scene.and_then(|scene| {
+                scene::save_to_file(&self.scenes_path, scene.clone())?;
+                scene::merge(&mut self.scenes, scene);
+                Ok(())
+            })
+        };
 
-            thread::spawn(move || demo::hello::run_with_config(
-                    output, disco_chan_configs, rx))
+        let ok_msg = format!("Saved {} to {}", name,
+                             self.scenes_path.display());
+        self.home_with(Some(FlashMsg::from_result(&result, &ok_msg)))
+    }
+
+    fn circadian(&mut self, control: Option<&str>)
+            -> tiny_http::Response<Cursor<Vec<u8>>> {
+        let result = match control {
+            None | Some("") => self.start_circadian(),
+            Some("stop") => match self.circadian.take() {
+                Some((_, task)) => {
+                    task.stop();
+                    Ok(())
+                },
+                None => Err("circadian is not running".to_string()),
+            },
+            Some("resume") => match &self.circadian {
+                Some((circadian, _)) => {
+                    circadian.lock().unwrap().resume();
+                    Ok(())
+                },
+                None => Err("circadian is not running".to_string()),
+            },
+            Some(other) => Err(format!("unknown circadian control '{}'",
+                                       other)),
         };
 
-        self.task = Some(Task {
-            name: "Hello task from web test".to_string(),
+        self.home_with(Some(FlashMsg::from_result(&result, "Circadian")))
+    }
+
+    fn start_circadian(&mut self) -> Result<(), String> {
+        self.stop_fade();
+        self.stop_task();
+        if let Some((_, task)) = self.circadian.take() {
+            task.stop();
+        }
+
+        let circadian = Arc::new(Mutex::new(Circadian::new(
+                    self.output.clone(), self.circadian_spec.clone())?));
+        let (tx, rx) = mpsc::channel::<TaskMsg>();
+        let join_handle = {
+            let circadian = circadian.clone();
+            thread::spawn(move || Runner::run(circadian, rx))
+        };
+
+        self.circadian = Some((circadian, Task {
+            name: "Circadian".to_string(),
             chan: tx,
             join_handle,
-        });
+        }));
+        Ok(())
+    }
 
+    fn start_effect(&mut self, req: &mut tiny_http::Request)
+            -> tiny_http::Response<Cursor<Vec<u8>>> {
+        let mut form = match read_form(req) {
+            Ok(form) => form,
+            Err(err) => return self.home_with(Some(FlashMsg::Err(&err))),
+        };
+        let name = form.get("name").cloned().unwrap_or_default();
+
+        // unchecked checkboxes are not sent at all
+        if let Ok(info) = effect::find(&name) {
+            for spec in (info.params)() {
+                if matches!(spec.kind, ParamKind::Bool) {
+                    form.entry(spec.name.to_string())
+                        .or_insert_with(|| "false".to_string());
+                }
+            }
+        }
+
+        let result = self.run_effect(&name, &form);
+        let ok_msg = format!("Running {}", name);
+        self.home_with(Some(FlashMsg::from_result(&result, &ok_msg)))
+    }
+
+    /// Params missing from `form` keep their defaults
+    fn run_effect(&mut self, name: &str, form: &HashMap<String, String>)
+            -> Result<(), String> {
+        let info = effect::find(name)?;
+        let schema = (info.params)();
+        let mut params = Params::defaults(&schema);
+        for spec in schema.iter() {
+            if let Some(val) = form.get(spec.name) {
+                params.set(&schema, spec.name, val)?;
+            }
+        }
+        let effect = (info.create)(&params)?;
+
+        let layer = LayerSpec {
+            name: name.to_string(),
+            ..Self::layer_spec(form)?
+        };
+        self.compositor.lock().unwrap().add_layer(layer, effect);
+        Ok(())
+    }
+
+    /// Blend mode, opacity, tags and fade from the `layer_*` form fields
+    fn layer_spec(form: &HashMap<String, String>)
+            -> Result<LayerSpec, String> {
+        let mut spec = LayerSpec::default();
+        for (key, val) in form.iter().filter(|(_, val)| !val.is_empty()) {
+            let float = || val.parse::<f32>()
+                .map_err(|e| format!("{}: {:?}", key, e));
+            match key.as_ref() {
+                "layer_blend" => spec.blend = val.parse()?,
+                "layer_opacity" => spec.opacity = float()?.clamp(0.0, 1.0),
+                "layer_fade" => {
+                    spec.fade_in = float()?.max(0.0);
+                    spec.fade_out = spec.fade_in;
+                },
+                "layer_tags" => spec.tags = val.split(',')
+                    .map(|tag| tag.trim().to_string())
+                    .filter(|tag| !tag.is_empty())
+                    .collect(),
+                _ => {},
+            }
+        }
+        Ok(spec)
+    }
+
+    /// Remove / opacity / clear for the running layers
+    fn control_layers(&mut self, control: Option<&str>,
+                      req: &mut tiny_http::Request)
+            -> tiny_http::Response<Cursor<Vec<u8>>> {
+        let form = match read_form(req) {
+            Ok(form) => form,
+            Err(err) => return self.home_with(Some(FlashMsg::Err(&err))),
+        };
+        let field = |name: &str| form.get(name).cloned().unwrap_or_default();
+
+        let result = {
+            let mut compositor = self.compositor.lock().unwrap();
+            match control {
+                Some("remove") => compositor.remove_layer(&field("name")),
+                Some("opacity") => field("opacity").parse::<f32>()
+                    .map_err(|e| format!("opacity: {:?}", e))
+                    .and_then(|opacity| compositor.set_opacity(
+                            &field("name"), opacity)),
+                Some("clear") => {
+                    compositor.clear();
+                    Ok(())
+                },
+                _ => Err("unknown layer control".to_string()),
+            }
+        };
+
+        self.home_with(Some(FlashMsg::from_result(&result, "Layers updated")))
+    }
+
+    /// Tap tempo or set the BPM
+    fn tempo(&mut self, control: Option<&str>, req: &mut tiny_http::Request)
+            -> tiny_http::Response<Cursor<Vec<u8>>> {
+        let form = match read_form(req) {
+            Ok(form) => form,
+            Err(err) => return self.home_with(Some(FlashMsg::Err(&err))),
+        };
+        let bpm = form.get("bpm").cloned().unwrap_or_default();
+
+        let tempo = tempo::shared();
+        let result = match control {
+            Some("tap") => {
+                tempo.lock().unwrap().tap();
+                Ok(())
+            },
+            None | Some("") => match bpm.parse::<f32>() {
+                Ok(bpm) if (1.0..=999.0).contains(&bpm) => {
+                    tempo.lock().unwrap().set_bpm(bpm);
+                    Ok(())
+                },
+                _ => Err(format!("invalid BPM '{}'", bpm)),
+            },
+            Some(other) => Err(format!("unknown tempo control '{}'", other)),
+        };
+
+        let ok_msg = format!("{:.1} BPM", tempo.lock().unwrap().bpm());
+        self.home_with(Some(FlashMsg::from_result(&result, &ok_msg)))
+    }
+
+    fn disco(&mut self) -> tiny_http::Response<Cursor<Vec<u8>>> {
+        self.start_disco();
         self.home_with(Some(FlashMsg::Ok("Wooooo111!!!")))
     }
 
+    /// Chans' own `disco_config` or the defaults
+    fn start_disco(&mut self) {
+        let effect = HelloEffect::new(DiscoChanConfig::default());
+        self.start_disco_layer(effect);
+    }
+
+    fn disco_harder(&mut self) -> tiny_http::Response<Cursor<Vec<u8>>> {
+        let effect = HelloEffect::uniform(self.disco_harder.clone());
+        self.start_disco_layer(effect);
+        self.home_with(Some(FlashMsg::Ok("Wooooo111!!!")))
+    }
+
+    fn schroom(&mut self) -> tiny_http::Response<Cursor<Vec<u8>>> {
+        self.start_schroom();
+        self.home_with(Some(FlashMsg::Ok("Schroooom")))
+    }
+
+    fn start_schroom(&mut self) {
+        let layer = LayerSpec {
+            name: "schroom".to_string(),
+            ..LayerSpec::default()
+        };
+        let effect = SchroomEffect::new(self.schroom.clone());
+        self.compositor.lock().unwrap().add_layer(layer, Box::new(effect));
+    }
+
+    /// Both discos share a layer, so one replaces the other
+    fn start_disco_layer(&mut self, effect: HelloEffect) {
+        let layer = LayerSpec {
+            name: "disco".to_string(),
+            ..LayerSpec::default()
+        };
+        self.compositor.lock().unwrap().add_layer(layer, Box::new(effect));
+    }
+
     fn chans_templates(&mut self) -> Vec<ChanTemplate> {
         let output = self.output.lock().unwrap();
         output
@@ -338,9 +827,35 @@ impl<T: 'static + Dev + HasChanDescriptions + fmt::Debug> WebState<T> {
 
     fn home_with(&mut self, msg: Option<FlashMsg>) 
             -> tiny_http::Response<Cursor<Vec<u8>>> {
+        let chans = self.chans_templates();
+        let sequence = self.sequence.as_ref().map(|seq| {
+            let seq = seq.lock().unwrap();
+            format!("{} {:?}{}", seq.name, seq.state(),
+                    if seq.is_paused() { " (paused)" } else { "" })
+        });
+        let schedule = match &self.scheduler {
+            Some(scheduler) => scheduler.lock().unwrap().upcoming(),
+            None => vec![],
+        };
+        let circadian = self.circadian.as_ref().map(|(circadian, _)| {
+            let circadian = circadian.lock().unwrap();
+            let (kelvin, brightness) = circadian.current();
+            format!("{:.0}K at {:.0}%{}", kelvin, brightness * 100.0,
+                    if circadian.is_overridden() { " (overridden)" }
+                    else { "" })
+        });
         let template = HomeTemplate {
             msg,
-            chans: self.chans_templates(),
+            chans,
+            scenes: &self.scenes,
+            sequences: &self.sequences,
+            sequence,
+            schedule,
+            circadian,
+            effects: effect::EFFECTS.iter().map(EffectTemplate::from)
+                .collect(),
+            layers: self.compositor.lock().unwrap().layers(),
+            bpm: tempo::shared().lock().unwrap().bpm(),
         };
         // todo fix unwrap
         let resp_str = template.render().unwrap();
@@ -526,7 +1041,31 @@ impl<T: 'static + Dev + HasChanDescriptions + fmt::Debug> WebState<T> {
                 self.slow_fade_in(),
             (tiny_http::Method::Post, Some("slow_fade_out")) =>
                 self.slow_fade_out(),
+            (tiny_http::Method::Post, Some("scene")) => self.scene(&mut req),
+            (tiny_http::Method::Post, Some("sequence")) => {
+                match path_segments.next() {
+                    None | Some("") => self.start_sequence(&mut req),
+                    control => self.control_sequence(control),
+                }
+            },
+            (tiny_http::Method::Post, Some("schedule")) => {
+                match path_segments.next() {
+                    Some("skip") => self.skip_scheduled(&mut req),
+                    _ => self.err404(req.method(), url.to_string().as_ref()),
+                }
+            },
+            (tiny_http::Method::Post, Some("snapshot")) =>
+                self.snapshot(&mut req),
+            (tiny_http::Method::Post, Some("circadian")) =>
+                self.circadian(path_segments.next()),
+            (tiny_http::Method::Post, Some("effect")) =>
+                self.start_effect(&mut req),
+            (tiny_http::Method::Post, Some("layers")) =>
+                self.control_layers(path_segments.next(), &mut req),
+            (tiny_http::Method::Post, Some("tempo")) =>
+                self.tempo(path_segments.next(), &mut req),
             (tiny_http::Method::Post, Some("disco")) => self.disco(),
+            (tiny_http::Method::Post, Some("schroom")) => self.schroom(),
             (tiny_http::Method::Post, Some("disco_harder")) =>
                 self.disco_harder(),
             (tiny_http::Method::Get, Some("")) => self.home(),
@@ -559,16 +1098,29 @@ impl Web {
     pub fn run<T: 'static + MsgHandler + Dev>(
         &mut self,
         srv: Arc<Mutex<T>>,
-        config: mux::Config,
+        config: Config,
     ) -> Result<(), String> {
         let http = tiny_http::Server::http::<&str>(self.listen_addr.as_ref())
             .map_err(|e| format!("server err: {:?}", e))?;
 
+        let compositor = Arc::new(Mutex::new(Compositor::new(srv)?));
+        let base = Arc::new(Mutex::new(compositor.lock().unwrap().base()));
+        let (compositor_tx, compositor_rx) = mpsc::channel::<TaskMsg>();
+        let compositor_task = Task {
+            name: "Compositor".to_string(),
+            chan: compositor_tx,
+            join_handle: {
+                let compositor = compositor.clone();
+                thread::spawn(move || Runner::run(compositor, compositor_rx))
+            },
+        };
+
         let fader = Arc::new(Mutex::new(Fade::new(
-                        srv.clone(),
+                        base.clone(),
                         FadeSpec {
                             fade_duration:  Duration::from_millis(6),
                             frame_duration: Duration::from_secs_f32(1.0 / 60.0),
+                            ..FadeSpec::default()
                         })));
 
         let (fade_tx, fade_rx) = mpsc::channel::<TaskMsg>();
@@ -585,11 +1137,50 @@ impl Web {
             join_handle: fade_join_handle,
         };
 
+        let (events_tx, schedule_events) = mpsc::channel::<ScheduleAction>();
+        let (scheduler, scheduler_task) = match &config.schedule {
+            Some(entries) if !entries.is_empty() => {
+                let scheduler = Arc::new(Mutex::new(Scheduler::new(
+                            entries, config.location.as_ref(),
+                            events_tx)?));
+
+                let (tx, rx) = mpsc::channel::<TaskMsg>();
+                let join_handle = {
+                    let scheduler = scheduler.clone();
+                    thread::spawn(move || Runner::run(scheduler, rx))
+                };
+
+                (Some(scheduler), Some(Task {
+                    name: "Scheduler".to_string(),
+                    chan: tx,
+                    join_handle,
+                }))
+            },
+            _ => (None, None),
+        };
+
+        let tempo_tasks = config.tempo.clone().unwrap_or_default().start()?;
+
         let mut server = WebState {
             base_url: Url::parse(format!("http://{}", self.listen_addr)
                                  .as_ref()).unwrap(),
-            output: srv,
-            output_config: config,
+            output: base,
+            compositor,
+            compositor_task,
+            scenes_path: config.scenes_path(),
+            output_config: config.mux,
+            scenes: config.scenes.unwrap_or_default(),
+            sequences: config.sequences.unwrap_or_default(),
+            sequence: None,
+            scheduler,
+            schedule_events,
+            scheduler_task,
+            circadian_spec: config.circadian.unwrap_or_default(),
+            circadian: None,
+            tempo_tasks,
+            disco_harder: config.disco_harder
+                .unwrap_or_else(DiscoChanConfig::harder),
+            schroom: config.schroom.unwrap_or_default(),
             http,
             task: None,
             fader,
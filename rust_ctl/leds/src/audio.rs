@@ -0,0 +1,872 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+use crate::clock::{self, Clock};
+use crate::dev::DevWrite;
+use crate::frame::Frame;
+use crate::runner::Runner;
+use crate::task::{Task, TaskMsg};
+
+use std::f32::consts::TAU;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub const DEFAULT_RATE: u32 = 44100;
+
+/// Band levels below this are silence, so the auto gain doesn't turn
+/// noise up to full brightness
+const PEAK_FLOOR: f32 = 0.01;
+
+/// RMS of a full scale sine, the peak when auto gain is off
+const FULL_SCALE_RMS: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Where the samples come from
+pub trait AudioInput: Send {
+    fn sample_rate(&self) -> u32;
+
+    /// Fills `buf` with mono samples from -1 to 1, blocks until it's full
+    /// or the input is over, 0 means it's over
+    fn read(&mut self, buf: &mut [f32]) -> Result<usize, String>;
+
+    /// Live inputs block until there's more, files are paced by the runner
+    fn is_live(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SampleFormat {
+    S16,
+    F32,
+}
+
+impl SampleFormat {
+    fn len(&self) -> usize {
+        match self {
+            SampleFormat::S16 => 2,
+            SampleFormat::F32 => 4,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleFormat::S16 =>
+                i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            SampleFormat::F32 =>
+                f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
+
+/// Interleaved little endian samples, downmixed to mono
+pub struct Pcm<R> {
+    reader: R,
+    rate: u32,
+    channels: u16,
+    format: SampleFormat,
+    live: bool,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> Pcm<R> {
+    /// Signed 16 bit, what `arecord -f S16_LE` outputs
+    pub fn s16(reader: R, rate: u32, channels: u16) -> Self {
+        Pcm {
+            reader,
+            rate,
+            channels: channels.max(1),
+            format: SampleFormat::S16,
+            live: true,
+            buf: vec![],
+        }
+    }
+
+    fn frame_len(&self) -> usize {
+        self.format.len() * self.channels as usize
+    }
+}
+
+impl<R: Read + Send> AudioInput for Pcm<R> {
+    fn sample_rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn read(&mut self, buf: &mut [f32]) -> Result<usize, String> {
+        let frame_len = self.frame_len();
+        self.buf.resize(buf.len() * frame_len, 0);
+
+        let mut got = 0;
+        while got < self.buf.len() {
+            match self.reader.read(&mut self.buf[got..]) {
+                Ok(0) => break,
+                Ok(n) => got += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(format!("reading audio: {:?}", e)),
+            }
+        }
+
+        // a partial frame at the end is dropped
+        let frames = self.buf[..got].chunks_exact(frame_len);
+        let num_frames = frames.len();
+        for (sample, frame) in buf.iter_mut().zip(frames) {
+            *sample = frame.chunks_exact(self.format.len())
+                .map(|bytes| self.format.decode(bytes))
+                .sum::<f32>() / self.channels as f32;
+        }
+        Ok(num_frames)
+    }
+
+    fn is_live(&self) -> bool {
+        self.live
+    }
+}
+
+fn read_bytes<R: Read, const N: usize>(reader: &mut R)
+        -> Result<[u8; N], String> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)
+        .map_err(|e| format!("reading wav header: {:?}", e))?;
+    Ok(bytes)
+}
+
+/// Reads the header up to the samples, 16 bit int and 32 bit float only
+pub fn wav<R: Read>(mut reader: R) -> Result<Pcm<R>, String> {
+    let riff: [u8; 12] = read_bytes(&mut reader)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err("not a wav file".to_string());
+    }
+
+    let mut format: Option<(u16, u32, SampleFormat)> = None;
+    loop {
+        let header: [u8; 8] = read_bytes(&mut reader)?;
+        let len = u32::from_le_bytes([header[4], header[5], header[6],
+                                      header[7]]) as usize;
+        match &header[0..4] {
+            b"fmt " => {
+                let mut fmt = vec![0u8; len];
+                reader.read_exact(&mut fmt)
+                    .map_err(|e| format!("reading wav format: {:?}", e))?;
+                if len < 16 {
+                    return Err("wav format chunk is too short".to_string());
+                }
+                let u16_at =
+                    |i: usize| u16::from_le_bytes([fmt[i], fmt[i + 1]]);
+                // extensible has the actual format in its subformat
+                let tag = match u16_at(0) {
+                    0xfffe if len >= 26 => u16_at(24),
+                    tag => tag,
+                };
+                let sample_format = match (tag, u16_at(14)) {
+                    (1, 16) => SampleFormat::S16,
+                    (3, 32) => SampleFormat::F32,
+                    (tag, bits) => return Err(format!(
+                            "unsupported wav format {} with {} bit samples, \
+                            expected 16 bit PCM or 32 bit float", tag, bits)),
+                };
+                let rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+                format = Some((u16_at(2), rate, sample_format));
+                if len % 2 == 1 {
+                    read_bytes::<R, 1>(&mut reader)?;
+                }
+            },
+            b"data" => break,
+            _ => {
+                // chunks are padded to even lengths
+                let skip = (len + len % 2) as u64;
+                io::copy(&mut reader.by_ref().take(skip), &mut io::sink())
+                    .map_err(|e| format!("skipping wav chunk: {:?}", e))?;
+            },
+        }
+    }
+
+    let (channels, rate, format) = format
+        .ok_or("wav has no format before its data")?;
+    if channels == 0 || rate == 0 {
+        return Err("wav has no channels or no sample rate".to_string());
+    }
+    Ok(Pcm { reader, rate, channels, format, live: false, buf: vec![] })
+}
+
+/// ALSA capture through `arecord`, mono 16 bit
+pub struct Arecord {
+    child: Child,
+    pcm: Pcm<ChildStdout>,
+}
+
+impl Arecord {
+    pub fn spawn(device: Option<&str>, rate: u32) -> Result<Self, String> {
+        let mut cmd = Command::new("arecord");
+        cmd.args(["-q", "-t", "raw", "-f", "S16_LE", "-c", "1", "-r"])
+            .arg(rate.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped());
+        if let Some(device) = device {
+            cmd.args(["-D", device]);
+        }
+
+        let mut child = cmd.spawn()
+            .map_err(|e| format!("starting arecord: {:?}", e))?;
+        let stdout = child.stdout.take()
+            .ok_or("arecord has no stdout")?;
+        Ok(Arecord { child, pcm: Pcm::s16(stdout, rate, 1) })
+    }
+}
+
+impl AudioInput for Arecord {
+    fn sample_rate(&self) -> u32 {
+        self.pcm.sample_rate()
+    }
+
+    fn read(&mut self, buf: &mut [f32]) -> Result<usize, String> {
+        self.pcm.read(buf)
+    }
+}
+
+impl Drop for Arecord {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Where `audio` gets its samples, i.e. `input: { wav: { path: a.wav } }`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioSpec {
+    /// Capture with `arecord`, the default device when `device` is missing
+    Alsa {
+        #[serde(default)]
+        device: Option<String>,
+        #[serde(default = "default_rate")]
+        rate: u32,
+    },
+    Wav { path: PathBuf },
+    /// Raw signed 16 bit little endian, i.e. `arecord -f S16_LE | ctl ...`
+    Stdin {
+        #[serde(default = "default_rate")]
+        rate: u32,
+        #[serde(default = "default_channels")]
+        channels: u16,
+    },
+}
+
+impl Default for AudioSpec {
+    fn default() -> Self {
+        AudioSpec::Alsa { device: None, rate: DEFAULT_RATE }
+    }
+}
+
+fn default_rate() -> u32 {
+    DEFAULT_RATE
+}
+
+fn default_channels() -> u16 {
+    1
+}
+
+impl AudioSpec {
+    /// `alsa[:DEVICE]`, `wav:PATH` (or just `PATH.wav`) or `stdin[:RATE]`
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (s, None),
+        };
+        match (kind, arg) {
+            ("alsa", device) => Ok(AudioSpec::Alsa {
+                device: device.map(|d| d.to_string()),
+                rate: DEFAULT_RATE,
+            }),
+            ("wav", Some(path)) => Ok(AudioSpec::Wav { path: path.into() }),
+            ("stdin", rate) => Ok(AudioSpec::Stdin {
+                rate: rate.map(|r| r.parse().map_err(
+                        |e| format!("stdin sample rate: {:?}", e)))
+                    .transpose()?
+                    .unwrap_or(DEFAULT_RATE),
+                channels: 1,
+            }),
+            _ if s.ends_with(".wav") => Ok(AudioSpec::Wav { path: s.into() }),
+            _ => Err(format!("unknown audio input '{}', expected \
+                             alsa[:DEVICE], wav:PATH or stdin[:RATE]", s)),
+        }
+    }
+
+    pub fn open(&self) -> Result<Box<dyn AudioInput>, String> {
+        match self {
+            AudioSpec::Alsa { device, rate } =>
+                Ok(Box::new(Arecord::spawn(device.as_deref(), *rate)?)),
+            AudioSpec::Wav { path } => {
+                let file = File::open(path)
+                    .map_err(|e| format!("opening {:?}: {:?}", path, e))?;
+                Ok(Box::new(wav(BufReader::new(file))?))
+            },
+            AudioSpec::Stdin { rate, channels } =>
+                Ok(Box::new(Pcm::s16(io::stdin(), *rate, *channels))),
+        }
+    }
+}
+
+/// Frequencies from `lo` to `hi` Hz light chans with any of `tags`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BandConfig {
+    pub name: String,
+    pub lo: f32,
+    pub hi: f32,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl BandConfig {
+    fn new(name: &str, lo: f32, hi: f32, tags: &[&str]) -> Self {
+        BandConfig {
+            name: name.to_string(),
+            lo,
+            hi,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+}
+
+/// ```yaml
+/// input: { alsa: { device: "hw:1" } }
+/// attack: 0.02     # seconds to rise
+/// release: 0.3     # seconds to fall
+/// rms_tags: [desk] # the overall level
+/// bands:           # first one with a matching tag wins
+///   - { name: bass, lo: 20, hi: 250, tags: [bed] }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub input: AudioSpec,
+    /// Samples per FFT, a power of 2
+    pub window: usize,
+    pub attack: f32,
+    pub release: f32,
+    /// Seconds for the auto gain to forget a loud part, 0 turns it off
+    pub agc: f32,
+    pub rms_tags: Vec<String>,
+    pub bands: Vec<BandConfig>,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            input: AudioSpec::default(),
+            window: 1024,
+            attack: 0.02,
+            release: 0.3,
+            agc: 10.0,
+            rms_tags: vec![],
+            bands: vec![
+                BandConfig::new("bass", 20.0, 250.0, &["bed"]),
+                BandConfig::new("mid", 250.0, 2000.0, &[]),
+                BandConfig::new("highs", 2000.0, 16000.0, &["ceiling"]),
+            ],
+        }
+    }
+}
+
+impl AudioConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.window.is_power_of_two() || self.window < 16 {
+            return Err(format!("window should be a power of 2 of at least \
+                               16, got {}", self.window));
+        }
+        if self.attack < 0.0 || self.release < 0.0 || self.agc < 0.0 {
+            return Err("attack, release and agc should not be negative"
+                       .to_string());
+        }
+        for band in self.bands.iter() {
+            if band.lo < 0.0 || band.lo >= band.hi {
+                return Err(format!("band '{}' should have 0 <= lo < hi",
+                                   band.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Smoothed levels from 0 to 1
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Levels {
+    pub rms: f32,
+    /// In the order of `AudioConfig::bands`
+    pub bands: Vec<f32>,
+}
+
+pub type SharedLevels = Arc<Mutex<Levels>>;
+
+/// In place radix-2 FFT, `re.len()` is a power of 2
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -TAU / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// RMS and band levels over a sliding window of samples
+pub struct Analyzer {
+    config: AudioConfig,
+    rate: u32,
+    hann: Vec<f32>,
+    /// The last `window` samples, oldest first
+    samples: Vec<f32>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    /// Loudest recently, rms first then bands
+    peaks: Vec<f32>,
+    levels: Vec<f32>,
+}
+
+impl Analyzer {
+    pub fn new(config: &AudioConfig, rate: u32) -> Result<Self, String> {
+        config.validate()?;
+        let n = config.window;
+        let hann = (0..n)
+            .map(|i| 0.5 - 0.5 * (TAU * i as f32 / n as f32).cos())
+            .collect();
+        let num_levels = config.bands.len() + 1;
+        Ok(Analyzer {
+            config: config.clone(),
+            rate,
+            hann,
+            samples: vec![0.0; n],
+            re: vec![0.0; n],
+            im: vec![0.0; n],
+            peaks: vec![PEAK_FLOOR; num_levels],
+            levels: vec![0.0; num_levels],
+        })
+    }
+
+    /// RMS of the whole window then of every band
+    fn measure(&mut self) -> Vec<f32> {
+        let n = self.samples.len();
+        let rms = (self.samples.iter().map(|s| s * s).sum::<f32>()
+                   / n as f32).sqrt();
+
+        for i in 0..n {
+            self.re[i] = self.samples[i] * self.hann[i];
+            self.im[i] = 0.0;
+        }
+        fft(&mut self.re, &mut self.im);
+
+        // Parseval, so a band's level is the RMS of what's in it
+        let window_power: f32 = self.hann.iter().map(|w| w * w).sum();
+        let bin_hz = self.rate as f32 / n as f32;
+        let mut result = vec![rms];
+        for band in self.config.bands.iter() {
+            let lo = (band.lo / bin_hz).ceil().max(1.0) as usize;
+            let hi = ((band.hi / bin_hz).floor() as usize).min(n / 2 - 1);
+            let power: f32 = (lo..=hi)
+                .map(|k| self.re[k] * self.re[k] + self.im[k] * self.im[k])
+                .sum();
+            result.push((2.0 * power / n as f32 / window_power).sqrt());
+        }
+        result
+    }
+
+    /// Adds the samples that came after the last ones
+    pub fn push(&mut self, samples: &[f32]) -> Levels {
+        let skip = samples.len().saturating_sub(self.config.window);
+        let newest = &samples[skip..];
+        self.samples.drain(..newest.len());
+        self.samples.extend_from_slice(newest);
+
+        let dt = samples.len() as f32 / self.rate as f32;
+        let smooth = |tau: f32| match tau {
+            tau if tau > 0.0 => 1.0 - (-dt / tau).exp(),
+            _ => 1.0,
+        };
+        let (attack, release) =
+            (smooth(self.config.attack), smooth(self.config.release));
+        let forget = match self.config.agc {
+            agc if agc > 0.0 => (-dt / agc).exp(),
+            _ => 0.0,
+        };
+
+        let measured = self.measure();
+        for (ii, val) in measured.into_iter().enumerate() {
+            let peak = match forget {
+                forget if forget > 0.0 =>
+                    (self.peaks[ii] * forget).max(val).max(PEAK_FLOOR),
+                _ => FULL_SCALE_RMS,
+            };
+            self.peaks[ii] = peak;
+
+            let target = (val / peak).clamp(0.0, 1.0);
+            let level = &mut self.levels[ii];
+            let rate = if target > *level { attack } else { release };
+            *level += (target - *level) * rate;
+        }
+
+        self.levels()
+    }
+
+    pub fn levels(&self) -> Levels {
+        Levels { rms: self.levels[0], bands: self.levels[1..].to_vec() }
+    }
+}
+
+fn has_any_tag(chan: &ChanDescription, tags: &[String]) -> bool {
+    chan.config.tags.iter().any(|tag| tags.iter().any(|t| t == tag.name()))
+}
+
+/// Audio levels onto chans by their tags, others are left alone
+pub struct AudioReactive<T: ?Sized> {
+    output: Arc<Mutex<T>>,
+    input: Box<dyn AudioInput>,
+    analyzer: Analyzer,
+    config: AudioConfig,
+    clock: Arc<dyn Clock>,
+    paused: bool,
+}
+
+impl<T: HasChanDescriptions + DevWrite + ?Sized> AudioReactive<T> {
+    pub fn new(output: Arc<Mutex<T>>, input: Box<dyn AudioInput>,
+               config: AudioConfig) -> Result<Self, String> {
+        let analyzer = Analyzer::new(&config, input.sample_rate())?;
+        Ok(AudioReactive {
+            output,
+            input,
+            analyzer,
+            config,
+            clock: clock::real(),
+            paused: false,
+        })
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn levels(&self) -> Levels {
+        self.analyzer.levels()
+    }
+
+    fn frame(&self, levels: &Levels, chans: &[ChanDescription])
+            -> Frame<f32> {
+        let mut frame = Frame::empty();
+        for chan in chans.iter() {
+            let band = self.config.bands.iter().zip(levels.bands.iter())
+                .find(|(band, _)| has_any_tag(chan, &band.tags))
+                .map(|(_, level)| *level);
+            let level = match band {
+                Some(level) => level,
+                None if has_any_tag(chan, &self.config.rms_tags) => levels.rms,
+                None => continue,
+            };
+            frame.set(chan.chan_id, level);
+        }
+        frame
+    }
+
+    /// Reads a hop of samples and sends the levels, false when it's over
+    fn step(&mut self, buf: &mut [f32]) -> Result<bool, String> {
+        let num = self.input.read(buf)?;
+        if num == 0 {
+            return Ok(false);
+        }
+
+        let levels = self.analyzer.push(&buf[..num]);
+        if !self.paused {
+            let mut output = self.output.lock().unwrap();
+            let frame = self.frame(&levels, &output.chan_descriptions());
+            output.set_frame(&frame)?;
+        }
+        Ok(true)
+    }
+}
+
+impl<T: HasChanDescriptions + DevWrite + ?Sized> Runner for AudioReactive<T> {
+    fn run(
+        self_lock: Arc<Mutex<AudioReactive<T>>>,
+        stop: mpsc::Receiver<TaskMsg>,
+    ) -> Result<(), String> {
+        let (clock, rate, hop, live) = {
+            let audio = self_lock.lock().unwrap();
+            (audio.clock.clone(), audio.input.sample_rate(),
+             audio.config.window / 2, audio.input.is_live())
+        };
+        // live inputs block until the next hop is there
+        let wait = match live {
+            true => Duration::ZERO,
+            false => Duration::from_secs_f64(hop as f64 / rate as f64),
+        };
+
+        let mut buf = vec![0.0; hop];
+        loop {
+            if !self_lock.lock().unwrap().step(&mut buf)? {
+                return Ok(());
+            }
+
+            match clock.recv_timeout(&stop, wait) {
+                Ok(TaskMsg::Stop) => return Ok(()),
+                Ok(TaskMsg::Pause) => {
+                    let mut audio = self_lock.lock().unwrap();
+                    audio.paused = !audio.paused;
+                },
+                Ok(TaskMsg::Ping) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Keeps `levels` up to date for whoever reads them, i.e. expressions
+pub struct AudioMeter {
+    input: Box<dyn AudioInput>,
+    analyzer: Analyzer,
+    levels: SharedLevels,
+    hop: usize,
+    clock: Arc<dyn Clock>,
+}
+
+impl AudioMeter {
+    pub fn new(input: Box<dyn AudioInput>, config: &AudioConfig,
+               levels: SharedLevels) -> Result<Self, String> {
+        let analyzer = Analyzer::new(config, input.sample_rate())?;
+        Ok(AudioMeter {
+            input,
+            analyzer,
+            levels,
+            hop: config.window / 2,
+            clock: clock::real(),
+        })
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl Runner for AudioMeter {
+    fn run(
+        self_lock: Arc<Mutex<AudioMeter>>,
+        stop: mpsc::Receiver<TaskMsg>,
+    ) -> Result<(), String> {
+        let (clock, hop, wait) = {
+            let meter = self_lock.lock().unwrap();
+            let wait = match meter.input.is_live() {
+                true => Duration::ZERO,
+                false => Duration::from_secs_f64(
+                    meter.hop as f64 / meter.input.sample_rate() as f64),
+            };
+            (meter.clock.clone(), meter.hop, wait)
+        };
+
+        let mut buf = vec![0.0; hop];
+        loop {
+            {
+                let mut meter = self_lock.lock().unwrap();
+                let num = meter.input.read(&mut buf)?;
+                if num == 0 {
+                    return Ok(());
+                }
+                let levels = meter.analyzer.push(&buf[..num]);
+                *meter.levels.lock().unwrap() = levels;
+            }
+
+            match clock.recv_timeout(&stop, wait) {
+                Ok(TaskMsg::Stop) => return Ok(()),
+                Ok(TaskMsg::Pause) | Ok(TaskMsg::Ping) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+impl AudioConfig {
+    /// Starts metering `input` in the background
+    pub fn start_meter(&self) -> Result<(SharedLevels, Task), String> {
+        let levels = Arc::new(Mutex::new(Levels {
+            rms: 0.0,
+            bands: vec![0.0; self.bands.len()],
+        }));
+        let meter = AudioMeter::new(self.input.open()?, self, levels.clone())?;
+        let meter = Arc::new(Mutex::new(meter));
+        let (tx, rx) = mpsc::channel::<TaskMsg>();
+        let task = Task {
+            name: "Audio meter".to_string(),
+            chan: tx,
+            join_handle: thread::spawn(move || Runner::run(meter, rx)),
+        };
+        Ok((levels, task))
+    }
+}
+
+pub fn run<T: HasChanDescriptions + DevWrite + ?Sized>(
+    dev: Arc<Mutex<T>>,
+    config: AudioConfig,
+    stop: mpsc::Receiver<TaskMsg>,
+) -> Result<(), String> {
+    let input = config.input.open()?;
+    println!("audio from {:?} at {} Hz...", config.input, input.sample_rate());
+    let audio = AudioReactive::new(dev, input, config)?;
+    AudioReactive::run(Arc::new(Mutex::new(audio)), stop)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use crate::chan::ChanConfig;
+    use crate::clock::VirtualClock;
+    use crate::dev::DevRead;
+    use crate::mux::Mux;
+    use crate::tag::Tag;
+    use crate::test_dev::TestDev;
+    use std::io::Cursor;
+
+    /// 16 bit stereo with a `LIST` chunk before the data
+    fn wav_bytes(rate: u32, left: &[f32], right: &[f32]) -> Vec<u8> {
+        let mut data = vec![];
+        for (l, r) in left.iter().zip(right.iter()) {
+            data.extend(((l * 32767.0) as i16).to_le_bytes());
+            data.extend(((r * 32767.0) as i16).to_le_bytes());
+        }
+
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        wav.extend(b"fmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(1u16.to_le_bytes());
+        wav.extend(2u16.to_le_bytes());
+        wav.extend(rate.to_le_bytes());
+        wav.extend((rate * 4).to_le_bytes());
+        wav.extend(4u16.to_le_bytes());
+        wav.extend(16u16.to_le_bytes());
+        wav.extend(b"LIST");
+        wav.extend(3u32.to_le_bytes());
+        wav.extend(b"abc\0");
+        wav.extend(b"data");
+        wav.extend((data.len() as u32).to_le_bytes());
+        wav.extend(data);
+        wav
+    }
+
+    fn sine(freq: f32, rate: u32, secs: f32) -> Vec<f32> {
+        (0..(rate as f32 * secs) as usize)
+            .map(|i| 0.5 * (TAU * freq * i as f32 / rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_wav_and_bands() {
+        let rate = 8000;
+        let low = sine(100.0, rate, 1.0);
+        let mut wav = wav(Cursor::new(wav_bytes(rate, &low, &low))).unwrap();
+        assert_eq!(rate, wav.sample_rate());
+        assert!(!wav.is_live());
+
+        let mut buf = vec![0.0; 6000];
+        assert_eq!(6000, wav.read(&mut buf).unwrap());
+        assert!((buf[20] - low[20]).abs() < 1e-3);
+        assert_eq!(2000, wav.read(&mut buf).unwrap());
+        assert_eq!(0, wav.read(&mut buf).unwrap());
+        assert!(super::wav(Cursor::new(b"RIFF\0\0\0\0AVI ".to_vec())).is_err());
+
+        let config = AudioConfig {
+            attack: 0.0,
+            release: 0.0,
+            agc: 0.0,
+            ..AudioConfig::default()
+        };
+        let mut analyzer = Analyzer::new(&config, rate).unwrap();
+        let levels = analyzer.push(&low);
+        // half of full scale everywhere, all of it in the bass
+        assert!((levels.rms - 0.5).abs() < 0.01, "{:?}", levels);
+        assert!((levels.bands[0] - 0.5).abs() < 0.02, "{:?}", levels);
+        assert!(levels.bands[2] < 0.01, "{:?}", levels);
+
+        let levels = analyzer.push(&sine(3000.0, rate, 0.2));
+        assert!((levels.bands[2] - 0.5).abs() < 0.02, "{:?}", levels);
+        assert!(levels.bands[0] < 0.01, "{:?}", levels);
+
+        assert!(AudioConfig { window: 1000, ..config }.validate().is_err());
+    }
+
+    #[test]
+    fn test_runner() {
+        let chan_cfgs: Vec<ChanConfig> = ["bed", "ceiling", "desk"].iter()
+            .enumerate()
+            .map(|(index, tag)| ChanConfig {
+                index: index as u16,
+                exp: None,
+                tags: vec![Tag::new(tag)],
+                ..ChanConfig::default()
+            })
+            .collect();
+        let dev = Arc::new(Mutex::new(TestDev::new(false)));
+        let mut mux = Mux::new();
+        mux.add_dev(dev.clone(), Some(chan_cfgs.into_iter()));
+        let mux = Arc::new(Mutex::new(mux));
+
+        let rate = 8000;
+        let bass = sine(60.0, rate, 2.0);
+        let input = wav(Cursor::new(wav_bytes(rate, &bass, &bass))).unwrap();
+        let audio = AudioReactive::new(mux.clone(), Box::new(input),
+                                       AudioConfig::default())
+            .unwrap()
+            .with_clock(Arc::new(VirtualClock::new()));
+
+        let (_stop, receiver) = mpsc::channel();
+        AudioReactive::run(Arc::new(Mutex::new(audio)), receiver).unwrap();
+
+        let mux = mux.lock().unwrap();
+        assert!(mux.get_f32(0).unwrap() > 0.9);
+        assert!(mux.get_f32(1).unwrap() < 0.05);
+        // no rms_tags, so untouched
+        assert_eq!(0.0, mux.get_f32(2).unwrap());
+
+        // the meter waits on its clock too, 2s of wav take 2s on it
+        let input = wav(Cursor::new(wav_bytes(rate, &bass, &bass))).unwrap();
+        let levels = Arc::new(Mutex::new(Levels { rms: 0.0, bands: vec![] }));
+        let clock = VirtualClock::new();
+        let start = clock.now();
+        let meter = AudioMeter::new(Box::new(input), &AudioConfig::default(),
+                                    levels.clone())
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()));
+        let (_stop, receiver) = mpsc::channel();
+        AudioMeter::run(Arc::new(Mutex::new(meter)), receiver).unwrap();
+        assert!(levels.lock().unwrap().bands[0] > 0.9);
+        assert!(clock.now() - start >= Duration::from_millis(1900));
+    }
+}
@@ -12,6 +12,8 @@ pub struct ChanConfig {
     pub tags: Vec<Tag>,
     pub cuboid: Option<Cuboid>,
     pub disco_config: Option<DiscoChanConfig>,
+    /// Where the chan is in `chase order=config`, lowest first
+    pub order: Option<f32>,
 }
 
 impl Default for ChanConfig {
@@ -24,6 +26,7 @@ impl Default for ChanConfig {
             tags:         Vec::new(),
             cuboid:       None,
             disco_config: None,
+            order:        None,
         }
     }
 }
@@ -65,7 +68,7 @@ mod tests {
         let chan_config = ChanConfig {
             index: 0, min: 0.15, max: 0.86, exp: Some(2.2),
             tags: Vec::new(), cuboid: None,
-            disco_config: None
+            disco_config: None, order: None,
         };
 
         assert_eq!(0.4,
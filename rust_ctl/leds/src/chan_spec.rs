@@ -1,8 +1,10 @@
 use serde_derive::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use std::fmt;
 use std::num::ParseIntError;
 
 use crate::chan_description::ChanDescription;
+use crate::frame::Frame;
 
 // TODO It seems to represent both a group of channels and a value, but 
 // it would be better to have it represent only a group of channels without
@@ -98,12 +100,45 @@ impl<F> ChanSpecGeneric<F> {
     }
 }
 
+impl<F: fmt::Display> fmt::Display for ChanSpecGeneric<F> {
+    /// The same format `ChanSpec::parse_f32` accepts
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let chanvals = |chanvals: &Vec<(String, F)>| -> Vec<String> {
+            chanvals.iter()
+                .map(|(chan, val)| format!("{}:{}", chan, val))
+                .collect()
+        };
+
+        let parts: Vec<String> = match self {
+            ChanSpecGeneric::Each(vals) =>
+                vals.iter().map(|v| v.to_string()).collect(),
+            ChanSpecGeneric::Some(vals) => chanvals(vals),
+            ChanSpecGeneric::SomeWithDefault(default, vals) => {
+                let mut parts = vec![default.to_string()];
+                parts.extend(chanvals(vals));
+                parts
+            }
+        };
+
+        write!(f, "{}", parts.join(","))
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub enum ChanSpec {
     F32(ChanSpecGeneric<f32>),
     U16(ChanSpecGeneric<u16>),
 }
 
+impl fmt::Display for ChanSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChanSpec::F32(spec) => write!(f, "{}", spec),
+            ChanSpec::U16(spec) => write!(f, "{}", spec),
+        }
+    }
+}
+
 // impl<'de> serde::Visitor<'de> for ChanSpec {
 //     type Value = String;
 // 
@@ -225,4 +260,37 @@ impl ChanSpec {
     pub fn parse_u16(_string: &str) -> Result<ChanSpec, String> {
         unimplemented!();
     }
+
+    /// Sets the values of the channels we specify in `frame`,
+    /// keeps the other channels as is
+    pub fn apply_to_frame(
+        &self,
+        chans: &[ChanDescription],
+        frame: &mut Frame<f32>,
+    ) -> Result<(), String> {
+        match self {
+            ChanSpec::F32(spec) => {
+                for (cid, val) in spec.resolve_for_chans(chans)? {
+                    frame.set(cid, val);
+                }
+                Ok(())
+            }
+            ChanSpec::U16(_) => Err("u16 chan specs are not supported"
+                                    .to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_parse_roundtrip() {
+        for spec in ["0.8", "1,0.7", "0:0.6,wall:1", "0.1,1:0.7"] {
+            let parsed = ChanSpec::parse_f32(spec).unwrap();
+            assert_eq!(parsed.to_string(), spec);
+            assert_eq!(ChanSpec::parse_f32(&parsed.to_string()), Ok(parsed));
+        }
+    }
 }
@@ -0,0 +1,292 @@
+use crate::chan_description::ChanDescription;
+use crate::coord::Coord;
+use crate::effect::{Assigned, Effect, EffectInfo, ParamSpec};
+use crate::frame::Frame;
+use crate::spatial::{Axis, Spotlight};
+
+use std::time::Duration;
+
+/// How chans are lined up for a chase
+#[derive(Clone, Debug, PartialEq)]
+pub enum Order {
+    /// By chan id
+    Id,
+    /// By the centre of their cuboid along the axis
+    Axis(Axis),
+    /// By where the centre of their cuboid is along the path
+    Path(Vec<Coord>),
+    /// By `order` in their chan config
+    Config,
+}
+
+impl Order {
+    pub const ALL: &'static [&'static str] =
+        &["id", "x", "y", "z", "path", "config"];
+
+    /// `order` is one of `ALL`, `path` is only used for `path`
+    pub fn parse(order: &str, path: &str) -> Result<Self, String> {
+        match order {
+            "id" => Ok(Order::Id),
+            "path" => Ok(Order::Path(Spotlight::parse_path(path)?)),
+            "config" => Ok(Order::Config),
+            axis => Ok(Order::Axis(axis.parse()?)),
+        }
+    }
+
+    /// Where `chan` goes in the line, None leaves it out
+    fn key(&self, chan: &ChanDescription) -> Option<f32> {
+        let center = chan.config.cuboid.map(|cuboid| cuboid.center());
+        match self {
+            Order::Id => Some(chan.chan_id as f32),
+            Order::Axis(axis) => center.map(|c| axis.of(c)),
+            Order::Path(path) => center.map(|c| along(path, c)),
+            Order::Config => chan.config.order,
+        }
+    }
+
+    /// Chan ids in order
+    pub fn line_up(&self, chans: &[ChanDescription]) -> Vec<u16> {
+        let mut keyed: Vec<(f32, u16)> = chans.iter()
+            .filter_map(|chan| Some((self.key(chan)?, chan.chan_id)))
+            .collect();
+        keyed.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        keyed.into_iter().map(|(_, cid)| cid).collect()
+    }
+}
+
+/// Distance along `path` to the point closest to `p`
+fn along(path: &[Coord], p: Coord) -> f32 {
+    let mut best = (f32::MAX, 0.0);
+    let mut walked = 0.0;
+    for (i, &from) in path.iter().enumerate() {
+        let to = path.get(i + 1).copied().unwrap_or(from);
+        let len = from.dist_to(&to);
+        let v = match len > 0.0 {
+            true => (((p.x - from.x) * (to.x - from.x)
+                      + (p.y - from.y) * (to.y - from.y)
+                      + (p.z - from.z) * (to.z - from.z)) / (len * len))
+                .clamp(0.0, 1.0),
+            false => 0.0,
+        };
+        let closest = Coord {
+            x: from.x + (to.x - from.x) * v,
+            y: from.y + (to.y - from.y) * v,
+            z: from.z + (to.z - from.z) * v,
+        };
+        let dist = closest.dist_to(&p);
+        if dist < best.0 {
+            best = (dist, walked + len * v);
+        }
+        walked += len;
+    }
+    best.1
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// `width` chans lit going round
+    Run,
+    /// `width` chans lit going to the end and back
+    Bounce,
+    /// A lit head with a tail `width` chans long going round
+    Comet,
+    /// Lights up one chan after another, then turns them off the same way
+    Fill,
+}
+
+impl Pattern {
+    pub const ALL: &'static [&'static str] = &["run", "bounce", "comet", "fill"];
+}
+
+impl std::str::FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "run" => Ok(Pattern::Run),
+            "bounce" => Ok(Pattern::Bounce),
+            "comet" => Ok(Pattern::Comet),
+            "fill" => Ok(Pattern::Fill),
+            other => Err(format!("unknown chase '{}', expected one of {}",
+                                 other, Pattern::ALL.join(", "))),
+        }
+    }
+}
+
+/// Light moving along chans lined up by `order`
+pub struct Chase {
+    pub order: Order,
+    pub pattern: Pattern,
+    /// Chans per second
+    pub speed: f32,
+    /// Chans lit, or the length of the comet's tail
+    pub width: f32,
+    /// From the last chan to the first
+    pub reverse: bool,
+    pub brightness: f32,
+    /// Chan ids in order, worked out when we first see the chans
+    line: Vec<u16>,
+    seen: Vec<u16>,
+}
+
+impl Chase {
+    pub fn new(order: Order, pattern: Pattern, speed: f32, width: f32,
+               reverse: bool, brightness: f32) -> Self {
+        Chase { order, pattern, speed, width, reverse, brightness,
+                line: vec![], seen: vec![] }
+    }
+
+    /// 0 to 1 for the chan at `i` of `n`, `steps` chans into the chase
+    fn level(&self, i: f32, n: f32, steps: f32) -> f32 {
+        // lit fully within half the width, fading over one chan past it
+        let lit = |dist: f32| (self.width / 2.0 + 0.5 - dist).clamp(0.0, 1.0);
+        match self.pattern {
+            Pattern::Run => {
+                let dist = (i - steps.rem_euclid(n)).abs();
+                lit(dist.min(n - dist))
+            },
+            Pattern::Bounce => {
+                let span = (n - 1.0).max(1.0);
+                let head = span - (steps.rem_euclid(2.0 * span) - span).abs();
+                lit((i - head).abs())
+            },
+            Pattern::Comet => {
+                let behind = (steps - i).rem_euclid(n);
+                match behind > n - 1.0 {
+                    // about to be reached
+                    true => behind - (n - 1.0),
+                    false => (1.0 - behind / self.width.max(1.0)).max(0.0),
+                }
+            },
+            Pattern::Fill => {
+                let steps = steps.rem_euclid(2.0 * n);
+                match steps < n {
+                    true => (steps - i).clamp(0.0, 1.0),
+                    false => 1.0 - (steps - n - i).clamp(0.0, 1.0),
+                }
+            },
+        }
+    }
+}
+
+impl Effect for Chase {
+    fn render(&mut self, t: Duration, chans: &[ChanDescription]) -> Frame<f32> {
+        let ids: Vec<u16> = chans.iter().map(|chan| chan.chan_id).collect();
+        if ids != self.seen {
+            self.line = self.order.line_up(chans);
+            self.seen = ids;
+        }
+
+        let mut frame = Frame::empty();
+        let n = self.line.len() as f32;
+        let steps = t.as_secs_f32() * self.speed;
+        for (i, &cid) in self.line.iter().enumerate() {
+            let i = match self.reverse {
+                true => n - 1.0 - i as f32,
+                false => i as f32,
+            };
+            let val = self.brightness * self.level(i, n, steps);
+            frame.set(cid, val.clamp(0.0, 1.0));
+        }
+        frame
+    }
+}
+
+pub const EFFECT: EffectInfo = EffectInfo {
+    name: "chase",
+    description: "Light running along chans in the order they are \
+                  in the room",
+    params: || vec![
+        ParamSpec::choice("pattern", "run, bounce, comet with a tail or \
+                          fill and empty", Pattern::ALL),
+        ParamSpec::choice("order", "chan id, cuboid centre along x, y or z \
+                          or along path, or `order` of the chans",
+                          Order::ALL),
+        ParamSpec::text("path", "x,y,z;x,y,z;... for order=path",
+                        "0,0.5,0;1,0.5,0;1,0.5,1;0,0.5,1"),
+        ParamSpec::text("chans", "a tag, a chan id or all", "all"),
+        ParamSpec::float("speed", "chans per second", 4.0, 0.01, 100.0),
+        ParamSpec::float("width", "chans lit, or comet tail length", 1.0,
+                         0.0, 100.0),
+        ParamSpec::bool("reverse", "from the last chan to the first", false),
+        ParamSpec::float("brightness", "how bright lit chans are", 1.0,
+                         0.0, 1.0),
+    ],
+    create: |params| Ok(Box::new(Assigned::new(params.str("chans"),
+        Chase::new(
+            Order::parse(params.str("order"), params.str("path"))?,
+            params.str("pattern").parse()?,
+            params.f32("speed"), params.f32("width"),
+            params.bool("reverse"), params.f32("brightness"))))),
+};
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use crate::cuboid::Cuboid;
+    use crate::test_dev::chan;
+
+    /// Chans along the window at `x`, in the order of the list, ones
+    /// without `x` have no cuboid
+    fn chans(xs: &[Option<f32>]) -> Vec<ChanDescription> {
+        xs.iter().enumerate().map(|(cid, x)| {
+            let cuboid = x.map(|x| Cuboid {
+                start: Coord { x, y: 0.1, z: 0.0 },
+                end: Coord { x: x + 0.05, y: 0.2, z: 0.0 },
+            });
+            let mut desc = chan(cid as u16, &[], cuboid);
+            desc.config.order = Some(-(cid as f32));
+            desc
+        }).collect()
+    }
+
+    #[test]
+    fn test_order() {
+        let chans = chans(&[Some(0.5), None, Some(0.1), Some(0.9)]);
+        assert_eq!(vec![0, 1, 2, 3], Order::Id.line_up(&chans));
+        assert_eq!(vec![2, 0, 3], Order::Axis(Axis::X).line_up(&chans));
+        assert_eq!(vec![3, 2, 1, 0], Order::Config.line_up(&chans));
+
+        // right along the top, then back along the floor
+        let path = Spotlight::parse_path("0,0,0;1,0,0;1,1,0;0,1,0").unwrap();
+        let mut chans = chans;
+        chans[0].config.cuboid = Some(Cuboid {
+            start: Coord { x: 0.5, y: 0.9, z: 0.0 },
+            end: Coord { x: 0.5, y: 1.0, z: 0.0 },
+        });
+        assert_eq!(vec![2, 3, 0], Order::Path(path).line_up(&chans));
+        assert!(Order::parse("w", "").is_err());
+    }
+
+    #[test]
+    fn test_patterns() {
+        let chans = chans(&[Some(0.0), Some(0.2), Some(0.4), Some(0.6)]);
+        let at = |pattern: Pattern, reverse: bool, steps: f32| {
+            let mut chase = Chase::new(Order::Axis(Axis::X), pattern, 1.0,
+                                       1.0, reverse, 1.0);
+            let frame = chase.render(Duration::from_secs_f32(steps), &chans);
+            (0..4).map(|cid| frame.get(cid).unwrap()).collect::<Vec<f32>>()
+        };
+
+        assert_eq!(vec![0.0, 1.0, 0.0, 0.0], at(Pattern::Run, false, 1.0));
+        assert_eq!(vec![0.0, 0.0, 1.0, 0.0], at(Pattern::Run, true, 1.0));
+        // wraps around to the first
+        assert_eq!(vec![0.5, 0.0, 0.0, 0.5], at(Pattern::Run, false, 3.5));
+
+        // back from the end
+        assert_eq!(vec![0.0, 0.0, 1.0, 0.0], at(Pattern::Bounce, false, 4.0));
+        assert_eq!(vec![1.0, 0.0, 0.0, 0.0], at(Pattern::Bounce, false, 6.0));
+
+        assert_eq!(vec![1.0, 1.0, 0.5, 0.0], at(Pattern::Fill, false, 2.5));
+        assert_eq!(vec![0.0, 1.0, 1.0, 1.0], at(Pattern::Fill, false, 5.0));
+
+        let mut comet = Chase::new(Order::Id, Pattern::Comet, 1.0, 2.0,
+                                   false, 1.0);
+        let frame = comet.render(Duration::from_secs(2), &chans);
+        assert_eq!(Some(1.0), frame.get(2));
+        assert_eq!(Some(0.5), frame.get(1));
+        assert_eq!(Some(0.0), frame.get(0));
+        assert_eq!(Some(0.0), frame.get(3));
+    }
+}
@@ -0,0 +1,530 @@
+use serde_derive::{Deserialize, Serialize};
+use chrono::{NaiveTime, Timelike};
+
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+use crate::clock::{self, Clock};
+use crate::dev::{DevRead, DevWrite};
+use crate::frame::Frame;
+use crate::runner::Runner;
+use crate::task::TaskMsg;
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Colour temperature and brightness at a time of day
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CircadianPoint {
+    /// Local time "HH:MM"
+    pub at: String,
+    pub kelvin: f32,
+    pub brightness: f32,
+}
+
+/// Mixes warm and cool white channels following a colour temperature and
+/// brightness curve over the day. Values are written through the output,
+/// so each chan's `ChanConfig` curve still applies.
+///
+/// ```yaml
+/// circadian:
+///   points:
+///     - { at: "07:00", kelvin: 2700, brightness: 0.3 }
+///     - { at: "12:00", kelvin: 6000, brightness: 1.0 }
+///     - { at: "22:00", kelvin: 2200, brightness: 0.2 }
+///   warm_tags: [warm]
+///   cool_tags: [cool]
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircadianSpec {
+    /// The curve, wraps around midnight
+    pub points: Vec<CircadianPoint>,
+
+    /// Chans of the warm strips
+    pub warm_tags: Vec<String>,
+    /// Chans of the cool strips
+    pub cool_tags: Vec<String>,
+    /// Single white chans only follow the brightness
+    pub white_tags: Vec<String>,
+
+    /// Colour temperature of the warm strips
+    pub warm_kelvin: f32,
+    /// Colour temperature of the cool strips
+    pub cool_kelvin: f32,
+
+    /// How long manual changes take precedence, in minutes
+    pub override_minutes: f32,
+}
+
+impl Default for CircadianSpec {
+    fn default() -> Self {
+        let point = |at: &str, kelvin, brightness| CircadianPoint {
+            at: at.to_string(), kelvin, brightness,
+        };
+
+        CircadianSpec {
+            points: vec![
+                point("06:30", 2700.0, 0.3),
+                point("09:00", 5000.0, 1.0),
+                point("13:00", 6500.0, 1.0),
+                point("18:00", 4000.0, 0.8),
+                point("21:00", 2700.0, 0.5),
+                point("23:00", 2200.0, 0.15),
+            ],
+            warm_tags: vec!["warm".to_string()],
+            cool_tags: vec!["cool".to_string()],
+            white_tags: vec!["w".to_string()],
+            warm_kelvin: 2700.0,
+            cool_kelvin: 6500.0,
+            override_minutes: 60.0,
+        }
+    }
+}
+
+/// `CircadianSpec::points` parsed and sorted by time
+#[derive(Clone, Debug, PartialEq)]
+pub struct CircadianCurve {
+    /// (seconds since midnight, kelvin, brightness)
+    points: Vec<(u32, f32, f32)>,
+}
+
+const SECS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// Colour temperatures are interpolated in mireds, which is closer to how
+/// we see the difference
+fn mired(kelvin: f32) -> f32 {
+    1_000_000.0 / kelvin
+}
+
+impl CircadianCurve {
+    pub fn new(points: &[CircadianPoint]) -> Result<Self, String> {
+        if points.is_empty() {
+            return Err("circadian curve needs at least one point".to_string());
+        }
+
+        let mut parsed = Vec::with_capacity(points.len());
+        for point in points {
+            let time = NaiveTime::parse_from_str(&point.at, "%H:%M")
+                .map_err(|e| format!("circadian point '{}': {:?}",
+                                     point.at, e))?;
+            if !(point.kelvin > 0.0 && point.kelvin.is_finite()) {
+                return Err(format!("circadian point '{}': invalid kelvin {}",
+                                   point.at, point.kelvin));
+            }
+            if !point.brightness.is_finite() {
+                return Err(format!("circadian point '{}': invalid \
+                                    brightness {}", point.at,
+                                   point.brightness));
+            }
+            parsed.push((time.num_seconds_from_midnight(),
+                         point.kelvin, point.brightness));
+        }
+        parsed.sort_by_key(|(secs, _, _)| *secs);
+
+        Ok(CircadianCurve { points: parsed })
+    }
+
+    /// Colour temperature and brightness at `time`
+    pub fn at(&self, time: NaiveTime) -> (f32, f32) {
+        let secs = time.num_seconds_from_midnight();
+        let next_idx = self.points.iter()
+            .position(|(at, _, _)| *at > secs)
+            .unwrap_or(0);
+        let prev_idx = (next_idx + self.points.len() - 1) % self.points.len();
+
+        let (prev_at, prev_k, prev_b) = self.points[prev_idx];
+        let (next_at, next_k, next_b) = self.points[next_idx];
+
+        // distances wrap around midnight
+        let span = (next_at + SECS_PER_DAY - prev_at) % SECS_PER_DAY;
+        if span == 0 {
+            return (prev_k, prev_b);
+        }
+        let progress = ((secs + SECS_PER_DAY - prev_at) % SECS_PER_DAY) as f32
+            / span as f32;
+
+        let mired = mired(prev_k) + (mired(next_k) - mired(prev_k)) * progress;
+        (1_000_000.0 / mired, prev_b + (next_b - prev_b) * progress)
+    }
+}
+
+impl CircadianSpec {
+    /// Makes sure the curve is valid and the mix can't come up with NaN
+    pub fn validate(&self) -> Result<(), String> {
+        CircadianCurve::new(&self.points)?;
+        for (name, kelvin) in [("warm_kelvin", self.warm_kelvin),
+                               ("cool_kelvin", self.cool_kelvin)] {
+            if !(kelvin > 0.0 && kelvin.is_finite()) {
+                return Err(format!("circadian {} should be more than 0, \
+                                    not {}", name, kelvin));
+            }
+        }
+        if self.warm_kelvin == self.cool_kelvin {
+            return Err("circadian warm_kelvin and cool_kelvin should differ"
+                       .to_string());
+        }
+        self.override_duration()?;
+        Ok(())
+    }
+
+    /// `override_minutes` as a duration
+    pub fn override_duration(&self) -> Result<Duration, String> {
+        Duration::try_from_secs_f32(self.override_minutes * 60.0)
+            .map_err(|e| format!("circadian override_minutes {}: {}",
+                                 self.override_minutes, e))
+    }
+
+    /// Warm and cool values that make `kelvin` at `brightness`
+    pub fn mix(&self, kelvin: f32, brightness: f32) -> (f32, f32) {
+        let warm = mired(self.warm_kelvin);
+        let cool = mired(self.cool_kelvin);
+        let cool_part = ((warm - mired(kelvin)) / (warm - cool))
+            .clamp(0.0, 1.0);
+        (brightness * (1.0 - cool_part), brightness * cool_part)
+    }
+
+    fn has_tag(chan: &ChanDescription, tags: &[String]) -> bool {
+        chan.config.tags.iter().any(|tag| tags.iter().any(|t| t == tag.name()))
+    }
+
+    /// Values for tagged chans, other chans are left alone
+    pub fn frame(&self, chans: &[ChanDescription], kelvin: f32,
+                 brightness: f32) -> Frame<f32> {
+        let (warm, cool) = self.mix(kelvin, brightness);
+
+        let mut frame = Frame::empty();
+        for chan in chans {
+            if Self::has_tag(chan, &self.warm_tags) {
+                frame.set(chan.chan_id, warm);
+            } else if Self::has_tag(chan, &self.cool_tags) {
+                frame.set(chan.chan_id, cool);
+            } else if Self::has_tag(chan, &self.white_tags) {
+                frame.set(chan.chan_id, brightness);
+            }
+        }
+        frame
+    }
+}
+
+/// How long it takes to get back to the curve after an override
+const RESUME_FADE: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Override {
+    None,
+    Until(Instant),
+    Indefinitely,
+}
+
+/// Drives the warm / cool chans in the background, other sources can take
+/// over for a while with `override_for`, after which it fades back
+pub struct Circadian<T: ?Sized> {
+    output: Arc<Mutex<T>>,
+    pub spec: CircadianSpec,
+    curve: CircadianCurve,
+    clock: Arc<dyn Clock>,
+    overridden: Override,
+
+    /// Values when the override ended, we fade from them to the curve
+    resuming_from: Option<(Instant, Frame<f32>)>,
+}
+
+impl<T: HasChanDescriptions + DevRead + DevWrite + ?Sized> Circadian<T> {
+    pub fn new(output: Arc<Mutex<T>>, spec: CircadianSpec)
+            -> Result<Self, String> {
+        spec.validate()?;
+        let curve = CircadianCurve::new(&spec.points)?;
+        Ok(Circadian {
+            output,
+            spec,
+            curve,
+            clock: clock::real(),
+            overridden: Override::None,
+            resuming_from: None,
+        })
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Colour temperature and brightness right now
+    pub fn current(&self) -> (f32, f32) {
+        self.curve.at(self.clock.local_now().time())
+    }
+
+    /// Stop writing for `duration`, or until `resume` if it's too long to
+    /// tell when it ends
+    pub fn override_for(&mut self, duration: Duration) {
+        match self.clock.now().checked_add(duration) {
+            Some(until) => self.override_until(until),
+            None => self.hold(),
+        }
+    }
+
+    pub fn override_until(&mut self, until: Instant) {
+        self.overridden = match self.overridden {
+            Override::Indefinitely => Override::Indefinitely,
+            _ => Override::Until(until),
+        };
+        self.resuming_from = None;
+    }
+
+    /// Take over for `override_minutes` from the spec
+    pub fn override_default(&mut self) -> Result<(), String> {
+        self.override_for(self.spec.override_duration()?);
+        Ok(())
+    }
+
+    /// Stop writing until `resume`
+    pub fn hold(&mut self) {
+        self.overridden = Override::Indefinitely;
+        self.resuming_from = None;
+    }
+
+    /// Fade back to the curve now
+    pub fn resume(&mut self) {
+        self.overridden = Override::Until(self.clock.now());
+    }
+
+    pub fn is_overridden(&self) -> bool {
+        match self.overridden {
+            Override::None => false,
+            Override::Until(until) => self.clock.now() < until,
+            Override::Indefinitely => true,
+        }
+    }
+
+    /// Writes the current values unless overridden, returns when to
+    /// update next
+    pub fn update(&mut self, now: Instant) -> Result<Duration, String> {
+        match self.overridden {
+            Override::Indefinitely => return Ok(Duration::from_secs(1)),
+            Override::Until(until) if now < until =>
+                return Ok((until - now).min(Duration::from_secs(1))),
+            Override::Until(_) => {
+                self.overridden = Override::None;
+                let output = self.output.lock().unwrap();
+                let mut from = Frame::empty();
+                for chan in output.chan_descriptions() {
+                    from.set(chan.chan_id, output.get_f32(chan.chan_id)?);
+                }
+                self.resuming_from = Some((now, from));
+            },
+            Override::None => {},
+        }
+
+        let (kelvin, brightness) = self.current();
+        let mut output = self.output.lock().unwrap();
+        let mut frame = self.spec.frame(&output.chan_descriptions(),
+                                        kelvin, brightness);
+
+        let mut next_update = Duration::from_secs(1);
+        if let Some((started, from)) = &self.resuming_from {
+            let progress = (now - *started).as_secs_f32()
+                / RESUME_FADE.as_secs_f32();
+            if progress < 1.0 {
+                for (cid, val) in frame.clone().iter_some() {
+                    let from = from.get(cid).unwrap_or(*val);
+                    frame.set(cid, from + (*val - from) * progress);
+                }
+                next_update = Duration::from_millis(33);
+            } else {
+                self.resuming_from = None;
+            }
+        }
+
+        output.set_frame(&frame)?;
+        Ok(next_update)
+    }
+}
+
+impl<T: HasChanDescriptions + DevRead + DevWrite + ?Sized> Runner
+        for Circadian<T> {
+    /// `Pause` toggles holding the current values
+    fn run(
+        self_lock: Arc<Mutex<Circadian<T>>>,
+        stop: mpsc::Receiver<TaskMsg>,
+    ) -> Result<(), String> {
+        let clock = self_lock.lock().unwrap().clock.clone();
+        loop {
+            let wait = {
+                let mut circadian = self_lock.lock().unwrap();
+                match circadian.update(clock.now()) {
+                    Ok(wait) => wait,
+                    Err(e) => {
+                        eprintln!("circadian: {}", e);
+                        Duration::from_secs(1)
+                    },
+                }
+            };
+
+            match clock.recv_timeout(&stop, wait) {
+                Ok(TaskMsg::Stop) => return Ok(()),
+                Ok(TaskMsg::Pause) => {
+                    let mut circadian = self_lock.lock().unwrap();
+                    if circadian.overridden == Override::Indefinitely {
+                        circadian.resume();
+                    } else {
+                        circadian.hold();
+                    }
+                },
+                Ok(TaskMsg::Ping) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use crate::chan::ChanConfig;
+    use crate::mux::Mux;
+    use crate::clock::VirtualClock;
+    use crate::tag::Tag;
+    use crate::test_dev::TestDev;
+    use chrono::NaiveDateTime;
+
+    fn time(s: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(s, "%H:%M").unwrap()
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 0.001,
+                "expected {} got {}", expected, actual);
+    }
+
+    #[test]
+    fn test_curve() {
+        let point = |at: &str, kelvin, brightness| CircadianPoint {
+            at: at.to_string(), kelvin, brightness,
+        };
+        // unsorted on purpose
+        let curve = CircadianCurve::new(&[
+            point("20:00", 2000.0, 0.2),
+            point("08:00", 4000.0, 1.0),
+        ]).unwrap();
+
+        assert_eq!(curve.at(time("08:00")), (4000.0, 1.0));
+        assert_eq!(curve.at(time("20:00")), (2000.0, 0.2));
+
+        // halfway in mireds is 375, not 3000K
+        let (kelvin, brightness) = curve.at(time("14:00"));
+        assert_near(kelvin, 1_000_000.0 / 375.0);
+        assert_near(brightness, 0.6);
+
+        // wraps around midnight
+        let (kelvin, brightness) = curve.at(time("02:00"));
+        assert_near(kelvin, 1_000_000.0 / 375.0);
+        assert_near(brightness, 0.6);
+
+        assert!(CircadianCurve::new(&[]).is_err());
+        assert!(CircadianCurve::new(&[point("25:00", 2000.0, 1.0)]).is_err());
+    }
+
+    #[test]
+    fn test_mix() {
+        let spec = CircadianSpec::default();
+        assert_eq!(spec.mix(2700.0, 0.5), (0.5, 0.0));
+        assert_eq!(spec.mix(6500.0, 0.5), (0.0, 0.5));
+        // out of range is clamped
+        assert_eq!(spec.mix(1800.0, 1.0), (1.0, 0.0));
+
+        let (warm, cool) = spec.mix(4000.0, 0.8);
+        assert!(warm > 0.0 && cool > 0.0);
+        assert_near(warm + cool, 0.8);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(CircadianSpec::default().validate().is_ok());
+        let spec = |f: fn(&mut CircadianSpec)| {
+            let mut spec = CircadianSpec::default();
+            f(&mut spec);
+            spec.validate()
+        };
+        assert!(spec(|s| s.cool_kelvin = s.warm_kelvin).is_err());
+        assert!(spec(|s| s.warm_kelvin = f32::NAN).is_err());
+        assert!(spec(|s| s.cool_kelvin = 0.0).is_err());
+        assert!(spec(|s| s.override_minutes = -1.0).is_err());
+        assert!(spec(|s| s.override_minutes = f32::INFINITY).is_err());
+        assert!(spec(|s| s.override_minutes = 1e30).is_err());
+        assert!(spec(|s| s.points[0].kelvin = f32::INFINITY).is_err());
+    }
+
+    fn mux() -> (Arc<Mutex<Mux>>, Arc<Mutex<TestDev>>) {
+        let tags = ["warm", "cool", "r"];
+        let chan_cfgs: Vec<ChanConfig> = tags.iter().enumerate()
+            .map(|(index, tag)| ChanConfig {
+                index: index as u16,
+                exp: Some(2.0),
+                tags: vec![Tag::new(tag)],
+                ..ChanConfig::default()
+            })
+            .collect();
+
+        let dev = Arc::new(Mutex::new(TestDev::new(false)));
+        let mut mux = Mux::new();
+        mux.add_dev(dev.clone(), Some(chan_cfgs.into_iter()));
+        (Arc::new(Mutex::new(mux)), dev)
+    }
+
+    #[test]
+    fn test_override_and_resume() {
+        let (output, dev) = mux();
+        let now = NaiveDateTime::parse_from_str("2026-10-19 09:00",
+                                                "%Y-%m-%d %H:%M").unwrap();
+        let clock = VirtualClock::new();
+        clock.set_local_time(now);
+        let mut circadian = Circadian::new(
+            output.clone(), CircadianSpec::default()).unwrap()
+            .with_clock(Arc::new(clock.clone()));
+
+        let (warm, cool) = circadian.spec.mix(5000.0, 1.0);
+        let start = clock.now();
+        circadian.update(start).unwrap();
+        {
+            let output = output.lock().unwrap();
+            assert_near(output.get_f32(0).unwrap(), warm);
+            assert_near(output.get_f32(1).unwrap(), cool);
+            // untagged chans are left alone
+            assert_eq!(output.get_f32(2).unwrap(), 0.0);
+        }
+        // the chan's curve is applied on the way out
+        assert_near(dev.lock().unwrap().get_f32(0).unwrap(), warm * warm);
+
+        // somebody else takes over
+        circadian.override_for(Duration::from_secs(60));
+        assert!(circadian.is_overridden());
+        let mut frame = Frame::empty();
+        frame.set(0, 0.0);
+        frame.set(1, 0.0);
+        output.lock().unwrap().set_frame(&frame).unwrap();
+
+        circadian.update(start + Duration::from_secs(30)).unwrap();
+        assert_eq!(output.lock().unwrap().get_f32(0).unwrap(), 0.0);
+
+        // and then we fade back
+        let resumed = start + Duration::from_secs(60);
+        circadian.update(resumed).unwrap();
+        assert!(!circadian.is_overridden());
+        assert_eq!(output.lock().unwrap().get_f32(0).unwrap(), 0.0);
+
+        circadian.update(resumed + RESUME_FADE / 2).unwrap();
+        assert_near(output.lock().unwrap().get_f32(0).unwrap(), warm / 2.0);
+
+        circadian.update(resumed + RESUME_FADE).unwrap();
+        assert_near(output.lock().unwrap().get_f32(1).unwrap(), cool);
+
+        // too long to tell when it ends
+        circadian.override_for(Duration::MAX);
+        assert!(circadian.is_overridden());
+        circadian.resume();
+        assert!(!circadian.is_overridden());
+    }
+}
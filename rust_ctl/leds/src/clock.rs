@@ -0,0 +1,198 @@
+use crate::task::TaskMsg;
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+use std::fmt;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Where `Runner`s get the time from and how they wait for the next frame
+pub trait Clock: Send + Sync + fmt::Debug {
+    fn now(&self) -> Instant;
+
+    /// Waits for a message on `stop` for up to `timeout`
+    fn recv_timeout(&self, stop: &mpsc::Receiver<TaskMsg>, timeout: Duration)
+        -> Result<TaskMsg, RecvTimeoutError>;
+
+    fn sleep(&self, duration: Duration);
+
+    /// Wall clock time in the local time zone, for times of day
+    fn local_now(&self) -> NaiveDateTime;
+
+    /// Local time at `utc`
+    fn local_from_utc(&self, utc: NaiveDateTime) -> NaiveDateTime;
+
+    /// Wall clock time in UTC, i.e. for where the sun is
+    fn utc_now(&self) -> NaiveDateTime;
+}
+
+/// The wall clock, waits for real
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn recv_timeout(&self, stop: &mpsc::Receiver<TaskMsg>, timeout: Duration)
+            -> Result<TaskMsg, RecvTimeoutError> {
+        stop.recv_timeout(timeout)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+
+    fn local_now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+
+    fn local_from_utc(&self, utc: NaiveDateTime) -> NaiveDateTime {
+        Local.from_utc_datetime(&utc).naive_local()
+    }
+
+    fn utc_now(&self) -> NaiveDateTime {
+        Utc::now().naive_utc()
+    }
+}
+
+pub fn real() -> Arc<dyn Clock> {
+    Arc::new(RealClock)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Times {
+    now: Instant,
+    local: NaiveDateTime,
+}
+
+/// Time only moves when stepped or waited on, so runners render the same
+/// frames on every run and as fast as they can
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    times: Arc<Mutex<Times>>,
+    /// Waiting past this acts as if the task was dropped, so a runner can
+    /// run to a given time on the current thread
+    until: Option<Instant>,
+    utc_offset: chrono::Duration,
+}
+
+impl VirtualClock {
+    /// Local time starts at midnight of 2000-01-01 and is UTC
+    pub fn new() -> Self {
+        let local = NaiveDate::from_ymd(2000, 1, 1).and_hms(0, 0, 0);
+        VirtualClock {
+            times: Arc::new(Mutex::new(Times { now: Instant::now(), local })),
+            until: None,
+            utc_offset: chrono::Duration::zero(),
+        }
+    }
+
+    pub fn with_utc_offset(mut self, utc_offset: chrono::Duration) -> Self {
+        self.utc_offset = utc_offset;
+        self
+    }
+
+    /// Sets the wall clock like a person would, `now` keeps going on
+    pub fn set_local_time(&self, local: NaiveDateTime) {
+        self.times.lock().unwrap().local = local;
+    }
+
+    /// Runners stop once they wait past `duration` from now
+    pub fn stop_after(mut self, duration: Duration) -> Self {
+        self.until = Some(self.now() + duration);
+        self
+    }
+
+    pub fn step(&self, duration: Duration) {
+        let mut times = self.times.lock().unwrap();
+        times.now += duration;
+        times.local += chrono::Duration::from_std(duration).unwrap();
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        VirtualClock::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.times.lock().unwrap().now
+    }
+
+    /// Messages already sent are delivered right away, otherwise the whole
+    /// `timeout` passes
+    fn recv_timeout(&self, stop: &mpsc::Receiver<TaskMsg>, timeout: Duration)
+            -> Result<TaskMsg, RecvTimeoutError> {
+        match stop.try_recv() {
+            Ok(msg) => return Ok(msg),
+            Err(mpsc::TryRecvError::Disconnected) =>
+                return Err(RecvTimeoutError::Disconnected),
+            Err(mpsc::TryRecvError::Empty) => {},
+        }
+
+        self.step(timeout);
+        match self.until {
+            Some(until) if self.now() > until =>
+                Err(RecvTimeoutError::Disconnected),
+            _ => Err(RecvTimeoutError::Timeout),
+        }
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.step(duration)
+    }
+
+    fn local_now(&self) -> NaiveDateTime {
+        self.times.lock().unwrap().local
+    }
+
+    fn local_from_utc(&self, utc: NaiveDateTime) -> NaiveDateTime {
+        utc + self.utc_offset
+    }
+
+    fn utc_now(&self) -> NaiveDateTime {
+        self.local_now() - self.utc_offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock() {
+        let clock = VirtualClock::new().stop_after(Duration::from_secs(1));
+        let start = clock.now();
+        let (tx, rx) = mpsc::channel();
+
+        tx.send(TaskMsg::Ping).unwrap();
+        assert_eq!(Ok(TaskMsg::Ping),
+                   clock.recv_timeout(&rx, Duration::from_millis(600)));
+        assert_eq!(start, clock.now());
+
+        assert_eq!(Err(RecvTimeoutError::Timeout),
+                   clock.recv_timeout(&rx, Duration::from_millis(600)));
+        assert_eq!(Err(RecvTimeoutError::Disconnected),
+                   clock.recv_timeout(&rx, Duration::from_millis(600)));
+        assert_eq!(Duration::from_millis(1200), clock.now() - start);
+
+        // clones share the time
+        let other = clock.clone();
+        other.step(Duration::from_secs(1));
+        assert_eq!(Duration::from_millis(2200), clock.now() - start);
+
+        // the wall clock moves with it, and can be set back
+        let midnight = NaiveDate::from_ymd(2000, 1, 1).and_hms(0, 0, 0);
+        assert_eq!(midnight + chrono::Duration::milliseconds(2200),
+                   clock.local_now());
+        clock.set_local_time(midnight);
+        clock.sleep(Duration::from_secs(1));
+        assert_eq!(midnight + chrono::Duration::seconds(1), clock.local_now());
+        assert_eq!(Duration::from_millis(3200), clock.now() - start);
+    }
+}
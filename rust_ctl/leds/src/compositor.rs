@@ -0,0 +1,564 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+use crate::clock::{self, Clock};
+use crate::dev::{Dev, DevNumChans, DevRead, DevWrite};
+use crate::effect::Effect;
+use crate::frame::Frame;
+use crate::runner::Runner;
+use crate::task::TaskMsg;
+
+use std::fmt;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How a layer combines with what's below it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    #[default]
+    Replace,
+    Add,
+    Multiply,
+    Max,
+}
+
+impl BlendMode {
+    pub const ALL: &'static [&'static str] =
+        &["replace", "add", "multiply", "max"];
+
+    pub fn blend(&self, below: f32, above: f32) -> f32 {
+        match self {
+            BlendMode::Replace => above,
+            BlendMode::Add => below + above,
+            BlendMode::Multiply => below * above,
+            BlendMode::Max => below.max(above),
+        }
+    }
+}
+
+impl std::str::FromStr for BlendMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "replace" => Ok(BlendMode::Replace),
+            "add" => Ok(BlendMode::Add),
+            "multiply" => Ok(BlendMode::Multiply),
+            "max" => Ok(BlendMode::Max),
+            other => Err(format!("unknown blend mode '{}', expected one of {}",
+                                 other, BlendMode::ALL.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for BlendMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BlendMode::Replace => "replace",
+            BlendMode::Add => "add",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Max => "max",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayerSpec {
+    /// Adding a layer with the same name replaces it
+    pub name: String,
+
+    /// Only chans with any of these tags, all chans if empty
+    pub tags: Vec<String>,
+
+    pub blend: BlendMode,
+    pub opacity: f32,
+
+    /// Seconds
+    pub fade_in: f32,
+    /// Seconds
+    pub fade_out: f32,
+}
+
+impl Default for LayerSpec {
+    fn default() -> Self {
+        LayerSpec {
+            name: String::new(),
+            tags: vec![],
+            blend: BlendMode::Replace,
+            opacity: 1.0,
+            fade_in: 1.0,
+            fade_out: 1.0,
+        }
+    }
+}
+
+/// Linear fade of the layer's level between `from` and `to`
+#[derive(Clone, Copy, Debug)]
+struct LevelFade {
+    from: f32,
+    to: f32,
+    start: Instant,
+    duration: Duration,
+}
+
+impl LevelFade {
+    fn at(&self, now: Instant) -> f32 {
+        if self.duration.is_zero() {
+            return self.to;
+        }
+        let progress = (now.saturating_duration_since(self.start).as_secs_f32()
+                        / self.duration.as_secs_f32()).min(1.0);
+        self.from + (self.to - self.from) * progress
+    }
+
+    fn is_done(&self, now: Instant) -> bool {
+        now >= self.start + self.duration
+    }
+}
+
+struct Layer {
+    spec: LayerSpec,
+    effect: Box<dyn Effect>,
+    started: Instant,
+    level: LevelFade,
+}
+
+impl Layer {
+    fn covers(&self, chan: &ChanDescription) -> bool {
+        self.spec.tags.is_empty() || chan.config.tags.iter()
+            .any(|tag| self.spec.tags.iter().any(|t| t == tag.name()))
+    }
+
+    fn is_disabled(&self) -> bool {
+        self.level.to == 0.0
+    }
+}
+
+/// What's running, for listing
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerInfo {
+    pub spec: LayerSpec,
+    /// Current fade level, 0 to 1
+    pub level: f32,
+    /// Fading out to be removed
+    pub disabled: bool,
+}
+
+impl fmt::Display for LayerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({} {:.0}%", self.spec.name, self.spec.blend,
+               self.spec.opacity * 100.0)?;
+        if !self.spec.tags.is_empty() {
+            write!(f, " on {}", self.spec.tags.join(","))?;
+        }
+        if self.disabled {
+            write!(f, ", fading out")?;
+        } else if self.level < 1.0 {
+            write!(f, ", fading in")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Renders layers of effects on top of a base frame and is the only thing
+/// writing to the output. Everything that used to write to the output
+/// directly (fades, scenes, sequences) writes to `base()` instead.
+pub struct Compositor<T: ?Sized> {
+    output: Arc<Mutex<T>>,
+    base: Arc<Mutex<Frame<f32>>>,
+
+    /// Bottom to top
+    layers: Vec<Layer>,
+
+    /// Not sent again if nothing changed
+    last_sent: Option<Frame<f32>>,
+
+    pub frame_duration: Duration,
+
+    clock: Arc<dyn Clock>,
+}
+
+impl<T: HasChanDescriptions + DevRead + DevWrite + ?Sized> Compositor<T> {
+    /// The base starts with whatever the output has now
+    pub fn new(output: Arc<Mutex<T>>) -> Result<Self, String> {
+        let mut base = Frame::empty();
+        output.lock().unwrap().get_to_frame(&mut base)?;
+
+        Ok(Compositor {
+            output,
+            base: Arc::new(Mutex::new(base)),
+            layers: vec![],
+            last_sent: None,
+            frame_duration: Duration::from_millis(10),
+            clock: clock::real(),
+        })
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Writing to it sets the values below all layers
+    pub fn base(&self) -> Base<T> {
+        Base { frame: self.base.clone(), output: self.output.clone() }
+    }
+
+    /// What's on the lights, the base with all layers on top
+    pub fn output(&self) -> Arc<Mutex<T>> {
+        self.output.clone()
+    }
+
+    /// Fades in a layer, or replaces the effect of one with the same name
+    pub fn add_layer(&mut self, spec: LayerSpec, effect: Box<dyn Effect>) {
+        self.add_layer_at(spec, effect, self.clock.now());
+    }
+
+    pub fn add_layer_at(&mut self, spec: LayerSpec, effect: Box<dyn Effect>,
+                        now: Instant) {
+        let existing = self.layers.iter()
+            .position(|layer| layer.spec.name == spec.name);
+        // a replaced layer keeps its place and level
+        let from = existing
+            .map(|idx| self.layers[idx].level.at(now))
+            .unwrap_or(0.0);
+
+        let layer = Layer {
+            level: LevelFade {
+                from,
+                to: 1.0,
+                start: now,
+                duration: Duration::from_secs_f32(
+                    spec.fade_in.max(0.0) * (1.0 - from)),
+            },
+            spec,
+            effect,
+            started: now,
+        };
+
+        match existing {
+            Some(idx) => self.layers[idx] = layer,
+            None => self.layers.push(layer),
+        }
+    }
+
+    /// Fades the layer out and removes it
+    pub fn remove_layer(&mut self, name: &str) -> Result<(), String> {
+        self.remove_layer_at(name, self.clock.now())
+    }
+
+    pub fn remove_layer_at(&mut self, name: &str, now: Instant)
+            -> Result<(), String> {
+        let layer = self.layers.iter_mut()
+            .find(|layer| layer.spec.name == name)
+            .ok_or_else(|| format!("no layer '{}'", name))?;
+        Self::fade_out(layer, now);
+        Ok(())
+    }
+
+    /// Fades out all layers
+    pub fn clear(&mut self) {
+        let now = self.clock.now();
+        for layer in self.layers.iter_mut() {
+            Self::fade_out(layer, now);
+        }
+    }
+
+    fn fade_out(layer: &mut Layer, now: Instant) {
+        let from = layer.level.at(now);
+        layer.level = LevelFade {
+            from,
+            to: 0.0,
+            start: now,
+            duration: Duration::from_secs_f32(
+                layer.spec.fade_out.max(0.0) * from),
+        };
+    }
+
+    pub fn set_opacity(&mut self, name: &str, opacity: f32)
+            -> Result<(), String> {
+        let layer = self.layers.iter_mut()
+            .find(|layer| layer.spec.name == name)
+            .ok_or_else(|| format!("no layer '{}'", name))?;
+        layer.spec.opacity = opacity.clamp(0.0, 1.0);
+        Ok(())
+    }
+
+    pub fn layers(&self) -> Vec<LayerInfo> {
+        let now = self.clock.now();
+        self.layers.iter()
+            .map(|layer| LayerInfo {
+                spec: layer.spec.clone(),
+                level: layer.level.at(now),
+                disabled: layer.is_disabled(),
+            })
+            .collect()
+    }
+
+    /// Base with all layers on top, drops layers that have faded out
+    pub fn render(&mut self, now: Instant, chans: &[ChanDescription])
+            -> Frame<f32> {
+        self.layers.retain(|layer|
+            !(layer.is_disabled() && layer.level.is_done(now)));
+
+        let mut frame = self.base.lock().unwrap().clone();
+        for layer in self.layers.iter_mut() {
+            let weight = layer.spec.opacity * layer.level.at(now);
+            let rendered = layer.effect.render(now - layer.started, chans);
+            for chan in chans.iter().filter(|chan| layer.covers(chan)) {
+                let above = match rendered.get(chan.chan_id) {
+                    Some(above) => above,
+                    None => continue,
+                };
+                let below = frame.get(chan.chan_id).unwrap_or(0.0);
+                let blended = layer.spec.blend.blend(below, above);
+                frame.set(chan.chan_id,
+                          (below + (blended - below) * weight).clamp(0.0, 1.0));
+            }
+        }
+        frame
+    }
+
+    fn render_to_output(&mut self, now: Instant) -> Result<(), String> {
+        let output = self.output.clone();
+        let mut output = output.lock()
+            .map_err(|e| format!("write lock: {:?}", e))?;
+        let frame = self.render(now, &output.chan_descriptions());
+        if self.last_sent.as_ref() == Some(&frame) {
+            return Ok(());
+        }
+        output.set_frame(&frame)?;
+        self.last_sent = Some(frame);
+        Ok(())
+    }
+}
+
+impl<T: HasChanDescriptions + DevRead + DevWrite + ?Sized> Runner
+        for Compositor<T> {
+    fn run(
+        self_lock: Arc<Mutex<Compositor<T>>>,
+        stop: mpsc::Receiver<TaskMsg>,
+    ) -> Result<(), String> {
+        let clock = self_lock.lock().unwrap().clock.clone();
+        loop {
+            let frame_duration = {
+                let mut compositor = self_lock.lock().unwrap();
+                if let Err(e) = compositor.render_to_output(clock.now()) {
+                    eprintln!("compositor: {}", e);
+                }
+                compositor.frame_duration
+            };
+
+            match clock.recv_timeout(&stop, frame_duration) {
+                Ok(TaskMsg::Stop) => return Ok(()),
+                Ok(TaskMsg::Pause) => self_lock.lock().unwrap().clear(),
+                Ok(TaskMsg::Ping) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// The bottom of the compositor, looks like the output
+pub struct Base<T: ?Sized> {
+    frame: Arc<Mutex<Frame<f32>>>,
+    output: Arc<Mutex<T>>,
+}
+
+impl<T: ?Sized> Clone for Base<T> {
+    fn clone(&self) -> Self {
+        Base { frame: self.frame.clone(), output: self.output.clone() }
+    }
+}
+
+impl<T: DevNumChans + ?Sized> DevNumChans for Base<T> {
+    fn num_chans(&self) -> u16 {
+        self.output.lock().unwrap().num_chans()
+    }
+}
+
+impl<T: DevNumChans + ?Sized> DevRead for Base<T> {
+    fn get_f32(&self, chan: u16) -> Result<f32, String> {
+        self.frame.lock().unwrap().get(chan)
+            .ok_or_else(|| format!("no base value for chan {}", chan))
+    }
+}
+
+impl<T: DevNumChans + ?Sized> DevWrite for Base<T> {
+    fn set_frame(&mut self, frame: &Frame<f32>) -> Result<(), String> {
+        self.frame.lock().unwrap().merge_frame(frame)
+    }
+}
+
+impl<T: HasChanDescriptions + ?Sized> HasChanDescriptions for Base<T> {
+    fn chans(&self) -> Vec<(proto::v1::ChanId, String)> {
+        self.output.lock().unwrap().chans()
+    }
+
+    fn chan_descriptions(&self) -> Vec<ChanDescription> {
+        self.output.lock().unwrap().chan_descriptions()
+    }
+}
+
+impl<T: ?Sized> fmt::Display for Base<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "compositor base")
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Base<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Base {:?}", self.frame.lock().unwrap())
+    }
+}
+
+impl<T: DevNumChans + Send + ?Sized> Dev for Base<T> {}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use crate::chan::ChanConfig;
+    use crate::mux::Mux;
+    use crate::tag::Tag;
+    use crate::test_dev::TestDev;
+
+    /// Same value on every chan
+    struct Constant(f32);
+
+    impl Effect for Constant {
+        fn render(&mut self, _t: Duration, chans: &[ChanDescription])
+                -> Frame<f32> {
+            let mut frame = Frame::empty();
+            for chan in chans {
+                frame.set(chan.chan_id, self.0);
+            }
+            frame
+        }
+    }
+
+    fn compositor() -> (Compositor<Mux>, Vec<ChanDescription>) {
+        let tags = ["wall", "wall", "door"];
+        let chan_cfgs: Vec<ChanConfig> = tags.iter().enumerate()
+            .map(|(index, tag)| ChanConfig {
+                index: index as u16,
+                tags: vec![Tag::new(tag)],
+                ..ChanConfig::default()
+            })
+            .collect();
+        let mut mux = Mux::new();
+        mux.add_dev(Arc::new(Mutex::new(TestDev::new(false))),
+                    Some(chan_cfgs.into_iter()));
+        let chans = mux.chan_descriptions();
+
+        let compositor = Compositor::new(Arc::new(Mutex::new(mux))).unwrap();
+        let mut base = Frame::empty();
+        for chan in chans.iter() {
+            base.set(chan.chan_id, 0.5);
+        }
+        compositor.base().set_frame(&base).unwrap();
+        (compositor, chans)
+    }
+
+    fn spec(name: &str, blend: BlendMode, opacity: f32) -> LayerSpec {
+        LayerSpec {
+            name: name.to_string(),
+            blend,
+            opacity,
+            fade_in: 0.0,
+            fade_out: 0.0,
+            ..LayerSpec::default()
+        }
+    }
+
+    fn assert_vals(frame: &Frame<f32>, expected: &[f32]) {
+        for (cid, expected) in expected.iter().enumerate() {
+            let actual = frame.get(cid as u16).unwrap();
+            assert!((actual - expected).abs() < 0.0001,
+                    "chan {}: expected {} got {}", cid, expected, actual);
+        }
+    }
+
+    #[test]
+    fn test_blend_modes_and_opacity() {
+        let (mut compositor, chans) = compositor();
+        let now = Instant::now();
+        assert_vals(&compositor.render(now, &chans), &[0.5, 0.5, 0.5]);
+
+        compositor.add_layer_at(spec("add", BlendMode::Add, 1.0),
+                                Box::new(Constant(0.2)), now);
+        assert_vals(&compositor.render(now, &chans), &[0.7, 0.7, 0.7]);
+
+        compositor.add_layer_at(spec("mul", BlendMode::Multiply, 0.5),
+                                Box::new(Constant(0.0)), now);
+        // half way between 0.7 and 0
+        assert_vals(&compositor.render(now, &chans), &[0.35, 0.35, 0.35]);
+
+        compositor.add_layer_at(
+            LayerSpec {
+                tags: vec!["door".to_string()],
+                ..spec("max", BlendMode::Max, 1.0)
+            },
+            Box::new(Constant(0.9)), now);
+        assert_vals(&compositor.render(now, &chans), &[0.35, 0.35, 0.9]);
+
+        compositor.add_layer_at(spec("replace", BlendMode::Replace, 1.0),
+                                Box::new(Constant(2.0)), now);
+        // clamped
+        assert_vals(&compositor.render(now, &chans), &[1.0, 1.0, 1.0]);
+
+        compositor.remove_layer_at("replace", now).unwrap();
+        assert_vals(&compositor.render(now, &chans), &[0.35, 0.35, 0.9]);
+        assert_eq!(compositor.layers().len(), 3);
+        assert!(compositor.remove_layer("nope").is_err());
+    }
+
+    #[test]
+    fn test_fade_in_and_out() {
+        let (mut compositor, chans) = compositor();
+        let now = Instant::now();
+        let secs = Duration::from_secs;
+
+        compositor.add_layer_at(
+            LayerSpec {
+                fade_in: 2.0,
+                fade_out: 4.0,
+                ..spec("accent", BlendMode::Replace, 1.0)
+            },
+            Box::new(Constant(1.0)), now);
+        assert_vals(&compositor.render(now, &chans), &[0.5, 0.5, 0.5]);
+        assert_vals(&compositor.render(now + secs(1), &chans),
+                    &[0.75, 0.75, 0.75]);
+        assert_vals(&compositor.render(now + secs(2), &chans),
+                    &[1.0, 1.0, 1.0]);
+
+        // fading out from full takes the whole fade_out
+        compositor.remove_layer_at("accent", now + secs(2)).unwrap();
+        assert_vals(&compositor.render(now + secs(4), &chans),
+                    &[0.75, 0.75, 0.75]);
+        assert_eq!(compositor.layers().len(), 1);
+        assert_vals(&compositor.render(now + secs(6), &chans),
+                    &[0.5, 0.5, 0.5]);
+        assert!(compositor.layers().is_empty());
+    }
+
+    #[test]
+    fn test_output() {
+        let (mut compositor, _) = compositor();
+        let now = Instant::now();
+        compositor.add_layer_at(spec("add", BlendMode::Add, 1.0),
+                                Box::new(Constant(0.2)), now);
+        compositor.render_to_output(now).unwrap();
+
+        // the lights have the layers on top, the base doesn't
+        let output = compositor.output();
+        assert!((output.lock().unwrap().get_f32(0).unwrap() - 0.7).abs() < 0.0001);
+        assert_eq!(compositor.base().get_f32(0), Ok(0.5));
+    }
+}
@@ -73,13 +73,228 @@ impl Cuboid {
         dists.iter().sum::<f32>() / dists.len() as f32
     }
 
-    // pub fn sphere_intersection(&self, center: Coord, radius: f32) -> f32 {
-    //     let corners = self.corners();
-    //     let dists: Vec<f32> = corners.into_iter().map(|corn| {
-    //         let dist = center.dist_to(&corn);
-    //         radius - dist
-    //     }).collect();
-
-    //     dists.iter().sum::<f32>() / dists.len() as f32
-    // }
+    pub fn min(&self) -> Coord {
+        Coord {
+            x: self.start.x.min(self.end.x),
+            y: self.start.y.min(self.end.y),
+            z: self.start.z.min(self.end.z),
+        }
+    }
+
+    pub fn max(&self) -> Coord {
+        Coord {
+            x: self.start.x.max(self.end.x),
+            y: self.start.y.max(self.end.y),
+            z: self.start.z.max(self.end.z),
+        }
+    }
+
+    pub fn center(&self) -> Coord {
+        Coord {
+            x: (self.start.x + self.end.x) / 2.0,
+            y: (self.start.y + self.end.y) / 2.0,
+            z: (self.start.z + self.end.z) / 2.0,
+        }
+    }
+
+    /// Centers of a grid of cells about `step` apart filling the cuboid, at
+    /// least one and at most `max_per_axis` per axis, so a thin strip is a
+    /// row of points and a flat panel a plane of them
+    pub fn samples(&self, step: f32, max_per_axis: usize) -> Vec<Coord> {
+        let (min, max) = (self.min(), self.max());
+        let count = |from: f32, to: f32| (((to - from) / step).round() as usize)
+            .clamp(1, max_per_axis);
+        let (nx, ny, nz) =
+            (count(min.x, max.x), count(min.y, max.y), count(min.z, max.z));
+        let at = |from: f32, to: f32, i: usize, n: usize|
+            from + (to - from) * (i as f32 + 0.5) / n as f32;
+
+        let mut result = Vec::with_capacity(nx * ny * nz);
+        for ix in 0..nx {
+            for iy in 0..ny {
+                for iz in 0..nz {
+                    result.push(Coord {
+                        x: at(min.x, max.x, ix, nx),
+                        y: at(min.y, max.y, iy, ny),
+                        z: at(min.z, max.z, iz, nz),
+                    });
+                }
+            }
+        }
+        result
+    }
+
+    /// Fraction of the cuboid's volume inside the sphere, 0 to 1. With
+    /// `falloff` above 0 the outer `falloff * radius` of the sphere fades
+    /// out linearly, so the light has a soft edge.
+    ///
+    /// The longest axis is integrated exactly (hard edge) or finely
+    /// (soft edge) and the other two are sampled, so long thin strips come
+    /// out right.
+    pub fn sphere_intersection(&self, center: Coord, radius: f32,
+                               falloff: f32) -> f32 {
+        if radius <= 0.0 {
+            return 0.0;
+        }
+        let (min, max) = (self.min(), self.max());
+        let axes = [
+            (min.x, max.x, center.x),
+            (min.y, max.y, center.y),
+            (min.z, max.z, center.z),
+        ];
+        let long = (0..3)
+            .max_by(|a, b| (axes[*a].1 - axes[*a].0)
+                    .total_cmp(&(axes[*b].1 - axes[*b].0)))
+            .unwrap();
+        let (l_min, l_max, l_center) = axes[long];
+        let (a, b) = match long {
+            0 => (axes[1], axes[2]),
+            1 => (axes[0], axes[2]),
+            _ => (axes[0], axes[1]),
+        };
+
+        let cells = |(from, to, _): (f32, f32, f32)| match to - from {
+            extent if extent > f32::EPSILON => SPHERE_SAMPLES,
+            _ => 1,
+        };
+        let at = |(from, to, _): (f32, f32, f32), i: usize, n: usize|
+            from + (to - from) * (i as f32 + 0.5) / n as f32;
+        let (na, nb) = (cells(a), cells(b));
+
+        let mut sum = 0.0;
+        for ia in 0..na {
+            for ib in 0..nb {
+                let da = at(a, ia, na) - a.2;
+                let db = at(b, ib, nb) - b.2;
+                sum += chord_fraction(l_min, l_max, l_center,
+                                      da * da + db * db, radius, falloff);
+            }
+        }
+        sum / (na * nb) as f32
+    }
+}
+
+/// Sampled cells per axis across the short sides of the cuboid, and steps
+/// along the long side for soft edges
+const SPHERE_SAMPLES: usize = 16;
+const FALLOFF_STEPS: usize = 64;
+
+/// How much of a point `dist` from the center a sphere lights, 1 inside
+/// `radius * (1 - falloff)`, fading to 0 at `radius`
+pub fn sphere_weight(dist: f32, radius: f32, falloff: f32) -> f32 {
+    let inner = radius * (1.0 - falloff.clamp(0.0, 1.0));
+    if dist <= inner {
+        1.0
+    } else if dist >= radius {
+        0.0
+    } else {
+        (radius - dist) / (radius - inner)
+    }
+}
+
+/// Average sphere weight along the line from `l_min` to `l_max` that's
+/// `sqrt(dist2)` away from the center on the other axes
+fn chord_fraction(l_min: f32, l_max: f32, l_center: f32, dist2: f32,
+                  radius: f32, falloff: f32) -> f32 {
+    let r2 = radius * radius;
+    if dist2 >= r2 {
+        return 0.0;
+    }
+    let half = (r2 - dist2).sqrt();
+    let (lo, hi) = ((l_center - half).max(l_min), (l_center + half).min(l_max));
+    if l_max - l_min <= f32::EPSILON {
+        // a point, or a flat box seen edge on
+        let dl = l_min - l_center;
+        return sphere_weight((dist2 + dl * dl).sqrt(), radius, falloff);
+    }
+    if hi <= lo {
+        return 0.0;
+    }
+    if falloff <= 0.0 {
+        return (hi - lo) / (l_max - l_min);
+    }
+
+    let step = (hi - lo) / FALLOFF_STEPS as f32;
+    let sum: f32 = (0..FALLOFF_STEPS)
+        .map(|i| lo + step * (i as f32 + 0.5) - l_center)
+        .map(|dl| sphere_weight((dist2 + dl * dl).sqrt(), radius, falloff))
+        .sum();
+    sum * step / (l_max - l_min)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+
+    fn coord(x: f32, y: f32, z: f32) -> Coord {
+        Coord { x, y, z }
+    }
+
+    fn cuboid(start: (f32, f32, f32), end: (f32, f32, f32)) -> Cuboid {
+        Cuboid { start: coord(start.0, start.1, start.2),
+                 end: coord(end.0, end.1, end.2) }
+    }
+
+    fn assert_near(expected: f32, actual: f32, eps: f32) {
+        assert!((expected - actual).abs() < eps,
+                "expected {} got {}", expected, actual);
+    }
+
+    #[test]
+    fn test_sphere_intersection_box() {
+        let unit = cuboid((0.0, 0.0, 0.0), (1.0, 1.0, 1.0));
+        let center = coord(0.5, 0.5, 0.5);
+
+        // inside, around and outside
+        assert_eq!(1.0, unit.sphere_intersection(center, 1.0, 0.0));
+        assert_eq!(0.0, unit.sphere_intersection(coord(3.0, 0.5, 0.5),
+                                                 1.0, 0.0));
+        assert_eq!(0.0, unit.sphere_intersection(center, 0.0, 0.0));
+
+        // a small sphere inside the box
+        let volume = 4.0 / 3.0 * std::f32::consts::PI * 0.3f32.powi(3);
+        assert_near(volume, unit.sphere_intersection(center, 0.3, 0.0), 0.01);
+
+        // a huge sphere whose surface cuts the box in half
+        assert_near(0.5, unit.sphere_intersection(coord(100.5, 0.5, 0.5),
+                                                  100.0, 0.0), 0.01);
+        // a sphere centered on a corner covers an eighth of itself
+        assert_near(volume / 8.0,
+                    unit.sphere_intersection(coord(0.0, 0.0, 0.0), 0.3, 0.0),
+                    0.005);
+    }
+
+    #[test]
+    fn test_sphere_intersection_strip() {
+        // 1 long along x, no thickness, reversed corners
+        let strip = cuboid((1.0, 0.0, 0.0), (0.0, 0.0, 0.0));
+
+        assert_near(0.25, strip.sphere_intersection(coord(0.0, 0.0, 0.0),
+                                                    0.25, 0.0), 1e-5);
+        // chord of a sphere 0.3 off the strip: 2 * sqrt(0.5^2 - 0.3^2)
+        assert_near(0.8, strip.sphere_intersection(coord(0.5, 0.3, 0.0),
+                                                   0.5, 0.0), 1e-5);
+        assert_eq!(0.0, strip.sphere_intersection(coord(0.5, 0.6, 0.0),
+                                                  0.5, 0.0));
+        // the average distance to the corners said this was far away
+        assert!(strip.avg_dist_to_point(coord(0.5, 0.0, 0.0)) >= 0.5);
+        assert_near(0.2, strip.sphere_intersection(coord(0.5, 0.0, 0.0),
+                                                   0.1, 0.0), 1e-5);
+
+        // soft edge: linear fade from the center over the whole radius
+        assert_near(0.5, strip.sphere_intersection(coord(0.0, 0.0, 0.0),
+                                                   1.0, 1.0), 1e-3);
+        assert_near(0.75, strip.sphere_intersection(coord(0.0, 0.0, 0.0),
+                                                    1.0, 0.5), 1e-3);
+
+        // a single point
+        let point = cuboid((0.5, 0.5, 0.5), (0.5, 0.5, 0.5));
+        assert_eq!(1.0, point.sphere_intersection(coord(0.5, 0.6, 0.5),
+                                                  0.2, 0.0));
+        assert_near(0.5, point.sphere_intersection(coord(0.5, 0.6, 0.5),
+                                                   0.2, 1.0), 1e-5);
+        assert_eq!(0.0, point.sphere_intersection(coord(0.5, 0.9, 0.5),
+                                                  0.2, 0.0));
+    }
 }
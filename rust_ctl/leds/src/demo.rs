@@ -11,6 +11,7 @@ pub use glitch::Glitch;
 pub use hello::Hello;
 pub use fade::Fade;
 pub use fade::FadeSpec;
+pub use fade::{FadeEnd, FadeMode};
 pub use whoosh::Whoosh;
 pub use schroom::Schroom;
 
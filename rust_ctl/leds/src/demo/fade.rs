@@ -1,248 +1,601 @@
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::mpsc;
-use std::fmt;
-use crate::dev::{DevNumChans, DevRead, DevWrite};
-use crate::chan_description::{HasChanDescriptions};
+use crate::dev::{DevRead, DevWrite};
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
 use crate::frame::Frame;
 use std::time::{Duration, Instant};
 use std::sync::{Arc, Mutex};
 use crate::task::TaskMsg;
 use crate::runner::Runner;
 use crate::wrapper::Wrapper;
-
-#[derive(Debug, Clone)]
+use crate::easing::{self, Easing};
+use crate::clock::{self, Clock};
+use crate::expr::Selector;
+
+const DEFAULT_FRAME_DURATION: Duration = Duration::from_millis(10);
+
+/// How a fade goes, scenes carry it next to their chans
+///
+/// ```yaml
+/// fade: 3.0
+/// easing: Cubic
+/// perceptual: true
+/// timing:
+///   - { chans: wall, delay: 0.5, stagger: 0.2, duration: 2.0 }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct FadeSpec {
     /// How long we show each frame, i.e. 1/FPS
+    #[serde(skip)]
     pub frame_duration: Duration,
 
-    /// The whole duration of the fade from start to finish
+    /// How long a chan takes from start to finish, unless its `timing` says
+    /// otherwise, 1 second if not specified
+    #[serde(rename = "fade", with = "secs")]
     pub fade_duration: Duration,
+
+    /// How we move from the start value to the target value
+    pub easing: Easing,
+
+    /// Move in even steps of perceived lightness rather than of values,
+    /// so fades don't rush through the dark end
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub perceptual: bool,
+
+    /// Delays and durations of some chans, the first match wins
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub timing: Vec<ChanTiming>,
 }
 
-#[derive(Debug)]
-pub struct Fade<T> {
-    /// We render frames to this
-    output: Arc<Mutex<T>>,
+impl Default for FadeSpec {
+    fn default() -> Self {
+        FadeSpec {
+            frame_duration: DEFAULT_FRAME_DURATION,
+            fade_duration: Duration::from_secs(1),
+            easing: Easing::Linear,
+            perceptual: false,
+            timing: vec![],
+        }
+    }
+}
 
-    /// Settings for this demo
-    pub settings: FadeSpec,
+/// Durations as seconds
+pub(crate) mod secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S)
+            -> Result<S::Ok, S::Error> {
+        s.serialize_f32(d.as_secs_f32())
+    }
 
-    /// When the fade ends
-    target_time: Instant,
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D)
+            -> Result<Duration, D::Error> {
+        let secs = f32::deserialize(d)?;
+        Duration::try_from_secs_f32(secs).map_err(serde::de::Error::custom)
+    }
+}
 
-    /// Fade to this frame
-    target_frame: Frame<f32>,
+/// When chans with a tag (or a chan id, or `all`) fade
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChanTiming {
+    pub chans: String,
 
-    /// When the fade has started
-    start_time: Instant,
+    /// Seconds before they start
+    #[serde(default)]
+    pub delay: f32,
 
-    /// Fade from this frame (got from `output` on `sync`)
-    from_frame: Frame<f32>,
+    /// Seconds added to `delay` for every next chan, in the order of chans
+    #[serde(default)]
+    pub stagger: f32,
 
-    /// buffer for rendering
-    current_frame: Frame<f32>,
+    /// Seconds from start to finish, `fade` if not specified
+    #[serde(default)]
+    pub duration: Option<f32>,
 }
 
-impl<T: HasChanDescriptions + DevNumChans + DevRead + fmt::Debug> Fade<T> {
-    pub fn new(
-        output: Arc<Mutex<T>>,
-        settings: FadeSpec,
-    ) -> Self {
-        let now = Instant::now();
-        let fade_duration = settings.fade_duration;
+/// Delay and duration of every chan of a fade
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timing {
+    /// By chan id, `None` for the ones `default` is for
+    chans: Vec<Option<(Duration, Duration)>>,
+    default: (Duration, Duration),
+}
 
-        let mut current_frame = Frame::empty();
-        let target_frame = {
-            let output = output.clone();
-            let output = output.lock().unwrap();
+impl Timing {
+    pub fn get(&self, chan: u16) -> (Duration, Duration) {
+        self.chans.get(chan as usize).copied().flatten()
+            .unwrap_or(self.default)
+    }
 
-            output.get_to_frame(&mut current_frame).unwrap();
+    /// When the last chan is done
+    pub fn end(&self) -> Duration {
+        self.chans.iter().flatten()
+            .map(|(delay, duration)| *delay + *duration)
+            .fold(self.default.1, Duration::max)
+    }
+}
 
-            let mut target_frame: Frame<f32> =
-                Frame::new(output.num_chans());
-            target_frame.set_all(1.0);
-            target_frame
+impl FadeSpec {
+    pub fn timing(&self, chans: &[ChanDescription]) -> Timing {
+        let mut timing = Timing {
+            chans: vec![],
+            default: (Duration::ZERO, self.fade_duration),
         };
+        let secs = |secs: f32| Duration::try_from_secs_f32(secs)
+            .unwrap_or_default();
+
+        let mut counts = vec![0; self.timing.len()];
+        for chan in chans {
+            let found = self.timing.iter().enumerate()
+                .find(|(_, t)| Selector::parse(&t.chans).matches(chan));
+            let (ii, chan_timing) = match found {
+                Some(found) => found,
+                None => continue,
+            };
+            let delay = chan_timing.delay
+                + chan_timing.stagger * counts[ii] as f32;
+            let duration = chan_timing.duration
+                .map(secs)
+                .unwrap_or(self.fade_duration);
+            counts[ii] += 1;
+
+            let cid = chan.chan_id as usize;
+            if timing.chans.len() <= cid {
+                timing.chans.resize(cid + 1, None);
+            }
+            timing.chans[cid] = Some((secs(delay), duration));
+        }
+        timing
+    }
 
-        Fade {
-            output,
-            settings,
-            target_time:   now + fade_duration,
-            start_time:    now,
-            target_frame,
-            from_frame:    current_frame.clone(),
-            current_frame,
+    /// Value of a chan `elapsed` into the fade
+    pub fn value_at(
+        &self,
+        from: f32,
+        to: f32,
+        elapsed: Duration,
+        (delay, duration): (Duration, Duration),
+    ) -> f32 {
+        let progress = match elapsed.checked_sub(delay) {
+            None => 0.0,
+            Some(_) if duration.is_zero() => 1.0,
+            Some(elapsed) => elapsed.div_duration_f64(duration).min(1.0),
+        };
+        let k = self.easing.apply(progress);
+
+        let (from, to) = (from as f64, to as f64);
+        let val = match self.perceptual {
+            false => from + (to - from) * k,
+            true => {
+                let (from, to) = (easing::lightness(from.max(0.0)),
+                                  easing::lightness(to.max(0.0)));
+                easing::from_lightness(from + (to - from) * k)
+            },
+        };
+        // no rounding errors at the ends
+        match progress {
+            p if p >= 1.0 => to as f32,
+            _ => val as f32,
         }
     }
 }
 
-impl<T> Wrapper for Fade<T> {
-    type Output = T;
+/// How a new target goes with what chans are already fading to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FadeMode {
+    /// Starts now from where the chans are, dropping what they fade to
+    Supersede,
+    /// Starts once the chans are done with what they fade to
+    Queue,
+}
 
-    fn output(&self) -> Arc<Mutex<T>> {
-        self.output.clone()
-    }
+/// How a `Fade::fade_to` has ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FadeEnd {
+    /// Every chan has reached the target
+    Done,
+    /// Some chans got another target, or were stopped, before that
+    Superseded,
 }
 
-impl<T: DevRead> Fade<T> {
-    fn fetch_vals(&mut self) -> Result<(), String> {
-        let output = self.output.lock().unwrap();
-        output.get_to_frame(&mut self.from_frame)?;
+/// Something we fade to and who wants to know when we're done
+#[derive(Debug)]
+struct Target {
+    spec: FadeSpec,
+    timing: Timing,
+
+    /// Chans that haven't reached it or been superseded yet
+    remaining: usize,
+    superseded: bool,
+    done: mpsc::Sender<FadeEnd>,
+}
 
-        Ok(())
-    }
+/// A chan fading to a target
+#[derive(Debug)]
+struct ChanFade {
+    target: u64,
+    to: f32,
+    /// Set when it starts, queued ones start where the one before ended
+    from: f32,
+    start: Instant,
 }
 
+/// The fade engine of an output, every chan fades to its own target
+///
+/// It only writes chans that are fading, so it doesn't fight whatever
+/// else writes to the output when there's nothing to fade
+#[derive(Debug)]
+pub struct Fade<T> {
+    /// We render frames to this
+    output: Arc<Mutex<T>>,
+
+    /// How `set_frame` fades
+    pub settings: FadeSpec,
 
-impl<T: HasChanDescriptions + DevRead> DevWrite for Fade<T> {
-    fn set_frame(&mut self, frame: &Frame<f32>) -> Result<(), String> {
-        self.fetch_vals()?;
-        // eprintln!("Fade set_frame {frame:?}");
-        self.start_time = Instant::now();
-        self.target_time = self.start_time + self.settings.fade_duration;
-
-        self.target_frame = frame.clone();
-        self.from_frame.vals.shrink_to(self.target_frame.vals.len());
-        // self.target_frame.vals.resize(self.from_frame.vals.len(), None);
-        self.current_frame.vals.resize(self.target_frame.vals.len(), None);
-        Ok(())
-    }
+    targets: HashMap<u64, Target>,
+    next_target: u64,
+
+    /// What chans fade to now and next, by chan id
+    chans: BTreeMap<u16, VecDeque<ChanFade>>,
+
+    /// When we've paused, `None` if we're not paused
+    paused_at: Option<Instant>,
+
+    clock: Arc<dyn Clock>,
 }
 
-impl<T: DevRead + DevWrite> Fade<T> {
-    fn send_current_frame(&mut self) -> Result<(), String> {
-        let mut output = self.output.lock().unwrap();
-        if let Err(e) = output.set_frame(&self.current_frame) {
-            // eprintln!("fade output.set_frame err: {:?}", e);
-            return Err(e)
+impl<T> Fade<T> {
+    pub fn new(output: Arc<Mutex<T>>, settings: FadeSpec) -> Self {
+        Fade {
+            output,
+            settings,
+            targets: HashMap::new(),
+            next_target: 0,
+            chans: BTreeMap::new(),
+            paused_at: None,
+            clock: clock::real(),
         }
+    }
 
-        Ok(())
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
     }
-}
 
-impl<T: DevRead + DevWrite> Fade<T> {
-    fn is_done(&self, t: Instant) -> bool {
-        if t >= self.target_time {
-            return true;
-        }
+    /// Whether any chan is fading or has a fade queued
+    pub fn is_fading(&self) -> bool {
+        !self.chans.is_empty()
+    }
 
-        if t <= self.start_time {
-            panic!("fade haven't reached start time in `run`, \
-                   which must be an error");
+    /// Counts a chan of `target` as done, tells the caller when it's the
+    /// last one
+    fn end_chan(targets: &mut HashMap<u64, Target>, target: u64,
+                superseded: bool) {
+        let entry = match targets.get_mut(&target) {
+            Some(entry) => entry,
+            None => return,
+        };
+        entry.remaining -= 1;
+        entry.superseded |= superseded;
+        if entry.remaining == 0 {
+            let entry = targets.remove(&target).unwrap();
+            let end = match entry.superseded {
+                true => FadeEnd::Superseded,
+                false => FadeEnd::Done,
+            };
+            // nobody may be listening
+            let _ = entry.done.send(end);
         }
+    }
 
-        false
+    /// Stops every fade where it is now
+    pub fn cancel(&mut self) {
+        for (_, fades) in std::mem::take(&mut self.chans) {
+            for fade in fades {
+                Self::end_chan(&mut self.targets, fade.target, true);
+            }
+        }
     }
 
-    fn set_current_frame(&mut self, t: Instant) -> Result<(), String> {
-        // At this point `t` is somewhere between `start_time` and
-        // `target_time`
+    /// Values of fading chans at `now`, moving on to queued targets and
+    /// letting callers know about the ones that are done
+    fn update(&mut self, now: Instant) -> Frame<f32> {
+        let now = self.paused_at.unwrap_or(now);
+        let mut frame = Frame::empty();
+
+        for (&cid, fades) in self.chans.iter_mut() {
+            while let Some(fade) = fades.front() {
+                let target = &self.targets[&fade.target];
+                let (delay, duration) = target.timing.get(cid);
+                let elapsed = now.saturating_duration_since(fade.start);
+                let val = target.spec.value_at(fade.from, fade.to, elapsed,
+                                               (delay, duration));
+                frame.set(cid, val);
+                if elapsed < delay + duration {
+                    break;
+                }
 
-        let time_passed: Duration = t.duration_since(self.start_time);
-        let mut progress = time_passed.as_secs_f64() /
-            self.settings.fade_duration.as_secs_f64();
-        if progress > 1.0 {
-            progress = 1.0
+                let end = fade.start + delay + duration;
+                let target = fade.target;
+                fades.pop_front();
+                Self::end_chan(&mut self.targets, target, false);
+                if let Some(next) = fades.front_mut() {
+                    next.from = val;
+                    next.start = end;
+                }
+            }
         }
+        self.chans.retain(|_, fades| !fades.is_empty());
+        frame
+    }
 
-        // eprintln!("Fade set_current_frame target_frame: {:?}",
-        //           self.target_frame);
-
-        for ii in 0..self.target_frame.vals.len() {
-            let target_val = self.target_frame.vals[ii];
-            let target_val = match target_val {
-                Some(val) => val,
-                None => continue,
-            };
-            let from_val = self.from_frame.vals[ii];
-            let from_val = match from_val {
-                Some(val) => val,
-                None => continue,
-            };
+    /// Pauses or resumes, the time spent paused doesn't count
+    fn toggle_pause(&mut self, now: Instant) {
+        match self.paused_at.take() {
+            Some(paused_at) => for fade in self.chans.values_mut()
+                    .filter_map(|fades| fades.front_mut()) {
+                fade.start += now - paused_at;
+            },
+            None => self.paused_at = Some(now),
+        }
+    }
+}
 
-            let from_val = from_val as f64;
-            let target_val = target_val as f64;
+impl<T: HasChanDescriptions + DevRead> Fade<T> {
+    /// Where chan `cid` is at `now`, fading or not
+    fn current_value(&self, cid: u16, now: Instant) -> f32 {
+        let now = self.paused_at.unwrap_or(now);
+        match self.chans.get(&cid).and_then(|fades| fades.front()) {
+            Some(fade) => {
+                let target = &self.targets[&fade.target];
+                target.spec.value_at(
+                    fade.from, fade.to,
+                    now.saturating_duration_since(fade.start),
+                    target.timing.get(cid))
+            },
+            None => self.output.lock().unwrap().get_f32(cid).unwrap_or(0.0),
+        }
+    }
 
-            let current_val =
-                if target_val > from_val {
-                    from_val + (target_val - from_val) * progress
-                } else if target_val < from_val {
-                    from_val - (from_val - target_val) * progress
-                } else {
-                    // ==
-                    target_val
-                };
+    /// Fades chans of `frame` to their values, `mode` says what happens to
+    /// what they fade to already, the receiver gets how it has ended
+    pub fn fade_to(
+        &mut self,
+        frame: &Frame<f32>,
+        spec: FadeSpec,
+        mode: FadeMode,
+    ) -> mpsc::Receiver<FadeEnd> {
+        let now = self.clock.now();
+        let (done, receiver) = mpsc::channel();
+        let id = self.next_target;
+        self.next_target += 1;
+
+        let timing = spec.timing(&self.output.lock().unwrap()
+                                 .chan_descriptions());
+        let mut remaining = 0;
+        for (cid, to) in frame.iter_some() {
+            remaining += 1;
+            let from = self.current_value(cid, now);
+            let fades = self.chans.entry(cid).or_default();
+            if mode == FadeMode::Supersede {
+                for fade in fades.drain(..) {
+                    Self::end_chan(&mut self.targets, fade.target, true);
+                }
+            }
+            fades.push_back(ChanFade { target: id, to: *to, from, start: now });
+        }
 
-            self.current_frame.vals[ii] = Some(current_val as f32);
+        if remaining == 0 {
+            let _ = done.send(FadeEnd::Done);
+        } else {
+            self.targets.insert(id, Target {
+                spec,
+                timing,
+                remaining,
+                superseded: false,
+                done,
+            });
         }
+        receiver
+    }
+}
+
+impl<T> Wrapper for Fade<T> {
+    type Output = T;
 
+    fn output(&self) -> Arc<Mutex<T>> {
+        self.output.clone()
+    }
+}
+
+/// Fades to `frame` with `settings`, superseding what's fading now
+impl<T: HasChanDescriptions + DevRead> DevWrite for Fade<T> {
+    fn set_frame(&mut self, frame: &Frame<f32>) -> Result<(), String> {
+        self.fade_to(frame, self.settings.clone(), FadeMode::Supersede);
         Ok(())
     }
 }
 
-impl<T: DevRead + DevWrite> Runner for Fade<T> {
+impl<T: DevWrite> Runner for Fade<T> {
     fn run(
         self_lock: Arc<Mutex<Fade<T>>>,
         stop: mpsc::Receiver<TaskMsg>,
     ) -> Result<(), String> {
-        let frame_duration = {
-            let self_lock = self_lock.clone();
-            let self_lock = self_lock.lock().unwrap();
-            self_lock.settings.frame_duration
+        let (frame_duration, clock) = {
+            let fade = self_lock.lock().unwrap();
+            (fade.settings.frame_duration, fade.clock.clone())
         };
 
-        // Whether we should set the last value after we reached `target_time`
-        // if it's true then we should not, if it's false then we should, and
-        // then we should set it to true
-        let mut is_paused = false;
         loop {
             {
-                let fade = self_lock.clone();
-                let mut fade = fade.lock().unwrap();
-
-                let t = Instant::now();
-                if fade.is_done(t) {
-                    if !is_paused {
-                        // set the value the final time, to make sure we've
-                        // reached exactly `target_frame`
-                        fade.set_current_frame(t)?;
-                        if let Err(e) = fade.send_current_frame() {
-                            eprintln!("fade send_current_frame err: {e:?}");
-                        }
-                        is_paused = true;
-                    }
-                } else {
-                    is_paused = false;
-                    fade.set_current_frame(t)?;
-                    if let Err(e) = fade.send_current_frame() {
-                        eprintln!("fade send_current_frame err: {e:?}");
+                let mut fade = self_lock.lock().unwrap();
+                let frame = fade.update(clock.now());
+                if frame.iter_some().next().is_some() {
+                    if let Err(e) = fade.output.lock().unwrap()
+                            .set_frame(&frame) {
+                        eprintln!("fade set_frame err: {e:?}");
                     }
                 }
             }
 
             // wait for the time to render next frame or a message from outside
-            match stop.recv_timeout(frame_duration) {
-                Ok(msg) => {
-                    match msg {
-                        TaskMsg::Pause => {
-                            is_paused = true;
-                            let self_lock = self_lock.clone();
-                            let mut self_lock = self_lock.lock().unwrap();
-                            self_lock.target_time = Instant::now();
-                        },
-                        TaskMsg::Stop => {
-                            return Ok(())
-                        },
-                        TaskMsg::Ping => {  }
-                    }
-                },
-                Err(e) => match e {
-                    mpsc::RecvTimeoutError::Timeout => {}
-                    mpsc::RecvTimeoutError::Disconnected => {
-                        return Ok(());
-                    }
-                },
+            match clock.recv_timeout(&stop, frame_duration) {
+                Ok(TaskMsg::Pause) =>
+                    self_lock.lock().unwrap().toggle_pause(clock.now()),
+                Ok(TaskMsg::Stop) => return Ok(()),
+                Ok(TaskMsg::Ping) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::test_dev::TestDev;
+
+    fn all_on() -> Frame<f32> {
+        let mut frame = Frame::new(3);
+        frame.set_all(1.0);
+        frame
+    }
+
+    #[test]
+    fn test_fade_frames() {
+        let dev = Arc::new(Mutex::new(TestDev::recording()));
+
+        // 10 frames a second for a second, stops once past the end
+        let clock = VirtualClock::new().stop_after(Duration::from_secs(1));
+        let mut fade = Fade::new(dev.clone(), FadeSpec {
+            frame_duration: Duration::from_millis(100),
+            fade_duration: Duration::from_secs(1),
+            ..FadeSpec::default()
+        }).with_clock(Arc::new(clock));
+        fade.set_frame(&all_on()).unwrap();
+
+        let (_stop, receiver) = mpsc::channel();
+        Fade::run(Arc::new(Mutex::new(fade)), receiver).unwrap();
+
+        let dev = dev.lock().unwrap();
+        let vals: Vec<f32> = dev.history().iter()
+            .map(|frame| frame.get(0).unwrap())
+            .collect();
+        assert_eq!(11, vals.len(), "{vals:?}");
+        for (ii, val) in vals.iter().enumerate() {
+            assert!((val - ii as f32 / 10.0).abs() < 1e-5, "{vals:?}");
+        }
+    }
+
+    #[test]
+    fn test_staggered_fade() {
+        let dev = Arc::new(Mutex::new(TestDev::recording()));
+        let spec: FadeSpec = serde_yaml::from_str("
+            fade: 1.0
+            timing: [{ chans: all, stagger: 0.5 }]
+        ").unwrap();
+        let clock = VirtualClock::new().stop_after(Duration::from_secs(2));
+        let mut fade = Fade::new(dev.clone(), FadeSpec {
+            frame_duration: Duration::from_millis(500),
+            ..spec
+        }).with_clock(Arc::new(clock));
+        fade.set_frame(&all_on()).unwrap();
+
+        let (_stop, receiver) = mpsc::channel();
+        Fade::run(Arc::new(Mutex::new(fade)), receiver).unwrap();
+
+        let dev = dev.lock().unwrap();
+        let vals: Vec<Vec<Option<f32>>> = dev.history().iter()
+            .map(|frame| (0..3).map(|cid| frame.get(cid)).collect())
+            .collect();
+        // only fading chans are sent
+        assert_eq!(vals, vec![
+            vec![Some(0.0), Some(0.0), Some(0.0)],
+            vec![Some(0.5), Some(0.0), Some(0.0)],
+            vec![Some(1.0), Some(0.5), Some(0.0)],
+            vec![None, Some(1.0), Some(0.5)],
+            vec![None, None, Some(1.0)],
+        ]);
+    }
+
+    #[test]
+    fn test_superseded_and_queued() {
+        let dev = Arc::new(Mutex::new(TestDev::new(false)));
+        let clock = VirtualClock::new();
+        let mut fade = Fade::new(dev, FadeSpec::default())
+            .with_clock(Arc::new(clock.clone()));
+        let chan0 = |val: f32| {
+            let mut frame = Frame::empty();
+            frame.set(0, val);
+            frame
+        };
+        let step = |fade: &mut Fade<TestDev>, millis| {
+            clock.step(Duration::from_millis(millis));
+            fade.update(clock.now()).get(0)
+        };
+
+        let first = fade.fade_to(&chan0(1.0), FadeSpec::default(),
+                                 FadeMode::Supersede);
+        assert_eq!(Some(0.5), step(&mut fade, 500));
+
+        // goes on from where the first one is
+        let second = fade.fade_to(&chan0(0.0), FadeSpec::default(),
+                                  FadeMode::Supersede);
+        assert_eq!(Ok(FadeEnd::Superseded), first.try_recv());
+        let third = fade.fade_to(&chan0(1.0), FadeSpec {
+            fade_duration: Duration::from_millis(500),
+            ..FadeSpec::default()
+        }, FadeMode::Queue);
+        assert_eq!(Some(0.25), step(&mut fade, 500));
+        assert!(second.try_recv().is_err());
+
+        // the queued one starts where the second one ends
+        assert_eq!(Some(0.5), step(&mut fade, 750));
+        assert_eq!(Ok(FadeEnd::Done), second.try_recv());
+        assert_eq!(Some(1.0), step(&mut fade, 250));
+        assert_eq!(Ok(FadeEnd::Done), third.try_recv());
+        assert!(!fade.is_fading());
+        assert_eq!(None, step(&mut fade, 10));
+
+        fade.set_frame(&all_on()).unwrap();
+        let cancelled = fade.fade_to(&chan0(0.0), FadeSpec::default(),
+                                     FadeMode::Queue);
+        fade.cancel();
+        assert_eq!(Ok(FadeEnd::Superseded), cancelled.try_recv());
+        assert!(!fade.is_fading());
+    }
+
+    #[test]
+    fn test_easings() {
+        use crate::easing;
+
+        for easing in [Easing::Linear, Easing::EaseIn, Easing::EaseOut,
+                       Easing::EaseInOut, Easing::Cubic, Easing::Exponential,
+                       Easing::Lightness] {
+            assert!(easing.apply(0.0).abs() < 1e-9, "{easing:?}");
+            assert!((easing.apply(1.0) - 1.0).abs() < 1e-9, "{easing:?}");
+            assert!(easing.apply(0.4) < easing.apply(0.6), "{easing:?}");
+        }
+        for val in [0.0, 0.001, 0.2, 1.0] {
+            let l = easing::lightness(val);
+            assert!((easing::from_lightness(l) - val).abs() < 1e-9);
+        }
+
+        let half = Duration::from_millis(500);
+        let timing = (Duration::ZERO, Duration::from_secs(1));
+        let spec = FadeSpec::default();
+        assert_eq!(spec.value_at(0.0, 1.0, half, timing), 0.5);
+        let spec = FadeSpec { perceptual: true, ..spec };
+        // half as light looks like way less than half the value
+        let val = spec.value_at(0.0, 1.0, half, timing);
+        assert!((val - 0.184).abs() < 0.001, "{val}");
+        assert_eq!(spec.value_at(0.3, 0.7, timing.1, timing), 0.7);
+    }
+}
@@ -1,8 +1,13 @@
-use crate::msg_handler::MsgHandler;
-use proto::v1::{ChanVal, Msg, Val};
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+use crate::dev::DevWrite;
+use crate::effect::{self, Effect, EffectInfo, ParamSpec};
+use crate::frame::Frame;
+use crate::runner::Runner;
+use crate::random;
+use crate::task::TaskMsg;
 use rand::{self, Rng};
+use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
-use std::thread::sleep;
 use std::time;
 
 #[derive(Clone, std::fmt::Debug,
@@ -16,64 +21,81 @@ struct DemoChan {
     phi: f64,
 }
 
-pub fn run<D: MsgHandler + ?Sized>(srv: Arc<Mutex<D>>) -> Result<(), String> {
-    println!("running glitch demo...");
-
-    let mut msg: Msg = {
-        let srv = srv.clone();
-        let srv = srv.lock().map_err(|e| format!("{:?}", e))?;
+pub struct GlitchEffect {
+    freq_min: f64,
+    freq_max: f64,
+    rng: random::Rng,
+    dchans: Vec<DemoChan>,
+}
 
-        Msg {
-            seq_num: 0,
-            timestamp: time::SystemTime::now(),
-            vals: srv
-                .chans()
-                .into_iter()
-                .map(|(id, _)| (ChanVal(id, Val::F32(0.0))))
-                .collect(),
+impl GlitchEffect {
+    pub fn new(freq_min: f64, freq_max: f64) -> Self {
+        GlitchEffect {
+            freq_min,
+            freq_max,
+            rng: random::rng(None),
+            dchans: vec![],
         }
-    };
-
-    let mut dchans: Vec<DemoChan> = Vec::with_capacity(msg.vals.len());
-
-    let freq_dist = rand::distributions::Uniform::new(0.1, 0.3);
-    let mut rng = rand::thread_rng();
-    for _ in 0..msg.vals.len() {
-        dchans.push(DemoChan {
-            freq: rng.sample(freq_dist),
-            min: 0.1,
-            max: 1.0,
-            phi: 0.0,
-        });
     }
+}
 
-    let delay = time::Duration::from_millis(20);
-    let mut t = time::Instant::now();
+impl Effect for GlitchEffect {
+    fn render(&mut self, t: time::Duration, chans: &[ChanDescription])
+            -> Frame<f32> {
+        let freq_dist = rand::distributions::Uniform::new(
+            self.freq_min, self.freq_max);
+        while self.dchans.len() < chans.len() {
+            self.dchans.push(DemoChan {
+                freq: self.rng.sample(freq_dist),
+                min: 0.1,
+                max: 1.0,
+                phi: 0.0,
+            });
+        }
 
-    loop {
-        let dt = t.elapsed().as_secs_f64();
-        t = time::Instant::now();
-        for (i, d) in dchans.iter_mut().enumerate() {
+        let mut frame = Frame::empty();
+        for (chan, d) in chans.iter().zip(self.dchans.iter()) {
             let amp = d.max - d.min;
-            let delta = dt * d.freq * std::f64::consts::PI * 2.0;
-            let new_sin = (d.phi.sin() * (amp / 2.0) + amp / 2.0).powf(2.2) * 2.0 + d.min;
-            d.phi += delta;
-            msg.vals[i].1 = Val::F32(new_sin as f32);
+            let phi = d.phi
+                + t.as_secs_f64() * d.freq * std::f64::consts::PI * 2.0;
+            // overshoots on purpose, that's the glitchy part
+            let new_sin = (phi.sin() * (amp / 2.0) + amp / 2.0).powf(2.2)
+                * 2.0 + d.min;
+            frame.set(chan.chan_id, new_sin.min(1.0) as f32);
         }
+        frame
+    }
 
-        // for ChanVal(_, v) in msg.vals.iter() {
-        //     let v = match v {
-        //         Val::F32(v) => v,
-        //         _ => unreachable!(),
-        //     };
-        //     print!("{:02.8} ", v);
-        // }
-        // println!("");
+    fn reseed(&mut self, seed: u64) {
+        self.rng = random::rng(Some(seed));
+        self.dchans.clear();
+    }
+}
 
-        {
-            let mut srv = srv.lock().map_err(|e| format!("{:?}", e))?;
-            srv.handle_msg(&msg).expect("demo: handle_msg error");
+pub const EFFECT: EffectInfo = EffectInfo {
+    name: "glitch",
+    description: "Glitchy sines that clip at the top",
+    params: || vec![
+        ParamSpec::float("freq_min", "lowest frequency, Hz", 0.1, 0.0, 10.0),
+        ParamSpec::float("freq_max", "highest frequency, Hz", 0.3, 0.0, 10.0),
+    ],
+    create: |params| {
+        let (freq_min, freq_max) =
+            (params.f32("freq_min") as f64, params.f32("freq_max") as f64);
+        if freq_min >= freq_max {
+            return Err("freq_min should be less than freq_max".to_string());
         }
-        sleep(delay);
-    }
+        Ok(Box::new(GlitchEffect::new(freq_min, freq_max)))
+    },
+};
+
+pub fn run<D: DevWrite + HasChanDescriptions + ?Sized>(srv: Arc<Mutex<D>>)
+        -> Result<(), String> {
+    println!("running glitch demo...");
+
+    let (_sender, receiver) = mpsc::channel::<TaskMsg>();
+    let mut runner = effect::EffectRunner::new(
+        srv, Box::new(GlitchEffect::new(0.1, 0.3)));
+    runner.frame_duration = time::Duration::from_millis(20);
+    Runner::run(Arc::new(Mutex::new(runner)), receiver)
 }
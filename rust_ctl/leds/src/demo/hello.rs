@@ -1,6 +1,10 @@
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+use crate::effect::{self, Effect, EffectInfo, ParamSpec, Params};
 use crate::frame::Frame;
 use crate::dev::{DevWrite};
+use crate::random;
 use crate::task::TaskMsg;
+use crate::tempo::{self, SharedTempo};
 use rand::{self, Rng};
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
@@ -16,19 +20,82 @@ const DEFAULT_FREQ_MAX: f64 = 0.1;
 const DEFAULT_MIN: f64 = 0.40;
 const DEFAULT_MAX: f64 = 1.0;
 
+const HARDER_FREQ_MIN: f64 = 0.05;
+const HARDER_FREQ_MAX: f64 = 0.6;
 
-// should derive serialize and deserialize
+const HARDER_MIN: f64 = 0.0;
+const HARDER_MAX: f64 = 1.0;
+
+/// Per chan disco settings, i.e. `disco_config: { freq_max: 0.05 }` in the
+/// chan config, missing fields are defaults
 #[derive(Debug, Clone, PartialEq,
          serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(default)]
 pub struct DiscoChanConfig {
-    freq_min: f64,
-    freq_max: f64,
-    min: f64,
-    max: f64,
+    pub freq_min: f64,
+    pub freq_max: f64,
+    pub min: f64,
+    pub max: f64,
     /// raise the value to this power
-    adjustment: f64,
+    pub adjustment: f64,
 }
 
+impl DiscoChanConfig {
+    /// Disco harder: faster and all the way down to dark
+    pub fn harder() -> Self {
+        DiscoChanConfig {
+            freq_min: HARDER_FREQ_MIN,
+            freq_max: HARDER_FREQ_MAX,
+            min: HARDER_MIN,
+            max: HARDER_MAX,
+            adjustment: 1.0,
+        }
+    }
+
+    /// Overrides fields from `key=value` args, i.e. `freq_max=0.5`
+    pub fn tune<S: AsRef<str>>(mut self, args: &[S]) -> Result<Self, String> {
+        let schema = params(&self);
+        for arg in args {
+            let arg = arg.as_ref();
+            let (key, val) = arg.split_once('=').ok_or_else(
+                || format!("expected key=value, got '{}'", arg))?;
+            let mut params = Params::defaults(&schema);
+            params.set(&schema, key.trim(), val)?;
+            let val = params.f32(key.trim()) as f64;
+            match key.trim() {
+                "freq_min" => self.freq_min = val,
+                "freq_max" => self.freq_max = val,
+                "min" => self.min = val,
+                "max" => self.max = val,
+                "adjustment" => self.adjustment = val,
+                other => return Err(format!("unknown disco param '{}'", other)),
+            }
+        }
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.freq_min > self.freq_max {
+            return Err("freq_min should not be more than freq_max"
+                       .to_string());
+        }
+        if self.min > self.max {
+            return Err("min should not be more than max".to_string());
+        }
+        Ok(())
+    }
+
+    fn from_params(params: &Params) -> Self {
+        DiscoChanConfig {
+            freq_min: params.f32("freq_min") as f64,
+            freq_max: params.f32("freq_max") as f64,
+            min: params.f32("min") as f64,
+            max: params.f32("max") as f64,
+            adjustment: params.f32("adjustment") as f64,
+        }
+    }
+}
 
 
 impl Default for DiscoChanConfig {
@@ -53,12 +120,12 @@ struct SineVal {
 }
 
 impl SineVal {
-    fn sine(&mut self, dt_secs: f64) -> f64 {
+    /// `cycles` since the start, `t_secs * freq` unless it's snapped
+    fn at(&self, cycles: f64) -> f64 {
         let amp = self.max - self.min;
-        let new_sin = ((((self.phi.sin() + 1.0) / 2.0) * amp) + self.min)
-            .powf(self.adjustment);
-        self.phi += dt_secs * self.freq * std::f64::consts::PI * 2.0;
-        new_sin
+        let phi = self.phi + cycles * std::f64::consts::PI * 2.0;
+        ((((phi.sin() + 1.0) / 2.0) * amp) + self.min)
+            .powf(self.adjustment)
     }
 }
 
@@ -73,45 +140,10 @@ struct DemoChan {
 }
 
 impl DemoChan {
-    fn sine(&mut self, dt_secs: f64) -> f64 {
-        let mut result = 0.0f64;
-
-        for s in self.sines.iter_mut() {
-            let sinval = s.sine(dt_secs).min(1.0).max(0.0);
-            match s.blend_mode {
-                BlendMode::Add => result += sinval,
-                BlendMode::Mul => result *= sinval,
-            }
-        }
-
-        result.min(1.0).max(0.0)
-    }
-}
-
-pub fn run<T: DevWrite + ?Sized>(dev: Arc<Mutex<T>>) -> Result<(), String> {
-    let (_sender, receiver) = mpsc::channel::<TaskMsg>();
-
-    // runs indefinitely
-    run_with_channel(dev, receiver)
-}
-
-pub fn run_with_config<T: DevWrite + ?Sized>(
-    dev: Arc<Mutex<T>>,
-    configs: Vec<DiscoChanConfig>,
-    stop: mpsc::Receiver<TaskMsg>,
-) -> Result<(), String> {
-    println!("running demo disco...");
-
-    let num_chans = configs.len();
-    let mut frame = Frame::new(num_chans as u16);
-
-    let mut dchans: Vec<DemoChan> = Vec::with_capacity(num_chans as usize);
-
-    let mut rng = rand::thread_rng();
-    // let secondary_freq_dist = rand::distributions::Uniform::new(0.5, 2.0);
-    for chan_conf in configs.iter() {
-        let freq_dist = rand::distributions::Uniform::new(
-            chan_conf.freq_min, chan_conf.freq_max);
+    fn new<R: Rng>(chan_conf: &DiscoChanConfig, rng: &mut R) -> Self {
+        // inclusive, so configs with freq_min == freq_max work
+        let freq_dist = rand::distributions::Uniform::new_inclusive(
+            chan_conf.freq_min, chan_conf.freq_max.max(chan_conf.freq_min));
 
         let main_sine = SineVal {
             freq: rng.sample(freq_dist),
@@ -140,72 +172,274 @@ pub fn run_with_config<T: DevWrite + ?Sized>(
             blend_mode: BlendMode::Mul,
         };
 
-        dchans.push(DemoChan {
+        DemoChan {
             sines: vec![main_sine, secondary_sine, third_sine],
-        });
+        }
     }
 
-    let delay = time::Duration::from_micros(10_000);
-    let mut t = time::Instant::now();
+    fn sine<F: Fn(&SineVal) -> f64>(&self, cycles: F) -> f64 {
+        let mut result = 0.0f64;
+
+        for s in self.sines.iter() {
+            let sinval = s.at(cycles(s)).min(1.0).max(0.0);
+            match s.blend_mode {
+                BlendMode::Add => result += sinval,
+                BlendMode::Mul => result *= sinval,
+            }
+        }
 
-    loop {
-        let dt = t.elapsed().as_secs_f64();
-        t = time::Instant::now();
+        result.min(1.0).max(0.0)
+    }
+}
 
-        // println!("dchans len = {}", dchans.len());
+/// Whole bars closest to the period of `freq`, a power of 2 so sines line
+/// up with each other every few bars
+fn bar_periods(freq: f64, bar_secs: f64) -> f64 {
+    let bars = 1.0 / (freq * bar_secs);
+    2f64.powf(bars.log2().round().max(0.0))
+}
 
-        for (i, d) in dchans.iter_mut().enumerate() {
-            let new_sin = d.sine(dt);
-            // TODO Probably should be applied earlier, so we stay within
-            // min-max limits
+/// Sines all chans at random-ish frequencies
+pub struct HelloEffect {
+    default_config: DiscoChanConfig,
+    /// Chans with `disco_config` in their chan config use that instead of
+    /// `default_config`
+    per_chan: bool,
+    rng: random::Rng,
+    dchans: Vec<DemoChan>,
+    /// Periods snap to bars of this tempo
+    bars: Option<SharedTempo>,
+}
 
-            // println!("set {i} to {new_sin}");
-            frame.set(i as u16, new_sin as f32);
+impl HelloEffect {
+    /// `default_config` for chans without their own `disco_config`
+    pub fn new(default_config: DiscoChanConfig) -> Self {
+        HelloEffect {
+            default_config,
+            per_chan: true,
+            rng: random::rng(None),
+            dchans: vec![],
+            bars: None,
         }
+    }
 
-        {
-            let mut dev =
-                dev.lock().map_err(|e| format!("write lock: {:?}", e))?;
-            // println!("DISCO set frame {:?}", frame);
-            if let Err(e) = dev.set_frame(&frame) {
-                eprintln!("disco err: {e:?}");
-            }
+    /// Sines start on a bar and last a power of 2 bars of `tempo`
+    pub fn on_bars(mut self, tempo: SharedTempo) -> Self {
+        self.bars = Some(tempo);
+        self
+    }
+
+    /// `config` for every chan, ignoring their `disco_config`
+    pub fn uniform(config: DiscoChanConfig) -> Self {
+        HelloEffect { per_chan: false, ..HelloEffect::new(config) }
+    }
+}
+
+impl Effect for HelloEffect {
+    fn render(&mut self, t: time::Duration, chans: &[ChanDescription])
+            -> Frame<f32> {
+        // chans get their frequencies when we first see them
+        while self.dchans.len() < chans.len() {
+            let conf = match &chans[self.dchans.len()].config.disco_config {
+                Some(conf) if self.per_chan => conf,
+                _ => &self.default_config,
+            };
+            self.dchans.push(DemoChan::new(conf, &mut self.rng));
         }
 
-        match stop.recv_timeout(delay) {
-            Ok(msg) => {
-                println!("received task msg: {:?}", msg);
-                match msg {
-                    TaskMsg::Pause => {
-                        todo!()
-                    },
-                    TaskMsg::Stop => return Ok(()),
-                    TaskMsg::Ping => {}
-                }
-            }
-            Err(e) => match e {
-                mpsc::RecvTimeoutError::Timeout => {}
-                mpsc::RecvTimeoutError::Disconnected => {
-                    return Ok(());
-                }
-            },
+        let bars = self.bars.as_ref().map(|tempo| {
+            let tempo = tempo.lock().unwrap();
+            (tempo.bars(), tempo.bar().as_secs_f64())
+        });
+
+        let mut frame = Frame::empty();
+        for (chan, d) in chans.iter().zip(self.dchans.iter()) {
+            let val = match bars {
+                Some((bars, bar_secs)) =>
+                    d.sine(|s| bars / bar_periods(s.freq, bar_secs)),
+                None => d.sine(|s| t.as_secs_f64() * s.freq),
+            };
+            // TODO Probably should be applied earlier, so we stay within
+            // min-max limits
+            frame.set(chan.chan_id, val as f32);
         }
+        frame
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.rng = random::rng(Some(seed));
+        self.dchans.clear();
+    }
+}
+
+fn params(defaults: &DiscoChanConfig) -> Vec<ParamSpec> {
+    vec![
+        ParamSpec::float("freq_min", "lowest frequency, Hz",
+                         defaults.freq_min as f32, 0.0, 10.0),
+        ParamSpec::float("freq_max", "highest frequency, Hz",
+                         defaults.freq_max as f32, 0.0, 10.0),
+        ParamSpec::float("min", "lowest value", defaults.min as f32, 0.0, 1.0),
+        ParamSpec::float("max", "highest value", defaults.max as f32, 0.0, 1.0),
+        ParamSpec::float("adjustment", "raise values to this power",
+                         defaults.adjustment as f32, 0.1, 10.0),
+    ]
+}
+
+fn bars_param() -> ParamSpec {
+    ParamSpec::bool("bars", "snap periods to bars of the tempo", false)
+}
+
+fn create(params: &Params) -> Result<Box<dyn Effect>, String> {
+    let config = DiscoChanConfig::from_params(params);
+    config.validate()?;
+    let effect = match params.bool("per_chan") {
+        true => HelloEffect::new(config),
+        false => HelloEffect::uniform(config),
+    };
+    match params.bool("bars") {
+        true => Ok(Box::new(effect.on_bars(tempo::shared()))),
+        false => Ok(Box::new(effect)),
     }
 }
 
-pub fn run_with_channel<T: DevWrite + ?Sized>(
+pub const EFFECT: EffectInfo = EffectInfo {
+    name: "hello",
+    description: "AKA Disco, sines all chans at random-ish frequencies",
+    params: || {
+        let mut params = params(&DiscoChanConfig::default());
+        params.push(ParamSpec::bool(
+                "per_chan", "chans' own disco_config wins over these", true));
+        params.push(bars_param());
+        params
+    },
+    create,
+};
+
+pub const HARDER_EFFECT: EffectInfo = EffectInfo {
+    name: "disco_harder",
+    description: "Disco, but faster and all the way down to dark",
+    params: || {
+        let mut params = params(&DiscoChanConfig::harder());
+        params.push(ParamSpec::bool(
+                "per_chan", "chans' own disco_config wins over these", false));
+        params.push(bars_param());
+        params
+    },
+    create,
+};
+
+pub fn run<T: DevWrite + HasChanDescriptions + ?Sized>(dev: Arc<Mutex<T>>)
+        -> Result<(), String> {
+    let (_sender, receiver) = mpsc::channel::<TaskMsg>();
+
+    // runs indefinitely
+    run_with_channel(dev, receiver)
+}
+
+/// Chans with their own `disco_config` use it, the rest use `config`
+pub fn run_with_config<T: DevWrite + HasChanDescriptions + ?Sized>(
+    dev: Arc<Mutex<T>>,
+    config: DiscoChanConfig,
+    stop: mpsc::Receiver<TaskMsg>,
+) -> Result<(), String> {
+    println!("running demo disco...");
+
+    effect::run(dev, Box::new(HelloEffect::new(config)), stop)
+}
+
+/// Disco harder, `config` for every chan
+pub fn run_harder<T: DevWrite + HasChanDescriptions + ?Sized>(
+    dev: Arc<Mutex<T>>,
+    config: DiscoChanConfig,
+    stop: mpsc::Receiver<TaskMsg>,
+) -> Result<(), String> {
+    println!("running demo disco harder...");
+
+    effect::run(dev, Box::new(HelloEffect::uniform(config)), stop)
+}
+
+pub fn run_with_channel<T: DevWrite + HasChanDescriptions + ?Sized>(
     dev: Arc<Mutex<T>>,
     stop: mpsc::Receiver<TaskMsg>,
 ) -> Result<(), String> {
     println!("running hello demo...");
 
-    let num_chans = {
-        let dev = dev.lock().map_err(|e| format!("read lock: {:?}", e))?;
-        dev.num_chans()
-    };
+    run_with_config(dev, DiscoChanConfig::default(), stop)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use crate::test_dev::chan;
+
+    fn chans(disco_config: Option<DiscoChanConfig>) -> Vec<ChanDescription> {
+        let mut quiet = chan(1, &[], None);
+        quiet.config.disco_config = disco_config;
+        vec![chan(0, &[], None), quiet]
+    }
 
-    let configs: Vec<DiscoChanConfig> =
-        vec![DiscoChanConfig::default(); num_chans as usize];
+    #[test]
+    fn test_per_chan_config() {
+        let dark = DiscoChanConfig { min: 0.0, max: 0.0,
+                                     ..DiscoChanConfig::default() };
+        let chans = chans(Some(dark));
+
+        let mut effect = HelloEffect::new(DiscoChanConfig::default());
+        let mut uniform = HelloEffect::uniform(DiscoChanConfig::default());
+        for secs in 0..20 {
+            let t = time::Duration::from_secs(secs);
+            let frame = effect.render(t, &chans);
+            assert!(frame.get(0).unwrap() > 0.2);
+            assert_eq!(Some(0.0), frame.get(1));
+
+            let frame = uniform.render(t, &chans);
+            assert!(frame.get(1).unwrap() > 0.2);
+        }
+    }
 
-    run_with_config(dev, configs, stop)
+    #[test]
+    fn test_on_bars() {
+        use crate::clock::VirtualClock;
+        use crate::tempo::Tempo;
+
+        let clock = VirtualClock::new();
+        let tempo = Tempo::new(120.0).with_clock(Arc::new(clock.clone()));
+        let mut effect = HelloEffect::new(DiscoChanConfig::default())
+            .on_bars(Arc::new(Mutex::new(tempo)));
+        effect.reseed(42);
+        let chans = chans(None);
+        let t = time::Duration::ZERO;
+        let first = effect.render(t, &chans);
+
+        // everything repeats after the longest snapped period, bars of 2s
+        let bars = effect.dchans.iter().flat_map(|d| d.sines.iter())
+            .map(|s| bar_periods(s.freq, 2.0))
+            .fold(1.0, f64::max);
+        let period = time::Duration::from_secs_f64(bars * 2.0);
+        clock.step(period / 4);
+        assert_ne!(first, effect.render(t, &chans));
+        clock.step(period * 3 / 4);
+        let again = effect.render(t, &chans);
+        for (cid, val) in first.iter_some() {
+            assert!((val - again.get(cid).unwrap()).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_tune() {
+        let tuned = DiscoChanConfig::harder()
+            .tune(&["freq_max=2", "min=0.1"]).unwrap();
+        assert_eq!(2.0, tuned.freq_max);
+        assert_eq!(HARDER_FREQ_MIN, tuned.freq_min);
+        assert!((tuned.min - 0.1).abs() < 1e-6);
+        assert_eq!(1.0, tuned.adjustment);
+        assert_eq!(2.0, DiscoChanConfig::default().tune(&["adjustment=2"])
+                   .unwrap().adjustment);
+
+        assert!(DiscoChanConfig::default().tune(&["min=0.9", "max=0.5"])
+                .is_err());
+        assert!(DiscoChanConfig::default().tune(&["speed=2"]).is_err());
+        assert!(DiscoChanConfig::default().tune(&["freq_max"]).is_err());
+    }
 }
@@ -1,9 +1,12 @@
-#![allow(unused)]
-
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+use crate::effect::{self, Effect, EffectInfo, ParamSpec};
 use crate::frame::Frame;
 use crate::dev::{DevWrite};
 use crate::task::TaskMsg;
+use crate::random;
 use rand::{self, Rng};
+use std::fs;
+use std::path::Path;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::time;
@@ -18,173 +21,341 @@ const DEFAULT_FREQ_MAX: f64 = 0.1;
 const DEFAULT_MIN: f64 = 0.40;
 const DEFAULT_MAX: f64 = 1.0;
 
+/// How an oscillator combines with the ones before it in the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default,
+         serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OscBlend {
+    #[default]
+    Add,
+    Mul,
+}
+
+/// A sine with its frequency picked between `freq_min` and `freq_max`
 #[derive(Debug, Clone, PartialEq,
          serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(default)]
 pub struct OscConfig {
-    freq_min: f64,
-    freq_max: f64,
-    min: f64,
-    max: f64,
+    pub freq_min: f64,
+    pub freq_max: f64,
+    pub min: f64,
+    pub max: f64,
     /// raise the value to this power
-    adjustment: f64,
+    pub adjustment: f64,
+    pub blend: OscBlend,
 }
 
+impl Default for OscConfig {
+    fn default() -> Self {
+        OscConfig {
+            freq_min: DEFAULT_FREQ_MIN,
+            freq_max: DEFAULT_FREQ_MAX,
+            min: DEFAULT_MIN,
+            max: DEFAULT_MAX,
+            adjustment: 1.0,
+            blend: OscBlend::Add,
+        }
+    }
+}
+
+impl OscConfig {
+    /// Slowly swings the stack between 70% and 110%
+    fn swell(slowdown: f64) -> Self {
+        OscConfig {
+            freq_min: DEFAULT_FREQ_MIN / slowdown,
+            freq_max: DEFAULT_FREQ_MAX / slowdown,
+            min: 0.7,
+            max: 1.1,
+            adjustment: 1.0,
+            blend: OscBlend::Mul,
+        }
+    }
+}
+
+/// Oscillator stack for chans with any of `tags`
 #[derive(Debug, Clone, PartialEq, Default,
          serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(default)]
+pub struct SchroomChanConfig {
+    pub tags: Vec<String>,
+    pub oscs: Vec<OscConfig>,
+}
+
+/// ```yaml
+/// seed: 42
+/// oscs:            # every chan not in `chans`
+///   - { freq_min: 0.01, freq_max: 0.1 }
+///   - { min: 0.7, max: 1.1, blend: mul }
+/// chans:           # first one with a matching tag wins
+///   - tags: [window]
+///     oscs: [ { freq_max: 0.02, max: 0.6 } ]
+/// ```
+#[derive(Debug, Clone, PartialEq,
+         serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(default)]
 pub struct SchroomConfig {
-    oscs: Vec<OscConfig>,
+    pub oscs: Vec<OscConfig>,
+    pub chans: Vec<SchroomChanConfig>,
+    /// Same seed, same frequencies and phases, random without it
+    pub seed: Option<u64>,
+}
+
+impl Default for SchroomConfig {
+    fn default() -> Self {
+        SchroomConfig {
+            oscs: vec![
+                OscConfig::default(),
+                OscConfig::swell(3.1),
+                OscConfig::swell(7.2),
+            ],
+            chans: vec![],
+            seed: None,
+        }
+    }
 }
 
-struct SineVal {
+impl SchroomConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let buf = fs::read_to_string(&path)
+            .map_err(|e| format!("reading {:?}: {:?}", path.as_ref(), e))?;
+        let config: SchroomConfig = serde_yaml::from_str(&buf)
+            .map_err(|e| format!("parsing {:?}: {:?}", path.as_ref(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let stacks = std::iter::once(&self.oscs)
+            .chain(self.chans.iter().map(|chan| &chan.oscs));
+        for osc in stacks.flatten() {
+            let values = [osc.freq_min, osc.freq_max, osc.min, osc.max,
+                          osc.adjustment];
+            if !values.iter().all(|v| v.is_finite()) {
+                return Err("oscillator values should be finite".to_string());
+            }
+            if osc.freq_min > osc.freq_max {
+                return Err("freq_min should not be more than freq_max"
+                           .to_string());
+            }
+            if osc.freq_min < 0.0 {
+                return Err("frequencies should not be negative".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn stack_for(&self, chan: &ChanDescription) -> &[OscConfig] {
+        self.chans.iter()
+            .find(|conf| chan.config.tags.iter()
+                  .any(|tag| conf.tags.iter().any(|t| t == tag.name())))
+            .map(|conf| conf.oscs.as_slice())
+            .unwrap_or(&self.oscs)
+    }
+}
+
+struct Osc {
     freq: f64,
     min: f64,
     max: f64,
     adjustment: f64,
     phi: f64,
-    blend_mode: BlendMode,
+    blend: OscBlend,
 }
 
-impl SineVal {
-    fn sine(&mut self, dt_secs: f64) -> f64 {
+impl Osc {
+    fn new<R: Rng>(conf: &OscConfig, rng: &mut R) -> Self {
+        // inclusive, so configs with freq_min == freq_max work
+        let freq_dist = rand::distributions::Uniform::new_inclusive(
+            conf.freq_min, conf.freq_max.max(conf.freq_min));
+        Osc {
+            freq: rng.sample(freq_dist),
+            min: conf.min,
+            max: conf.max,
+            adjustment: conf.adjustment,
+            phi: rng.gen_range(0.0..std::f64::consts::TAU),
+            blend: conf.blend,
+        }
+    }
+
+    fn value(&self, t_secs: f64) -> f64 {
         let amp = self.max - self.min;
-        let new_sin = ((((self.phi.sin() + 1.0) / 2.0) * amp) + self.min)
-            .powf(self.adjustment);
-        self.phi += dt_secs * self.freq * std::f64::consts::PI * 2.0;
-        new_sin
+        let phi = self.phi + t_secs * self.freq * std::f64::consts::TAU;
+        ((((phi.sin() + 1.0) / 2.0) * amp) + self.min)
+            .powf(self.adjustment)
     }
 }
 
-enum BlendMode {
-    Add,
-    Mul,
+/// Oscillators blended in order onto 0
+struct OscStack {
+    oscs: Vec<Osc>,
 }
 
+impl OscStack {
+    fn value(&self, t_secs: f64) -> f64 {
+        let mut result = 0.0f64;
+
+        for osc in self.oscs.iter() {
+            let val = osc.value(t_secs).clamp(0.0, 1.0);
+            match osc.blend {
+                OscBlend::Add => result += val,
+                OscBlend::Mul => result *= val,
+            }
+        }
 
-struct DemoChan {
-    sines: Vec<SineVal>,
+        result.clamp(0.0, 1.0)
+    }
 }
 
-impl DemoChan {
-    fn sine(&mut self, dt_secs: f64) -> f64 {
-        let mut result = 0.0f64;
+/// Stacks of oscillators per chan, like disco but configurable
+pub struct SchroomEffect {
+    config: SchroomConfig,
+    rng: random::Rng,
+    stacks: Vec<OscStack>,
+}
 
-        for s in self.sines.iter_mut() {
-            let sinval = s.sine(dt_secs).min(1.0).max(0.0);
-            match s.blend_mode {
-                BlendMode::Add => result += sinval,
-                BlendMode::Mul => result *= sinval,
-            }
+impl SchroomEffect {
+    pub fn new(config: SchroomConfig) -> Self {
+        let rng = random::rng(config.seed);
+        SchroomEffect { config, rng, stacks: vec![] }
+    }
+}
+
+impl Effect for SchroomEffect {
+    fn render(&mut self, t: time::Duration, chans: &[ChanDescription])
+            -> Frame<f32> {
+        // chans get their oscillators when we first see them
+        while self.stacks.len() < chans.len() {
+            let chan = &chans[self.stacks.len()];
+            let oscs = self.config.stack_for(chan).iter()
+                .map(|conf| Osc::new(conf, &mut self.rng))
+                .collect();
+            self.stacks.push(OscStack { oscs });
         }
 
-        result.min(1.0).max(0.0)
+        let mut frame = Frame::empty();
+        for (chan, stack) in chans.iter().zip(self.stacks.iter()) {
+            frame.set(chan.chan_id, stack.value(t.as_secs_f64()) as f32);
+        }
+        frame
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.rng = random::rng(Some(seed));
+        self.stacks.clear();
     }
 }
 
-pub fn run<T: DevWrite + ?Sized>(dev: Arc<Mutex<T>>) -> Result<(), String> {
+pub const EFFECT: EffectInfo = EffectInfo {
+    name: "schroom",
+    description: "Stacks of oscillators per chan, configured in YAML",
+    params: || vec![
+        ParamSpec::text("file", "YAML oscillator config, built-in stack \
+                        when empty", ""),
+        ParamSpec::int("seed", "random seed, 0 for a different show \
+                       every time", 0, 0, i64::MAX),
+    ],
+    create: |params| {
+        let mut config = match params.str("file") {
+            "" => SchroomConfig::default(),
+            path => SchroomConfig::load(path)?,
+        };
+        if params.i64("seed") != 0 {
+            config.seed = Some(params.i64("seed") as u64);
+        }
+        Ok(Box::new(SchroomEffect::new(config)))
+    },
+};
+
+pub fn run<T: DevWrite + HasChanDescriptions + ?Sized>(dev: Arc<Mutex<T>>)
+        -> Result<(), String> {
     let (_sender, receiver) = mpsc::channel::<TaskMsg>();
 
     // runs indefinitely
     run_with_channel(dev, receiver)
 }
 
-pub fn run_with_config<T: DevWrite + ?Sized>(
+pub fn run_with_config<T: DevWrite + HasChanDescriptions + ?Sized>(
     dev: Arc<Mutex<T>>,
     config: SchroomConfig,
     stop: mpsc::Receiver<TaskMsg>,
 ) -> Result<(), String> {
-    println!("running hello demo...");
-
-    todo!()
-    // let num_chans = configs.len();
-    // let mut frame = Frame::new(num_chans as u16);
-
-    // let mut dchans: Vec<DemoChan> = Vec::with_capacity(num_chans as usize);
-
-    // let mut rng = rand::thread_rng();
-    // // let secondary_freq_dist = rand::distributions::Uniform::new(0.5, 2.0);
-    // for chan_conf in configs.iter() {
-    //     let freq_dist = rand::distributions::Uniform::new(
-    //         chan_conf.freq_min, chan_conf.freq_max);
-
-    //     let main_sine = SineVal {
-    //         freq: rng.sample(freq_dist),
-    //         min: chan_conf.min,
-    //         max: chan_conf.max,
-    //         adjustment: chan_conf.adjustment,
-    //         phi: 0.0,
-    //         blend_mode: BlendMode::Add,
-    //     };
-
-    //     let secondary_sine = SineVal {
-    //         freq: rng.sample(freq_dist) / 3.1,
-    //         min: 0.7,
-    //         max: 1.1,
-    //         adjustment: 1.0,
-    //         phi: 0.0,
-    //         blend_mode: BlendMode::Mul,
-    //     };
-
-    //     let third_sine = SineVal {
-    //         freq: rng.sample(freq_dist) / 7.2,
-    //         min: 0.7,
-    //         max: 1.1,
-    //         adjustment: 1.0,
-    //         phi: 0.0,
-    //         blend_mode: BlendMode::Mul,
-    //     };
-
-    //     dchans.push(DemoChan {
-    //         sines: vec![main_sine, secondary_sine, third_sine],
-    //     });
-    // }
-
-    // let delay = time::Duration::from_micros(10_000);
-    // let mut t = time::Instant::now();
-
-    // loop {
-    //     let dt = t.elapsed().as_secs_f64();
-    //     t = time::Instant::now();
-    //     for (i, d) in dchans.iter_mut().enumerate() {
-    //         let new_sin = d.sine(dt);
-    //         // TODO Probably should be applied earlier, so we stay within
-    //         // min-max limits
-    //         frame.set(i as u16, new_sin as f32);
-    //     }
-
-    //     {
-    //         let mut dev = dev.lock().map_err(|e| format!("write lock: {:?}", e))?;
-    //         dev.set_frame(&frame)?;
-    //         dev.sync()?;
-    //     }
-
-    //     match stop.recv_timeout(delay) {
-    //         Ok(msg) => {
-    //             println!("received task msg: {:?}", msg);
-    //             match msg {
-    //                 TaskMsg::Pause => {
-    //                     todo!()
-    //                 },
-    //                 TaskMsg::Stop => return Ok(()),
-    //                 TaskMsg::Ping => {}
-    //             }
-    //         }
-    //         Err(e) => match e {
-    //             mpsc::RecvTimeoutError::Timeout => {}
-    //             mpsc::RecvTimeoutError::Disconnected => {
-    //                 return Ok(());
-    //             }
-    //         },
-    //     }
-    // }
-}
-
-pub fn run_with_channel<T: DevWrite + ?Sized>(
+    println!("running schroom demo...");
+
+    config.validate()?;
+    effect::run(dev, Box::new(SchroomEffect::new(config)), stop)
+}
+
+pub fn run_with_channel<T: DevWrite + HasChanDescriptions + ?Sized>(
     dev: Arc<Mutex<T>>,
     stop: mpsc::Receiver<TaskMsg>,
 ) -> Result<(), String> {
-    println!("running hello schroom...");
+    run_with_config(dev, SchroomConfig::default(), stop)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use crate::test_dev::chan;
+
+    fn chans() -> Vec<ChanDescription> {
+        vec![chan(0, &[], None), chan(1, &["window"], None)]
+    }
+
+    fn render(effect: &mut SchroomEffect) -> Vec<Frame<f32>> {
+        (0..50).map(|i| time::Duration::from_millis(i * 700))
+            .map(|t| effect.render(t, &chans()))
+            .collect()
+    }
 
-    let config = SchroomConfig::default();
+    #[test]
+    fn test_seeded() {
+        let seeded = |seed| SchroomConfig {
+            seed: Some(seed),
+            ..SchroomConfig::default()
+        };
 
-    run_with_config(dev, config, stop)
+        let frames = render(&mut SchroomEffect::new(seeded(7)));
+        assert_eq!(frames, render(&mut SchroomEffect::new(seeded(7))));
+        assert_ne!(frames, render(&mut SchroomEffect::new(seeded(8))));
+        for frame in frames.iter() {
+            for (_, val) in frame.iter_some() {
+                assert!((0.0..=1.0).contains(val));
+            }
+        }
+    }
+
+    #[test]
+    fn test_stacks_from_yaml() {
+        // constant oscillators: min == max
+        let config: SchroomConfig = serde_yaml::from_str("
+            seed: 1
+            oscs:
+              - { min: 0.5, max: 0.5 }
+              - { min: 0.5, max: 0.5, blend: mul }
+            chans:
+              - tags: [window]
+                oscs:
+                  - { min: 0.25, max: 0.25 }
+                  - { min: 0.5, max: 0.5 }
+                  - { min: 0.2, max: 0.2, freq_min: 1.0, freq_max: 1.0 }
+        ").unwrap();
+        config.validate().unwrap();
+
+        let mut effect = SchroomEffect::new(config);
+        for frame in render(&mut effect) {
+            assert_eq!(Some(0.25), frame.get(0));
+            assert!((frame.get(1).unwrap() - 0.95).abs() < 1e-6);
+        }
+
+        let invalid: SchroomConfig = serde_yaml::from_str(
+            "oscs: [ { freq_min: 0.5, freq_max: 0.1 } ]").unwrap();
+        assert!(invalid.validate().is_err());
+        for osc in ["freq_max: .inf", "freq_min: .nan", "min: .nan",
+                    "max: .inf", "adjustment: .nan"] {
+            let invalid: SchroomConfig = serde_yaml::from_str(
+                &format!("oscs: [ {{ {} }} ]", osc)).unwrap();
+            assert!(invalid.validate().is_err(), "{}", osc);
+        }
+    }
 }
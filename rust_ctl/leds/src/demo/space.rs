@@ -1,9 +1,12 @@
+use crate::chan_description::ChanDescription;
 use crate::coord::Coord;
+use crate::cuboid::Cuboid;
+use crate::effect::{Effect, EffectInfo, ParamSpec};
+use crate::frame::Frame;
 use crate::msg_handler::MsgHandler;
 use proto::v1::{ChanVal, Msg, Val};
-use crate::wacom;
+use crate::pointer::Pointer;
 use std::sync::{Arc, Mutex};
-use std::thread::sleep;
 use std::time;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -11,9 +14,70 @@ pub struct Config {
     pub location: Coord,
     pub radius: f32,
     pub brightness: f32,
+    /// Soft edge, the outer part of the radius that fades out, 0 to 1
+    pub falloff: f32,
 }
 
-pub fn run<T: MsgHandler + ?Sized>(srv: Arc<Mutex<T>>, conf: Config) -> Result<(), String> {
+pub const DEFAULT_FALLOFF: f32 = 0.5;
+
+fn cuboid_val(cuboid: &Cuboid, location: Coord, conf: &Config) -> f32 {
+    let intersection =
+        cuboid.sphere_intersection(location, conf.radius, conf.falloff);
+    (intersection * conf.brightness).clamp(0.0, 1.0)
+}
+
+/// Lights chans with a cuboid around a point in the room
+pub struct SpaceEffect {
+    pub conf: Config,
+}
+
+impl Effect for SpaceEffect {
+    fn render(&mut self, _t: time::Duration, chans: &[ChanDescription])
+            -> Frame<f32> {
+        let mut frame = Frame::empty();
+        for chan in chans {
+            if let Some(cuboid) = &chan.config.cuboid {
+                frame.set(chan.chan_id,
+                          cuboid_val(cuboid, self.conf.location, &self.conf));
+            }
+        }
+        frame
+    }
+}
+
+pub const EFFECT: EffectInfo = EffectInfo {
+    name: "space",
+    description: "Lights chans close to a point in the room, \
+                  only chans with a cuboid",
+    params: || vec![
+        ParamSpec::float("x", "left-right, left is 0", 0.5, 0.0, 1.0),
+        ParamSpec::float("y", "up-down, ceiling is 0", 0.5, 0.0, 1.0),
+        ParamSpec::float("z", "distance from the window", 0.5, 0.0, 1.0),
+        ParamSpec::float("radius", "how far it reaches", 0.5, 0.0, 2.0),
+        ParamSpec::float("brightness", "multiplier", 1.0, 0.0, 10.0),
+        ParamSpec::float("falloff", "soft edge, 0 hard, 1 fades from the \
+                         center", DEFAULT_FALLOFF, 0.0, 1.0),
+    ],
+    create: |params| Ok(Box::new(SpaceEffect {
+        conf: Config {
+            location: Coord {
+                x: params.f32("x"),
+                y: params.f32("y"),
+                z: params.f32("z"),
+            },
+            radius: params.f32("radius"),
+            brightness: params.f32("brightness"),
+            falloff: params.f32("falloff"),
+        },
+    })),
+};
+
+/// Lights chans around wherever `pointer` points
+pub fn run<T: MsgHandler + ?Sized>(
+    srv: Arc<Mutex<T>>,
+    conf: Config,
+    mut pointer: Box<dyn Pointer>,
+) -> Result<(), String> {
     let mut msg: Msg = {
         let srv = srv.lock().map_err(|e| format!("write lock: {:?}", e))?;
 
@@ -28,30 +92,8 @@ pub fn run<T: MsgHandler + ?Sized>(srv: Arc<Mutex<T>>, conf: Config) -> Result<(
         }
     };
 
-    let mut wacom_packet = wacom::WacomPacket::default();
-    let mut wacom = wacom::Wacom::new("/dev/hidraw8")?;
-
-    let delay = time::Duration::from_micros(2000);
-    // let t = time::Instant::now();
-    // let x_freq = 0.15;
-    // let y_freq = 0.11;
-    // let z_freq = 0.15;
-    let mut loc = conf.location;
     loop {
-        // let dt = t.elapsed().as_secs_f32();
-        // loc.x = (dt * x_freq * std::f32::consts::PI * 2.0).sin();
-        // loc.y = (dt * y_freq * std::f32::consts::PI * 2.0).sin();
-        // loc.z = (dt * z_freq * std::f32::consts::PI * 2.0).cos();
-
-        wacom.read(&mut wacom_packet)?;
-        // y max: 34bc
-        // x max: 5460
-        // dist: 2a
-        // pressure: ff
-
-        loc.x = 1.0 - (wacom_packet.y as f32 / 0x34bc as f32).min(1.0);
-        loc.y = (wacom_packet.x as f32 / 0x5460 as f32).min(1.0);
-        loc.z = (wacom_packet.distance as f32 / 0x29 as f32).min(1.0);
+        let loc = pointer.read()?;
 
         {
             let mut srv = srv.lock().map_err(|e| format!("write lock: {:?}", e))?;
@@ -61,13 +103,7 @@ pub fn run<T: MsgHandler + ?Sized>(srv: Arc<Mutex<T>>, conf: Config) -> Result<(
                 .enumerate()
                 .filter_map(|(i, cfg)| cfg.config.cuboid.map(|c| (i, c)))
             {
-                // let intersection = cuboid.sphere_intersection(loc, conf.radius);
-                // let result = (intersection * conf.brightness).min(1.0);
-
-                let avg_dist = cuboid.avg_dist_to_point(loc);
-                let result = ((conf.radius - avg_dist) * conf.brightness)
-                    .min(1.0)
-                    .max(0.0);
+                let result = cuboid_val(&cuboid, loc, &conf);
 
                 // println!("led {} = {}; dist = {}", i, result, dist);
                 msg.vals[i].1 = Val::F32(result);
@@ -78,6 +114,5 @@ pub fn run<T: MsgHandler + ?Sized>(srv: Arc<Mutex<T>>, conf: Config) -> Result<(
 
             srv.handle_msg(&msg).expect("space: handle_msg error");
         }
-        sleep(delay);
     }
 }
@@ -1,55 +1,60 @@
-use crate::dev::{DevWrite, DevNumChans};
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+use crate::dev::DevWrite;
+use crate::effect::{self, Effect, EffectInfo, ParamSpec};
 use crate::frame::Frame;
+use crate::task::TaskMsg;
+use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
-use std::thread::sleep;
-use std::time;
+use std::time::Duration;
 
 #[derive(Clone, std::fmt::Debug,
          serde_derive::Serialize, serde_derive::Deserialize)]
 pub struct TestSeq;
 
-pub fn run<T: DevWrite + DevNumChans + ?Sized>(
-    srv: Arc<Mutex<T>>) -> Result<(), String> {
-    println!("running test_seq...");
+/// Fades each chan in and out, one after another
+pub struct TestSeqEffect {
+    /// How long it takes to fade in, fading out takes as long
+    pub fade: Duration,
+}
+
+impl Effect for TestSeqEffect {
+    fn render(&mut self, t: Duration, chans: &[ChanDescription])
+            -> Frame<f32> {
+        let mut frame = Frame::empty();
+        if chans.is_empty() {
+            return frame;
+        }
+
+        let fade = self.fade.as_secs_f64();
+        let steps = t.as_secs_f64() / fade;
+        let current = (steps / 2.0) as usize % chans.len();
+        let progress = steps.rem_euclid(2.0);
+        let val = if progress < 1.0 { progress } else { 2.0 - progress };
 
-    const MIN: f32 = 0.0;
-    const MAX: f32 = 1.0;
-    const STEP: f32 = 0.01;
-    const DELAY: time::Duration = time::Duration::from_millis(10);
-
-    let num_chans = {
-        let srv = srv.lock().unwrap();
-        srv.num_chans()
-    };
-
-    let mut frame = Frame::<f32>::new(num_chans);
-
-    loop {
-        for cid in 0..num_chans {
-            let set = |frame: &mut Frame<f32>, fval: f32| -> Result<(), String> {
-                frame.set(cid, fval);
-                {
-                    frame.set(cid, fval);
-                    let srv = srv.clone();
-                    let mut srv = srv.lock().unwrap();
-                    srv.set_frame(&frame)?;
-                }
-                sleep(DELAY);
-                Ok(())
-            };
-
-            frame.clear();
-            let mut fval: f32 = 0.0;
-
-            while fval < MAX {
-                fval = (fval + STEP).min(MAX);
-                set(&mut frame, fval)?;
-            }
-
-            while fval > MIN {
-                fval = (fval - STEP).max(MIN);
-                set(&mut frame, fval)?;
-            }
+        for (i, chan) in chans.iter().enumerate() {
+            frame.set(chan.chan_id,
+                      if i == current { val as f32 } else { 0.0 });
         }
+        frame
     }
 }
+
+pub const EFFECT: EffectInfo = EffectInfo {
+    name: "test_seq",
+    description: "Sequentially fade each chan in and out, useful for testing",
+    params: || vec![
+        ParamSpec::float("fade", "seconds to fade in", 1.0, 0.01, 60.0),
+    ],
+    create: |params| Ok(Box::new(TestSeqEffect {
+        fade: Duration::from_secs_f32(params.f32("fade")),
+    })),
+};
+
+pub fn run<T: DevWrite + HasChanDescriptions + ?Sized>(
+    srv: Arc<Mutex<T>>) -> Result<(), String> {
+    println!("running test_seq...");
+
+    let (_sender, receiver) = mpsc::channel::<TaskMsg>();
+    let effect = TestSeqEffect { fade: Duration::from_secs(1) };
+    effect::run(srv, Box::new(effect), receiver)
+}
@@ -1,91 +1,87 @@
-use crate::msg_handler::MsgHandler;
-use proto::v1::{ChanVal, Msg, Val};
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+use crate::dev::DevWrite;
+use crate::effect::{Effect, EffectInfo, EffectRunner, ParamSpec};
+use crate::frame::Frame;
+use crate::runner::Runner;
+use crate::task::TaskMsg;
+use crate::tempo::BeatSync;
+use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
-use std::thread::sleep;
-use std::time::{self, Duration};
+use std::time::Duration;
 
 #[derive(Clone, std::fmt::Debug,
          serde_derive::Serialize, serde_derive::Deserialize)]
 pub struct Whoosh;
 
-#[derive(Debug)]
-struct DemoChan {
-    min: f64,
-    max: f64,
-    position: f64,
+/// A bump that runs across all chans in chan order again and again
+pub struct WhooshEffect {
+    /// Time to cross all chans
+    pub period: Duration,
+    /// Fraction of the chans lit at once
+    pub radius: f64,
+    /// Crosses once every so many beats instead of every `period`
+    pub beat: Option<BeatSync>,
 }
 
-pub fn run<D: MsgHandler + ?Sized>(srv: Arc<Mutex<D>>) -> Result<(), String> {
-    println!("running hello whoosh...");
+const MIN: f64 = 0.01;
+const MAX: f64 = 1.0;
 
-    let mut msg: Msg = {
-        let srv = srv.lock().map_err(|e| format!("read lock: {:?}", e))?;
+impl Effect for WhooshEffect {
+    fn render(&mut self, t: Duration, chans: &[ChanDescription])
+            -> Frame<f32> {
+        let start = -self.radius;
+        let finish = 1.0 + self.radius;
+        let loc = match &self.beat {
+            Some(beat) => start + beat.phase() * (finish - start),
+            None => start + (t.as_secs_f64() / self.period.as_secs_f64())
+                .rem_euclid(finish - start),
+        };
 
-        Msg {
-            seq_num: 0,
-            timestamp: time::SystemTime::now(),
-            vals: srv
-                .chans()
-                .into_iter()
-                .map(|(id, _)| (ChanVal(id, Val::F32(0.0))))
-                .collect(),
+        let num_chans = chans.len();
+        let mut frame = Frame::empty();
+        for (i, chan) in chans.iter().enumerate() {
+            let position = 0.1 + (0.8 * (i as f64 / num_chans as f64));
+            let dist = (position - loc).abs();
+            let val = (MIN + (self.radius - dist).max(0.0) * 1.3 * MAX)
+                .min(MAX);
+            frame.set(chan.chan_id, val as f32);
         }
-    };
-
-    let num_chans = msg.vals.len();
-    let mut dchans: Vec<DemoChan> = Vec::with_capacity(num_chans);
-
-    for i in 0..msg.vals.len() {
-        dchans.push(DemoChan {
-            min: 0.01,
-            max: 1.0,
-            position: 0.1 + (0.8 * (i as f64 / num_chans as f64)),
-        });
+        frame
     }
+}
 
-    let delay = time::Duration::from_millis(13);
-    let mut t = time::Instant::now();
-    let radius = 0.5;
-    let period = Duration::from_millis(1000);
-    let start = -radius;
-    let finish = 1.0 + radius;
-    let mut loc = start;
-
-    loop {
-        let dt = t.elapsed().as_secs_f64();
-        t = time::Instant::now();
-
-        let pos_diff = dt / period.as_secs_f64();
-        loc += pos_diff;
-        if loc > finish {
-            loc = (loc - finish) + start;
-        }
-        // dbg!(loc);
-
-        for (i, d) in dchans.iter_mut().enumerate() {
-            // msg.vals[i].1 = Val::F32(new_sin as f32);
-            let dist = {
-                if d.position > loc {
-                    d.position - loc
-                } else {
-                    loc - d.position
-                }
-            };
-            // let val = d.max * (radius - (1.0 / dist)).max(0.0);
-
-            // let val = d.min + (radius - dist).max(0.0).powf(2.2) * d.max;
-            let val = (d.min + (radius - dist).max(0.0) * 1.3 * d.max).min(d.max);
-
-            msg.vals[i].1 = Val::F32(val as f32);
-            // dbg!(d.position, loc, dist, val);
-        }
+pub const EFFECT: EffectInfo = EffectInfo {
+    name: "whoosh",
+    description: "Quick fade across all chans sequentially",
+    params: || vec![
+        ParamSpec::float("period", "seconds to cross all chans",
+                         1.0, 0.01, 600.0),
+        ParamSpec::float("radius", "how many chans are lit, 1.0 is all",
+                         0.5, 0.01, 1.0),
+        ParamSpec::float("beats", "cross every this many beats of the \
+                         tempo instead, 0 uses period", 0.0, 0.0, 64.0),
+    ],
+    create: |params| Ok(Box::new(WhooshEffect {
+        period: Duration::from_secs_f32(params.f32("period")),
+        radius: params.f32("radius") as f64,
+        beat: match params.f32("beats") {
+            beats if beats > 0.0 => Some(BeatSync::shared(beats as f64)),
+            _ => None,
+        },
+    })),
+};
 
-        dbg!(&msg.vals);
+pub fn run<D: DevWrite + HasChanDescriptions + ?Sized>(srv: Arc<Mutex<D>>)
+        -> Result<(), String> {
+    println!("running hello whoosh...");
 
-        {
-            let mut srv = srv.lock().map_err(|e| format!("write lock: {:?}", e))?;
-            srv.handle_msg(&msg).expect("demo: handle_msg error");
-        }
-        sleep(delay);
-    }
+    let (_sender, receiver) = mpsc::channel::<TaskMsg>();
+    let effect = WhooshEffect {
+        period: Duration::from_millis(1000),
+        radius: 0.5,
+        beat: None,
+    };
+    let mut runner = EffectRunner::new(srv, Box::new(effect));
+    runner.frame_duration = Duration::from_millis(13);
+    EffectRunner::run(Arc::new(Mutex::new(runner)), receiver)
 }
@@ -0,0 +1,54 @@
+use serde_derive::{Deserialize, Serialize};
+
+/// Maps fade progress (0.0 - 1.0) to interpolation amount (0.0 - 1.0)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Easing {
+    #[default]
+    Linear,
+    /// Starts slow, ends fast
+    EaseIn,
+    /// Starts fast, ends slow
+    EaseOut,
+    /// Slow at both ends
+    EaseInOut,
+    /// Slower at both ends than `EaseInOut`
+    Cubic,
+    /// Barely moves at first, then shoots up
+    Exponential,
+    /// Even steps in perceived lightness when fading up from off
+    Lightness,
+}
+
+impl Easing {
+    pub fn apply(&self, progress: f64) -> f64 {
+        let p = progress.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => p,
+            Easing::EaseIn => p * p,
+            Easing::EaseOut => 1.0 - (1.0 - p) * (1.0 - p),
+            Easing::EaseInOut => p * p * (3.0 - 2.0 * p),
+            Easing::Cubic => match p < 0.5 {
+                true => 4.0 * p * p * p,
+                false => 1.0 - (2.0 - 2.0 * p).powi(3) / 2.0,
+            },
+            Easing::Exponential => (2f64.powf(10.0 * p) - 1.0) / 1023.0,
+            Easing::Lightness => from_lightness(p),
+        }
+    }
+}
+
+/// CIE lightness (0 - 1) of a value (0 - 1) taken as linear light
+pub fn lightness(val: f64) -> f64 {
+    match val > 216.0 / 24389.0 {
+        true => 1.16 * val.cbrt() - 0.16,
+        false => val * 24389.0 / 2700.0,
+    }
+}
+
+/// Value of a CIE lightness, the inverse of `lightness`
+pub fn from_lightness(l: f64) -> f64 {
+    match l > 0.08 {
+        true => ((l + 0.16) / 1.16).powi(3),
+        false => l * 2700.0 / 24389.0,
+    }
+}
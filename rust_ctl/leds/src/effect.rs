@@ -1 +1,498 @@
+use serde_derive::{Deserialize, Serialize};
 
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+use crate::chase;
+use crate::clock::{self, Clock};
+use crate::demo;
+use crate::expr::{self, Selector};
+use crate::dev::DevWrite;
+use crate::frame::Frame;
+use crate::natural;
+use crate::runner::Runner;
+use crate::spatial;
+use crate::task::TaskMsg;
+
+use std::fmt;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Something that renders frames over time
+pub trait Effect: Send {
+    /// Values at `t` since the effect started, chans the effect doesn't
+    /// touch are left empty
+    fn render(&mut self, t: Duration, chans: &[ChanDescription]) -> Frame<f32>;
+
+    /// Start over with random numbers from `seed`, for effects using any
+    fn reseed(&mut self, _seed: u64) {}
+}
+
+/// An effect for the `chans` it's assigned to, the rest are left alone
+pub struct Assigned<E: Effect> {
+    chans: Selector,
+    pub effect: E,
+}
+
+impl<E: Effect> Assigned<E> {
+    /// `chans` is a tag, a chan id or `all`
+    pub fn new(chans: &str, effect: E) -> Self {
+        Assigned { chans: Selector::parse(chans), effect }
+    }
+}
+
+impl<E: Effect> Effect for Assigned<E> {
+    fn render(&mut self, t: Duration, chans: &[ChanDescription]) -> Frame<f32> {
+        let chans: Vec<ChanDescription> = chans.iter()
+            .filter(|chan| self.chans.matches(chan))
+            .cloned()
+            .collect();
+        self.effect.render(t, &chans)
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.effect.reseed(seed);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParamKind {
+    Float { min: f32, max: f32 },
+    Int { min: i64, max: i64 },
+    Bool,
+    Choice(&'static [&'static str]),
+    Text,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    Text(String),
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamValue::Float(val) => write!(f, "{}", val),
+            ParamValue::Int(val) => write!(f, "{}", val),
+            ParamValue::Bool(val) => write!(f, "{}", val),
+            ParamValue::Text(val) => write!(f, "{}", val),
+        }
+    }
+}
+
+/// Declares a parameter an effect takes
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub kind: ParamKind,
+    pub default: ParamValue,
+}
+
+impl ParamSpec {
+    pub fn float(name: &'static str, description: &'static str,
+                 default: f32, min: f32, max: f32) -> Self {
+        ParamSpec {
+            name, description,
+            kind: ParamKind::Float { min, max },
+            default: ParamValue::Float(default),
+        }
+    }
+
+    pub fn int(name: &'static str, description: &'static str,
+               default: i64, min: i64, max: i64) -> Self {
+        ParamSpec {
+            name, description,
+            kind: ParamKind::Int { min, max },
+            default: ParamValue::Int(default),
+        }
+    }
+
+    pub fn bool(name: &'static str, description: &'static str,
+                default: bool) -> Self {
+        ParamSpec {
+            name, description,
+            kind: ParamKind::Bool,
+            default: ParamValue::Bool(default),
+        }
+    }
+
+    /// `default` is the first choice
+    pub fn choice(name: &'static str, description: &'static str,
+                  choices: &'static [&'static str]) -> Self {
+        ParamSpec {
+            name, description,
+            kind: ParamKind::Choice(choices),
+            default: ParamValue::Text(choices[0].to_string()),
+        }
+    }
+
+    pub fn text(name: &'static str, description: &'static str,
+                default: &str) -> Self {
+        ParamSpec {
+            name, description,
+            kind: ParamKind::Text,
+            default: ParamValue::Text(default.to_string()),
+        }
+    }
+
+    pub fn parse(&self, s: &str) -> Result<ParamValue, String> {
+        let err = |e: &dyn fmt::Debug| format!(
+            "invalid value '{}' for {}: {:?}", s, self.name, e);
+
+        match &self.kind {
+            ParamKind::Float { min, max } => {
+                let val: f32 = s.trim().parse().map_err(|e| err(&e))?;
+                if !(*min..=*max).contains(&val) {
+                    return Err(format!("{} should be between {} and {}",
+                                       self.name, min, max));
+                }
+                Ok(ParamValue::Float(val))
+            },
+            ParamKind::Int { min, max } => {
+                let val: i64 = s.trim().parse().map_err(|e| err(&e))?;
+                if !(*min..=*max).contains(&val) {
+                    return Err(format!("{} should be between {} and {}",
+                                       self.name, min, max));
+                }
+                Ok(ParamValue::Int(val))
+            },
+            ParamKind::Bool => match s.trim() {
+                "true" | "on" | "yes" | "1" => Ok(ParamValue::Bool(true)),
+                "false" | "off" | "no" | "0" => Ok(ParamValue::Bool(false)),
+                other => Err(err(&other)),
+            },
+            ParamKind::Choice(choices) => {
+                if !choices.contains(&s.trim()) {
+                    return Err(format!("{} should be one of {}",
+                                       self.name, choices.join(", ")));
+                }
+                Ok(ParamValue::Text(s.trim().to_string()))
+            },
+            ParamKind::Text => Ok(ParamValue::Text(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ParamSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = match &self.kind {
+            ParamKind::Float { min, max } => format!(" ({}-{})", min, max),
+            ParamKind::Int { min, max } => format!(" ({}-{})", min, max),
+            ParamKind::Choice(choices) => format!(" ({})", choices.join("|")),
+            ParamKind::Bool | ParamKind::Text => String::new(),
+        };
+        write!(f, "{}={}{} -- {}", self.name, self.default, range,
+               self.description)
+    }
+}
+
+/// Parameter values, every declared parameter has one
+#[derive(Clone, Debug, PartialEq)]
+pub struct Params {
+    values: Vec<(&'static str, ParamValue)>,
+}
+
+impl Params {
+    pub fn defaults(schema: &[ParamSpec]) -> Self {
+        Params {
+            values: schema.iter()
+                .map(|spec| (spec.name, spec.default.clone()))
+                .collect(),
+        }
+    }
+
+    /// Defaults overridden by `key=value` args
+    pub fn parse<I, S>(schema: &[ParamSpec], args: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut params = Params::defaults(schema);
+        for arg in args {
+            let arg = arg.as_ref();
+            let (key, val) = arg.split_once('=').ok_or_else(
+                || format!("expected key=value, got '{}'", arg))?;
+            params.set(schema, key.trim(), val)?;
+        }
+        Ok(params)
+    }
+
+    pub fn set(&mut self, schema: &[ParamSpec], key: &str, val: &str)
+            -> Result<(), String> {
+        let spec = schema.iter().find(|spec| spec.name == key)
+            .ok_or_else(|| format!(
+                    "unknown param '{}', expected one of {}", key,
+                    schema.iter().map(|spec| spec.name)
+                    .collect::<Vec<_>>().join(", ")))?;
+        let val = spec.parse(val)?;
+        match self.values.iter_mut().find(|(name, _)| *name == key) {
+            Some((_, old)) => *old = val,
+            None => self.values.push((spec.name, val)),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ParamValue> {
+        self.values.iter().find(|(n, _)| *n == name).map(|(_, val)| val)
+    }
+
+    // Effects only ask for the params they've declared, so a missing or
+    // mistyped one is a bug in the effect
+
+    pub fn f32(&self, name: &str) -> f32 {
+        match self.get(name) {
+            Some(ParamValue::Float(val)) => *val,
+            Some(ParamValue::Int(val)) => *val as f32,
+            other => panic!("param {} is not a float: {:?}", name, other),
+        }
+    }
+
+    pub fn i64(&self, name: &str) -> i64 {
+        match self.get(name) {
+            Some(ParamValue::Int(val)) => *val,
+            other => panic!("param {} is not an int: {:?}", name, other),
+        }
+    }
+
+    pub fn bool(&self, name: &str) -> bool {
+        match self.get(name) {
+            Some(ParamValue::Bool(val)) => *val,
+            other => panic!("param {} is not a bool: {:?}", name, other),
+        }
+    }
+
+    pub fn str(&self, name: &str) -> &str {
+        match self.get(name) {
+            Some(ParamValue::Text(val)) => val,
+            other => panic!("param {} is not text: {:?}", name, other),
+        }
+    }
+}
+
+/// An effect in the registry
+pub struct EffectInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub params: fn() -> Vec<ParamSpec>,
+    pub create: fn(&Params) -> Result<Box<dyn Effect>, String>,
+}
+
+impl EffectInfo {
+    /// Creates the effect with `key=value` args
+    pub fn create_with_args<I, S>(&self, args: I)
+            -> Result<Box<dyn Effect>, String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let params = Params::parse(&(self.params)(), args)
+            .map_err(|e| format!("{}: {}", self.name, e))?;
+        (self.create)(&params)
+    }
+}
+
+/// All effects that can be started by name
+pub static EFFECTS: &[EffectInfo] = &[
+    demo::hello::EFFECT,
+    demo::hello::HARDER_EFFECT,
+    demo::glitch::EFFECT,
+    demo::whoosh::EFFECT,
+    demo::test_seq::EFFECT,
+    demo::space::EFFECT,
+    demo::schroom::EFFECT,
+    spatial::SWEEP,
+    spatial::RIPPLE,
+    spatial::NOISE,
+    spatial::SPOTLIGHT,
+    chase::EFFECT,
+    expr::EFFECT,
+    natural::CANDLE,
+    natural::FIRE,
+    natural::LIGHTNING,
+];
+
+pub fn find(name: &str) -> Result<&'static EffectInfo, String> {
+    EFFECTS.iter().find(|info| info.name == name)
+        .ok_or_else(|| format!(
+                "effect '{}' does not exist, expected one of {}", name,
+                EFFECTS.iter().map(|info| info.name)
+                .collect::<Vec<_>>().join(", ")))
+}
+
+pub const DEFAULT_FRAME_DURATION: Duration = Duration::from_millis(10);
+
+/// Renders an effect into `output`, `Pause` freezes and resumes it
+pub struct EffectRunner<T: ?Sized> {
+    output: Arc<Mutex<T>>,
+    effect: Box<dyn Effect>,
+    pub frame_duration: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl<T: HasChanDescriptions + DevWrite + ?Sized> EffectRunner<T> {
+    pub fn new(output: Arc<Mutex<T>>, effect: Box<dyn Effect>) -> Self {
+        EffectRunner {
+            output,
+            effect,
+            frame_duration: DEFAULT_FRAME_DURATION,
+            clock: clock::real(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.effect.reseed(seed);
+        self
+    }
+
+    fn render_to_output(&mut self, t: Duration) -> Result<(), String> {
+        let mut output = self.output.lock()
+            .map_err(|e| format!("write lock: {:?}", e))?;
+        let frame = self.effect.render(t, &output.chan_descriptions());
+        output.set_frame(&frame)
+    }
+}
+
+impl<T: HasChanDescriptions + DevWrite + ?Sized> Runner for EffectRunner<T> {
+    fn run(
+        self_lock: Arc<Mutex<EffectRunner<T>>>,
+        stop: mpsc::Receiver<TaskMsg>,
+    ) -> Result<(), String> {
+        let (frame_duration, clock) = {
+            let runner = self_lock.lock().unwrap();
+            (runner.frame_duration, runner.clock.clone())
+        };
+
+        let mut started = clock.now();
+        let mut paused_at: Option<Instant> = None;
+        loop {
+            if paused_at.is_none() {
+                let mut runner = self_lock.lock().unwrap();
+                if let Err(e) = runner.render_to_output(clock.now() - started) {
+                    eprintln!("effect err: {:?}", e);
+                }
+            }
+
+            match clock.recv_timeout(&stop, frame_duration) {
+                Ok(TaskMsg::Stop) => return Ok(()),
+                Ok(TaskMsg::Pause) => match paused_at.take() {
+                    // continue where we've stopped
+                    Some(paused_at) => started += clock.now() - paused_at,
+                    None => paused_at = Some(clock.now()),
+                },
+                Ok(TaskMsg::Ping) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Runs `effect` until the task is stopped
+pub fn run<T: HasChanDescriptions + DevWrite + ?Sized>(
+    output: Arc<Mutex<T>>,
+    effect: Box<dyn Effect>,
+    stop: mpsc::Receiver<TaskMsg>,
+) -> Result<(), String> {
+    Runner::run(Arc::new(Mutex::new(EffectRunner::new(output, effect))), stop)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use crate::chan::ChanConfig;
+    use crate::clock::VirtualClock;
+    use crate::mux::Mux;
+    use crate::test_dev::TestDev;
+
+    fn schema() -> Vec<ParamSpec> {
+        vec![
+            ParamSpec::float("speed", "how fast", 1.0, 0.0, 10.0),
+            ParamSpec::int("count", "how many", 3, 1, 5),
+            ParamSpec::bool("wrap", "start over", false),
+            ParamSpec::choice("dir", "direction", &["left", "right"]),
+        ]
+    }
+
+    #[test]
+    fn test_params() {
+        let params = Params::parse(&schema(), Vec::<String>::new()).unwrap();
+        assert_eq!(params.f32("speed"), 1.0);
+        assert_eq!(params.i64("count"), 3);
+        assert!(!params.bool("wrap"));
+        assert_eq!(params.str("dir"), "left");
+
+        let params = Params::parse(
+            &schema(), ["speed=2.5", "wrap=on", "dir=right"]).unwrap();
+        assert_eq!(params.f32("speed"), 2.5);
+        assert!(params.bool("wrap"));
+        assert_eq!(params.str("dir"), "right");
+
+        assert!(Params::parse(&schema(), ["speed=11"]).is_err());
+        assert!(Params::parse(&schema(), ["count=2.5"]).is_err());
+        assert!(Params::parse(&schema(), ["dir=up"]).is_err());
+        assert!(Params::parse(&schema(), ["nope=1"]).is_err());
+        assert!(Params::parse(&schema(), ["speed"]).is_err());
+    }
+
+    #[test]
+    fn test_all_effects_render() {
+        let mut mux = Mux::new();
+        mux.add_dev(Arc::new(Mutex::new(TestDev::new(false))),
+                    None::<std::iter::Empty<ChanConfig>>);
+        let chans = mux.chan_descriptions();
+
+        for info in EFFECTS {
+            assert_eq!(find(info.name).unwrap().name, info.name);
+
+            let mut effect = info.create_with_args(Vec::<String>::new())
+                .unwrap_or_else(|e| panic!("{}: {}", info.name, e));
+            for ms in [0, 100, 1500, 60_000] {
+                let frame = effect.render(Duration::from_millis(ms), &chans);
+                for (cid, val) in frame.iter_some() {
+                    assert!((0.0..=1.0).contains(val),
+                            "{} chan {} = {} at {}ms", info.name, cid, val, ms);
+                }
+            }
+        }
+
+        assert!(find("nope").is_err());
+    }
+
+    /// Runs every effect for 2 virtual seconds at 10 frames a second
+    fn record(seed: u64) -> Vec<Vec<Frame<f32>>> {
+        EFFECTS.iter().map(|info| {
+            let dev = Arc::new(Mutex::new(TestDev::recording()));
+            let effect = info.create_with_args(Vec::<String>::new()).unwrap();
+            let clock = VirtualClock::new().stop_after(Duration::from_secs(2));
+            let mut runner = EffectRunner::new(dev.clone(), effect)
+                .with_clock(Arc::new(clock))
+                .with_seed(seed);
+            runner.frame_duration = Duration::from_millis(100);
+
+            let (_stop, receiver) = mpsc::channel();
+            EffectRunner::run(Arc::new(Mutex::new(runner)), receiver).unwrap();
+            let history = dev.lock().unwrap().history().to_vec();
+            history
+        }).collect()
+    }
+
+    #[test]
+    fn test_seeded_runs_repeat() {
+        let frames = record(3);
+        for (info, history) in EFFECTS.iter().zip(frames.iter()) {
+            assert_eq!(21, history.len(), "{}", info.name);
+        }
+        assert_eq!(frames, record(3));
+    }
+}
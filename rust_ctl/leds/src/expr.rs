@@ -0,0 +1,737 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::audio::{AudioConfig, Levels, SharedLevels};
+use crate::chan_description::ChanDescription;
+use crate::coord::Coord;
+use crate::effect::{Effect, EffectInfo, ParamSpec};
+use crate::frame::Frame;
+use crate::tempo::{self, SharedTempo};
+
+use std::f64::consts::{PI, TAU};
+use std::time::Duration;
+
+const VARS: &str = "t, i, id, n, x, y, z, v, rms, beat, bar, pi, tau, \
+                    tag_NAME or an audio band";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Op {
+    Lt, Gt, Le, Ge, Eq, Ne,
+    Add, Sub,
+    Mul, Div, Rem,
+    Pow,
+}
+
+impl Op {
+    fn precedence(&self) -> u8 {
+        match self {
+            Op::Lt | Op::Gt | Op::Le | Op::Ge | Op::Eq | Op::Ne => 1,
+            Op::Add | Op::Sub => 2,
+            Op::Mul | Op::Div | Op::Rem => 3,
+            Op::Pow => 5,
+        }
+    }
+
+    fn apply(&self, a: f64, b: f64) -> f64 {
+        let truth = |cond: bool| if cond { 1.0 } else { 0.0 };
+        match self {
+            Op::Lt => truth(a < b),
+            Op::Gt => truth(a > b),
+            Op::Le => truth(a <= b),
+            Op::Ge => truth(a >= b),
+            Op::Eq => truth(a == b),
+            Op::Ne => truth(a != b),
+            Op::Add => a + b,
+            Op::Sub => a - b,
+            Op::Mul => a * b,
+            Op::Div => a / b,
+            Op::Rem => a.rem_euclid(b),
+            Op::Pow => a.powf(b),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Func {
+    Sin, Cos, Tan, Abs, Floor, Ceil, Fract, Sqrt, Exp, Ln,
+    Min, Max, Pow, Step,
+    Clamp, Mix, Smoothstep, If,
+    Chan, Noise, Tri,
+}
+
+impl Func {
+    fn lookup(name: &str) -> Option<(Func, usize)> {
+        let func = match name {
+            "sin" => (Func::Sin, 1),
+            "cos" => (Func::Cos, 1),
+            "tan" => (Func::Tan, 1),
+            "abs" => (Func::Abs, 1),
+            "floor" => (Func::Floor, 1),
+            "ceil" => (Func::Ceil, 1),
+            "fract" => (Func::Fract, 1),
+            "sqrt" => (Func::Sqrt, 1),
+            "exp" => (Func::Exp, 1),
+            "ln" => (Func::Ln, 1),
+            "chan" => (Func::Chan, 1),
+            "noise" => (Func::Noise, 1),
+            "tri" => (Func::Tri, 1),
+            "min" => (Func::Min, 2),
+            "max" => (Func::Max, 2),
+            "pow" => (Func::Pow, 2),
+            "step" => (Func::Step, 2),
+            "clamp" => (Func::Clamp, 3),
+            "mix" => (Func::Mix, 3),
+            "smoothstep" => (Func::Smoothstep, 3),
+            "if" => (Func::If, 3),
+            _ => return None,
+        };
+        Some(func)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Var {
+    T,
+    Index,
+    Id,
+    Count,
+    X,
+    Y,
+    Z,
+    /// What this chan's expression gave last frame
+    Value,
+    Rms,
+    Band(usize),
+    Tag(String),
+    Beat,
+    Bar,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Expr {
+    Num(f64),
+    Var(Var),
+    Neg(Box<Expr>),
+    Bin(Op, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+/// Everything an expression can see while rendering a chan
+pub(crate) struct Ctx<'a> {
+    pub t: f64,
+    pub index: usize,
+    pub count: usize,
+    pub chan: &'a ChanDescription,
+    pub center: Coord,
+    pub prev: &'a Frame<f32>,
+    pub levels: &'a Levels,
+    pub beat: f64,
+    pub bar: f64,
+}
+
+/// Smooth value noise, 0 to 1
+fn noise(x: f64) -> f64 {
+    let hash = |i: f64| {
+        let h = (i as i64).wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as i64);
+        let h = (h ^ (h >> 29)).wrapping_mul(0xbf58_476d_1ce4_e5b9_u64 as i64);
+        ((h ^ (h >> 32)) as u64 >> 11) as f64 / (1u64 << 53) as f64
+    };
+    let (i, f) = (x.floor(), x - x.floor());
+    let k = f * f * (3.0 - 2.0 * f);
+    hash(i) + (hash(i + 1.0) - hash(i)) * k
+}
+
+impl Expr {
+    pub fn eval(&self, ctx: &Ctx) -> f64 {
+        match self {
+            Expr::Num(n) => *n,
+            Expr::Var(var) => match var {
+                Var::T => ctx.t,
+                Var::Index => ctx.index as f64,
+                Var::Id => ctx.chan.chan_id as f64,
+                Var::Count => ctx.count as f64,
+                Var::X => ctx.center.x as f64,
+                Var::Y => ctx.center.y as f64,
+                Var::Z => ctx.center.z as f64,
+                Var::Value => ctx.prev.get(ctx.chan.chan_id).unwrap_or(0.0)
+                    as f64,
+                Var::Rms => ctx.levels.rms as f64,
+                Var::Band(i) => ctx.levels.bands.get(*i).copied()
+                    .unwrap_or(0.0) as f64,
+                Var::Tag(name) => match ctx.chan.config.tags.iter()
+                        .any(|tag| tag.name() == name) {
+                    true => 1.0,
+                    false => 0.0,
+                },
+                Var::Beat => ctx.beat,
+                Var::Bar => ctx.bar,
+            },
+            Expr::Neg(expr) => -expr.eval(ctx),
+            Expr::Bin(op, a, b) => op.apply(a.eval(ctx), b.eval(ctx)),
+            Expr::Call(func, args) => {
+                let arg = |i: usize| args[i].eval(ctx);
+                match func {
+                    Func::Sin => arg(0).sin(),
+                    Func::Cos => arg(0).cos(),
+                    Func::Tan => arg(0).tan(),
+                    Func::Abs => arg(0).abs(),
+                    Func::Floor => arg(0).floor(),
+                    Func::Ceil => arg(0).ceil(),
+                    Func::Fract => arg(0).rem_euclid(1.0),
+                    Func::Sqrt => arg(0).sqrt(),
+                    Func::Exp => arg(0).exp(),
+                    Func::Ln => arg(0).ln(),
+                    Func::Chan => ctx.prev.get(arg(0).max(0.0) as u16)
+                        .unwrap_or(0.0) as f64,
+                    Func::Noise => noise(arg(0)),
+                    // 0 to 1 and back over a period of 1
+                    Func::Tri => 1.0 - (2.0 * arg(0).rem_euclid(1.0) - 1.0)
+                        .abs(),
+                    Func::Min => arg(0).min(arg(1)),
+                    Func::Max => arg(0).max(arg(1)),
+                    Func::Pow => arg(0).powf(arg(1)),
+                    Func::Step => if arg(1) < arg(0) { 0.0 } else { 1.0 },
+                    Func::Clamp => arg(0).max(arg(1)).min(arg(2)),
+                    Func::Mix => {
+                        let (a, b) = (arg(0), arg(1));
+                        a + (b - a) * arg(2)
+                    },
+                    Func::Smoothstep => {
+                        let (lo, hi) = (arg(0), arg(1));
+                        let k = ((arg(2) - lo) / (hi - lo)).clamp(0.0, 1.0);
+                        k * k * (3.0 - 2.0 * k)
+                    },
+                    Func::If => match arg(0) != 0.0 {
+                        true => arg(1),
+                        false => arg(2),
+                    },
+                }
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(f64),
+    Ident(String),
+    Op(Op),
+    LParen,
+    RParen,
+    Comma,
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        i += 1;
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            '+' => Token::Op(Op::Add),
+            '-' => Token::Op(Op::Sub),
+            '*' => Token::Op(Op::Mul),
+            '/' => Token::Op(Op::Div),
+            '%' => Token::Op(Op::Rem),
+            '^' => Token::Op(Op::Pow),
+            '<' | '>' | '=' | '!' => {
+                let eq = chars.get(i) == Some(&'=');
+                if eq {
+                    i += 1;
+                }
+                Token::Op(match (c, eq) {
+                    ('<', false) => Op::Lt,
+                    ('>', false) => Op::Gt,
+                    ('<', true) => Op::Le,
+                    ('>', true) => Op::Ge,
+                    ('=', true) => Op::Eq,
+                    ('!', true) => Op::Ne,
+                    _ => return Err(format!(
+                            "expected '{}=' at {}", c, start)),
+                })
+            },
+            c if c.is_ascii_digit() || c == '.' => {
+                while i < chars.len() && (chars[i].is_ascii_digit()
+                                          || chars[i] == '.') {
+                    i += 1;
+                }
+                // exponent, i.e. 1e-3
+                if i < chars.len() && chars[i] == 'e' {
+                    let sign = matches!(chars.get(i + 1), Some('-' | '+'));
+                    let digits = i + 1 + sign as usize;
+                    if chars.get(digits).is_some_and(|c| c.is_ascii_digit()) {
+                        i = digits;
+                        while i < chars.len() && chars[i].is_ascii_digit() {
+                            i += 1;
+                        }
+                    }
+                }
+                let num: String = chars[start..i].iter().collect();
+                Token::Num(num.parse().map_err(
+                        |_| format!("invalid number '{}' at {}", num, start))?)
+            },
+            c if c.is_alphabetic() || c == '_' => {
+                while i < chars.len() && (chars[i].is_alphanumeric()
+                                          || chars[i] == '_') {
+                    i += 1;
+                }
+                Token::Ident(chars[start..i].iter().collect())
+            },
+            c => return Err(format!("unexpected '{}' at {}", c, start)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Audio band names, in order
+    bands: &'a [String],
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected {:?}, got {:?}",
+                                       expected, token)),
+            None => Err(format!("expected {:?} at the end", expected)),
+        }
+    }
+
+    /// Operators binding at least as tight as `min_precedence`
+    fn expr(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut lhs = self.operand()?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            if op.precedence() < min_precedence {
+                break;
+            }
+            self.pos += 1;
+            // `^` is right associative
+            let next = match op {
+                Op::Pow => op.precedence(),
+                _ => op.precedence() + 1,
+            };
+            let rhs = self.expr(next)?;
+            lhs = Expr::Bin(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn operand(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            // binds tighter than everything but `^`, so -x^2 is -(x^2)
+            Some(Token::Op(Op::Sub)) => Ok(Expr::Neg(Box::new(self.expr(4)?))),
+            Some(Token::Op(Op::Add)) => self.expr(4),
+            Some(Token::Num(n)) => Ok(Expr::Num(n)),
+            Some(Token::LParen) => {
+                let expr = self.expr(0)?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            },
+            Some(Token::Ident(name)) if self.peek() == Some(&Token::LParen) => {
+                self.pos += 1;
+                self.call(&name)
+            },
+            Some(Token::Ident(name)) => self.var(&name),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    fn call(&mut self, name: &str) -> Result<Expr, String> {
+        let (func, arity) = Func::lookup(name)
+            .ok_or_else(|| format!("unknown function '{}'", name))?;
+
+        let mut args = vec![];
+        if self.peek() != Some(&Token::RParen) {
+            loop {
+                args.push(self.expr(0)?);
+                match self.next() {
+                    Some(Token::Comma) => {},
+                    Some(Token::RParen) => break,
+                    _ => return Err(format!("expected ',' or ')' in {}()",
+                                            name)),
+                }
+            }
+        } else {
+            self.pos += 1;
+        }
+
+        if args.len() != arity {
+            return Err(format!("{}() takes {} arguments, got {}",
+                               name, arity, args.len()));
+        }
+        Ok(Expr::Call(func, args))
+    }
+
+    fn var(&self, name: &str) -> Result<Expr, String> {
+        let var = match name {
+            "t" => Var::T,
+            "i" => Var::Index,
+            "id" => Var::Id,
+            "n" => Var::Count,
+            "x" => Var::X,
+            "y" => Var::Y,
+            "z" => Var::Z,
+            "v" => Var::Value,
+            "rms" => Var::Rms,
+            "beat" => Var::Beat,
+            "bar" => Var::Bar,
+            "pi" => return Ok(Expr::Num(PI)),
+            "tau" => return Ok(Expr::Num(TAU)),
+            _ => match (name.strip_prefix("tag_"),
+                        self.bands.iter().position(|b| b == name)) {
+                (_, Some(band)) => Var::Band(band),
+                (Some(tag), None) => Var::Tag(tag.to_string()),
+                (None, None) => return Err(format!(
+                        "unknown variable '{}', expected {}", name, VARS)),
+            },
+        };
+        Ok(Expr::Var(var))
+    }
+}
+
+pub(crate) fn compile(src: &str, bands: &[String]) -> Result<Expr, String> {
+    let mut parser = Parser { tokens: tokenize(src)?, pos: 0, bands };
+    let expr = parser.expr(0)?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(format!("unexpected {:?} after the expression",
+                                   token)),
+    }
+}
+
+/// A chan value from whatever an expression has come up with
+pub(crate) fn level(val: f64) -> f32 {
+    match val {
+        val if val.is_finite() => val.clamp(0.0, 1.0) as f32,
+        _ => 0.0,
+    }
+}
+
+/// Which chans an expression is for
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Selector {
+    All,
+    Id(u16),
+    Tag(String),
+}
+
+impl Selector {
+    pub fn parse(s: &str) -> Self {
+        match s.trim() {
+            "all" | "*" => Selector::All,
+            s => match s.parse() {
+                Ok(id) => Selector::Id(id),
+                Err(_) => Selector::Tag(s.to_string()),
+            },
+        }
+    }
+
+    pub fn matches(&self, chan: &ChanDescription) -> bool {
+        match self {
+            Selector::All => true,
+            Selector::Id(id) => chan.chan_id == *id,
+            Selector::Tag(name) =>
+                chan.config.tags.iter().any(|tag| tag.name() == name),
+        }
+    }
+}
+
+/// Expressions compiled once for the chans they're for
+#[derive(Clone, Debug)]
+pub struct Program {
+    rules: Vec<(Selector, Expr)>,
+}
+
+impl Program {
+    /// `(selector, expression)` pairs, a selector is a tag, a chan id or
+    /// `all`, `bands` are the audio band names the expressions can use
+    pub fn compile<S: AsRef<str>>(rules: &[(S, S)], bands: &[String])
+            -> Result<Self, String> {
+        let rules = rules.iter()
+            .map(|(selector, src)| {
+                let (selector, src) = (selector.as_ref(), src.as_ref());
+                compile(src, bands)
+                    .map(|expr| (Selector::parse(selector), expr))
+                    .map_err(|e| format!("{}: '{}': {}", selector, src, e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Program { rules })
+    }
+
+    fn expr_for(&self, chan: &ChanDescription) -> Option<&Expr> {
+        self.rules.iter()
+            .find(|(selector, _)| selector.matches(chan))
+            .map(|(_, expr)| expr)
+    }
+}
+
+/// `selector: expression` keeping their order, i.e.
+/// `wall: 0.5 + 0.4*sin(t*0.1 + x)`, `3: chan(2)` or `all: 0`
+mod ordered {
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::ser::{SerializeMap, Serializer};
+
+    pub fn serialize<S: Serializer>(rules: &[(String, String)], s: S)
+            -> Result<S::Ok, S::Error> {
+        let mut map = s.serialize_map(Some(rules.len()))?;
+        for (selector, expr) in rules {
+            map.serialize_entry(selector, expr)?;
+        }
+        map.end()
+    }
+
+    fn scalar<E: de::Error>(val: serde_yaml::Value) -> Result<String, E> {
+        match val {
+            serde_yaml::Value::String(s) => Ok(s),
+            serde_yaml::Value::Number(n) => Ok(n.to_string()),
+            other => Err(E::custom(format!(
+                        "expected a selector or an expression, got {:?}",
+                        other))),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D)
+            -> Result<Vec<(String, String)>, D::Error> {
+        serde_yaml::Mapping::deserialize(d)?.into_iter()
+            .map(|(selector, expr)| Ok((scalar(selector)?, scalar(expr)?)))
+            .collect()
+    }
+}
+
+/// ```yaml
+/// expr:
+///   chans:           # first matching selector wins, others are left alone
+///     wall: 0.5 + 0.4*sin(t*0.1 + x)
+///     2: 0.5 + 0.5*sin(t)
+///     3: chan(2)*0.5 # chan 2 as of last frame
+///   audio: { input: { alsa: { } } }   # rms and bands, 0 without it
+/// ```
+///
+/// `v` and `chan()` only see what the expressions rendered, not what's on
+/// the lights, so they're 0 for chans no selector matches
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExprConfig {
+    #[serde(with = "ordered")]
+    pub chans: Vec<(String, String)>,
+    pub audio: Option<AudioConfig>,
+}
+
+impl ExprConfig {
+    pub fn program(&self) -> Result<Program, String> {
+        let bands = self.audio.clone().unwrap_or_default().bands.iter()
+            .map(|band| band.name.clone())
+            .collect::<Vec<_>>();
+        Program::compile(&self.chans, &bands)
+    }
+}
+
+/// Chans set by expressions, the ones no selector matches are left alone
+pub struct ExprEffect {
+    program: Program,
+    levels: Option<SharedLevels>,
+    tempo: SharedTempo,
+    /// What we've rendered last, for `v` and `chan()`, the chans we don't
+    /// set aren't in it
+    prev: Frame<f32>,
+}
+
+impl ExprEffect {
+    pub fn new(program: Program) -> Self {
+        ExprEffect {
+            program,
+            levels: None,
+            tempo: tempo::shared(),
+            prev: Frame::empty(),
+        }
+    }
+
+    pub fn with_levels(mut self, levels: SharedLevels) -> Self {
+        self.levels = Some(levels);
+        self
+    }
+
+    pub fn with_tempo(mut self, tempo: SharedTempo) -> Self {
+        self.tempo = tempo;
+        self
+    }
+}
+
+impl Effect for ExprEffect {
+    fn render(&mut self, t: Duration, chans: &[ChanDescription])
+            -> Frame<f32> {
+        let levels = self.levels.as_ref()
+            .map(|levels| levels.lock().unwrap().clone())
+            .unwrap_or_default();
+        let (beat, bar) = {
+            let tempo = self.tempo.lock().unwrap();
+            (tempo.beats(), tempo.bars())
+        };
+
+        let mut frame = Frame::empty();
+        for (index, chan) in chans.iter().enumerate() {
+            let expr = match self.program.expr_for(chan) {
+                Some(expr) => expr,
+                None => continue,
+            };
+            let ctx = Ctx {
+                t: t.as_secs_f64(),
+                index,
+                count: chans.len(),
+                chan,
+                center: chan.config.cuboid.map(|c| c.center())
+                    .unwrap_or(Coord { x: 0.0, y: 0.0, z: 0.0 }),
+                prev: &self.prev,
+                levels: &levels,
+                beat,
+                bar,
+            };
+            frame.set(chan.chan_id, level(expr.eval(&ctx)));
+        }
+
+        self.prev = frame.clone();
+        frame
+    }
+}
+
+pub const EFFECT: EffectInfo = EffectInfo {
+    name: "expr",
+    description: "Chans set by an expression of t, i, x, y, z, beat, ...",
+    params: || vec![
+        ParamSpec::text("value", "expression, i.e. 0.5 + 0.4*sin(t + x)",
+                        "0.5 + 0.5*sin(t + i)"),
+        ParamSpec::text("chans", "a tag, a chan id or all", "all"),
+    ],
+    create: |params| {
+        let bands = AudioConfig::default().bands.into_iter()
+            .map(|band| band.name)
+            .collect::<Vec<_>>();
+        let program = Program::compile(
+            &[(params.str("chans"), params.str("value"))], &bands)?;
+        Ok(Box::new(ExprEffect::new(program)))
+    },
+};
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use crate::chan::ChanConfig;
+    use crate::clock::VirtualClock;
+    use crate::cuboid::Cuboid;
+    use crate::tempo::Tempo;
+    use crate::test_dev::chan;
+    use std::sync::{Arc, Mutex};
+
+    fn eval(src: &str) -> f64 {
+        let chan = ChanDescription::new(0, "test".to_string(),
+                                        ChanConfig::default());
+        let ctx = Ctx {
+            t: 2.0,
+            index: 0,
+            count: 1,
+            chan: &chan,
+            center: Coord { x: 0.25, y: 0.5, z: 1.0 },
+            prev: &Frame::empty(),
+            levels: &Levels::default(),
+            beat: 0.0,
+            bar: 0.0,
+        };
+        compile(src, &[]).unwrap().eval(&ctx)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(7.0, eval("1 + 2 * 3"));
+        assert_eq!(9.0, eval("(1 + 2) * 3"));
+        assert_eq!(-4.0, eval("-2^2"));
+        assert_eq!(512.0, eval("2^3^2"));
+        assert_eq!(1.0, eval("1 + 1 == 2"));
+        assert_eq!(0.75, eval("-t * 0.5 % 1 + 0.75 * (x < y)"));
+        assert_eq!(0.002, eval("2e-3"));
+        assert_eq!(3.0, eval("if(t >= 2, max(1, 3), 0)"));
+        assert!((eval("sin(pi / 2) * clamp(z, 0, 0.5)") - 0.5).abs() < 1e-9);
+
+        for bad in ["1 +", "foo", "sin(1, 2)", "nope(1)", "(1", "1 2",
+                    "1 = 2", "3 $ 4"] {
+            assert!(compile(bad, &[]).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_effect() {
+        let cuboid = Cuboid {
+            start: Coord { x: 0.0, y: 0.0, z: 0.0 },
+            end: Coord { x: 1.0, y: 0.5, z: 0.0 },
+        };
+        let chans = vec![
+            chan(0, &["wall"], Some(cuboid)),
+            chan(1, &["desk"], None),
+            chan(2, &[], None),
+            chan(3, &["lamp"], None),
+        ];
+
+        let config: ExprConfig = serde_yaml::from_str("
+            chans:
+              wall: x + y * tag_wall
+              1: min(v + 0.25, 1)
+              desk: 0
+              lamp: chan(1) * bass + beat
+        ").unwrap();
+        let levels = Arc::new(Mutex::new(Levels {
+            rms: 1.0,
+            bands: vec![0.5, 0.0, 0.0],
+        }));
+        let clock = VirtualClock::new();
+        let tempo = Tempo::new(60.0).with_clock(Arc::new(clock.clone()));
+        let mut effect = ExprEffect::new(config.program().unwrap())
+            .with_levels(levels)
+            .with_tempo(Arc::new(Mutex::new(tempo)));
+
+        let frame = effect.render(Duration::ZERO, &chans);
+        assert_eq!(Some(0.75), frame.get(0));
+        // the chan id wins, it's first
+        assert_eq!(Some(0.25), frame.get(1));
+        assert_eq!(None, frame.get(2));
+        assert_eq!(Some(0.0), frame.get(3));
+
+        clock.step(Duration::from_millis(250));
+        let frame = effect.render(Duration::ZERO, &chans);
+        assert_eq!(Some(0.5), frame.get(1));
+        assert_eq!(Some(0.375), frame.get(3));
+
+        // chan 2 isn't set by any expression, so chan() reads it as 0
+        let config: ExprConfig = serde_yaml::from_str("
+            chans:
+              3: chan(2)*0.5 + 0.25
+        ").unwrap();
+        let mut effect = ExprEffect::new(config.program().unwrap());
+        assert_eq!(Some(0.25), effect.render(Duration::ZERO, &chans).get(3));
+
+        assert!(ExprConfig {
+            chans: vec![("all".to_string(), "treble".to_string())],
+            audio: None,
+        }.program().is_err());
+    }
+}
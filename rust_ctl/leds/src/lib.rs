@@ -6,18 +6,39 @@ mod wrapper;
 pub mod chan;
 pub mod chan_spec;
 pub mod chan_description;
+pub mod chase;
+pub mod circadian;
+pub mod clock;
+pub mod audio;
+pub mod compositor;
 pub mod parse_ip_port;
 mod controller;
 pub mod coord;
 mod cuboid;
 pub mod demo;
+pub mod easing;
+pub mod expr;
+pub mod effect;
 pub mod dev;
 mod dev_stats;
 mod filters;
 pub mod msg_handler;
+pub mod pointer;
 pub mod runner;
+pub mod safety;
+pub mod schedule;
+pub mod scene;
+pub mod script;
+pub mod sun;
+pub mod spatial;
+pub mod sequence;
 pub mod mux;
+pub mod natural;
+pub mod random;
+pub mod recording;
 pub mod task;
+pub mod timeline;
+pub mod tempo;
 mod test_dev;
 pub mod udp_srv;
 pub mod udp_srv_v3;
@@ -28,4 +49,3 @@ mod usb;
 mod wacom;
 pub mod frame;
 pub mod tag;
-pub mod template;
@@ -5,7 +5,8 @@ use crate::msg_handler::{MsgHandler};
 use crate::chan_description::{ChanDescription, HasChanDescriptions};
 use proto::v1::{ChanId, ChanVal, Msg, Val};
 use crate::dev_stats;
-use std::time::Duration;
+use crate::safety::{SafetyConfig, StrobeLimiter};
+use std::time::{Duration, Instant};
 use std::fmt::{self, Display, Formatter};
 use std::sync::{Arc, Mutex};
 
@@ -18,6 +19,7 @@ pub use config::{Config, DevConfig, DevChanConfig};
 pub struct Mux {
     devs: Vec<MuxDev>,
     chans: Vec<MuxChan>,
+    limiter: Option<StrobeLimiter>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -61,6 +63,7 @@ impl Mux {
             Result<Arc<Mutex<dev_stats::DevStats<Mux>>>, String> {
         let devs = init_devs::init_devs(config)?; // dyn
         let mut srv = Mux::new();
+        srv.set_safety(config.safety.clone());
         for (dev, chancfg) in devs.into_iter() {
             srv.add_dev(dev, chancfg.map(|c| c.into_iter()));
         }
@@ -132,6 +135,21 @@ impl Mux {
         dev_id
     }
 
+    /// Limits flashing of the chans `cfg` applies to
+    pub fn set_safety(&mut self, cfg: SafetyConfig) {
+        self.limiter = (cfg.enabled || !cfg.forced.is_empty())
+            .then(|| StrobeLimiter::new(cfg));
+    }
+
+    /// Lets the strobe limiter hold back the values of `frame`
+    fn limit(&mut self, frame: &mut Frame<f32>) {
+        let Some(limiter) = &mut self.limiter else { return };
+        let chans = &self.chans;
+        limiter.limit(frame, |cid| chans.get(cid as usize)
+                .map(|c| c.cfg.tags.as_slice()).unwrap_or(&[]),
+            Instant::now());
+    }
+
     fn get_dev(&self, id: &DevId) -> Arc<Mutex<dyn Dev>> {
         let DevId(idx) = id;
         self.devs[*idx as usize].dev.clone()
@@ -140,9 +158,20 @@ impl Mux {
 
 impl MsgHandler for Mux {
     fn handle_msg(&mut self, msg: &Msg) -> Result<(), String> {
+        let mut limited = Frame::empty();
+        if self.limiter.is_some() {
+            for ChanVal(ChanId(cid), val) in msg.vals.iter() {
+                if let Val::F32(fval) = val {
+                    limited.set(*cid, *fval);
+                }
+            }
+            self.limit(&mut limited);
+        }
+
         for ChanVal(ChanId(cid), val) in msg.vals.iter() {
             match val {
                 Val::F32(fval) => {
+                    let fval = &limited.get(*cid).unwrap_or(*fval);
                     if *cid as usize >= self.chans.len() {
                         eprintln!("srv: chan {cid} out of bounds");
                         return Ok(())
@@ -275,6 +304,15 @@ impl Mux {
 impl DevWrite for Mux {
     fn set_frame(&mut self, frame: &Frame<f32>) -> Result<(), String> {
         // eprintln!("Mux set_frame {frame:?}");
+        let mut limited;
+        let frame = if self.limiter.is_some() {
+            limited = frame.clone();
+            self.limit(&mut limited);
+            &limited
+        } else {
+            frame
+        };
+
         for (cid, val) in frame.vals.iter().enumerate() {
             let cid = cid as u16;
             if let Some(val) = val {
@@ -316,17 +354,17 @@ mod tests {
             ChanConfig {
                 index: 0, min: 0.0, max: 1.0, exp: Some(2.2),
                 tags: Vec::new(), cuboid: None,
-                disco_config: None,
+                disco_config: None, order: None,
             },
             ChanConfig {
                 index: 1, min: 0.0, max: 1.0, exp: Some(2.2),
                 tags: Vec::new(), cuboid: None,
-                disco_config: None,
+                disco_config: None, order: None,
             },
             ChanConfig {
                 index: 2, min: 0.0, max: 1.0, exp: Some(2.2),
                 tags: Vec::new(), cuboid: None,
-                disco_config: None,
+                disco_config: None, order: None,
             },
 
         ];
@@ -367,17 +405,17 @@ mod tests {
             ChanConfig {
                 index: 0, min: 0.0, max: 1.0, exp: Some(2.2),
                 tags: Vec::new(), cuboid: None,
-                disco_config: None,
+                disco_config: None, order: None,
             },
             ChanConfig {
                 index: 1, min: 0.0, max: 1.0, exp: Some(2.2),
                 tags: Vec::new(), cuboid: None,
-                disco_config: None,
+                disco_config: None, order: None,
             },
             ChanConfig {
                 index: 2, min: 0.0, max: 1.0, exp: Some(2.2),
                 tags: Vec::new(), cuboid: None,
-                disco_config: None,
+                disco_config: None, order: None,
             },
 
         ]; // TODO: try with it too
@@ -1,12 +1,17 @@
 use core::num::ParseIntError;
 use crate::chan::ChanConfig;
 use crate::parse_ip_port::parse_ip_port;
+use crate::safety::SafetyConfig;
 use serde_derive::{Deserialize, Serialize};
 use std::net::IpAddr;
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Config {
     pub devs: Vec<DevChanConfig>,
+
+    /// Strobe limiter for everything going out
+    #[serde(default)]
+    pub safety: SafetyConfig,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
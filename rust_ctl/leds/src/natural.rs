@@ -0,0 +1,380 @@
+use crate::chan_description::ChanDescription;
+use crate::coord::Coord;
+use crate::effect::{Assigned, Effect, EffectInfo, ParamSpec};
+use crate::frame::Frame;
+use crate::random;
+use crate::spatial::{Field, Noise, SpatialEffect};
+
+use rand::Rng;
+use std::time::Duration;
+
+/// Random events are decided per slot this long, so at most one per slot
+const SLOT: f32 = 1.0;
+
+/// How long a gutter takes to go dark and to recover
+const GUTTER_FALL: f32 = 0.15;
+const GUTTER_RECOVERY: f32 = 0.5;
+
+/// Return strokes of a strike come this far apart, and fade this fast
+const STROKE_GAP: (f32, f32) = (0.04, 0.14);
+const STROKE_DECAY: f32 = 0.03;
+const MAX_STROKES: f32 = 4.0;
+
+/// Hashes and smooth noise over time, the same for the same seed
+struct Random(Noise);
+
+impl Random {
+    fn new(seed: u32) -> Self {
+        Random(Noise { scale: 1.0, drift: Coord { x: 0.0, y: 0.0, z: 0.0 },
+                       seed })
+    }
+
+    fn seeded(seed: Option<u64>) -> Self {
+        Random::new(random::rng(seed).gen())
+    }
+
+    /// 0 to 1 for `(a, b, c)`
+    fn hash(&self, a: i32, b: i32, c: i32) -> f32 {
+        self.0.lattice(a, b, c)
+    }
+
+    /// 0 to 1 changing smoothly with `t`, a different curve for every `row`
+    fn smooth(&self, row: i32, t: f32) -> f32 {
+        self.0.sample(t, row as f32, 0.0)
+    }
+
+    /// Start times and slots of events happening `rate` times a second on
+    /// average, the ones in the `lookback` seconds up to `t`
+    fn events(&self, row: i32, rate: f32, t: f32, lookback: f32)
+            -> impl Iterator<Item = (f32, i32)> + '_ {
+        let first = ((t - lookback) / SLOT).floor() as i32;
+        let last = (t / SLOT).floor() as i32;
+        (first..=last)
+            .filter(move |&slot| self.hash(slot, row, 0) < rate * SLOT)
+            .map(move |slot| ((slot as f32 + self.hash(slot, row, 1)) * SLOT,
+                              slot))
+            .filter(move |&(start, _)| start <= t && start > t - lookback)
+    }
+}
+
+/// A candle flame per chan: correlated flicker and now and then a gutter
+/// when it nearly goes out
+pub struct Candle {
+    pub brightness: f32,
+    /// How much of the brightness flickers away
+    pub flicker: f32,
+    pub speed: f32,
+    /// Gutters per second per chan
+    pub gutters: f32,
+    random: Random,
+}
+
+impl Candle {
+    pub fn new(brightness: f32, flicker: f32, speed: f32, gutters: f32)
+            -> Self {
+        Candle { brightness, flicker, speed, gutters,
+                 random: Random::seeded(None) }
+    }
+
+    /// How far down gutters have taken the flame of `row` at `t`, 0 to 1
+    fn gutter(&self, row: i32, t: f32) -> f32 {
+        let lookback = GUTTER_FALL + GUTTER_RECOVERY * 6.0;
+        self.random.events(row, self.gutters, t, lookback)
+            .map(|(start, slot)| {
+                let depth = 0.5 + 0.4 * self.random.hash(slot, row, 2);
+                let dt = t - start;
+                depth * match dt < GUTTER_FALL {
+                    true => dt / GUTTER_FALL,
+                    false => (-(dt - GUTTER_FALL) / GUTTER_RECOVERY).exp(),
+                }
+            })
+            .fold(0.0, f32::max)
+    }
+}
+
+impl Effect for Candle {
+    fn render(&mut self, t: Duration, chans: &[ChanDescription]) -> Frame<f32> {
+        let t = t.as_secs_f32();
+        let ts = t * self.speed;
+        let mut frame = Frame::empty();
+        for chan in chans {
+            let row = chan.chan_id as i32 * 4;
+            // slow sway with quicker flutter on top
+            let flame = 0.5 * self.random.smooth(row, ts * 1.5)
+                + 0.3 * self.random.smooth(row + 1, ts * 4.0)
+                + 0.2 * self.random.smooth(row + 2, ts * 11.0);
+            let level = self.brightness * (1.0 - self.flicker * (1.0 - flame));
+            let val = level * (1.0 - self.gutter(row + 3, t));
+            frame.set(chan.chan_id, val.clamp(0.0, 1.0));
+        }
+        frame
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.random = Random::seeded(Some(seed));
+    }
+}
+
+/// Flames licking up from the floor, hottest at the bottom and dimmer
+/// towards `height` above it
+pub struct Fire {
+    pub brightness: f32,
+    /// Room units above the floor where flames are a third as bright
+    pub height: f32,
+    pub scale: f32,
+    /// Room units per second the flames go up
+    pub rise: f32,
+    noise: Noise,
+}
+
+impl Fire {
+    pub fn new(brightness: f32, height: f32, scale: f32, rise: f32) -> Self {
+        Fire { brightness, height, scale, rise,
+               noise: Fire::noise(scale, rise, None) }
+    }
+
+    fn noise(scale: f32, rise: f32, seed: Option<u64>) -> Noise {
+        // up is towards the ceiling at y = 0, z makes the flames change shape
+        // as they go up rather than just moving
+        Noise {
+            scale,
+            drift: Coord { x: 0.0, y: rise, z: rise * 0.5 },
+            seed: random::rng(seed).gen(),
+        }
+    }
+}
+
+impl Field for Fire {
+    fn at(&self, t: f32, p: Coord) -> f32 {
+        let above_floor = (1.0 - p.y).max(0.0);
+        let heat = 0.3 + 0.7 * self.noise.at(t, p);
+        self.brightness * heat * (-above_floor / self.height).exp()
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.noise = Fire::noise(self.scale, self.rise, Some(seed));
+    }
+}
+
+/// A storm: strikes of a few strokes each, `rate` per second, with an
+/// afterglow, brighter for chans closer to where it strikes
+pub struct Lightning {
+    pub rate: f32,
+    pub brightness: f32,
+    /// Seconds the afterglow takes to fade to a third
+    pub afterglow: f32,
+    /// Level between the strikes
+    pub ambient: f32,
+    random: Random,
+}
+
+impl Lightning {
+    pub fn new(rate: f32, brightness: f32, afterglow: f32, ambient: f32)
+            -> Self {
+        Lightning { rate, brightness, afterglow, ambient,
+                    random: Random::seeded(None) }
+    }
+
+    /// Light of the strike in `slot`, `dt` seconds after it started
+    fn strike(&self, slot: i32, dt: f32) -> f32 {
+        let strokes = 1 + (self.random.hash(slot, 0, 2) * MAX_STROKES) as i32;
+        let mut light = 0.0;
+        let mut last = 0.0;
+        for stroke in 0..strokes {
+            let start = match stroke {
+                0 => 0.0,
+                _ => last + STROKE_GAP.0 + (STROKE_GAP.1 - STROKE_GAP.0)
+                    * self.random.hash(slot, stroke, 3),
+            };
+            if dt < start {
+                break;
+            }
+            last = start;
+            let strength = match stroke {
+                0 => 1.0,
+                _ => 0.5 + 0.5 * self.random.hash(slot, stroke, 4),
+            };
+            light += strength * (-(dt - start) / STROKE_DECAY).exp();
+        }
+        let glow = 0.3 * (-(dt - last) / self.afterglow).exp();
+        light.max(glow)
+    }
+
+    /// How much of a strike in `slot` reaches `chan`
+    fn reach(&self, slot: i32, chan: &ChanDescription) -> f32 {
+        match &chan.config.cuboid {
+            Some(cuboid) => {
+                let at = Coord {
+                    x: self.random.hash(slot, 1, 5),
+                    y: 0.0,
+                    z: self.random.hash(slot, 2, 5),
+                };
+                1.0 / (1.0 + 2.0 * cuboid.center().dist_to(&at))
+            },
+            None => 0.6 + 0.4 * self.random.hash(slot, chan.chan_id as i32, 6),
+        }
+    }
+}
+
+impl Effect for Lightning {
+    fn render(&mut self, t: Duration, chans: &[ChanDescription]) -> Frame<f32> {
+        let t = t.as_secs_f32();
+        let lookback = STROKE_GAP.1 * MAX_STROKES + self.afterglow * 5.0;
+        let strikes: Vec<(i32, f32)> = self.random
+            .events(0, self.rate, t, lookback)
+            .map(|(start, slot)| (slot, self.strike(slot, t - start)))
+            .collect();
+
+        let mut frame = Frame::empty();
+        for chan in chans {
+            let light = strikes.iter()
+                .map(|&(slot, light)| light * self.reach(slot, chan))
+                .fold(0.0, f32::max);
+            let val = self.ambient + self.brightness * light;
+            frame.set(chan.chan_id, val.clamp(0.0, 1.0));
+        }
+        frame
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.random = Random::seeded(Some(seed));
+    }
+}
+
+pub const CANDLE: EffectInfo = EffectInfo {
+    name: "candle",
+    description: "Candle flames flickering, guttering now and then",
+    params: || vec![
+        ParamSpec::text("chans", "a tag, a chan id or all", "all"),
+        ParamSpec::float("brightness", "brightest the flame gets", 0.8,
+                         0.0, 1.0),
+        ParamSpec::float("flicker", "how much of it flickers away", 0.35,
+                         0.0, 1.0),
+        ParamSpec::float("speed", "faster or slower flicker", 1.0, 0.1, 10.0),
+        ParamSpec::float("gutters", "gutters per minute per chan", 2.0,
+                         0.0, 60.0),
+    ],
+    create: |params| Ok(Box::new(Assigned::new(params.str("chans"),
+        Candle::new(params.f32("brightness"), params.f32("flicker"),
+                    params.f32("speed"), params.f32("gutters") / 60.0)))),
+};
+
+pub const FIRE: EffectInfo = EffectInfo {
+    name: "fire",
+    description: "Flames going up from the floor, chans with a cuboid",
+    params: || vec![
+        ParamSpec::text("chans", "a tag, a chan id or all", "all"),
+        ParamSpec::float("brightness", "brightest the flames get", 1.0,
+                         0.0, 1.0),
+        ParamSpec::float("height", "how high the flames reach", 0.4,
+                         0.01, 10.0),
+        ParamSpec::float("scale", "flames across the room", 4.0, 0.1, 100.0),
+        ParamSpec::float("rise", "how fast the flames go up", 0.6,
+                         0.0, 10.0),
+    ],
+    create: |params| Ok(Box::new(Assigned::new(params.str("chans"),
+        SpatialEffect::new(Fire::new(
+            params.f32("brightness"), params.f32("height"),
+            params.f32("scale"), params.f32("rise")))))),
+};
+
+pub const LIGHTNING: EffectInfo = EffectInfo {
+    name: "lightning",
+    description: "A storm, strikes of a few flashes with an afterglow",
+    params: || vec![
+        ParamSpec::text("chans", "a tag, a chan id or all", "all"),
+        ParamSpec::float("rate", "strikes per minute", 6.0, 0.0, 60.0),
+        ParamSpec::float("brightness", "brightest a flash gets", 1.0,
+                         0.0, 1.0),
+        ParamSpec::float("afterglow", "seconds the glow lingers", 0.4,
+                         0.01, 10.0),
+        ParamSpec::float("ambient", "level between strikes", 0.02, 0.0, 1.0),
+    ],
+    create: |params| Ok(Box::new(Assigned::new(params.str("chans"),
+        Lightning::new(params.f32("rate") / 60.0, params.f32("brightness"),
+                       params.f32("afterglow"), params.f32("ambient"))))),
+};
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use crate::cuboid::Cuboid;
+    use crate::test_dev::chan;
+
+    /// A chan at the floor, one at the ceiling and one without a cuboid
+    fn chans() -> Vec<ChanDescription> {
+        let at = |y: f32| Some(Cuboid {
+            start: Coord { x: 0.2, y, z: 0.2 },
+            end: Coord { x: 0.8, y, z: 0.3 },
+        });
+        // on the floor and on the ceiling
+        vec![chan(0, &["fireplace"], at(0.95)),
+             chan(1, &["fireplace"], at(0.05)),
+             chan(2, &[], None)]
+    }
+
+    /// Values of `chan` every 10ms for `secs`
+    fn render(effect: &mut dyn Effect, chan: u16, secs: u32) -> Vec<f32> {
+        let chans = chans();
+        (0..secs * 100)
+            .map(|i| effect.render(Duration::from_millis(i as u64 * 10), &chans)
+                 .get(chan).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_candle() {
+        let mut candle = Candle::new(0.8, 0.35, 1.0, 0.0);
+        candle.reseed(1);
+        let vals = render(&mut candle, 2, 10);
+        assert!(vals.iter().all(|v| (0.8 * 0.65 - 1e-6..=0.8).contains(v)));
+        // flickering but not jumping around
+        assert!(vals.windows(2).all(|w| (w[0] - w[1]).abs() < 0.05));
+        assert!(vals.iter().any(|v| (v - vals[0]).abs() > 0.02));
+
+        // a gutter every second, nearly out at times
+        let mut guttering = Candle::new(0.8, 0.35, 1.0, 1.0);
+        guttering.reseed(1);
+        let low = render(&mut guttering, 2, 10).into_iter()
+            .fold(1.0, f32::min);
+        assert!(low < 0.25, "{}", low);
+    }
+
+    #[test]
+    fn test_fire() {
+        let mut fire = Assigned::new("fireplace",
+            SpatialEffect::new(Fire::new(1.0, 0.4, 4.0, 0.6)));
+        fire.reseed(2);
+        let chans = chans();
+        let frames: Vec<Frame<f32>> = (0..100)
+            .map(|i| fire.render(Duration::from_millis(i * 50), &chans))
+            .collect();
+        assert!(frames.iter().all(|f| f.get(2).is_none()));
+
+        let avg = |cid: u16| frames.iter()
+            .map(|f| f.get(cid).unwrap()).sum::<f32>() / frames.len() as f32;
+        assert!(avg(0) > 0.3, "{}", avg(0));
+        assert!(avg(1) < avg(0) / 5.0, "{} {}", avg(1), avg(0));
+    }
+
+    #[test]
+    fn test_lightning() {
+        let mut storm = Lightning::new(0.5, 1.0, 0.4, 0.0);
+        storm.reseed(3);
+        let vals = render(&mut storm, 2, 60);
+
+        // mostly dark with a few strikes
+        let lit = vals.iter().filter(|&&v| v > 0.2).count();
+        assert!(lit > 0 && lit < vals.len() / 5, "{}", lit);
+
+        // flashes of a strike are separate: up, down and back up
+        let flashes = vals.windows(2).filter(|w| w[1] - w[0] > 0.2).count();
+        let strikes = storm.random.events(0, 0.5, 60.0, 60.0).count();
+        assert!(flashes > strikes, "{} {}", flashes, strikes);
+
+        let mut again = Lightning::new(0.5, 1.0, 0.4, 0.0);
+        again.reseed(3);
+        assert_eq!(vals, render(&mut again, 2, 60));
+    }
+}
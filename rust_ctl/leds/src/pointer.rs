@@ -0,0 +1,539 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::clock::{self, Clock};
+use crate::coord::Coord;
+use crate::wacom::Wacom;
+
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+use std::fs::File;
+use std::io::Read;
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_WACOM_PATH: &str = "/dev/hidraw8";
+pub const DEFAULT_UDP_ADDR: &str = "0.0.0.0:9000";
+
+// evdev absolute axis codes, wacom packets are mapped to them too
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const ABS_Z: u16 = 0x02;
+const ABS_PRESSURE: u16 = 0x18;
+const ABS_DISTANCE: u16 = 0x19;
+
+const EV_SYN: u16 = 0x00;
+const EV_ABS: u16 = 0x03;
+
+/// `struct input_event` on 64 bit: 16 bytes of timeval, type, code, value
+const EVDEV_EVENT_LEN: usize = 24;
+
+const LISSAJOUS_FRAME: Duration = Duration::from_millis(10);
+
+/// Something pointing somewhere in the room, 0 to 1 on every axis
+pub trait Pointer: Send {
+    /// Blocks until there's a new position
+    fn read(&mut self) -> Result<Coord, String>;
+}
+
+/// Maps a raw device axis onto 0 to 1
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AxisMap {
+    /// `x`, `y`, `z`, `pressure`, `distance` or an evdev ABS_* code
+    pub axis: String,
+    pub min: f32,
+    pub max: f32,
+    #[serde(default)]
+    pub invert: bool,
+}
+
+impl AxisMap {
+    fn new(axis: &str, min: f32, max: f32, invert: bool) -> Self {
+        AxisMap { axis: axis.to_string(), min, max, invert }
+    }
+
+    fn code(&self) -> Result<u16, String> {
+        match self.axis.as_ref() {
+            "x" => Ok(ABS_X),
+            "y" => Ok(ABS_Y),
+            "z" => Ok(ABS_Z),
+            "pressure" => Ok(ABS_PRESSURE),
+            "distance" => Ok(ABS_DISTANCE),
+            code => code.parse().map_err(|_| format!(
+                    "unknown axis '{}', expected x, y, z, pressure, \
+                    distance or an ABS_* code", code)),
+        }
+    }
+
+    fn map(&self, raw: &HashMap<u16, f32>) -> Option<f32> {
+        let val = *raw.get(&self.code().ok()?)?;
+        let val = ((val - self.min) / (self.max - self.min)).clamp(0.0, 1.0);
+        match self.invert {
+            true => Some(1.0 - val),
+            false => Some(val),
+        }
+    }
+}
+
+/// Which raw axis goes where in the room, a missing one stays put
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub x: Option<AxisMap>,
+    pub y: Option<AxisMap>,
+    pub z: Option<AxisMap>,
+}
+
+impl Calibration {
+    /// The tablet lying with its long side along the window, the pen
+    /// hovering higher is further from the window
+    pub fn wacom() -> Self {
+        Calibration {
+            x: Some(AxisMap::new("y", 0.0, 0x34bc as f32, true)),
+            y: Some(AxisMap::new("x", 0.0, 0x5460 as f32, false)),
+            z: Some(AxisMap::new("distance", 0.0, 0x29 as f32, false)),
+        }
+    }
+
+    /// Needs the ranges from `evtest` for anything but the most common
+    /// 15 bit devices
+    pub fn evdev() -> Self {
+        Calibration {
+            x: Some(AxisMap::new("x", 0.0, 32767.0, false)),
+            y: Some(AxisMap::new("y", 0.0, 32767.0, false)),
+            z: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for axis in [&self.x, &self.y, &self.z].into_iter().flatten() {
+            axis.code()?;
+            if axis.min == axis.max {
+                return Err(format!("axis {}: min and max are the same",
+                                   axis.axis));
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&self, raw: &HashMap<u16, f32>, pos: &mut Coord) {
+        if let Some(x) = self.x.as_ref().and_then(|axis| axis.map(raw)) {
+            pos.x = x;
+        }
+        if let Some(y) = self.y.as_ref().and_then(|axis| axis.map(raw)) {
+            pos.y = y;
+        }
+        if let Some(z) = self.z.as_ref().and_then(|axis| axis.map(raw)) {
+            pos.z = z;
+        }
+    }
+}
+
+fn center() -> Coord {
+    Coord { x: 0.5, y: 0.5, z: 0.5 }
+}
+
+/// Wacom tablet on hidraw
+pub struct WacomPointer<R: Read> {
+    wacom: Wacom<R>,
+    calibration: Calibration,
+    pos: Coord,
+}
+
+impl<R: Read> WacomPointer<R> {
+    pub fn new(wacom: Wacom<R>, calibration: Calibration) -> Self {
+        WacomPointer { wacom, calibration, pos: center() }
+    }
+}
+
+impl<R: Read + Send> Pointer for WacomPointer<R> {
+    fn read(&mut self) -> Result<Coord, String> {
+        let packet = self.wacom.read()?;
+        let raw = HashMap::from([
+            (ABS_X, packet.x as f32),
+            (ABS_Y, packet.y as f32),
+            (ABS_PRESSURE, packet.pressure as f32),
+            (ABS_DISTANCE, packet.distance as f32),
+        ]);
+        self.calibration.apply(&raw, &mut self.pos);
+        Ok(self.pos)
+    }
+}
+
+/// Linux input device with absolute axes, i.e. `/dev/input/event5`
+pub struct Evdev<R: Read> {
+    file: R,
+    calibration: Calibration,
+    raw: HashMap<u16, f32>,
+    pos: Coord,
+}
+
+impl<R: Read> Evdev<R> {
+    pub fn new(file: R, calibration: Calibration) -> Self {
+        Evdev { file, calibration, raw: HashMap::new(), pos: center() }
+    }
+}
+
+impl<R: Read + Send> Pointer for Evdev<R> {
+    /// Collects axis events until the device says they're complete
+    fn read(&mut self) -> Result<Coord, String> {
+        let mut buf = [0u8; EVDEV_EVENT_LEN];
+        loop {
+            self.file.read_exact(&mut buf)
+                .map_err(|e| format!("reading evdev: {:?}", e))?;
+            let kind = u16::from_ne_bytes([buf[16], buf[17]]);
+            let code = u16::from_ne_bytes([buf[18], buf[19]]);
+            let value = i32::from_ne_bytes([buf[20], buf[21], buf[22], buf[23]]);
+            match kind {
+                EV_ABS => {
+                    self.raw.insert(code, value as f32);
+                },
+                EV_SYN => {
+                    self.calibration.apply(&self.raw, &mut self.pos);
+                    return Ok(self.pos);
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+/// Positions over UDP, either OSC messages with 2 or 3 numbers
+/// (`/space ,fff 0.1 0.5 0.3`) or text like `0.1,0.5,0.3`
+pub struct UdpPointer {
+    socket: UdpSocket,
+    pos: Coord,
+}
+
+impl UdpPointer {
+    pub fn bind(addr: &str) -> Result<Self, String> {
+        let socket = UdpSocket::bind(addr)
+            .map_err(|e| format!("binding {}: {:?}", addr, e))?;
+        Ok(UdpPointer { socket, pos: center() })
+    }
+
+    fn update(&mut self, datagram: &[u8]) -> Result<Coord, String> {
+        let vals = match datagram.first() {
+            Some(b'/') => parse_osc(datagram)?,
+            _ => parse_text(datagram)?,
+        };
+        match vals[..] {
+            [x, y] => {
+                self.pos.x = x;
+                self.pos.y = y;
+            },
+            [x, y, z, ..] => self.pos = Coord { x, y, z },
+            _ => return Err(format!("expected 2 or 3 numbers, got {}",
+                                    vals.len())),
+        }
+        Ok(self.pos)
+    }
+}
+
+impl Pointer for UdpPointer {
+    fn read(&mut self) -> Result<Coord, String> {
+        let mut buf = [0u8; 1024];
+        loop {
+            let len = self.socket.recv(&mut buf)
+                .map_err(|e| format!("udp pointer: {:?}", e))?;
+            match self.update(&buf[..len]) {
+                Ok(pos) => return Ok(pos),
+                // someone else's garbage shouldn't stop the show
+                Err(e) => eprintln!("udp pointer: {}", e),
+            }
+        }
+    }
+}
+
+/// `x,y,z` or `x y z`
+fn parse_text(datagram: &[u8]) -> Result<Vec<f32>, String> {
+    let text = std::str::from_utf8(datagram)
+        .map_err(|e| format!("{:?}", e))?;
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<f32>()
+             .map_err(|e| format!("'{}': {:?}", part, e)))
+        .collect()
+}
+
+/// Number arguments of an OSC message, the address is ignored
+fn parse_osc(datagram: &[u8]) -> Result<Vec<f32>, String> {
+    // strings are null terminated and padded to 4 bytes
+    let string_end = |from: usize| -> Result<(usize, usize), String> {
+        let len = datagram[from..].iter().position(|b| *b == 0)
+            .ok_or("unterminated OSC string")?;
+        Ok((from + len, (from + len + 4) & !3))
+    };
+    let (_, tags_start) = string_end(0)?;
+    if datagram.get(tags_start) != Some(&b',') {
+        return Err("OSC message without type tags".to_string());
+    }
+    let (tags_end, mut at) = string_end(tags_start)?;
+
+    let mut arg = |len: usize| -> Result<&[u8], String> {
+        let bytes = datagram.get(at..at + len)
+            .ok_or("OSC message too short")?;
+        at += len;
+        Ok(bytes)
+    };
+    let mut vals = vec![];
+    for tag in &datagram[tags_start + 1..tags_end] {
+        vals.push(match tag {
+            b'f' => f32::from_be_bytes(arg(4)?.try_into().unwrap()),
+            b'i' => i32::from_be_bytes(arg(4)?.try_into().unwrap()) as f32,
+            b'd' => f64::from_be_bytes(arg(8)?.try_into().unwrap()) as f32,
+            other => return Err(format!("unsupported OSC type '{}'",
+                                        *other as char)),
+        });
+    }
+    Ok(vals)
+}
+
+/// Goes around the room on its own, no hardware needed
+pub struct Lissajous {
+    /// Hz per axis
+    pub freq: Coord,
+    started: Instant,
+    clock: Arc<dyn Clock>,
+}
+
+impl Lissajous {
+    pub fn new(freq: Coord) -> Self {
+        let clock = clock::real();
+        Lissajous { freq, started: clock.now(), clock }
+    }
+
+    /// Starts over at the time of `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.started = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    pub fn at(&self, t: f32) -> Coord {
+        Coord {
+            x: 0.5 + 0.5 * (t * self.freq.x * TAU).sin(),
+            y: 0.5 + 0.5 * (t * self.freq.y * TAU).sin(),
+            z: 0.5 + 0.5 * (t * self.freq.z * TAU).cos(),
+        }
+    }
+}
+
+impl Pointer for Lissajous {
+    fn read(&mut self) -> Result<Coord, String> {
+        self.clock.sleep(LISSAJOUS_FRAME);
+        Ok(self.at((self.clock.now() - self.started).as_secs_f32()))
+    }
+}
+
+fn default_wacom_path() -> PathBuf {
+    PathBuf::from(DEFAULT_WACOM_PATH)
+}
+
+fn default_udp_addr() -> String {
+    DEFAULT_UDP_ADDR.to_string()
+}
+
+fn default_lissajous_freq() -> Coord {
+    Coord { x: 0.15, y: 0.11, z: 0.15 }
+}
+
+/// Where `space` gets its position from, i.e.
+/// `pointer: { evdev: { path: /dev/input/event5 } }` in the config
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PointerSpec {
+    Wacom {
+        #[serde(default = "default_wacom_path")]
+        path: PathBuf,
+        #[serde(default = "Calibration::wacom")]
+        calibration: Calibration,
+    },
+    Evdev {
+        path: PathBuf,
+        #[serde(default = "Calibration::evdev")]
+        calibration: Calibration,
+    },
+    Udp {
+        #[serde(default = "default_udp_addr")]
+        listen: String,
+    },
+    Lissajous {
+        #[serde(default = "default_lissajous_freq")]
+        freq: Coord,
+    },
+}
+
+impl Default for PointerSpec {
+    fn default() -> Self {
+        PointerSpec::Wacom {
+            path: default_wacom_path(),
+            calibration: Calibration::wacom(),
+        }
+    }
+}
+
+impl PointerSpec {
+    /// `wacom[:PATH]`, `evdev:PATH`, `udp[:ADDR:PORT]` or `lissajous`
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (s, None),
+        };
+        match (kind, arg) {
+            ("wacom", path) => Ok(PointerSpec::Wacom {
+                path: path.map(PathBuf::from)
+                    .unwrap_or_else(default_wacom_path),
+                calibration: Calibration::wacom(),
+            }),
+            ("evdev", Some(path)) => Ok(PointerSpec::Evdev {
+                path: path.into(),
+                calibration: Calibration::evdev(),
+            }),
+            ("evdev", None) => Err("evdev needs a device, i.e. \
+                                   evdev:/dev/input/event5".to_string()),
+            ("udp", listen) => Ok(PointerSpec::Udp {
+                listen: listen.map(|l| l.to_string())
+                    .unwrap_or_else(default_udp_addr),
+            }),
+            ("lissajous", None) => Ok(PointerSpec::Lissajous {
+                freq: default_lissajous_freq(),
+            }),
+            _ => Err(format!("unknown pointer '{}', expected wacom[:PATH], \
+                             evdev:PATH, udp[:ADDR:PORT] or lissajous", s)),
+        }
+    }
+
+    pub fn open(&self) -> Result<Box<dyn Pointer>, String> {
+        match self {
+            PointerSpec::Wacom { path, calibration } => {
+                calibration.validate()?;
+                Ok(Box::new(WacomPointer::new(
+                            Wacom::open(path)?, calibration.clone())))
+            },
+            PointerSpec::Evdev { path, calibration } => {
+                calibration.validate()?;
+                let file = File::open(path)
+                    .map_err(|e| format!("opening {:?}: {:?}", path, e))?;
+                Ok(Box::new(Evdev::new(file, calibration.clone())))
+            },
+            PointerSpec::Udp { listen } =>
+                Ok(Box::new(UdpPointer::bind(listen)?)),
+            PointerSpec::Lissajous { freq } =>
+                Ok(Box::new(Lissajous::new(*freq))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use std::io::Cursor;
+
+    fn assert_near(expected: Coord, actual: Coord) {
+        assert!(expected.dist_to(&actual) < 1e-3,
+                "expected {:?} got {:?}", expected, actual);
+    }
+
+    fn evdev_event(kind: u16, code: u16, value: i32) -> Vec<u8> {
+        let mut event = vec![0u8; 16];
+        event.extend(kind.to_ne_bytes());
+        event.extend(code.to_ne_bytes());
+        event.extend(value.to_ne_bytes());
+        event
+    }
+
+    #[test]
+    fn test_devices() {
+        // y 0x34bc is x 0, x 0x2a30 is y 0.5
+        let packet = [0x02, 0x60, 0x30, 0x2a, 0xbc, 0x34, 0, 0, 0x29, 0];
+        let wacom = Wacom::new(Cursor::new(packet.to_vec()));
+        let mut pointer = WacomPointer::new(wacom, Calibration::wacom());
+        assert_near(Coord { x: 0.0, y: 0.5, z: 1.0 }, pointer.read().unwrap());
+        assert!(pointer.read().is_err());
+
+        let mut events = vec![];
+        events.extend(evdev_event(EV_ABS, ABS_X, 100));
+        events.extend(evdev_event(EV_ABS, ABS_Y, 300));
+        events.extend(evdev_event(0x01, 0x14a, 1)); // a key, ignored
+        events.extend(evdev_event(EV_SYN, 0, 0));
+        events.extend(evdev_event(EV_ABS, ABS_Y, 0));
+        events.extend(evdev_event(EV_SYN, 0, 0));
+        let calibration = Calibration {
+            x: Some(AxisMap::new("x", 0.0, 400.0, false)),
+            y: Some(AxisMap::new("1", 0.0, 400.0, true)),
+            z: None,
+        };
+        let mut evdev = Evdev::new(Cursor::new(events), calibration);
+        assert_near(Coord { x: 0.25, y: 0.25, z: 0.5 }, evdev.read().unwrap());
+        assert_near(Coord { x: 0.25, y: 1.0, z: 0.5 }, evdev.read().unwrap());
+        assert!(evdev.read().is_err());
+    }
+
+    #[test]
+    fn test_udp_datagrams() {
+        let mut osc = b"/space\0\0,fif\0\0\0\0".to_vec();
+        osc.extend(0.25f32.to_be_bytes());
+        osc.extend(1i32.to_be_bytes());
+        osc.extend(0.75f32.to_be_bytes());
+        assert_eq!(vec![0.25, 1.0, 0.75], parse_osc(&osc).unwrap());
+        assert!(parse_osc(&osc[..osc.len() - 2]).is_err());
+        assert!(parse_osc(b"/space\0\0,s\0\0abc\0").is_err());
+
+        assert_eq!(vec![0.1, 0.2, 0.3], parse_text(b"0.1,0.2 0.3\n").unwrap());
+        assert!(parse_text(b"0.1,up").is_err());
+
+        let mut pointer = UdpPointer::bind("127.0.0.1:0").unwrap();
+        assert_near(Coord { x: 0.25, y: 1.0, z: 0.75 },
+                    pointer.update(&osc).unwrap());
+        assert_near(Coord { x: 0.1, y: 0.2, z: 0.75 },
+                    pointer.update(b"0.1 0.2").unwrap());
+        assert!(pointer.update(b"0.1").is_err());
+
+        // over the wire
+        let addr = pointer.socket.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"nonsense", addr).unwrap();
+        sender.send_to(b"0.3,0.4,0.5", addr).unwrap();
+        assert_near(Coord { x: 0.3, y: 0.4, z: 0.5 }, pointer.read().unwrap());
+    }
+
+    #[test]
+    fn test_spec() {
+        assert_eq!(PointerSpec::default(), PointerSpec::parse("wacom").unwrap());
+        assert_eq!(PointerSpec::Udp { listen: "127.0.0.1:9001".to_string() },
+                   PointerSpec::parse("udp:127.0.0.1:9001").unwrap());
+        assert!(PointerSpec::parse("evdev").is_err());
+        assert!(PointerSpec::parse("mouse").is_err());
+
+        let spec: PointerSpec = serde_yaml::from_str(
+            "evdev: { path: /dev/input/event5, calibration: \
+             { x: { axis: x, min: 0, max: 4095 }, y: ~, z: ~ } }").unwrap();
+        match &spec {
+            PointerSpec::Evdev { calibration, .. } =>
+                assert_eq!(4095.0, calibration.x.as_ref().unwrap().max),
+            other => panic!("{:?}", other),
+        }
+
+        let bad = Calibration {
+            x: Some(AxisMap::new("tilt", 0.0, 1.0, false)),
+            y: None,
+            z: None,
+        };
+        assert!(bad.validate().is_err());
+
+        let lissajous = Lissajous::new(Coord { x: 0.25, y: 0.5, z: 1.0 });
+        assert_near(Coord { x: 0.5, y: 0.5, z: 1.0 }, lissajous.at(0.0));
+        assert_near(Coord { x: 1.0, y: 0.5, z: 1.0 }, lissajous.at(1.0));
+        assert_near(lissajous.at(0.3), lissajous.at(4.3));
+
+        let mut lissajous = lissajous
+            .with_clock(Arc::new(crate::clock::VirtualClock::new()));
+        for _ in 0..100 {
+            lissajous.read().unwrap();
+        }
+        // a frame after a second
+        assert_near(lissajous.at(1.01), lissajous.read().unwrap());
+    }
+}
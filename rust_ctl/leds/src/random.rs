@@ -0,0 +1,13 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+/// Random numbers for effects, seeded ones repeat the same show
+pub type Rng = StdRng;
+
+/// Same seed, same numbers, random without one
+pub fn rng(seed: Option<u64>) -> Rng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
@@ -0,0 +1,527 @@
+use crate::msg_handler::MsgHandler;
+use crate::chan::ChanConfig;
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+use crate::clock::{self, Clock};
+use crate::dev::{Dev, DevNumChans, DevRead, DevWrite};
+use crate::frame::Frame;
+use crate::runner::Runner;
+use crate::task::TaskMsg;
+use crate::wrapper::Wrapper;
+use proto::v1::{ChanId, ChanVal, Msg, Val};
+use serde_derive::{Deserialize, Serialize};
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// File format (all numbers are little endian):
+//
+//   6b   magic "LEDREC"
+//   2b   format version
+//   4b   header length
+//   ...  header, yaml serialized `Header`
+//
+// followed by frames until the end of the file:
+//
+//   8b   microseconds since the start of the recording
+//   2b   number of values in the frame
+//   6b   per value: 2b chan id, 4b f32 value
+
+const MAGIC: &[u8; 6] = b"LEDREC";
+const VERSION: u16 = 1;
+
+/// How often we flush the recording to disk
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a paused player waits for messages at a time
+const PAUSED_WAIT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedChan {
+    pub chan_id: u16,
+    pub name: String,
+    pub config: ChanConfig,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    pub chans: Vec<RecordedChan>,
+}
+
+impl Header {
+    pub fn from_chan_descriptions(descriptions: &[ChanDescription]) -> Self {
+        Header {
+            chans: descriptions
+                .iter()
+                .map(|d| RecordedChan {
+                    chan_id: d.chan_id,
+                    name: d.name.clone(),
+                    config: d.config.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn io_err(e: io::Error) -> String {
+    format!("recording: {:?}", e)
+}
+
+pub struct RecordWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(mut out: W, header: &Header) -> Result<Self, String> {
+        let header = serde_yaml::to_string(header)
+            .map_err(|e| format!("recording header: {:?}", e))?;
+
+        out.write_all(MAGIC).map_err(io_err)?;
+        out.write_all(&VERSION.to_le_bytes()).map_err(io_err)?;
+        out.write_all(&(header.len() as u32).to_le_bytes()).map_err(io_err)?;
+        out.write_all(header.as_bytes()).map_err(io_err)?;
+
+        Ok(RecordWriter { out })
+    }
+
+    /// `t` is the time since the start of the recording
+    pub fn write_frame(&mut self, t: Duration, frame: &Frame<f32>)
+            -> Result<(), String> {
+        let num_vals = frame.iter_some().count() as u16;
+
+        let mut buf: Vec<u8> = Vec::with_capacity(10 + num_vals as usize * 6);
+        buf.extend_from_slice(&(t.as_micros() as u64).to_le_bytes());
+        buf.extend_from_slice(&num_vals.to_le_bytes());
+        for (cid, val) in frame.iter_some() {
+            buf.extend_from_slice(&cid.to_le_bytes());
+            buf.extend_from_slice(&val.to_le_bytes());
+        }
+
+        self.out.write_all(&buf).map_err(io_err)
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.out.flush().map_err(io_err)
+    }
+}
+
+pub struct RecordReader<R: Read> {
+    input: R,
+    pub header: Header,
+}
+
+impl RecordReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let file = File::open(path.as_ref())
+            .map_err(|e| format!("open {:?}: {:?}", path.as_ref(), e))?;
+        RecordReader::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(mut input: R) -> Result<Self, String> {
+        let mut magic = [0u8; 6];
+        input.read_exact(&mut magic).map_err(io_err)?;
+        if &magic != MAGIC {
+            return Err("not a recording (invalid magic)".to_string());
+        }
+
+        let mut version = [0u8; 2];
+        input.read_exact(&mut version).map_err(io_err)?;
+        let version = u16::from_le_bytes(version);
+        if version != VERSION {
+            return Err(format!(
+                    "unsupported recording version {} (expected {})",
+                    version, VERSION));
+        }
+
+        let mut header_len = [0u8; 4];
+        input.read_exact(&mut header_len).map_err(io_err)?;
+        let mut header = vec![0u8; u32::from_le_bytes(header_len) as usize];
+        input.read_exact(&mut header).map_err(io_err)?;
+        let header: Header = serde_yaml::from_slice(&header)
+            .map_err(|e| format!("recording header: {:?}", e))?;
+
+        Ok(RecordReader { input, header })
+    }
+
+    /// Returns `None` at the end of the recording
+    pub fn next_frame(&mut self)
+            -> Result<Option<(Duration, Frame<f32>)>, String> {
+        let mut micros = [0u8; 8];
+        match self.input.read_exact(&mut micros) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(None)
+            }
+            Err(e) => return Err(io_err(e)),
+        }
+        let t = Duration::from_micros(u64::from_le_bytes(micros));
+
+        let mut num_vals = [0u8; 2];
+        self.input.read_exact(&mut num_vals).map_err(io_err)?;
+        let num_vals = u16::from_le_bytes(num_vals);
+
+        let mut frame = Frame::new(self.header.chans.len() as u16);
+        let mut chanval = [0u8; 6];
+        for _ in 0..num_vals {
+            self.input.read_exact(&mut chanval).map_err(io_err)?;
+            let cid = u16::from_le_bytes([chanval[0], chanval[1]]);
+            let val = f32::from_le_bytes(
+                [chanval[2], chanval[3], chanval[4], chanval[5]]);
+            frame.set(cid, val);
+        }
+
+        Ok(Some((t, frame)))
+    }
+}
+
+/// Records everything that passes through it to a file
+pub struct Recorder<D> {
+    name: String,
+    dev: Arc<Mutex<D>>,
+    writer: RecordWriter<BufWriter<File>>,
+    start_time: Instant,
+    last_flush: Instant,
+    warned_u16: bool,
+}
+
+impl<D: HasChanDescriptions> Recorder<D> {
+    pub fn create<P: AsRef<Path>>(path: P, dev: Arc<Mutex<D>>)
+            -> Result<Self, String> {
+        let path = path.as_ref();
+        let (header, name) = {
+            let dev = dev.lock().unwrap();
+            let header =
+                Header::from_chan_descriptions(&dev.chan_descriptions());
+            (header, format!("Recording to {:?}", path))
+        };
+
+        let file = File::create(path)
+            .map_err(|e| format!("create {:?}: {:?}", path, e))?;
+        let writer = RecordWriter::new(BufWriter::new(file), &header)?;
+
+        let now = Instant::now();
+        Ok(Recorder {
+            name,
+            dev,
+            writer,
+            start_time: now,
+            last_flush: now,
+            warned_u16: false,
+        })
+    }
+}
+
+impl<D> Recorder<D> {
+    fn record(&mut self, frame: &Frame<f32>) {
+        let now = Instant::now();
+        if let Err(e) = self.writer.write_frame(now - self.start_time, frame) {
+            eprintln!("recorder: {}", e);
+        }
+
+        if now - self.last_flush >= FLUSH_INTERVAL {
+            self.flush();
+            self.last_flush = now;
+        }
+    }
+
+    /// Writes out what's buffered, so nothing is lost when we're stopped
+    pub fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            eprintln!("recorder: {}", e);
+        }
+    }
+}
+
+impl<D> Drop for Recorder<D> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl<D: MsgHandler> MsgHandler for Recorder<D> {
+    fn handle_msg(&mut self, msg: &Msg) -> Result<(), String> {
+        let mut frame = Frame::empty();
+        for ChanVal(ChanId(cid), val) in msg.vals.iter() {
+            match val {
+                Val::F32(v) => frame.set(*cid, *v),
+                Val::U16(_) if !self.warned_u16 => {
+                    eprintln!("recorder: u16 values are not supported, \
+                               they won't be recorded");
+                    self.warned_u16 = true;
+                },
+                Val::U16(_) => {},
+            }
+        }
+        self.record(&frame);
+
+        let mut dev = self.dev.lock().unwrap();
+        dev.handle_msg(msg)
+    }
+}
+
+impl<D> Wrapper for Recorder<D> {
+    type Output = D;
+
+    fn output(&self) -> Arc<Mutex<D>> {
+        self.dev.clone()
+    }
+}
+
+impl<D> fmt::Display for Recorder<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl<D> fmt::Debug for Recorder<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl<D: DevRead> DevRead for Recorder<D> {
+    fn get_f32(&self, chan: u16) -> Result<f32, String> {
+        let dev = self.dev.lock().unwrap();
+        dev.get_f32(chan)
+    }
+}
+
+impl<D: DevWrite> DevWrite for Recorder<D> {
+    fn set_frame(&mut self, frame: &Frame<f32>) -> Result<(), String> {
+        self.record(frame);
+
+        let mut dev = self.dev.lock().unwrap();
+        dev.set_frame(frame)
+    }
+}
+
+impl<D: Dev> Dev for Recorder<D> {}
+
+#[derive(Debug, Clone)]
+pub struct PlaySpec {
+    pub path: PathBuf,
+
+    /// 2.0 plays twice as fast, 0.5 twice as slow
+    pub speed: f32,
+
+    /// Start over when we reach the end
+    pub looped: bool,
+}
+
+/// Plays a recording into `output` with the original timing
+pub struct Player<T: ?Sized> {
+    output: Arc<Mutex<T>>,
+    pub settings: PlaySpec,
+    clock: Arc<dyn Clock>,
+}
+
+impl<T: DevWrite + DevNumChans + ?Sized> Player<T> {
+    pub fn new(output: Arc<Mutex<T>>, settings: PlaySpec)
+            -> Result<Self, String> {
+        if !(settings.speed > 0.0 && settings.speed.is_finite()) {
+            return Err(format!(
+                    "play speed must be positive and finite, got {}", settings.speed));
+        }
+
+        let reader = RecordReader::open(&settings.path)?;
+        let num_chans = {
+            let output = output.lock().unwrap();
+            output.num_chans()
+        };
+        let extra_chans = reader.header.chans.iter()
+            .filter(|c| c.chan_id >= num_chans)
+            .count();
+        if extra_chans > 0 {
+            eprintln!("play: recording has {} chans we don't have, \
+                       they will be ignored", extra_chans);
+        }
+
+        Ok(Player { output, settings, clock: clock::real() })
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+/// Plays the recording once
+/// Returns `false` if we were asked to stop
+fn play_once<T: DevWrite + DevNumChans + ?Sized>(
+    output: &Arc<Mutex<T>>,
+    settings: &PlaySpec,
+    clock: &dyn Clock,
+    stop: &mpsc::Receiver<TaskMsg>,
+) -> Result<bool, String> {
+    let mut reader = RecordReader::open(&settings.path)?;
+    let num_chans = {
+        let output = output.lock().unwrap();
+        output.num_chans()
+    };
+    let mut start = clock.now();
+    let mut paused_at: Option<Instant> = None;
+
+    while let Some((t, mut frame)) = reader.next_frame()? {
+        frame.vals.truncate(num_chans as usize);
+
+        loop {
+            let frame_time = start + t.div_f32(settings.speed);
+            let now = clock.now();
+            let timeout = match paused_at {
+                Some(_) => PAUSED_WAIT,
+                None if frame_time > now => frame_time - now,
+                None => break,
+            };
+            match clock.recv_timeout(stop, timeout) {
+                Ok(TaskMsg::Stop) => return Ok(false),
+                Ok(TaskMsg::Pause) => match paused_at.take() {
+                    // continue where we've stopped
+                    Some(paused_at) => start += clock.now() - paused_at,
+                    None => paused_at = Some(clock.now()),
+                },
+                Ok(TaskMsg::Ping) => {}
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Ok(false)
+                }
+            }
+        }
+
+        let mut output = output.lock().unwrap();
+        if let Err(e) = output.set_frame(&frame) {
+            eprintln!("play set_frame err: {e:?}");
+        }
+    }
+
+    Ok(true)
+}
+
+impl<T: DevWrite + DevNumChans + ?Sized> Runner for Player<T> {
+    fn run(
+        self_lock: Arc<Mutex<Player<T>>>,
+        stop: mpsc::Receiver<TaskMsg>,
+    ) -> Result<(), String> {
+        let (output, settings, clock) = {
+            let player = self_lock.lock().unwrap();
+            (player.output.clone(), player.settings.clone(),
+             player.clock.clone())
+        };
+        println!("playing {:?}...", settings.path);
+
+        loop {
+            if !play_once(&output, &settings, &*clock, &stop)? || !settings.looped {
+                return Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_roundtrip() {
+        let header = Header {
+            chans: vec![RecordedChan {
+                chan_id: 0,
+                name: "chan".to_string(),
+                config: ChanConfig::default(),
+            }],
+        };
+
+        let mut frame = Frame::new(2);
+        frame.set(1, 0.25);
+
+        let mut buf: Vec<u8> = Vec::new();
+        {
+            let mut writer = RecordWriter::new(&mut buf, &header).unwrap();
+            writer.write_frame(Duration::from_millis(10), &frame).unwrap();
+            writer.write_frame(Duration::from_millis(25), &Frame::new(0))
+                .unwrap();
+        }
+
+        let mut reader = RecordReader::new(Cursor::new(buf)).unwrap();
+        assert_eq!(reader.header, header);
+
+        let (t, read_frame) = reader.next_frame().unwrap().unwrap();
+        assert_eq!(t, Duration::from_millis(10));
+        assert_eq!(read_frame.get(0), None);
+        assert_eq!(read_frame.get(1), Some(0.25));
+
+        let (t, read_frame) = reader.next_frame().unwrap().unwrap();
+        assert_eq!(t, Duration::from_millis(25));
+        assert_eq!(read_frame.iter_some().count(), 0);
+
+        assert_eq!(reader.next_frame(), Ok(None));
+    }
+
+    #[test]
+    fn test_play_and_pause() {
+        use crate::clock::VirtualClock;
+        use crate::test_dev::TestDev;
+
+        let path = std::env::temp_dir()
+            .join(format!("led_play_test_{}.rec", std::process::id()));
+        {
+            let header = Header {
+                chans: vec![RecordedChan {
+                    chan_id: 0,
+                    name: "chan".to_string(),
+                    config: ChanConfig::default(),
+                }],
+            };
+            let file = File::create(&path).unwrap();
+            let mut writer = RecordWriter::new(file, &header).unwrap();
+            for (ms, val) in [(0, 0.1), (500, 0.5), (1000, 0.9)] {
+                let mut frame = Frame::new(1);
+                frame.set(0, val);
+                writer.write_frame(Duration::from_millis(ms), &frame).unwrap();
+            }
+        }
+
+        let play = |pause: bool| {
+            let dev = Arc::new(Mutex::new(TestDev::recording()));
+            let clock = VirtualClock::new().stop_after(Duration::from_secs(5));
+            let settings = PlaySpec { path: path.clone(), speed: 2.0,
+                                      looped: false };
+            let player = Player::new(dev.clone(), settings).unwrap()
+                .with_clock(Arc::new(clock));
+            let (tx, rx) = mpsc::channel();
+            if pause {
+                tx.send(TaskMsg::Pause).unwrap();
+            }
+            Runner::run(Arc::new(Mutex::new(player)), rx).unwrap();
+            let dev = dev.lock().unwrap();
+            dev.history().iter().map(|f| f.get(0).unwrap()).collect::<Vec<f32>>()
+        };
+
+        let played = play(false);
+        // paused after the first frame until the clock runs out
+        let paused = play(true);
+        for speed in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let dev = Arc::new(Mutex::new(TestDev::recording()));
+            let settings = PlaySpec { path: path.clone(), speed,
+                                      looped: false };
+            assert!(Player::new(dev, settings).is_err(), "{}", speed);
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(played, vec![0.1, 0.5, 0.9]);
+        assert_eq!(paused, vec![0.1]);
+    }
+
+    #[test]
+    fn test_invalid_magic() {
+        assert!(RecordReader::new(Cursor::new(b"NOTREC\x01\x00".to_vec()))
+                .is_err());
+    }
+}
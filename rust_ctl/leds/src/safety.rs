@@ -0,0 +1,263 @@
+use crate::frame::Frame;
+use crate::tag::Tag;
+
+use serde_derive::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Changes only count as flashes when the darker side is below this,
+/// as in the general flash threshold of WCAG 2.x
+const DARK_LEVEL: f32 = 0.8;
+
+/// Flashes are counted over the last second
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Limits how often chans may flash, chans that would flash more often
+/// are held within `delta` of their last peak until it's safe again
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SafetyConfig {
+    /// Limit every chan
+    pub enabled: bool,
+
+    /// Chans with any of these tags are limited even when not `enabled`
+    pub forced: Vec<Tag>,
+
+    /// Flashes per second allowed for a group, a flash is a pair of
+    /// opposing changes
+    pub flashes: f32,
+
+    /// The smallest change of a level counted as half of a flash
+    pub delta: f32,
+
+    /// Chans sharing one of these tags flash as a group,
+    /// the rest of the limited chans are one more group
+    pub groups: Vec<Tag>,
+}
+
+impl Default for SafetyConfig {
+    fn default() -> Self {
+        SafetyConfig {
+            enabled: false,
+            forced: vec![],
+            flashes: 3.0,
+            delta: 0.1,
+            groups: vec![],
+        }
+    }
+}
+
+impl SafetyConfig {
+    /// Group of the chan with `tags`, None when it's not limited
+    fn group(&self, tags: &[Tag]) -> Option<usize> {
+        if !self.enabled && !tags.iter().any(|t| self.forced.contains(t)) {
+            return None;
+        }
+
+        let group = self.groups.iter().position(|g| tags.contains(g));
+        Some(group.unwrap_or(self.groups.len()))
+    }
+
+    fn group_name(&self, group: usize) -> &str {
+        self.groups.get(group).map(Tag::name).unwrap_or("other chans")
+    }
+}
+
+/// Where a chan is in its swing
+#[derive(Clone, Copy, Default)]
+struct ChanState {
+    /// The furthest level in the current direction
+    peak: Option<f32>,
+
+    /// 1.0 rising, -1.0 falling, 0.0 not known yet
+    dir: f32,
+}
+
+#[derive(Default)]
+struct GroupState {
+    transitions: VecDeque<Instant>,
+    limited_frames: Option<u64>,
+}
+
+pub struct StrobeLimiter {
+    cfg: SafetyConfig,
+    chans: Vec<ChanState>,
+    groups: Vec<GroupState>,
+}
+
+impl StrobeLimiter {
+    pub fn new(cfg: SafetyConfig) -> Self {
+        let groups = (0..=cfg.groups.len()).map(|_| Default::default())
+            .collect();
+        StrobeLimiter { cfg, chans: vec![], groups }
+    }
+
+    pub fn config(&self) -> &SafetyConfig {
+        &self.cfg
+    }
+
+    /// Is moving from `peak` in `dir` to `val` a change that counts
+    fn is_transition(&self, chan: ChanState, val: f32) -> bool {
+        let Some(peak) = chan.peak else { return false };
+        let change = val - peak;
+        change.abs() >= self.cfg.delta
+            && change * chan.dir <= 0.0
+            && peak.min(val) < DARK_LEVEL
+    }
+
+    fn advance(chan: &mut ChanState, val: f32, transition: bool) {
+        match chan.peak {
+            _ if transition => {
+                chan.dir = (val - chan.peak.unwrap()).signum();
+                chan.peak = Some(val);
+            },
+            None => chan.peak = Some(val),
+            Some(peak) if (val - peak) * chan.dir > 0.0 => chan.peak = Some(val),
+            Some(_) => {},
+        }
+    }
+
+    /// Level closest to `val` that's not a transition
+    fn hold(&self, chan: ChanState, val: f32) -> f32 {
+        let peak = chan.peak.unwrap();
+        let max_change = self.cfg.delta / 2.0;
+        val.clamp(peak - max_change, peak + max_change)
+    }
+
+    /// Holds back the changes of `frame` that would flash too often,
+    /// `tags` gives the tags of a chan
+    pub fn limit<'a>(
+        &mut self,
+        frame: &mut Frame<f32>,
+        tags: impl Fn(u16) -> &'a [Tag],
+        now: Instant,
+    ) {
+        // the limit is for pairs of changes
+        let max_transitions = (self.cfg.flashes * 2.0).floor() as usize;
+
+        let mut changes: Vec<Vec<(u16, f32)>> = vec![vec![]; self.groups.len()];
+        for (cid, &val) in frame.iter_some() {
+            let Some(group) = self.cfg.group(tags(cid)) else { continue };
+            if self.chans.len() <= cid as usize {
+                self.chans.resize(cid as usize + 1, ChanState::default());
+            }
+            changes[group].push((cid, val));
+        }
+
+        for (gid, changes) in changes.into_iter().enumerate() {
+            let flashing = changes.iter()
+                .any(|&(cid, val)| self.is_transition(self.chans[cid as usize], val));
+
+            let state = &mut self.groups[gid];
+            while state.transitions.front()
+                    .is_some_and(|&t| now.saturating_duration_since(t) >= WINDOW) {
+                state.transitions.pop_front();
+            }
+            let allowed = state.transitions.len() < max_transitions;
+            if flashing && allowed {
+                state.transitions.push_back(now);
+            }
+
+            let limiting = flashing && !allowed;
+            match (state.limited_frames, limiting) {
+                (None, true) => {
+                    eprintln!("strobe limiter: holding back {} flashing over {}/s",
+                              self.cfg.group_name(gid), self.cfg.flashes);
+                    state.limited_frames = Some(1);
+                },
+                (Some(n), true) => state.limited_frames = Some(n + 1),
+                (Some(n), false) if allowed => {
+                    eprintln!("strobe limiter: released {} after {} frames",
+                              self.cfg.group_name(gid), n);
+                    state.limited_frames = None;
+                },
+                _ => {},
+            }
+
+            for (cid, val) in changes {
+                let chan = self.chans[cid as usize];
+                let transition = self.is_transition(chan, val);
+                let val = if transition && limiting {
+                    let held = self.hold(chan, val);
+                    frame.set(cid, held);
+                    held
+                } else {
+                    val
+                };
+                Self::advance(&mut self.chans[cid as usize], val,
+                              transition && !limiting);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+
+    fn flashes(limiter: &mut StrobeLimiter, tags: &[Vec<Tag>], hz: u32)
+            -> Vec<Frame<f32>> {
+        let start = Instant::now();
+        let period = Duration::from_secs(1) / (hz * 2);
+        (0..hz * 2 * 2).map(|i| {
+            let mut frame = Frame::new(tags.len() as u16);
+            frame.set_all(if i % 2 == 0 { 1.0 } else { 0.0 });
+            limiter.limit(&mut frame, |cid| &tags[cid as usize], start + period * i);
+            frame
+        }).collect()
+    }
+
+    #[test]
+    fn test_strobe_limiter() {
+        let tags = vec![vec![Tag::new("strobe")], vec![]];
+
+        let mut limiter = StrobeLimiter::new(SafetyConfig::default());
+        let frames = flashes(&mut limiter, &tags, 10);
+        assert!(frames.iter().all(|f| f.vals.iter().all(|v| v.is_some_and(|v| v == 0.0 || v == 1.0))),
+                "nothing should be limited when it's not enabled");
+
+        let mut limiter = StrobeLimiter::new(SafetyConfig {
+            forced: vec![Tag::new("strobe")],
+            ..SafetyConfig::default()
+        });
+        let frames = flashes(&mut limiter, &tags, 10);
+        let swings = |cid: usize| frames.windows(2)
+            .filter(|f| (f[0].vals[cid].unwrap() - f[1].vals[cid].unwrap()).abs() >= 0.1)
+            .count();
+        // 2 seconds at 3 flashes per second, the first frame doesn't count
+        assert_eq!(swings(0), 12);
+        assert_eq!(swings(1), 39);
+        assert!(frames.iter().all(|f| f.vals[0].unwrap() >= 0.95 || f.vals[0].unwrap() == 0.0));
+
+        // slow enough
+        let mut limiter = StrobeLimiter::new(SafetyConfig {
+            enabled: true,
+            ..SafetyConfig::default()
+        });
+        let frames = flashes(&mut limiter, &tags, 2);
+        assert!(frames.iter().all(|f| f.vals.iter().all(|v| v.is_some_and(|v| v == 0.0 || v == 1.0))));
+    }
+
+    #[test]
+    fn test_groups() {
+        let cfg = SafetyConfig {
+            enabled: true,
+            groups: vec![Tag::new("window"), Tag::new("desk")],
+            ..SafetyConfig::default()
+        };
+        assert_eq!(cfg.group(&[Tag::new("r"), Tag::new("desk")]), Some(1));
+        assert_eq!(cfg.group(&[Tag::new("r")]), Some(2));
+        assert_eq!(SafetyConfig::default().group(&[Tag::new("desk")]), None);
+
+        // dim flickering above the dark level isn't a flash
+        let mut limiter = StrobeLimiter::new(cfg);
+        let start = Instant::now();
+        for i in 0..100u32 {
+            let mut frame = Frame::new(1);
+            frame.set_all(if i % 2 == 0 { 1.0 } else { 0.85 });
+            limiter.limit(&mut frame, |_| &[], start + Duration::from_millis(10) * i);
+            assert_eq!(frame.get(0), Some(if i % 2 == 0 { 1.0 } else { 0.85 }));
+        }
+    }
+}
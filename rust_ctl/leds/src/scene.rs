@@ -0,0 +1,437 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+use crate::chan_spec::{ChanSpec, ChanSpecGeneric};
+use crate::demo::fade::{Fade, FadeEnd, FadeMode, FadeSpec};
+use crate::dev::{Dev, DevRead};
+use crate::frame::Frame;
+use crate::runner::Runner;
+use crate::task::{Task, TaskMsg};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Named set of channel values we fade to from whatever is on now
+///
+/// ```yaml
+/// scenes:
+///   - name: evening
+///     chans: ["0.2", "wall:0.7"]
+///     fade: 3.0
+///     easing: EaseInOut
+///     # optional, see `FadeSpec`
+///     perceptual: true
+///     timing: [{ chans: wall, stagger: 0.3 }]
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    pub name: String,
+
+    /// Applied in order, so later specs override earlier ones
+    #[serde(with = "chan_specs_str")]
+    pub chans: Vec<ChanSpec>,
+
+    /// `fade` in seconds, `easing` and the rest of how we fade to it
+    #[serde(flatten)]
+    pub fade: FadeSpec,
+
+    /// Id for triggering the scene via UDP,
+    /// defaults to the position of the scene in the list
+    #[serde(default)]
+    pub id: Option<u16>,
+}
+
+impl Scene {
+    /// Only has values for the channels the scene specifies
+    pub fn frame(&self, chans: &[ChanDescription])
+            -> Result<Frame<f32>, String> {
+        let mut frame = Frame::empty();
+        for spec in self.chans.iter() {
+            spec.apply_to_frame(chans, &mut frame)
+                .map_err(|e| format!("scene '{}': {}", self.name, e))?;
+        }
+        Ok(frame)
+    }
+
+    pub fn fade_duration(&self) -> Duration {
+        self.fade.fade_duration
+    }
+
+    pub fn fade_spec(&self, frame_duration: Duration) -> FadeSpec {
+        FadeSpec { frame_duration, ..self.fade.clone() }
+    }
+
+    /// Starts fading `fader` to this scene, superseding what it fades to,
+    /// the receiver gets how it has ended
+    pub fn fade_with<T: Dev + HasChanDescriptions + fmt::Debug>(
+        &self,
+        fader: &mut Fade<T>,
+    ) -> Result<mpsc::Receiver<FadeEnd>, String> {
+        let frame = self.frame(&fader.chan_descriptions())?;
+        let spec = self.fade_spec(fader.settings.frame_duration);
+        Ok(fader.fade_to(&frame, spec, FadeMode::Supersede))
+    }
+}
+
+/// Scene with the current values of channels tagged with any of `tags`,
+/// or of all channels if `tags` is empty
+///
+/// Values are read via `DevRead::get_to_frame`, so when `output` is `Mux`
+/// they are un-adjusted and we can set them back as is
+pub fn snapshot<T: DevRead + HasChanDescriptions>(
+    name: &str,
+    output: &T,
+    tags: &[String],
+) -> Result<Scene, String> {
+    let mut current = Frame::empty();
+    output.get_to_frame(&mut current)?;
+
+    let mut chanvals: Vec<(String, f32)> = Vec::new();
+    for chan in output.chan_descriptions() {
+        let is_tagged = tags.is_empty() || chan.config.tags.iter()
+            .any(|tag| tags.iter().any(|t| t == tag.name()));
+        if !is_tagged {
+            continue;
+        }
+
+        let val = current.get(chan.chan_id)
+            .ok_or_else(|| format!("no value for chan {}", chan.chan_id))?;
+        chanvals.push((chan.chan_id.to_string(), val));
+    }
+
+    if chanvals.is_empty() {
+        return Err(format!("no channels tagged with any of {:?}", tags));
+    }
+
+    Ok(Scene {
+        name: name.to_string(),
+        chans: vec![ChanSpec::F32(ChanSpecGeneric::Some(chanvals))],
+        fade: FadeSpec::default(),
+        id: None,
+    })
+}
+
+/// Adds `scene` or replaces the scene with the same name
+pub fn merge(scenes: &mut Vec<Scene>, scene: Scene) {
+    match scenes.iter_mut().find(|s| s.name == scene.name) {
+        Some(existing) => *existing = scene,
+        None => scenes.push(scene),
+    }
+}
+
+/// Reads a list of scenes, a missing file is the same as no scenes
+pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Vec<Scene>, String> {
+    let path = path.as_ref();
+    let buf = match fs::read_to_string(path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(format!("reading {:?}: {:?}", path, e)),
+    };
+
+    serde_yaml::from_str(&buf)
+        .map_err(|e| format!("parsing scenes {:?}: {:?}", path, e))
+}
+
+/// Adds `scene` to the scenes file, replacing the one with the same name
+pub fn save_to_file<P: AsRef<Path>>(path: P, scene: Scene)
+        -> Result<(), String> {
+    let path = path.as_ref();
+    let mut scenes = load_file(path)?;
+    merge(&mut scenes, scene);
+
+    let yaml = serde_yaml::to_string(&scenes)
+        .map_err(|e| format!("{:?}", e))?;
+    fs::write(path, yaml)
+        .map_err(|e| format!("writing {:?}: {:?}", path, e))
+}
+
+/// Finds scene by name or by id
+pub fn find<'a>(scenes: &'a [Scene], name_or_id: &str) -> Option<&'a Scene> {
+    if let Some(scene) = scenes.iter().find(|s| s.name == name_or_id) {
+        return Some(scene);
+    }
+
+    let id: u16 = name_or_id.parse().ok()?;
+    scenes.iter().enumerate()
+        .find(|(ii, s)| s.id.unwrap_or(*ii as u16) == id)
+        .map(|(_, s)| s)
+}
+
+/// Scene chans are written as chan spec strings, same as for `set f32`
+pub(crate) mod chan_specs_str {
+    use crate::chan_spec::ChanSpec;
+    use serde::{Deserializer, Serializer};
+    use serde::ser::SerializeSeq;
+    use serde_derive::Deserialize;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ChanSpecRepr {
+        Str(String),
+        Num(f32),
+        Spec(ChanSpec),
+    }
+
+    pub fn serialize<S: Serializer>(specs: &[ChanSpec], ser: S)
+            -> Result<S::Ok, S::Error> {
+        let mut seq = ser.serialize_seq(Some(specs.len()))?;
+        for spec in specs.iter() {
+            seq.serialize_element(&spec.to_string())?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D)
+            -> Result<Vec<ChanSpec>, D::Error> {
+        use serde::de::Error;
+
+        <Vec<ChanSpecRepr> as serde::Deserialize>::deserialize(de)?
+            .into_iter()
+            .map(|repr| match repr {
+                ChanSpecRepr::Str(s) =>
+                    ChanSpec::parse_f32(&s).map_err(D::Error::custom),
+                ChanSpecRepr::Num(val) =>
+                    ChanSpec::parse_f32(&val.to_string())
+                    .map_err(D::Error::custom),
+                ChanSpecRepr::Spec(spec) => Ok(spec),
+            })
+            .collect()
+    }
+}
+
+pub struct SceneSrv<T: fmt::Debug> {
+    listen_ip: net::IpAddr,
+    listen_port: u16,
+    socket: net::UdpSocket,
+    buf: [u8; 256],
+    scenes: Vec<Scene>,
+    fader: Arc<Mutex<Fade<T>>>,
+    fade_task: Task,
+}
+
+const DEFAULT_IP: &str = "0.0.0.0";
+pub const DEFAULT_PORT: u16 = 8933;
+
+/// Listens for UDP datagrams containing a scene name or id,
+/// i.e. `echo -n evening | nc -u -w0 host 8933`
+impl<T: 'static + Dev + HasChanDescriptions + fmt::Debug> SceneSrv<T> {
+    pub fn new(
+        listen_ip: Option<net::IpAddr>,
+        listen_port: Option<u16>,
+        output: Arc<Mutex<T>>,
+        scenes: Vec<Scene>,
+    ) -> Result<Self, String> {
+        let listen_ip = listen_ip
+            .unwrap_or_else(|| DEFAULT_IP.parse().unwrap());
+        let listen_port = listen_port.unwrap_or(DEFAULT_PORT);
+
+        let socket = net::UdpSocket::bind((listen_ip, listen_port))
+            .map_err(|e| format!("SceneSrv new: {:?}", e))?;
+
+        let fader = Arc::new(Mutex::new(Fade::new(output, FadeSpec {
+            frame_duration: Duration::from_secs_f32(1.0 / 60.0),
+            ..FadeSpec::default()
+        })));
+
+        let (tx, rx) = mpsc::channel::<TaskMsg>();
+        let join_handle = {
+            let fader = fader.clone();
+            thread::spawn(move || Runner::run(fader, rx))
+        };
+
+        let fade_task = Task {
+            name: "Scene fade".to_string(),
+            chan: tx,
+            join_handle,
+        };
+
+        Ok(SceneSrv {
+            listen_ip,
+            listen_port,
+            socket,
+            buf: [0; 256],
+            scenes,
+            fader,
+            fade_task,
+        })
+    }
+
+    fn recv(&mut self) -> Result<String, String> {
+        let (len, _addr) = self.socket.recv_from(&mut self.buf)
+            .map_err(|e| format!("{:?}", e))?;
+        let msg = std::str::from_utf8(&self.buf[0..len])
+            .map_err(|e| format!("{:?}", e))?;
+        Ok(msg.trim().to_string())
+    }
+
+    /// Returns an error when the fader has stopped
+    pub fn run(&mut self) -> Result<(), String> {
+        println!("listening for scenes on {}:{}",
+                 self.listen_ip, self.listen_port);
+        loop {
+            let name_or_id = match self.recv() {
+                Ok(name_or_id) => name_or_id,
+                Err(e) => {
+                    eprintln!("SceneSrv recv error: {}", e);
+                    continue;
+                }
+            };
+
+            let scene = match find(&self.scenes, &name_or_id) {
+                Some(scene) => scene,
+                None => {
+                    eprintln!("SceneSrv: no scene '{}'", name_or_id);
+                    continue;
+                }
+            };
+
+            if !self.fade_task.is_running() {
+                return Err("SceneSrv: scene fade task has stopped".to_string());
+            }
+            let mut fader = self.fader.lock().unwrap();
+            if let Err(e) = scene.fade_with(&mut fader) {
+                eprintln!("SceneSrv: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use crate::easing::Easing;
+    use crate::chan::ChanConfig;
+    use crate::test_dev::chan;
+
+    fn chans() -> Vec<ChanDescription> {
+        vec![chan(0, &[], None), chan(1, &["wall"], None),
+             chan(2, &["wall"], None)]
+    }
+
+    #[test]
+    fn test_scene_from_yaml() {
+        let scenes: Vec<Scene> = serde_yaml::from_str(r#"
+- name: evening
+  chans: ["0.2", "wall:0.7"]
+  fade: 3.0
+  easing: EaseInOut
+  perceptual: true
+  timing: [{ chans: wall, stagger: 0.5 }]
+- name: off
+  chans: [0]
+  id: 7
+"#).unwrap();
+
+        assert_eq!(scenes[0].fade_duration(), Duration::from_secs(3));
+        assert_eq!(scenes[0].fade.easing, Easing::EaseInOut);
+        let timing = scenes[0].fade.timing(&chans());
+        assert_eq!(timing.get(2), (Duration::from_millis(500),
+                                   Duration::from_secs(3)));
+        assert_eq!(timing.end(), Duration::from_millis(3500));
+        assert_eq!(scenes[0].frame(&chans()).unwrap().vals,
+                   vec![Some(0.2), Some(0.7), Some(0.7)]);
+
+        assert_eq!(scenes[1].fade_duration(), Duration::from_secs(1));
+        assert_eq!(scenes[1].frame(&chans()).unwrap().vals,
+                   vec![Some(0.0), Some(0.0), Some(0.0)]);
+
+        let yaml = serde_yaml::to_string(&scenes).unwrap();
+        let parsed: Vec<Scene> = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed, scenes);
+    }
+
+    #[test]
+    fn test_scene_only_sets_specified_chans() {
+        let scene = Scene {
+            name: "wall".to_string(),
+            chans: vec![ChanSpec::parse_f32("wall:0.5").unwrap()],
+            fade: FadeSpec::default(),
+            id: None,
+        };
+        assert_eq!(scene.frame(&chans()).unwrap().vals,
+                   vec![None, Some(0.5), Some(0.5)]);
+    }
+
+    #[test]
+    fn test_snapshot_tagged_chans() {
+        use crate::mux::Mux;
+        use crate::test_dev::TestDev;
+        use crate::dev::DevWrite;
+
+        let mut mux = Mux::new();
+        let chan_cfgs: Vec<ChanConfig> = chans().into_iter()
+            .map(|chan| ChanConfig {
+                index: chan.chan_id,
+                exp: Some(2.0),
+                ..chan.config
+            })
+            .collect();
+        mux.add_dev(Arc::new(Mutex::new(TestDev::new(false))),
+                    Some(chan_cfgs.into_iter()));
+
+        let mut frame = Frame::empty();
+        frame.set(0, 0.1);
+        frame.set(1, 0.5);
+        frame.set(2, 0.8);
+        mux.set_frame(&frame).unwrap();
+
+        let scene = snapshot("wall", &mux, &["wall".to_string()]).unwrap();
+        let snapped = scene.frame(&mux.chan_descriptions()).unwrap();
+        assert_eq!(snapped.get(0), None);
+        assert!((snapped.get(1).unwrap() - 0.5).abs() < 0.0001);
+        assert!((snapped.get(2).unwrap() - 0.8).abs() < 0.0001);
+
+        let all = snapshot("all", &mux, &[]).unwrap();
+        assert_eq!(all.frame(&mux.chan_descriptions()).unwrap()
+                   .iter_some().count(), 3);
+
+        assert!(snapshot("none", &mux, &["door".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_save_to_file() {
+        let path = std::env::temp_dir()
+            .join(format!("led_scenes_test_{}.yaml", std::process::id()));
+        let scene = |name: &str, val: &str| Scene {
+            name: name.to_string(),
+            chans: vec![ChanSpec::parse_f32(val).unwrap()],
+            fade: FadeSpec::default(),
+            id: None,
+        };
+
+        assert_eq!(load_file(&path).unwrap(), vec![]);
+        save_to_file(&path, scene("a", "0.1")).unwrap();
+        save_to_file(&path, scene("b", "0.2")).unwrap();
+        save_to_file(&path, scene("a", "0.3")).unwrap();
+
+        let scenes = load_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(scenes, vec![scene("a", "0.3"), scene("b", "0.2")]);
+    }
+
+    #[test]
+    fn test_find() {
+        let scene = |name: &str, id: Option<u16>| Scene {
+            name: name.to_string(),
+            chans: vec![],
+            fade: FadeSpec::default(),
+            id,
+        };
+        let scenes = vec![scene("a", None), scene("b", Some(5))];
+
+        assert_eq!(find(&scenes, "a").unwrap().name, "a");
+        assert_eq!(find(&scenes, "0").unwrap().name, "a");
+        assert_eq!(find(&scenes, "5").unwrap().name, "b");
+        assert!(find(&scenes, "1").is_none());
+        assert!(find(&scenes, "c").is_none());
+    }
+}
@@ -0,0 +1,719 @@
+use serde_derive::{Deserialize, Serialize};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+
+use crate::chan_spec::ChanSpec;
+use crate::clock::{self, Clock};
+use crate::demo::fade::FadeSpec;
+use crate::easing::Easing;
+use crate::runner::Runner;
+use crate::scene::Scene;
+use crate::sun::{self, Location, SunEvent};
+use crate::task::TaskMsg;
+
+use std::fmt;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Entry of the `schedule` config section
+///
+/// ```yaml
+/// schedule:
+///   - name: wake up
+///     ends_at: "07:30"    # start the fade so it's done by 07:30
+///     days: [Mon, Tue, Wed, Thu, Fri]
+///     action:
+///       fade: { chans: ["1"], duration: 1800 }
+///   - name: evening
+///     cron: "0 19 * * *"  # minute hour day-of-month month day-of-week
+///     action: { scene: evening }
+///   - name: new year
+///     once: "2026-12-31 23:59"
+///     action: { demo: hello }
+///   - name: night
+///     at: "01:00"
+///     action: off
+///   - name: window at sunset
+///     sun: "sunset-20m"   # needs `location` in the config
+///     action: { scene: window }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleEntry {
+    pub name: String,
+
+    /// Time of day, "HH:MM" or "HH:MM:SS"
+    #[serde(default)]
+    pub at: Option<String>,
+
+    /// Same as `at`, but the action finishes at this time instead of
+    /// starting at it
+    #[serde(default)]
+    pub ends_at: Option<String>,
+
+    /// "minute hour day-of-month month day-of-week", supports `*`, `a-b`,
+    /// `a,b` and `/step`
+    #[serde(default)]
+    pub cron: Option<String>,
+
+    /// Local date and time "YYYY-MM-DD HH:MM[:SS]" to run once
+    #[serde(default)]
+    pub once: Option<String>,
+
+    /// dawn, sunrise, sunset or dusk with an optional offset,
+    /// i.e. "sunset-20m", "sunrise+1h", "dusk+90s"
+    #[serde(default)]
+    pub sun: Option<String>,
+
+    /// Weekdays for `at`, `ends_at` or `sun` (Mon, Tue...),
+    /// every day by default
+    #[serde(default)]
+    pub days: Option<Vec<String>>,
+
+    pub action: ScheduleAction,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleAction {
+    /// Scene name or id
+    Scene(String),
+    Sequence(String),
+    /// Fade `chans` to their values over `duration` seconds
+    Fade {
+        #[serde(with = "crate::scene::chan_specs_str")]
+        chans: Vec<ChanSpec>,
+        #[serde(with = "crate::demo::fade::secs")]
+        duration: Duration,
+        #[serde(default)]
+        easing: Easing,
+    },
+    /// Effect name from `effects`, i.e. hello
+    Demo(String),
+    /// Fade everything out
+    Off,
+}
+
+impl ScheduleAction {
+    /// How long it takes, if we know it
+    pub fn duration(&self) -> Option<Duration> {
+        match self {
+            ScheduleAction::Fade { duration, .. } => Some(*duration),
+            _ => None,
+        }
+    }
+
+    /// `Fade` and `Off` are the same as fading to a scene
+    pub fn as_scene(&self) -> Option<Scene> {
+        match self {
+            ScheduleAction::Fade { chans, duration, easing } => Some(Scene {
+                name: "scheduled fade".to_string(),
+                chans: chans.clone(),
+                fade: FadeSpec {
+                    fade_duration: *duration,
+                    easing: *easing,
+                    ..FadeSpec::default()
+                },
+                id: None,
+            }),
+            ScheduleAction::Off => Some(Scene {
+                name: "off".to_string(),
+                chans: vec![ChanSpec::parse_f32("0").unwrap()],
+                fade: FadeSpec::default(),
+                id: None,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ScheduleAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleAction::Scene(name) => write!(f, "scene {}", name),
+            ScheduleAction::Sequence(name) => write!(f, "sequence {}", name),
+            ScheduleAction::Fade { chans, duration, .. } => {
+                let chans: Vec<String> =
+                    chans.iter().map(|c| c.to_string()).collect();
+                write!(f, "fade to {} over {}s", chans.join(" "),
+                       duration.as_secs_f32())
+            },
+            ScheduleAction::Demo(name) => write!(f, "demo {}", name),
+            ScheduleAction::Off => write!(f, "off"),
+        }
+    }
+}
+
+/// Set of allowed values of a cron field as a bit mask
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct CronField {
+    mask: u64,
+    /// Whether it's `*`, for day-of-month / day-of-week logic
+    any: bool,
+}
+
+impl CronField {
+    fn parse(field: &str, min: u32, max: u32) -> Result<Self, String> {
+        let mut mask: u64 = 0;
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>()
+                    .map_err(|e| format!("cron step '{}': {:?}", step, e))?),
+                None => (part, 1),
+            };
+            if step == 0 {
+                return Err(format!("cron step can't be 0 in '{}'", field));
+            }
+
+            let parse = |s: &str| -> Result<u32, String> {
+                s.parse().map_err(|e| format!("cron '{}': {:?}", s, e))
+            };
+            let (from, to) = match range {
+                "*" => (min, max),
+                range => match range.split_once('-') {
+                    Some((from, to)) => (parse(from)?, parse(to)?),
+                    None if step > 1 => (parse(range)?, max),
+                    None => (parse(range)?, parse(range)?),
+                },
+            };
+
+            if from < min || to > max || from > to {
+                return Err(format!("cron field '{}' must be within {}-{}",
+                                   field, min, max));
+            }
+            for val in (from..=to).step_by(step as usize) {
+                mask |= 1 << val;
+            }
+        }
+
+        Ok(CronField { mask, any: field == "*" })
+    }
+
+    fn has(&self, val: u32) -> bool {
+        self.mask & (1 << val) != 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Cron {
+    minutes: CronField,
+    hours: CronField,
+    days_of_month: CronField,
+    months: CronField,
+    days_of_week: CronField,
+}
+
+impl Cron {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "cron '{}' needs 5 fields: minute hour day-of-month month \
+                 day-of-week", spec));
+        }
+
+        let mut days_of_week = CronField::parse(fields[4], 0, 7)?;
+        // both 0 and 7 are sunday
+        if days_of_week.has(7) {
+            days_of_week.mask |= 1;
+        }
+
+        Ok(Cron {
+            minutes: CronField::parse(fields[0], 0, 59)?,
+            hours: CronField::parse(fields[1], 0, 23)?,
+            days_of_month: CronField::parse(fields[2], 1, 31)?,
+            months: CronField::parse(fields[3], 1, 12)?,
+            days_of_week,
+        })
+    }
+
+    fn has_date(&self, date: NaiveDate) -> bool {
+        if !self.months.has(date.month()) {
+            return false;
+        }
+
+        let dom = self.days_of_month.has(date.day());
+        let dow = self.days_of_week.has(date.weekday().num_days_from_sunday());
+        // like in cron, if both are restricted then either of them matches
+        match (self.days_of_month.any, self.days_of_week.any) {
+            (false, false) => dom || dow,
+            _ => dom && dow,
+        }
+    }
+
+    fn next_after(&self, t: NaiveDateTime) -> Option<NaiveDateTime> {
+        // enough to find Feb 29 from anywhere
+        for day in 0..(366 * 8) {
+            let date = t.date() + chrono::Duration::days(day);
+            if !self.has_date(date) {
+                continue;
+            }
+
+            for hour in (0..24).filter(|h| self.hours.has(*h)) {
+                for minute in (0..60).filter(|m| self.minutes.has(*m)) {
+                    let dt = date.and_hms(hour, minute, 0);
+                    if dt > t {
+                        return Some(dt);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum When {
+    Daily { time: NaiveTime, days: Vec<Weekday> },
+    Cron(Cron),
+    Once(NaiveDateTime),
+    Sun {
+        event: SunEvent,
+        offset: chrono::Duration,
+        days: Vec<Weekday>,
+        location: Location,
+    },
+}
+
+impl When {
+    /// The first time strictly after `t`
+    fn next_after(&self, t: NaiveDateTime, clock: &dyn Clock)
+            -> Option<NaiveDateTime> {
+        match self {
+            When::Daily { time, days } => (0..=7)
+                .map(|day| (t.date() + chrono::Duration::days(day))
+                     .and_time(*time))
+                .find(|dt| *dt > t && days.contains(&dt.weekday())),
+            When::Cron(cron) => cron.next_after(t),
+            When::Once(dt) => Some(*dt).filter(|dt| *dt > t),
+            When::Sun { event, offset, days, location } => {
+                // starting from the day before in case of a large offset,
+                // and looking far ahead in case of a polar night
+                (-1..=366)
+                    .filter_map(|day| {
+                        let date = t.date() + chrono::Duration::days(day);
+                        let utc = sun::sun_event(date, location, *event)?;
+                        Some(clock.local_from_utc(utc) + *offset)
+                    })
+                    .find(|dt| *dt > t && days.contains(&dt.weekday()))
+            },
+        }
+    }
+}
+
+/// "sunset-20m" => (Sunset, -20 minutes)
+fn parse_sun(s: &str) -> Result<(SunEvent, chrono::Duration), String> {
+    let (event, offset) = match s.find(['+', '-']) {
+        Some(pos) => (&s[..pos], Some(&s[pos..])),
+        None => (s, None),
+    };
+    let event: SunEvent = event.trim().parse()?;
+
+    let offset = match offset {
+        None => chrono::Duration::zero(),
+        Some(offset) => {
+            let offset = offset.replace(' ', "");
+            let (num, unit) = match offset.chars().last() {
+                Some(unit @ ('s' | 'm' | 'h')) =>
+                    (&offset[..offset.len() - 1], unit),
+                _ => (&offset[..], 'm'),
+            };
+            let num: i64 = num.parse()
+                .map_err(|e| format!("invalid offset in '{}': {:?}", s, e))?;
+            match unit {
+                's' => chrono::Duration::seconds(num),
+                'h' => chrono::Duration::hours(num),
+                _ => chrono::Duration::minutes(num),
+            }
+        },
+    };
+
+    Ok((event, offset))
+}
+
+fn parse_time(s: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(s, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M"))
+        .map_err(|e| format!("invalid time '{}', expected HH:MM: {}", s, e))
+}
+
+fn parse_datetime(s: &str) -> Result<NaiveDateTime, String> {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M"))
+        .map_err(|e| format!("invalid date and time '{}', \
+                              expected YYYY-MM-DD HH:MM: {}", s, e))
+}
+
+const ALL_DAYS: [Weekday; 7] = [
+    Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu,
+    Weekday::Fri, Weekday::Sat, Weekday::Sun,
+];
+
+impl ScheduleEntry {
+    fn when(&self, location: Option<&Location>)
+            -> Result<(When, chrono::Duration), String> {
+        let err = |e: String| format!("schedule '{}': {}", self.name, e);
+
+        let days: Vec<Weekday> = match &self.days {
+            Some(days) => days.iter()
+                .map(|d| d.parse::<Weekday>()
+                     .map_err(|_| err(format!("invalid weekday '{}'", d))))
+                .collect::<Result<_, _>>()?,
+            None => ALL_DAYS.to_vec(),
+        };
+        if self.days.is_some() && self.cron.is_some() {
+            return Err(err("days don't work with cron, use its day-of-week"
+                           .to_string()));
+        }
+
+        let mut offset = chrono::Duration::zero();
+        let when = match (&self.at, &self.ends_at, &self.cron, &self.once,
+                          &self.sun) {
+            (Some(at), None, None, None, None) =>
+                When::Daily { time: parse_time(at).map_err(err)?, days },
+            (None, Some(ends_at), None, None, None) => {
+                let duration = self.action.duration().ok_or_else(|| err(
+                        "ends_at needs an action with a duration, i.e. fade"
+                        .to_string()))?;
+                offset = chrono::Duration::from_std(duration)
+                    .map_err(|e| err(format!("{:?}", e)))?;
+                When::Daily { time: parse_time(ends_at).map_err(err)?, days }
+            },
+            (None, None, Some(cron), None, None) =>
+                When::Cron(Cron::parse(cron).map_err(err)?),
+            (None, None, None, Some(once), None) =>
+                When::Once(parse_datetime(once).map_err(err)?),
+            (None, None, None, None, Some(sun)) => {
+                let (event, offset) = parse_sun(sun).map_err(err)?;
+                let location = *location.ok_or_else(|| err(
+                        "sun needs `location` in the config".to_string()))?;
+                When::Sun { event, offset, days, location }
+            },
+            _ => return Err(err(
+                    "needs exactly one of at, ends_at, cron, once or sun"
+                    .to_string())),
+        };
+
+        Ok((when, offset))
+    }
+}
+
+struct ScheduledEntry {
+    entry: ScheduleEntry,
+    when: When,
+
+    /// We start this long before the time in `when`
+    offset: chrono::Duration,
+
+    /// Occurrence we shouldn't run
+    skip: Option<NaiveDateTime>,
+}
+
+impl ScheduledEntry {
+    fn next_after(&self, t: NaiveDateTime, clock: &dyn Clock)
+            -> Option<NaiveDateTime> {
+        self.when.next_after(t + self.offset, clock)
+            .map(|dt| dt - self.offset)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Upcoming {
+    pub name: String,
+    pub time: NaiveDateTime,
+    pub action: ScheduleAction,
+    pub skipped: bool,
+}
+
+impl fmt::Display for Upcoming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}{}", self.time.format("%a %Y-%m-%d %H:%M:%S"),
+               self.name, self.action,
+               if self.skipped { " (skipped)" } else { "" })
+    }
+}
+
+/// Sends the actions to `events` when their time comes
+pub struct Scheduler {
+    entries: Vec<ScheduledEntry>,
+    clock: Arc<dyn Clock>,
+    events: mpsc::Sender<ScheduleAction>,
+
+    /// Everything up to this time has been triggered
+    last_check: NaiveDateTime,
+}
+
+impl Scheduler {
+    pub fn new(
+        entries: &[ScheduleEntry],
+        location: Option<&Location>,
+        events: mpsc::Sender<ScheduleAction>,
+    ) -> Result<Self, String> {
+        let entries = entries.iter()
+            .map(|entry| {
+                let (when, offset) = entry.when(location)?;
+                Ok(ScheduledEntry {
+                    entry: entry.clone(),
+                    when,
+                    offset,
+                    skip: None,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let clock = clock::real();
+        let last_check = clock.local_now();
+        Ok(Scheduler { entries, clock, events, last_check })
+    }
+
+    /// Starts checking from the time of `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.last_check = clock.local_now();
+        self.clock = clock;
+        self
+    }
+
+    /// Next occurrence of each entry, soonest first
+    pub fn upcoming(&self) -> Vec<Upcoming> {
+        let mut upcoming: Vec<Upcoming> = self.entries.iter()
+            .filter_map(|e| {
+                let time = e.next_after(self.last_check, &*self.clock)?;
+                Some(Upcoming {
+                    name: e.entry.name.clone(),
+                    time,
+                    action: e.entry.action.clone(),
+                    skipped: e.skip == Some(time),
+                })
+            })
+            .collect();
+        upcoming.sort_by_key(|u| u.time);
+        upcoming
+    }
+
+    /// Don't run the next occurrence of entry `name`,
+    /// returns when it would've run
+    pub fn skip_next(&mut self, name: &str) -> Result<NaiveDateTime, String> {
+        let last_check = self.last_check;
+        let entry = self.entries.iter_mut()
+            .find(|e| e.entry.name == name)
+            .ok_or_else(|| format!("no schedule entry '{}'", name))?;
+        let next = entry.next_after(last_check, &*self.clock)
+            .ok_or_else(|| format!("'{}' won't run again", name))?;
+        entry.skip = Some(next);
+        Ok(next)
+    }
+
+    /// Sends actions that are due since the last check
+    pub fn check(&mut self) -> Result<(), String> {
+        let now = self.clock.local_now();
+        if now < self.last_check {
+            // clock went back, don't repeat what we've already done
+            self.last_check = now;
+            return Ok(());
+        }
+
+        for entry in self.entries.iter_mut() {
+            let time = match entry.next_after(self.last_check, &*self.clock) {
+                Some(time) if time <= now => time,
+                _ => continue,
+            };
+
+            if entry.skip == Some(time) {
+                entry.skip = None;
+                continue;
+            }
+
+            self.events.send(entry.entry.action.clone())
+                .map_err(|e| format!("scheduler: {:?}", e))?;
+        }
+
+        self.last_check = now;
+        Ok(())
+    }
+}
+
+impl Runner for Scheduler {
+    fn run(
+        self_lock: Arc<Mutex<Scheduler>>,
+        stop: mpsc::Receiver<TaskMsg>,
+    ) -> Result<(), String> {
+        let clock = self_lock.lock().unwrap().clock.clone();
+        loop {
+            self_lock.lock().unwrap().check()?;
+
+            match clock.recv_timeout(&stop, Duration::from_secs(1)) {
+                Ok(TaskMsg::Stop) => return Ok(()),
+                Ok(_) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    fn dt(s: &str) -> NaiveDateTime {
+        parse_datetime(s).unwrap()
+    }
+
+    fn scheduler(yaml: &str, now: &str)
+            -> (Scheduler, VirtualClock, mpsc::Receiver<ScheduleAction>) {
+        let entries: Vec<ScheduleEntry> = serde_yaml::from_str(yaml).unwrap();
+        let clock = VirtualClock::new();
+        clock.set_local_time(dt(now));
+        let (tx, rx) = mpsc::channel();
+        let scheduler = Scheduler::new(&entries, None, tx).unwrap()
+            .with_clock(Arc::new(clock.clone()));
+        (scheduler, clock, rx)
+    }
+
+    #[test]
+    fn test_fade_ending_at_on_weekdays() {
+        let (mut sched, clock, rx) = scheduler(r#"
+- name: wake up
+  ends_at: "07:30"
+  days: [Mon, Tue, Wed, Thu, Fri]
+  action:
+    fade: { chans: ["1"], duration: 1800 }
+"#, "2026-10-16 12:00"); // friday
+
+        // next is monday, 30 minutes before 07:30
+        assert_eq!(sched.upcoming()[0].time, dt("2026-10-19 07:00"));
+
+        clock.set_local_time(dt("2026-10-19 06:59:59"));
+        sched.check().unwrap();
+        assert!(rx.try_recv().is_err());
+
+        clock.set_local_time(dt("2026-10-19 07:00:00"));
+        sched.check().unwrap();
+        assert!(matches!(rx.try_recv(), Ok(ScheduleAction::Fade { .. })));
+
+        clock.set_local_time(dt("2026-10-19 07:10:00"));
+        sched.check().unwrap();
+        assert!(rx.try_recv().is_err());
+        assert_eq!(sched.upcoming()[0].time, dt("2026-10-20 07:00"));
+    }
+
+    #[test]
+    fn test_cron() {
+        let cron = Cron::parse("*/15 9-17 * * 1-5").unwrap();
+        assert_eq!(cron.next_after(dt("2026-10-16 17:50")),
+                   Some(dt("2026-10-19 09:00")));
+        assert_eq!(cron.next_after(dt("2026-10-19 09:00")),
+                   Some(dt("2026-10-19 09:15")));
+
+        // either day of month or day of week when both are set
+        let cron = Cron::parse("0 0 13 * 5").unwrap();
+        assert_eq!(cron.next_after(dt("2026-10-10 00:00")),
+                   Some(dt("2026-10-13 00:00")));
+        assert_eq!(cron.next_after(dt("2026-10-13 00:00")),
+                   Some(dt("2026-10-16 00:00")));
+
+        let cron = Cron::parse("30 12 29 2 *").unwrap();
+        assert_eq!(cron.next_after(dt("2026-10-10 00:00")),
+                   Some(dt("2028-02-29 12:30")));
+
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("* * * *").is_err());
+    }
+
+    #[test]
+    fn test_once_and_skip() {
+        let (mut sched, clock, rx) = scheduler(r#"
+- name: party
+  once: "2026-12-31 23:59"
+  action: { demo: hello }
+- name: night
+  at: "01:00"
+  action: off
+"#, "2026-12-31 12:00");
+
+        let names: Vec<String> =
+            sched.upcoming().into_iter().map(|u| u.name).collect();
+        assert_eq!(names, vec!["party", "night"]);
+
+        assert_eq!(sched.skip_next("night").unwrap(), dt("2027-01-01 01:00"));
+        assert!(sched.upcoming()[1].skipped);
+
+        clock.set_local_time(dt("2027-01-01 02:00"));
+        sched.check().unwrap();
+        assert_eq!(rx.try_recv(), Ok(ScheduleAction::Demo("hello".into())));
+        assert!(rx.try_recv().is_err());
+
+        // the one after the skipped one runs, the party is over for good
+        let upcoming = sched.upcoming();
+        assert_eq!(upcoming.len(), 1);
+        assert_eq!(upcoming[0].time, dt("2027-01-02 01:00"));
+        assert!(!upcoming[0].skipped);
+        assert!(sched.skip_next("party").is_err());
+    }
+
+    #[test]
+    fn test_invalid_entries() {
+        let (tx, _rx) = mpsc::channel();
+        let entries: Vec<ScheduleEntry> = serde_yaml::from_str(r#"
+- name: scene with ends_at
+  ends_at: "07:30"
+  action: { scene: evening }
+"#).unwrap();
+        assert!(Scheduler::new(&entries, None, tx.clone()).is_err());
+
+        let entries: Vec<ScheduleEntry> = serde_yaml::from_str(r#"
+- name: both
+  at: "07:30"
+  cron: "* * * * *"
+  action: off
+"#).unwrap();
+        assert!(Scheduler::new(&entries, None, tx.clone()).is_err());
+
+        let entries: Vec<ScheduleEntry> = serde_yaml::from_str(r#"
+- name: no location
+  sun: sunset
+  action: off
+"#).unwrap();
+        assert!(Scheduler::new(&entries, None, tx).is_err());
+
+        for duration in ["-1", ".nan", "1e30"] {
+            let res = serde_yaml::from_str::<Vec<ScheduleEntry>>(&format!(r#"
+- name: bad fade
+  ends_at: "07:30"
+  action:
+    fade: {{ chans: ["1"], duration: {} }}
+"#, duration));
+            assert!(res.is_err(), "{}", duration);
+        }
+    }
+
+    #[test]
+    fn test_sun_relative() {
+        let entries: Vec<ScheduleEntry> = serde_yaml::from_str(r#"
+- name: window
+  sun: "sunset-20m"
+  action: { scene: window }
+"#).unwrap();
+        let london = Location { lat: 51.5074, lon: -0.1278 };
+        // BST
+        let clock = VirtualClock::new()
+            .with_utc_offset(chrono::Duration::hours(1));
+        clock.set_local_time(dt("2026-06-21 12:00"));
+        let (tx, _rx) = mpsc::channel();
+        let sched = Scheduler::new(&entries, Some(&london), tx).unwrap()
+            .with_clock(Arc::new(clock));
+
+        // sunset is 21:21 BST
+        let time = sched.upcoming()[0].time;
+        let diff = (time - dt("2026-06-21 21:01")).num_seconds().abs();
+        assert!(diff < 3 * 60, "{} is too far from 21:01", time);
+
+        assert_eq!(parse_sun("dusk+1h").unwrap(),
+                   (SunEvent::Dusk, chrono::Duration::hours(1)));
+        assert_eq!(parse_sun("sunrise").unwrap(),
+                   (SunEvent::Sunrise, chrono::Duration::zero()));
+        assert!(parse_sun("moonrise").is_err());
+    }
+}
@@ -0,0 +1,731 @@
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, AST, FLOAT, INT};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::audio::Levels;
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+use crate::chan_spec::ChanSpec;
+use crate::clock::{self, Clock};
+use crate::coord::Coord;
+use crate::demo::fade::FadeSpec;
+use crate::dev::DevWrite;
+use crate::expr::{self, Ctx, Expr, Selector};
+use crate::frame::Frame;
+use crate::runner::Runner;
+use crate::scene::{self, Scene};
+use crate::task::TaskMsg;
+use crate::tempo::{self, SharedTempo};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often we look for changes of the script file
+const RELOAD_CHECK: Duration = Duration::from_millis(500);
+
+/// `script: { max_operations: 10000 }`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScriptConfig {
+    /// Operations a script can run without a `sleep` or a `wait`,
+    /// it's stopped when it goes over
+    pub max_operations: usize,
+}
+
+impl Default for ScriptConfig {
+    fn default() -> Self {
+        ScriptConfig { max_operations: 10_000 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Event {
+    Beat,
+    Bar,
+    /// Sent by `Script::event`, i.e. a line on stdin
+    Named(String),
+}
+
+impl Event {
+    fn parse(name: &str) -> Self {
+        match name {
+            "beat" => Event::Beat,
+            "bar" => Event::Bar,
+            name => Event::Named(name.to_string()),
+        }
+    }
+}
+
+/// An engine that can't load modules or `eval`, with limits on how deep
+/// a script can go and how much it can allocate
+fn sandbox() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_module_resolver(DummyModuleResolver::new())
+        .disable_symbol("eval")
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(10_000)
+        .set_max_array_size(10_000)
+        .set_max_map_size(10_000);
+    engine
+}
+
+/// A Rhai script, compiled
+///
+/// ```text
+/// // chan specs, like in scenes
+/// frame("0,red:1");
+/// for k in 1..=4 {
+///     // an `expr` for the chans, they follow it until they're set again
+///     set("wall", `${k}/4 * (0.5 + 0.5*sin(t + x))`);
+///     sleep(0.5);
+/// }
+/// for chan in chans() {           // id, name, tags and x, y, z
+///     if "window" in chan.tags { set(chan.id, 0.8); }
+/// }
+/// wait("go", 10);                 // until `go` comes or 10s have passed
+/// wait("beat");                   // or "bar", from the tempo
+/// scene("evening");               // fades to a scene from the config
+/// ```
+#[derive(Clone, Debug)]
+pub struct Program {
+    ast: AST,
+}
+
+impl Program {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let ast = sandbox().compile(src).map_err(|e| e.to_string())?;
+        Ok(Program { ast })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path)
+            .map_err(|e| format!("can't read {:?}: {}", path, e))?;
+        Program::parse(&src).map_err(|e| format!("{:?}: {}", path, e))
+    }
+}
+
+/// Where the value of a chan comes from
+#[derive(Clone, Debug)]
+enum Source {
+    Const(f32),
+    Expr(Arc<Expr>),
+    Fade {
+        from: f32,
+        to: f32,
+        start: Instant,
+        /// Delay and duration
+        timing: (Duration, Duration),
+        spec: Arc<FadeSpec>,
+    },
+}
+
+/// What a script asks of the `Script` running it
+enum Call {
+    Set(Vec<(u16, Source)>),
+    Scene(Scene),
+    Sleep(f64),
+    Wait(Event, Option<f64>),
+    Done(Result<(), String>),
+}
+
+type RhaiResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// The script's end of the thread it runs on
+struct Host {
+    calls: mpsc::Sender<Call>,
+    /// Chans as they are when we're told to go on
+    resume: Mutex<mpsc::Receiver<Arc<Vec<ChanDescription>>>>,
+    chans: Mutex<Arc<Vec<ChanDescription>>>,
+    scenes: Vec<Scene>,
+    /// Since the last `sleep` or `wait`
+    ops: AtomicU64,
+}
+
+impl Host {
+    fn chans(&self) -> Arc<Vec<ChanDescription>> {
+        self.chans.lock().unwrap().clone()
+    }
+
+    fn send(&self, call: Call) -> RhaiResult<()> {
+        self.calls.send(call).map_err(|_| "script stopped".into())
+    }
+
+    /// Hands over to the `Script` until it's time to go on
+    fn pause(&self, call: Call) -> RhaiResult<()> {
+        self.send(call)?;
+        let chans = self.resume.lock().unwrap().recv()
+            .map_err(|_| "script stopped")?;
+        *self.chans.lock().unwrap() = chans;
+        self.ops.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn set(&self, selector: Dynamic, val: Dynamic) -> RhaiResult<()> {
+        let source = match val.clone().into_string() {
+            Ok(src) => Source::Expr(Arc::new(expr::compile(&src, &[])?)),
+            Err(_) => Source::Const(expr::level(number(&val)?)),
+        };
+        let selector = Selector::parse(&selector.to_string());
+        let sources = self.chans().iter()
+            .filter(|chan| selector.matches(chan))
+            .map(|chan| (chan.chan_id, source.clone()))
+            .collect();
+        self.send(Call::Set(sources))
+    }
+
+    fn frame(&self, spec: &str) -> RhaiResult<()> {
+        let mut frame = Frame::empty();
+        ChanSpec::parse_f32(spec)?.apply_to_frame(&self.chans(), &mut frame)?;
+        self.send(Call::Set(frame.iter_some()
+                            .map(|(id, val)| (id, Source::Const(*val)))
+                            .collect()))
+    }
+
+    fn scene(&self, name: &str) -> RhaiResult<()> {
+        let scene = scene::find(&self.scenes, name)
+            .ok_or_else(|| format!("scene '{}' does not exist", name))?;
+        self.send(Call::Scene(scene.clone()))
+    }
+}
+
+fn number(val: &Dynamic) -> RhaiResult<f64> {
+    val.as_float()
+        .or_else(|_| val.as_int().map(|val| val as f64))
+        .map_err(|kind| format!("expected a number, got {}", kind).into())
+}
+
+fn chan_map(chan: &ChanDescription) -> Map {
+    let mut map = Map::new();
+    map.insert("id".into(), (chan.chan_id as INT).into());
+    map.insert("name".into(), chan.name.clone().into());
+    map.insert("tags".into(), chan.config.tags.iter()
+               .map(|tag| tag.name().to_string().into())
+               .collect::<Array>().into());
+    if let Some(center) = chan.config.cuboid.map(|c| c.center()) {
+        map.insert("x".into(), (center.x as FLOAT).into());
+        map.insert("y".into(), (center.y as FLOAT).into());
+        map.insert("z".into(), (center.z as FLOAT).into());
+    }
+    map
+}
+
+/// A sandboxed engine with our functions, stopped when it goes over
+/// `max_operations` between two `sleep`s or `wait`s
+fn engine(host: &Arc<Host>, max_operations: u64) -> Engine {
+    let mut engine = sandbox();
+    // `set_max_operations` counts the whole run, which never ends for
+    // scripts that loop
+    let h = host.clone();
+    engine.on_progress(move |_| {
+        let ops = h.ops.fetch_add(1, Ordering::Relaxed) + 1;
+        (ops > max_operations).then_some(Dynamic::UNIT)
+    });
+
+    let h = host.clone();
+    engine.register_fn("chans", move || -> Array {
+        h.chans().iter().map(|chan| chan_map(chan).into()).collect()
+    });
+    let h = host.clone();
+    engine.register_fn("set", move |selector: Dynamic, val: Dynamic| {
+        h.set(selector, val)
+    });
+    let h = host.clone();
+    engine.register_fn("frame", move |spec: &str| h.frame(spec));
+    let h = host.clone();
+    engine.register_fn("scene", move |name: &str| h.scene(name));
+    // typed, so they're picked over Rhai's own `sleep`
+    let h = host.clone();
+    engine.register_fn("sleep", move |secs: FLOAT| h.pause(Call::Sleep(secs)));
+    let h = host.clone();
+    engine.register_fn("sleep", move |secs: INT| {
+        h.pause(Call::Sleep(secs as f64))
+    });
+    let h = host.clone();
+    engine.register_fn("wait", move |event: &str| {
+        h.pause(Call::Wait(Event::parse(event), None))
+    });
+    let h = host.clone();
+    engine.register_fn("wait", move |event: &str, timeout: Dynamic| {
+        h.pause(Call::Wait(Event::parse(event), Some(number(&timeout)?)))
+    });
+    engine
+}
+
+/// Our end of the thread a script runs on, dropping it stops the script
+/// at its next call
+struct ScriptThread {
+    calls: mpsc::Receiver<Call>,
+    resume: mpsc::Sender<Arc<Vec<ChanDescription>>>,
+}
+
+impl ScriptThread {
+    fn spawn(program: Arc<Program>, config: &ScriptConfig,
+             scenes: Vec<Scene>, chans: Arc<Vec<ChanDescription>>) -> Self {
+        let (calls_tx, calls) = mpsc::channel();
+        let (resume, resume_rx) = mpsc::channel();
+        let host = Arc::new(Host {
+            calls: calls_tx,
+            resume: Mutex::new(resume_rx),
+            chans: Mutex::new(chans),
+            scenes,
+            ops: AtomicU64::new(0),
+        });
+        let max_operations = config.max_operations as u64;
+
+        thread::spawn(move || {
+            let res = engine(&host, max_operations).run_ast(&program.ast)
+                .map_err(|e| match *e {
+                    EvalAltResult::ErrorTerminated(..) => format!(
+                        "script stopped after {} operations without a \
+                         `sleep` or a `wait`", max_operations),
+                    e => e.to_string(),
+                });
+            let _ = host.calls.send(Call::Done(res));
+        });
+        ScriptThread { calls, resume }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum State {
+    Running,
+    Sleeping(Instant),
+    Waiting { event: Event, until: Option<Instant>, target: f64 },
+    Done,
+}
+
+fn secs(val: f64) -> Duration {
+    match val {
+        val if val.is_finite() => Duration::from_secs_f64(val.max(0.0)),
+        _ => Duration::ZERO,
+    }
+}
+
+/// Runs a `Program` on `output`, reloading it when its file changes
+pub struct Script<T: ?Sized> {
+    output: Arc<Mutex<T>>,
+    program: Arc<Program>,
+    config: ScriptConfig,
+    scenes: Vec<Scene>,
+    pub frame_duration: Duration,
+    clock: Arc<dyn Clock>,
+    tempo: SharedTempo,
+
+    /// Where we've loaded it from and when that has changed
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    checked_at: Instant,
+
+    thread: Option<ScriptThread>,
+    started: Instant,
+    state: State,
+    sources: HashMap<u16, Source>,
+    /// What we've output last, for `v`, `chan()` and fades
+    prev: Frame<f32>,
+}
+
+impl<T: HasChanDescriptions + DevWrite + ?Sized> Script<T> {
+    pub fn new(output: Arc<Mutex<T>>, program: Program) -> Self {
+        let clock = clock::real();
+        let now = clock.now();
+        Script {
+            output,
+            program: Arc::new(program),
+            config: ScriptConfig::default(),
+            scenes: vec![],
+            frame_duration: Duration::from_millis(10),
+            clock,
+            tempo: tempo::shared(),
+            path: None,
+            modified: None,
+            checked_at: now,
+            thread: None,
+            started: now,
+            state: State::Running,
+            sources: HashMap::new(),
+            prev: Frame::empty(),
+        }
+    }
+
+    /// Loads the script from `path` and reloads it whenever it changes
+    pub fn load<P: AsRef<Path>>(output: Arc<Mutex<T>>, path: P)
+            -> Result<Self, String> {
+        let path = path.as_ref();
+        let mut script = Script::new(output, Program::load(path)?);
+        script.modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        script.path = Some(path.to_path_buf());
+        Ok(script)
+    }
+
+    pub fn with_config(mut self, config: ScriptConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Scenes for `scene(NAME)`
+    pub fn with_scenes(mut self, scenes: Vec<Scene>) -> Self {
+        self.scenes = scenes;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.started = clock.now();
+        self.checked_at = self.started;
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_tempo(mut self, tempo: SharedTempo) -> Self {
+        self.tempo = tempo;
+        self
+    }
+
+    /// Ends a `wait` for `name`
+    pub fn event(&mut self, name: &str) {
+        if let State::Waiting { event: Event::Named(waiting), .. } = &self.state {
+            if waiting == name {
+                self.state = State::Running;
+            }
+        }
+    }
+
+    /// Starts over, chans keep their values until they're set again
+    fn restart(&mut self, now: Instant) {
+        self.thread = None;
+        self.started = now;
+        self.state = State::Running;
+        self.sources = self.prev.iter_some()
+            .map(|(id, val)| (id, Source::Const(*val)))
+            .collect();
+    }
+
+    fn reload_if_changed(&mut self, now: Instant) {
+        let path = match &self.path {
+            Some(path) if now - self.checked_at >= RELOAD_CHECK => path,
+            _ => return,
+        };
+        self.checked_at = now;
+
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+
+        match Program::load(path) {
+            Ok(program) => {
+                println!("reloaded {:?}", path);
+                self.program = Arc::new(program);
+                self.restart(now);
+            },
+            Err(e) => eprintln!("{}, keeping the old script", e),
+        }
+    }
+
+    /// Pushes everything time based `by` later, after a pause
+    fn shift(&mut self, by: Duration) {
+        self.started += by;
+        match &mut self.state {
+            State::Sleeping(at) => *at += by,
+            State::Waiting { until: Some(until), .. } => *until += by,
+            _ => {},
+        }
+        for source in self.sources.values_mut() {
+            if let Source::Fade { start, .. } = source {
+                *start += by;
+            }
+        }
+    }
+
+    fn eval(&self, expr: &Expr, now: Instant, chans: &[ChanDescription],
+            index: usize, chan: &ChanDescription) -> f64 {
+        let (beat, bar) = {
+            let tempo = self.tempo.lock().unwrap();
+            (tempo.beats(), tempo.bars())
+        };
+        expr.eval(&Ctx {
+            t: (now - self.started).as_secs_f64(),
+            index,
+            count: chans.len(),
+            chan,
+            center: chan.config.cuboid.map(|c| c.center())
+                .unwrap_or(Coord { x: 0.0, y: 0.0, z: 0.0 }),
+            prev: &self.prev,
+            levels: &Levels::default(),
+            beat,
+            bar,
+        })
+    }
+
+    /// Whether we're done sleeping or waiting
+    fn ready(&mut self, now: Instant) -> bool {
+        let ready = match &self.state {
+            State::Running => true,
+            State::Sleeping(at) => now >= *at,
+            State::Waiting { event, until, target } => {
+                let reached = match event {
+                    Event::Beat => self.tempo.lock().unwrap().beats() >= *target,
+                    Event::Bar => self.tempo.lock().unwrap().bars() >= *target,
+                    Event::Named(_) => false,
+                };
+                reached || until.is_some_and(|until| now >= until)
+            },
+            State::Done => false,
+        };
+        if ready {
+            self.state = State::Running;
+        }
+        ready
+    }
+
+    /// Lets the script run until its next `sleep` or `wait`, or the end
+    fn resume(&mut self, now: Instant, chans: &[ChanDescription])
+            -> Result<(), String> {
+        let chans = Arc::new(chans.to_vec());
+        let thread = match self.thread.take() {
+            Some(thread) => {
+                // it has stopped if it's not there to go on
+                let _ = thread.resume.send(chans.clone());
+                thread
+            },
+            None => ScriptThread::spawn(self.program.clone(), &self.config,
+                                        self.scenes.clone(), chans.clone()),
+        };
+
+        loop {
+            let call = match thread.calls.recv() {
+                Ok(call) => call,
+                Err(_) => {
+                    self.state = State::Done;
+                    return Err("script thread has died".to_string());
+                },
+            };
+            match call {
+                Call::Set(sources) => self.sources.extend(sources),
+                Call::Scene(scene) => {
+                    let (spec, timing) = (Arc::new(scene.fade.clone()),
+                                          scene.fade.timing(&chans));
+                    for (id, to) in scene.frame(&chans)?.iter_some() {
+                        self.sources.insert(id, Source::Fade {
+                            from: self.prev.get(id).unwrap_or(*to),
+                            to: *to,
+                            start: now,
+                            timing: timing.get(id),
+                            spec: spec.clone(),
+                        });
+                    }
+                },
+                Call::Sleep(val) => {
+                    self.state = State::Sleeping(now + secs(val));
+                    break;
+                },
+                Call::Wait(event, timeout) => {
+                    let target = {
+                        let tempo = self.tempo.lock().unwrap();
+                        match event {
+                            Event::Beat => tempo.beats().floor() + 1.0,
+                            Event::Bar => tempo.bars().floor() + 1.0,
+                            Event::Named(_) => 0.0,
+                        }
+                    };
+                    self.state = State::Waiting {
+                        event,
+                        until: timeout.map(|val| now + secs(val)),
+                        target,
+                    };
+                    break;
+                },
+                Call::Done(res) => {
+                    self.state = State::Done;
+                    return res;
+                },
+            }
+        }
+        self.thread = Some(thread);
+        Ok(())
+    }
+
+    fn render(&mut self, now: Instant, chans: &[ChanDescription])
+            -> Frame<f32> {
+        let mut frame = Frame::empty();
+        for (index, chan) in chans.iter().enumerate() {
+            let val = match self.sources.get(&chan.chan_id) {
+                None => continue,
+                Some(Source::Const(val)) => *val,
+                Some(Source::Expr(expr)) =>
+                    expr::level(self.eval(expr, now, chans, index, chan)),
+                Some(Source::Fade { from, to, start, timing, spec }) => {
+                    let elapsed = now - *start;
+                    let val = spec.value_at(*from, *to, elapsed, *timing);
+                    if elapsed >= timing.0 + timing.1 {
+                        self.sources.insert(chan.chan_id, Source::Const(val));
+                    }
+                    val
+                },
+            };
+            frame.set(chan.chan_id, val);
+        }
+        frame
+    }
+
+    fn tick(&mut self, now: Instant) -> Result<(), String> {
+        self.reload_if_changed(now);
+
+        let chans = self.output.lock()
+            .map_err(|e| format!("read lock: {:?}", e))?
+            .chan_descriptions();
+        let res = match self.ready(now) {
+            true => self.resume(now, &chans),
+            false => Ok(()),
+        };
+
+        let frame = self.render(now, &chans);
+        self.output.lock()
+            .map_err(|e| format!("write lock: {:?}", e))?
+            .set_frame(&frame)?;
+        self.prev = frame;
+        res
+    }
+
+    /// Done and there's no file to change
+    fn finished(&self) -> bool {
+        self.state == State::Done && self.path.is_none()
+    }
+}
+
+impl<T: HasChanDescriptions + DevWrite + ?Sized> Runner for Script<T> {
+    fn run(
+        self_lock: Arc<Mutex<Script<T>>>,
+        stop: mpsc::Receiver<TaskMsg>,
+    ) -> Result<(), String> {
+        let (frame_duration, clock) = {
+            let script = self_lock.lock().unwrap();
+            (script.frame_duration, script.clock.clone())
+        };
+
+        let mut paused_at: Option<Instant> = None;
+        loop {
+            if paused_at.is_none() {
+                let mut script = self_lock.lock().unwrap();
+                if let Err(e) = script.tick(clock.now()) {
+                    eprintln!("script err: {}", e);
+                }
+                if script.finished() {
+                    return Ok(());
+                }
+            }
+
+            match clock.recv_timeout(&stop, frame_duration) {
+                Ok(TaskMsg::Stop) => return Ok(()),
+                Ok(TaskMsg::Pause) => match paused_at.take() {
+                    Some(paused_at) => self_lock.lock().unwrap()
+                        .shift(clock.now() - paused_at),
+                    None => paused_at = Some(clock.now()),
+                },
+                Ok(TaskMsg::Ping) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::tempo::Tempo;
+    use crate::test_dev::TestDev;
+
+    fn run(src: &str) -> Result<(), String> {
+        let dev = Arc::new(Mutex::new(TestDev::new(false)));
+        let mut script = Script::new(dev, Program::parse(src)?);
+        script.tick(Instant::now())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(Program::parse("for k in 1..=2 { sleep(k); }").is_ok());
+        for (bad, err) in [
+            ("sleep(1", "Expecting ')'"),
+            ("let x = eval(\"1\");", "'eval' is disabled"),
+        ] {
+            let res = Program::parse(bad).unwrap_err();
+            assert!(res.contains(err), "{}: {}", bad, res);
+        }
+
+        // no files, no scenes that aren't there
+        for (bad, err) in [
+            ("import \"script\" as s;", "Module not found"),
+            ("scene(\"evening\");", "scene 'evening' does not exist"),
+            ("set(\"all\", \"sin(\");", "unexpected end"),
+            ("wait(\"go\", \"1\");", "expected a number"),
+        ] {
+            let res = run(bad).unwrap_err();
+            assert!(res.contains(err), "{}: {}", bad, res);
+        }
+    }
+
+    #[test]
+    fn test_script() {
+        let program = Program::parse("
+            for chan in chans() { set(chan.id, 0); }
+            for k in 1..=2 {
+                set(1, `${k}/4 + v`);
+                sleep(0.1);
+            }
+            wait(\"go\", 1);
+            scene(\"up\");
+            wait(\"beat\");
+            frame(\"2:0.5\");
+            loop {}
+        ").unwrap();
+        let up = Scene {
+            name: "up".to_string(),
+            chans: vec![ChanSpec::parse_f32("0:1").unwrap()],
+            fade: FadeSpec {
+                fade_duration: Duration::from_millis(100),
+                ..FadeSpec::default()
+            },
+            id: None,
+        };
+        let clock = VirtualClock::new();
+        let tempo = Tempo::new(60.0).with_clock(Arc::new(clock.clone()));
+        let dev = Arc::new(Mutex::new(TestDev::new(false)));
+        let mut script = Script::new(dev.clone(), program)
+            .with_clock(Arc::new(clock.clone()))
+            .with_tempo(Arc::new(Mutex::new(tempo)))
+            .with_scenes(vec![up]);
+
+        let step = |script: &mut Script<TestDev>, millis, vals: [f32; 3]| {
+            clock.step(Duration::from_millis(millis));
+            let res = script.tick(clock.now());
+            assert_eq!(vals.to_vec(), (0..3)
+                       .map(|id| script.prev.get(id).unwrap())
+                       .collect::<Vec<_>>());
+            res
+        };
+        // k/4 plus what it was, every frame
+        step(&mut script, 0, [0.0, 0.25, 0.0]).unwrap();
+        step(&mut script, 10, [0.0, 0.5, 0.0]).unwrap();
+        step(&mut script, 90, [0.0, 1.0, 0.0]).unwrap();
+        // waiting for `go`
+        step(&mut script, 2000, [0.0, 1.0, 0.0]).unwrap();
+        script.event("go");
+        step(&mut script, 0, [0.0, 1.0, 0.0]).unwrap();
+        step(&mut script, 50, [0.5, 1.0, 0.0]).unwrap();
+        step(&mut script, 50, [1.0, 1.0, 0.0]).unwrap();
+        // the next beat is 3s in, then it spins
+        assert!(step(&mut script, 800, [1.0, 1.0, 0.5]).unwrap_err()
+                .starts_with("script stopped after 10000 operations"));
+        assert!(script.finished());
+    }
+}
@@ -0,0 +1,535 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::chan_description::HasChanDescriptions;
+use crate::chan_spec::ChanSpec;
+use crate::clock::{self, Clock};
+use crate::demo::fade::{FadeSpec, Timing};
+use crate::dev::{DevRead, DevWrite};
+use crate::easing::Easing;
+use crate::frame::Frame;
+use crate::runner::Runner;
+use crate::scene::{self, Scene};
+use crate::task::TaskMsg;
+
+use std::fmt;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Ordered list of cues, i.e. a slow evening transition in a few steps
+///
+/// ```yaml
+/// sequences:
+///   - name: evening
+///     cues:
+///       - scene: evening
+///         fade: 600
+///         hold: 1200
+///       - chans: ["0.1", "wall:0.3"]
+///         fade: 900
+///         easing: EaseOut
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SequenceSpec {
+    pub name: String,
+
+    /// Start from the first cue after the last one
+    #[serde(default)]
+    pub looped: bool,
+
+    pub cues: Vec<CueSpec>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CueSpec {
+    /// Name or id of a scene from the config
+    #[serde(default)]
+    pub scene: Option<String>,
+
+    /// Applied on top of the scene if there is one
+    #[serde(default, with = "crate::scene::chan_specs_str")]
+    pub chans: Vec<ChanSpec>,
+
+    /// Fade duration in seconds, defaults to the scene's fade duration
+    #[serde(default)]
+    pub fade: Option<f32>,
+
+    /// How long to stay on this cue after the fade in seconds,
+    /// waits for GO if not specified
+    #[serde(default)]
+    pub hold: Option<f32>,
+
+    #[serde(default)]
+    pub easing: Option<Easing>,
+}
+
+/// Seconds of `what` from the config
+fn secs(what: &str, secs: f32) -> Result<Duration, String> {
+    Duration::try_from_secs_f32(secs)
+        .map_err(|e| format!("{} {}: {}", what, secs, e))
+}
+
+impl CueSpec {
+    /// Combines the cue with its scene
+    fn resolve(&self, scenes: &[Scene]) -> Result<Scene, String> {
+        let mut resolved = match &self.scene {
+            Some(name) => scene::find(scenes, name)
+                .ok_or_else(|| format!("scene '{}' not found", name))?
+                .clone(),
+            None => Scene {
+                name: String::new(),
+                chans: vec![],
+                fade: FadeSpec::default(),
+                id: None,
+            },
+        };
+
+        resolved.chans.extend(self.chans.iter().cloned());
+        if let Some(fade) = self.fade {
+            resolved.fade.fade_duration = secs("fade", fade)?;
+        }
+        resolved.fade.easing = self.easing.unwrap_or(resolved.fade.easing);
+        Ok(resolved)
+    }
+}
+
+#[derive(Debug)]
+struct Cue {
+    target: Frame<f32>,
+    fade: FadeSpec,
+    timing: Timing,
+    hold: Option<Duration>,
+}
+
+impl Cue {
+    /// Goes straight to the next cue
+    fn takes_no_time(&self) -> bool {
+        self.timing.end() == Duration::ZERO && self.hold == Some(Duration::ZERO)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SequenceState {
+    /// Fading to the cue or holding it
+    Running { cue: usize },
+
+    /// The last cue is done and we don't loop
+    Done,
+}
+
+#[derive(Debug)]
+pub struct Sequence<T> {
+    output: Arc<Mutex<T>>,
+
+    pub name: String,
+    pub looped: bool,
+    pub frame_duration: Duration,
+
+    cues: Vec<Cue>,
+    state: SequenceState,
+
+    /// When the current cue has started
+    cue_start: Instant,
+
+    /// When we've paused, `None` if we're not paused
+    paused_at: Option<Instant>,
+
+    /// Values at the start of the current cue, we crossfade from them
+    from_frame: Frame<f32>,
+
+    /// What we've rendered last
+    current_frame: Frame<f32>,
+
+    clock: Arc<dyn Clock>,
+}
+
+impl<T: DevRead + HasChanDescriptions> Sequence<T> {
+    pub fn new(
+        output: Arc<Mutex<T>>,
+        spec: &SequenceSpec,
+        scenes: &[Scene],
+    ) -> Result<Self, String> {
+        if spec.cues.is_empty() {
+            return Err(format!("sequence '{}' has no cues", spec.name));
+        }
+
+        let mut current_frame = Frame::empty();
+        let cues = {
+            let output = output.lock().unwrap();
+            output.get_to_frame(&mut current_frame)?;
+            let chans = output.chan_descriptions();
+
+            spec.cues.iter().enumerate()
+                .map(|(ii, cue)| {
+                    let err = |e: String| {
+                        format!("sequence '{}' cue {}: {}", spec.name, ii, e)
+                    };
+                    let scene = cue.resolve(scenes).map_err(err)?;
+                    let hold = cue.hold.map(|hold| secs("hold", hold))
+                        .transpose().map_err(err)?;
+                    Ok(Cue {
+                        target: scene.frame(&chans)?,
+                        timing: scene.fade.timing(&chans),
+                        fade: scene.fade,
+                        hold,
+                    })
+                })
+                .collect::<Result<Vec<Cue>, String>>()?
+        };
+
+        if spec.looped && cues.iter().all(|cue| cue.takes_no_time()) {
+            return Err(format!("looped sequence '{}' takes no time, give \
+                                its cues a fade or hold", spec.name));
+        }
+
+        let clock = clock::real();
+        Ok(Sequence {
+            output,
+            name: spec.name.clone(),
+            looped: spec.looped,
+            frame_duration: Duration::from_secs_f32(1.0 / 60.0),
+            cues,
+            state: SequenceState::Running { cue: 0 },
+            cue_start: clock.now(),
+            paused_at: None,
+            from_frame: current_frame.clone(),
+            current_frame,
+            clock,
+        })
+    }
+}
+
+impl<T> Sequence<T> {
+    /// Starts the first cue over at the time of `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.cue_start = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    pub fn state(&self) -> SequenceState {
+        self.state
+    }
+
+    pub fn num_cues(&self) -> usize {
+        self.cues.len()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    /// Starts cue `cue` at `now`, crossfading from whatever we have now
+    fn start_cue(&mut self, cue: usize, now: Instant) {
+        self.state = SequenceState::Running { cue };
+        self.cue_start = now;
+        self.from_frame = self.current_frame.clone();
+        if self.paused_at.is_some() {
+            self.paused_at = Some(now);
+        }
+    }
+
+    fn next_cue(&self, cue: usize) -> Option<usize> {
+        if cue + 1 < self.cues.len() {
+            Some(cue + 1)
+        } else if self.looped {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    /// Go to the next cue now without waiting for the hold
+    pub fn go_at(&mut self, now: Instant) {
+        match self.state {
+            SequenceState::Running { cue } => match self.next_cue(cue) {
+                Some(next) => self.start_cue(next, now),
+                None => self.state = SequenceState::Done,
+            },
+            SequenceState::Done => {}
+        }
+    }
+
+    /// Go to the previous cue, or restart the first one
+    pub fn back_at(&mut self, now: Instant) {
+        let cue = match self.state {
+            SequenceState::Running { cue } => cue.saturating_sub(1),
+            SequenceState::Done => self.cues.len() - 1,
+        };
+        self.start_cue(cue, now);
+    }
+
+    /// Pauses or resumes, the time spent paused doesn't count
+    pub fn toggle_pause_at(&mut self, now: Instant) {
+        match self.paused_at.take() {
+            Some(paused_at) => self.cue_start += now - paused_at,
+            None => self.paused_at = Some(now),
+        }
+    }
+
+    pub fn go(&mut self) {
+        self.go_at(self.clock.now())
+    }
+
+    pub fn back(&mut self) {
+        self.back_at(self.clock.now())
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.toggle_pause_at(self.clock.now())
+    }
+
+    /// Advances the state to `now` and renders the frame to show
+    pub fn update(&mut self, now: Instant) -> &Frame<f32> {
+        let now = self.paused_at.unwrap_or(now);
+
+        // cues started without time moving, a whole loop of them would go
+        // round forever
+        let mut instant_cues = 0;
+        loop {
+            let cue_id = match self.state {
+                SequenceState::Running { cue } => cue,
+                SequenceState::Done => return &self.current_frame,
+            };
+            let cue = &self.cues[cue_id];
+            let (fade, hold) = (cue.timing.end(), cue.hold);
+            let elapsed = now.saturating_duration_since(self.cue_start);
+
+            if elapsed < fade {
+                self.render(cue_id, elapsed);
+                return &self.current_frame;
+            }
+            self.render(cue_id, fade);
+
+            let hold = match hold {
+                Some(hold) => hold,
+                // wait for GO
+                None => return &self.current_frame,
+            };
+            let cue_end = self.cue_start + fade + hold;
+            if now < cue_end {
+                return &self.current_frame;
+            }
+
+            instant_cues = match cue_end == self.cue_start {
+                true => instant_cues + 1,
+                false => 0,
+            };
+            if instant_cues >= self.cues.len() {
+                return &self.current_frame;
+            }
+
+            match self.next_cue(cue_id) {
+                Some(next) => self.start_cue(next, cue_end),
+                None => {
+                    self.state = SequenceState::Done;
+                    return &self.current_frame;
+                }
+            }
+        }
+    }
+
+    fn render(&mut self, cue: usize, elapsed: Duration) {
+        let cue = &self.cues[cue];
+
+        for (cid, target) in cue.target.iter_some() {
+            let from = self.from_frame.get(cid).unwrap_or(*target);
+            let val = cue.fade.value_at(from, *target, elapsed,
+                                        cue.timing.get(cid));
+            self.current_frame.set(cid, val);
+        }
+    }
+}
+
+impl<T: DevRead + DevWrite + fmt::Debug> Runner for Sequence<T> {
+    fn run(
+        self_lock: Arc<Mutex<Sequence<T>>>,
+        stop: mpsc::Receiver<TaskMsg>,
+    ) -> Result<(), String> {
+        let clock = self_lock.lock().unwrap().clock.clone();
+        loop {
+            let frame_duration = {
+                let mut seq = self_lock.lock().unwrap();
+                let output = seq.output.clone();
+                let frame = seq.update(clock.now());
+                if let Err(e) = output.lock().unwrap().set_frame(frame) {
+                    eprintln!("sequence set_frame err: {e:?}");
+                }
+
+                if seq.state() == SequenceState::Done {
+                    return Ok(());
+                }
+                seq.frame_duration
+            };
+
+            match clock.recv_timeout(&stop, frame_duration) {
+                Ok(TaskMsg::Pause) => {
+                    self_lock.lock().unwrap().toggle_pause();
+                },
+                Ok(TaskMsg::Stop) => return Ok(()),
+                Ok(TaskMsg::Ping) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use crate::chan::ChanConfig;
+    use crate::mux::Mux;
+    use crate::test_dev::TestDev;
+
+    fn output() -> Arc<Mutex<Mux>> {
+        let mut mux = Mux::new();
+        mux.add_dev(Arc::new(Mutex::new(TestDev::new(false))),
+                    None::<std::iter::Empty<ChanConfig>>);
+        Arc::new(Mutex::new(mux))
+    }
+
+    fn sequence(looped: bool) -> (Sequence<Mux>, Instant) {
+        let spec: SequenceSpec = serde_yaml::from_str(r#"
+name: test
+cues:
+  - scene: half
+    hold: 1.0
+  - chans: ["1:1"]
+    fade: 2.0
+"#).unwrap();
+        let scenes: Vec<Scene> = serde_yaml::from_str(r#"
+- name: half
+  chans: ["0.5"]
+  fade: 1.0
+"#).unwrap();
+
+        let mut seq = Sequence::new(output(), &spec, &scenes).unwrap();
+        seq.looped = looped;
+        let start = Instant::now();
+        seq.cue_start = start;
+        (seq, start)
+    }
+
+    fn vals(seq: &mut Sequence<Mux>, t: Instant) -> Vec<f32> {
+        seq.update(t).vals.iter().map(|v| v.unwrap()).collect()
+    }
+
+    fn secs(s: f32) -> Duration {
+        Duration::from_secs_f32(s)
+    }
+
+    #[test]
+    fn test_fade_hold_and_wait_for_go() {
+        let (mut seq, t) = sequence(false);
+
+        assert_eq!(vals(&mut seq, t + secs(0.5)), vec![0.25, 0.25, 0.25]);
+        assert_eq!(vals(&mut seq, t + secs(1.5)), vec![0.5, 0.5, 0.5]);
+
+        // fading to the second cue after 1s fade + 1s hold
+        assert_eq!(vals(&mut seq, t + secs(3.0)), vec![0.5, 0.75, 0.5]);
+        assert_eq!(seq.state(), SequenceState::Running { cue: 1 });
+
+        // the last cue has no hold, so we stay there until GO
+        assert_eq!(vals(&mut seq, t + secs(100.0)), vec![0.5, 1.0, 0.5]);
+        assert_eq!(seq.state(), SequenceState::Running { cue: 1 });
+
+        seq.go_at(t + secs(101.0));
+        assert_eq!(seq.state(), SequenceState::Done);
+    }
+
+    #[test]
+    fn test_loop() {
+        let (mut seq, t) = sequence(true);
+        vals(&mut seq, t + secs(100.0));
+        seq.go_at(t + secs(100.0));
+        assert_eq!(seq.state(), SequenceState::Running { cue: 0 });
+
+        // crossfades from the second cue values
+        assert_eq!(vals(&mut seq, t + secs(100.5)), vec![0.5, 0.75, 0.5]);
+    }
+
+    #[test]
+    fn test_go_crossfades_from_current_values() {
+        let (mut seq, t) = sequence(false);
+        assert_eq!(vals(&mut seq, t + secs(0.5)), vec![0.25, 0.25, 0.25]);
+
+        seq.go_at(t + secs(0.5));
+        assert_eq!(vals(&mut seq, t + secs(1.5)), vec![0.25, 0.625, 0.25]);
+
+        seq.back_at(t + secs(1.5));
+        assert_eq!(seq.state(), SequenceState::Running { cue: 0 });
+        assert_eq!(vals(&mut seq, t + secs(2.0)), vec![0.375, 0.5625, 0.375]);
+    }
+
+    #[test]
+    fn test_pause() {
+        let (mut seq, t) = sequence(false);
+        seq.toggle_pause_at(t + secs(0.5));
+        assert_eq!(vals(&mut seq, t + secs(10.0)), vec![0.25, 0.25, 0.25]);
+
+        seq.toggle_pause_at(t + secs(10.0));
+        assert!(!seq.is_paused());
+        assert_eq!(vals(&mut seq, t + secs(10.5)), vec![0.5, 0.5, 0.5]);
+        assert_eq!(seq.state(), SequenceState::Running { cue: 0 });
+    }
+
+    #[test]
+    fn test_loop_taking_no_time() {
+        let mut spec: SequenceSpec = serde_yaml::from_str(r#"
+name: instant
+cues:
+  - chans: ["0.2"]
+    fade: 0
+    hold: 0
+  - chans: ["0.8"]
+    fade: 0
+    hold: 0
+"#).unwrap();
+        let mut seq = Sequence::new(output(), &spec, &[]).unwrap();
+
+        // doesn't go round forever when looped after all
+        seq.looped = true;
+        let t = Instant::now();
+        seq.cue_start = t;
+        assert_eq!(vals(&mut seq, t + secs(1.0)), vec![0.8, 0.8, 0.8]);
+        assert!(matches!(seq.state(), SequenceState::Running { .. }));
+
+        spec.looped = true;
+        assert!(Sequence::new(output(), &spec, &[]).is_err());
+    }
+
+    #[test]
+    fn test_unknown_scene() {
+        let spec = SequenceSpec {
+            name: "bad".to_string(),
+            looped: false,
+            cues: vec![CueSpec {
+                scene: Some("nope".to_string()),
+                chans: vec![],
+                fade: None,
+                hold: None,
+                easing: None,
+            }],
+        };
+        assert!(Sequence::new(output(), &spec, &[]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_durations() {
+        for (cue, err) in [
+            ("fade: -1", "sequence 'bad' cue 0: fade -1"),
+            ("hold: -1", "sequence 'bad' cue 0: hold -1"),
+            ("hold: .nan", "sequence 'bad' cue 0: hold NaN"),
+            ("fade: 1e30", "sequence 'bad' cue 0: fade 1000000000"),
+        ] {
+            let spec: SequenceSpec = serde_yaml::from_str(&format!(
+                "{{ name: bad, cues: [{{ chans: ['1'], {} }}] }}", cue))
+                .unwrap();
+            let res = Sequence::new(output(), &spec, &[]).unwrap_err();
+            assert!(res.starts_with(err), "{}: {}", cue, res);
+        }
+    }
+}
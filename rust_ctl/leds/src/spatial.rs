@@ -0,0 +1,441 @@
+use crate::chan_description::ChanDescription;
+use crate::coord::Coord;
+use crate::cuboid::{self, Cuboid};
+use crate::effect::{Effect, EffectInfo, ParamSpec};
+use crate::frame::Frame;
+
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+/// Room coordinates are 0 to 1 on every axis, cells this far apart are
+/// sampled inside each cuboid
+const SAMPLE_STEP: f32 = 0.05;
+const MAX_SAMPLES_PER_AXIS: usize = 12;
+
+/// Brightness anywhere in the room over time
+pub trait Field: Send {
+    /// 0 to 1 at `p`, `t` seconds since the effect started
+    fn at(&self, t: f32, p: Coord) -> f32;
+
+    /// Average over the whole `cuboid` when the field knows better than
+    /// sampling it
+    fn over(&self, _t: f32, _cuboid: &Cuboid) -> Option<f32> {
+        None
+    }
+
+    /// Start over with random numbers from `seed`, for fields using any
+    fn reseed(&mut self, _seed: u64) {}
+}
+
+/// Lights every chan with a cuboid by the average of `field` over its
+/// volume, chans without one are left alone
+pub struct SpatialEffect<F: Field> {
+    pub field: F,
+    /// Sample points per chan, computed when we first see the chans
+    samples: Vec<(u16, Cuboid, Vec<Coord>)>,
+}
+
+impl<F: Field> SpatialEffect<F> {
+    pub fn new(field: F) -> Self {
+        SpatialEffect { field, samples: vec![] }
+    }
+
+    fn update_samples(&mut self, chans: &[ChanDescription]) {
+        let with_cuboids = chans.iter()
+            .filter(|chan| chan.config.cuboid.is_some())
+            .count();
+        if with_cuboids == self.samples.len() {
+            return;
+        }
+
+        self.samples = chans.iter()
+            .filter_map(|chan| chan.config.cuboid.map(|cuboid| (
+                chan.chan_id,
+                cuboid,
+                cuboid.samples(SAMPLE_STEP, MAX_SAMPLES_PER_AXIS),
+            )))
+            .collect();
+    }
+}
+
+impl<F: Field> Effect for SpatialEffect<F> {
+    fn render(&mut self, t: Duration, chans: &[ChanDescription]) -> Frame<f32> {
+        self.update_samples(chans);
+
+        let t = t.as_secs_f32();
+        let mut frame = Frame::empty();
+        for (chan_id, cuboid, samples) in self.samples.iter() {
+            let val = self.field.over(t, cuboid).unwrap_or_else(|| {
+                let sum: f32 = samples.iter()
+                    .map(|p| self.field.at(t, *p).clamp(0.0, 1.0))
+                    .sum();
+                sum / samples.len() as f32
+            });
+            frame.set(*chan_id, val.clamp(0.0, 1.0));
+        }
+        frame
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.field.reseed(seed);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub const ALL: &'static [&'static str] = &["x", "y", "z"];
+
+    pub(crate) fn of(&self, p: Coord) -> f32 {
+        match self {
+            Axis::X => p.x,
+            Axis::Y => p.y,
+            Axis::Z => p.z,
+        }
+    }
+}
+
+impl std::str::FromStr for Axis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "x" => Ok(Axis::X),
+            "y" => Ok(Axis::Y),
+            "z" => Ok(Axis::Z),
+            other => Err(format!("unknown axis '{}', expected x, y or z",
+                                 other)),
+        }
+    }
+}
+
+/// A lit slab `width` thick crossing the room along `axis` every `period`
+/// seconds, back and forth when `bounce`
+pub struct PlaneSweep {
+    pub axis: Axis,
+    pub period: f32,
+    pub width: f32,
+    pub bounce: bool,
+}
+
+impl Field for PlaneSweep {
+    fn at(&self, t: f32, p: Coord) -> f32 {
+        let phase = (t / self.period).rem_euclid(1.0);
+        let phase = match self.bounce {
+            true => 1.0 - (phase * 2.0 - 1.0).abs(),
+            false => phase,
+        };
+        // starts and ends fully outside the room
+        let pos = -self.width + phase * (1.0 + 2.0 * self.width);
+        (1.0 - (self.axis.of(p) - pos).abs() / self.width).max(0.0)
+    }
+}
+
+/// Rings moving out of `center` at `speed`, dimmer further away
+pub struct Ripple {
+    pub center: Coord,
+    pub speed: f32,
+    pub wavelength: f32,
+    pub decay: f32,
+}
+
+impl Field for Ripple {
+    fn at(&self, t: f32, p: Coord) -> f32 {
+        let dist = self.center.dist_to(&p);
+        let wave = ((dist - self.speed * t) / self.wavelength * TAU).cos();
+        (0.5 + 0.5 * wave) * (-dist * self.decay).exp()
+    }
+}
+
+/// Smooth value noise drifting through the room at `drift` per second
+pub struct Noise {
+    /// Noise cells per room
+    pub scale: f32,
+    pub drift: Coord,
+    pub seed: u32,
+}
+
+impl Noise {
+    /// 0 to 1 for a lattice point
+    pub(crate) fn lattice(&self, x: i32, y: i32, z: i32) -> f32 {
+        let mut h = self.seed
+            ^ (x as u32).wrapping_mul(0x8da6_b343)
+            ^ (y as u32).wrapping_mul(0xd816_3841)
+            ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+        h = (h ^ (h >> 16)).wrapping_mul(0x7feb_352d);
+        h = (h ^ (h >> 15)).wrapping_mul(0x846c_a68b);
+        h ^= h >> 16;
+        h as f32 / u32::MAX as f32
+    }
+
+    pub(crate) fn sample(&self, x: f32, y: f32, z: f32) -> f32 {
+        let smooth = |v: f32| v * v * (3.0 - 2.0 * v);
+        let lerp = |a: f32, b: f32, v: f32| a + (b - a) * v;
+        let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (smooth(x - x0), smooth(y - y0), smooth(z - z0));
+        let (x0, y0, z0) = (x0 as i32, y0 as i32, z0 as i32);
+
+        let edge = |dy: i32, dz: i32| lerp(
+            self.lattice(x0, y0 + dy, z0 + dz),
+            self.lattice(x0 + 1, y0 + dy, z0 + dz), fx);
+        let face = |dz: i32| lerp(edge(0, dz), edge(1, dz), fy);
+        lerp(face(0), face(1), fz)
+    }
+}
+
+impl Field for Noise {
+    fn at(&self, t: f32, p: Coord) -> f32 {
+        self.sample((p.x + self.drift.x * t) * self.scale,
+                    (p.y + self.drift.y * t) * self.scale,
+                    (p.z + self.drift.z * t) * self.scale)
+    }
+}
+
+/// A ball of light `radius` big going around `path` once per `period`
+pub struct Spotlight {
+    pub path: Vec<Coord>,
+    pub period: f32,
+    pub radius: f32,
+    /// 0 for a hard edge, 1 fades from the center, see
+    /// `cuboid::sphere_weight`
+    pub softness: f32,
+}
+
+impl Spotlight {
+    /// `x,y,z;x,y,z;...`
+    pub fn parse_path(s: &str) -> Result<Vec<Coord>, String> {
+        let path = s.split(';')
+            .filter(|point| !point.trim().is_empty())
+            .map(|point| {
+                let parts: Vec<f32> = point.split(',')
+                    .map(|part| part.trim().parse::<f32>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| format!("path point '{}': {:?}", point, e))?;
+                match parts[..] {
+                    [x, y, z] => Ok(Coord { x, y, z }),
+                    _ => Err(format!("path point '{}' should be x,y,z",
+                                     point)),
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if path.is_empty() {
+            return Err("path needs at least one point".to_string());
+        }
+        Ok(path)
+    }
+
+    /// Where on the looped path we are at `t`
+    pub fn position(&self, t: f32) -> Coord {
+        let n = self.path.len();
+        let pos = (t / self.period).rem_euclid(1.0) * n as f32;
+        let (from, to) = (self.path[pos as usize % n],
+                          self.path[(pos as usize + 1) % n]);
+        let v = pos.fract();
+        Coord {
+            x: from.x + (to.x - from.x) * v,
+            y: from.y + (to.y - from.y) * v,
+            z: from.z + (to.z - from.z) * v,
+        }
+    }
+}
+
+impl Field for Spotlight {
+    fn at(&self, t: f32, p: Coord) -> f32 {
+        let dist = self.position(t).dist_to(&p);
+        cuboid::sphere_weight(dist, self.radius, self.softness)
+    }
+
+    fn over(&self, t: f32, cuboid: &Cuboid) -> Option<f32> {
+        Some(cuboid.sphere_intersection(
+                self.position(t), self.radius, self.softness))
+    }
+}
+
+pub const SWEEP: EffectInfo = EffectInfo {
+    name: "sweep",
+    description: "A plane of light crossing the room, chans with a cuboid",
+    params: || vec![
+        ParamSpec::choice("axis", "x left-right, y up-down, \
+                          z away from the window", Axis::ALL),
+        ParamSpec::float("period", "seconds per sweep", 4.0, 0.1, 3600.0),
+        ParamSpec::float("width", "how thick the plane is", 0.2, 0.01, 1.0),
+        ParamSpec::bool("bounce", "go back and forth", false),
+    ],
+    create: |params| Ok(Box::new(SpatialEffect::new(PlaneSweep {
+        axis: params.str("axis").parse()?,
+        period: params.f32("period"),
+        width: params.f32("width"),
+        bounce: params.bool("bounce"),
+    }))),
+};
+
+pub const RIPPLE: EffectInfo = EffectInfo {
+    name: "ripple",
+    description: "Rings spreading from a point, chans with a cuboid",
+    params: || vec![
+        ParamSpec::float("x", "left-right, left is 0", 0.5, 0.0, 1.0),
+        ParamSpec::float("y", "up-down, ceiling is 0", 0.5, 0.0, 1.0),
+        ParamSpec::float("z", "distance from the window", 0.5, 0.0, 1.0),
+        ParamSpec::float("speed", "room units per second", 0.3, 0.0, 10.0),
+        ParamSpec::float("wavelength", "distance between rings", 0.4,
+                         0.01, 10.0),
+        ParamSpec::float("decay", "dimming with distance", 1.0, 0.0, 20.0),
+    ],
+    create: |params| Ok(Box::new(SpatialEffect::new(Ripple {
+        center: Coord {
+            x: params.f32("x"),
+            y: params.f32("y"),
+            z: params.f32("z"),
+        },
+        speed: params.f32("speed"),
+        wavelength: params.f32("wavelength"),
+        decay: params.f32("decay"),
+    }))),
+};
+
+pub const NOISE: EffectInfo = EffectInfo {
+    name: "noise",
+    description: "Clouds of light drifting through the room, \
+                  chans with a cuboid",
+    params: || vec![
+        ParamSpec::float("scale", "clouds across the room", 3.0, 0.1, 100.0),
+        ParamSpec::float("dx", "drift left-right per second", 0.05,
+                         -10.0, 10.0),
+        ParamSpec::float("dy", "drift up-down per second", 0.0, -10.0, 10.0),
+        ParamSpec::float("dz", "drift away from the window per second", 0.02,
+                         -10.0, 10.0),
+        ParamSpec::int("seed", "different seed, different clouds", 0,
+                       0, u32::MAX as i64),
+    ],
+    create: |params| Ok(Box::new(SpatialEffect::new(Noise {
+        scale: params.f32("scale"),
+        drift: Coord {
+            x: params.f32("dx"),
+            y: params.f32("dy"),
+            z: params.f32("dz"),
+        },
+        seed: params.i64("seed") as u32,
+    }))),
+};
+
+pub const SPOTLIGHT: EffectInfo = EffectInfo {
+    name: "spotlight",
+    description: "A ball of light going around a path, chans with a cuboid",
+    params: || vec![
+        ParamSpec::text("path", "x,y,z;x,y,z;... looped",
+                        "0.2,0.5,0.2;0.8,0.5,0.2;0.8,0.5,0.8;0.2,0.5,0.8"),
+        ParamSpec::float("period", "seconds per loop", 10.0, 0.1, 3600.0),
+        ParamSpec::float("radius", "how big the ball is", 0.3, 0.01, 2.0),
+        ParamSpec::float("softness", "0 hard edge, 1 fades from the center",
+                         0.5, 0.0, 1.0),
+    ],
+    create: |params| Ok(Box::new(SpatialEffect::new(Spotlight {
+        path: Spotlight::parse_path(params.str("path"))?,
+        period: params.f32("period"),
+        radius: params.f32("radius"),
+        softness: params.f32("softness"),
+    }))),
+};
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use crate::cuboid::Cuboid;
+    use crate::test_dev::chan;
+
+    fn coord(x: f32, y: f32, z: f32) -> Coord {
+        Coord { x, y, z }
+    }
+
+    /// A strip along x at `y`, a thin panel and a chan without a cuboid
+    fn chans(y: f32) -> Vec<ChanDescription> {
+        let strip = Cuboid { start: coord(0.1, y, 0.0),
+                             end: coord(0.9, y, 0.001) };
+        let panel = Cuboid { start: coord(0.0, 0.9, 0.0),
+                             end: coord(0.2, 1.0, 0.2) };
+        vec![chan(0, &[], Some(strip)), chan(1, &[], Some(panel)),
+             chan(2, &[], None)]
+    }
+
+    #[test]
+    fn test_samples() {
+        let strip = Cuboid { start: coord(0.9, 0.1, 0.0),
+                             end: coord(0.1, 0.1, 0.001) };
+        let samples = strip.samples(0.1, 100);
+        assert_eq!(8, samples.len());
+        assert!((samples[0].x - 0.15).abs() < 1e-6);
+        assert!(samples.iter().all(|p| p.y == 0.1));
+
+        let room = Cuboid { start: coord(0.0, 0.0, 0.0),
+                            end: coord(1.0, 1.0, 1.0) };
+        assert_eq!(27, room.samples(0.1, 3).len());
+    }
+
+    #[test]
+    fn test_sweep() {
+        // a slab crossing y, top to bottom in 10s
+        let mut effect = SpatialEffect::new(PlaneSweep {
+            axis: Axis::Y, period: 10.0, width: 0.1, bounce: false });
+        let chans = chans(0.5);
+        let at = |effect: &mut SpatialEffect<PlaneSweep>, secs: f32|
+            effect.render(Duration::from_secs_f32(secs), &chans);
+
+        // y = 0.5 is reached half way through
+        let frame = at(&mut effect, 5.0);
+        assert!(frame.get(0).unwrap() > 0.99);
+        assert_eq!(Some(0.0), frame.get(1));
+        assert_eq!(None, frame.get(2));
+
+        // the panel's bottom part at y 0.9 to 1.0
+        let frame = at(&mut effect, 9.0);
+        assert_eq!(Some(0.0), frame.get(0));
+        let panel = frame.get(1).unwrap();
+        assert!(panel > 0.2 && panel < 1.0, "{}", panel);
+
+        // starts outside the room
+        assert_eq!(Some(0.0), at(&mut effect, 0.0).get(1));
+    }
+
+    #[test]
+    fn test_fields() {
+        let ripple = Ripple { center: coord(0.5, 0.5, 0.5), speed: 1.0,
+                              wavelength: 0.5, decay: 0.0 };
+        assert!((ripple.at(0.0, coord(0.5, 0.5, 0.5)) - 1.0).abs() < 1e-6);
+        // the crest moves 0.25 out in 0.25s
+        assert!(ripple.at(0.25, coord(0.75, 0.5, 0.5)) > 0.99);
+        assert!(ripple.at(0.25, coord(0.5, 0.5, 0.5)) < 0.01);
+
+        let noise = Noise { scale: 3.0, drift: coord(0.1, 0.0, 0.0),
+                            seed: 1 };
+        let other = Noise { seed: 2, ..noise };
+        let points: Vec<Coord> = (0..100)
+            .map(|i| coord(i as f32 / 100.0, 0.3, 0.7))
+            .collect();
+        let vals: Vec<f32> = points.iter().map(|p| noise.at(1.0, *p)).collect();
+        assert!(vals.iter().all(|v| (0.0..=1.0).contains(v)));
+        // drifting: the same clouds 0.1 further along a second later
+        assert!((noise.at(1.0, coord(0.2, 0.3, 0.7))
+                 - noise.at(0.0, coord(0.3, 0.3, 0.7))).abs() < 1e-5);
+        assert_ne!(vals, points.iter().map(|p| other.at(1.0, *p))
+                   .collect::<Vec<_>>());
+        // smooth
+        assert!(vals.windows(2).all(|w| (w[0] - w[1]).abs() < 0.15));
+
+        let spot = Spotlight {
+            path: Spotlight::parse_path("0,0,0; 1,0,0").unwrap(),
+            period: 4.0, radius: 0.2, softness: 0.0 };
+        assert_eq!(coord(0.5, 0.0, 0.0), spot.position(1.0));
+        assert_eq!(coord(1.0, 0.0, 0.0), spot.position(2.0));
+        assert_eq!(coord(0.5, 0.0, 0.0), spot.position(3.0));
+        assert_eq!(1.0, spot.at(1.0, coord(0.6, 0.0, 0.0)));
+        assert_eq!(0.0, spot.at(1.0, coord(0.8, 0.0, 0.0)));
+        assert!(Spotlight::parse_path("0,0").is_err());
+        assert!(Spotlight::parse_path("").is_err());
+    }
+}
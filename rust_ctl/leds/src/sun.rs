@@ -0,0 +1,373 @@
+use serde_derive::{Deserialize, Serialize};
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::chan_description::HasChanDescriptions;
+use crate::clock::{self, Clock};
+use crate::dev::DevWrite;
+use crate::frame::Frame;
+use crate::runner::Runner;
+use crate::task::TaskMsg;
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Sunrise equation as described in
+// https://en.wikipedia.org/wiki/Sunrise_equation
+// it's accurate to about a minute, which is plenty for lights
+
+/// Julian date of 2000-01-01 12:00 UTC
+const J2000: f64 = 2451545.0;
+
+/// Obliquity of the ecliptic
+const EARTH_TILT_DEG: f64 = 23.4397;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    /// Degrees, north is positive
+    pub lat: f64,
+    /// Degrees, east is positive
+    pub lon: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SunEvent {
+    /// Civil twilight start, the sun is 6° below the horizon
+    Dawn,
+    Sunrise,
+    Sunset,
+    /// Civil twilight end
+    Dusk,
+}
+
+impl SunEvent {
+    /// Altitude of the center of the sun at the event
+    fn altitude_deg(&self) -> f64 {
+        match self {
+            // accounts for refraction and the radius of the sun
+            SunEvent::Sunrise | SunEvent::Sunset => -0.833,
+            SunEvent::Dawn | SunEvent::Dusk => -6.0,
+        }
+    }
+
+    fn is_morning(&self) -> bool {
+        matches!(self, SunEvent::Dawn | SunEvent::Sunrise)
+    }
+}
+
+impl FromStr for SunEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "dawn" => Ok(SunEvent::Dawn),
+            "sunrise" => Ok(SunEvent::Sunrise),
+            "sunset" => Ok(SunEvent::Sunset),
+            "dusk" => Ok(SunEvent::Dusk),
+            other => Err(format!(
+                    "unknown sun event '{}', expected dawn, sunrise, sunset \
+                     or dusk", other)),
+        }
+    }
+}
+
+impl fmt::Display for SunEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SunEvent::Dawn => "dawn",
+            SunEvent::Sunrise => "sunrise",
+            SunEvent::Sunset => "sunset",
+            SunEvent::Dusk => "dusk",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn julian_date(utc: NaiveDateTime) -> f64 {
+    let unix_secs = utc.timestamp() as f64 +
+        utc.timestamp_subsec_nanos() as f64 / 1e9;
+    unix_secs / 86400.0 + 2440587.5
+}
+
+fn from_julian_date(jd: f64) -> NaiveDateTime {
+    let unix_secs = (jd - 2440587.5) * 86400.0;
+    NaiveDateTime::from_timestamp(unix_secs.floor() as i64,
+                                  (unix_secs.fract() * 1e9) as u32)
+}
+
+/// Ecliptic longitude of the sun and mean anomaly, degrees
+fn ecliptic_longitude(days: f64) -> (f64, f64) {
+    let mean_anomaly = (357.5291 + 0.98560028 * days).rem_euclid(360.0);
+    let m = mean_anomaly.to_radians();
+    let center = 1.9148 * m.sin() + 0.0200 * (2.0 * m).sin() +
+        0.0003 * (3.0 * m).sin();
+    let lambda = (mean_anomaly + center + 180.0 + 102.9372).rem_euclid(360.0);
+    (lambda, mean_anomaly)
+}
+
+fn declination(lambda_deg: f64) -> f64 {
+    (lambda_deg.to_radians().sin() * EARTH_TILT_DEG.to_radians().sin()).asin()
+}
+
+/// UTC time of `event` on the day `date` at `location`,
+/// `None` if the sun doesn't get that high or low that day
+pub fn sun_event(date: NaiveDate, location: &Location, event: SunEvent)
+        -> Option<NaiveDateTime> {
+    let noon_jd = julian_date(date.and_hms(12, 0, 0));
+    let n = (noon_jd - J2000 + 0.0008).round();
+
+    // mean solar noon
+    let j_star = n - location.lon / 360.0;
+    let (lambda, mean_anomaly) = ecliptic_longitude(j_star);
+    let transit = J2000 + j_star
+        + 0.0053 * mean_anomaly.to_radians().sin()
+        - 0.0069 * (2.0 * lambda.to_radians()).sin();
+
+    let decl = declination(lambda);
+    let lat = location.lat.to_radians();
+    let cos_hour_angle = (event.altitude_deg().to_radians().sin()
+                          - lat.sin() * decl.sin())
+        / (lat.cos() * decl.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+    let jd = if event.is_morning() {
+        transit - hour_angle / 360.0
+    } else {
+        transit + hour_angle / 360.0
+    };
+    Some(from_julian_date(jd))
+}
+
+/// Altitude of the sun above the horizon in degrees at `utc`
+pub fn elevation(utc: NaiveDateTime, location: &Location) -> f64 {
+    let days = julian_date(utc) - J2000;
+    let (lambda, _) = ecliptic_longitude(days);
+    let lambda = lambda.to_radians();
+    let tilt = EARTH_TILT_DEG.to_radians();
+
+    let decl = declination(lambda.to_degrees());
+    let right_ascension =
+        (tilt.cos() * lambda.sin()).atan2(lambda.cos()).to_degrees();
+    let sidereal = 280.46061837 + 360.98564736629 * days;
+    let hour_angle =
+        (sidereal + location.lon - right_ascension).to_radians();
+
+    let lat = location.lat.to_radians();
+    (lat.sin() * decl.sin() + lat.cos() * decl.cos() * hour_angle.cos())
+        .asin()
+        .to_degrees()
+}
+
+/// Sets channels depending on how high the sun is, i.e. to dim the window
+/// lights as the sun rises
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SunFollowSpec {
+    pub location: Location,
+
+    /// Channels with any of these tags, all channels if empty
+    pub tags: Vec<String>,
+
+    /// Value when the sun is up
+    pub day: f32,
+
+    /// Value at night
+    pub night: f32,
+}
+
+/// Between civil twilight and the sun being this high we
+/// move from `night` to `day`
+const SUN_FOLLOW_DAY_ELEVATION: f64 = 6.0;
+
+impl SunFollowSpec {
+    pub fn value_at(&self, utc: NaiveDateTime) -> f32 {
+        let night_elevation = -SunEvent::Dusk.altitude_deg();
+        let elevation = elevation(utc, &self.location);
+        let progress = ((elevation + night_elevation) /
+                        (SUN_FOLLOW_DAY_ELEVATION + night_elevation))
+            .clamp(0.0, 1.0);
+        let progress = crate::easing::Easing::EaseInOut.apply(progress);
+        (self.night as f64 + (self.day - self.night) as f64 * progress) as f32
+    }
+}
+
+pub struct SunFollow<T: ?Sized> {
+    output: Arc<Mutex<T>>,
+    pub settings: SunFollowSpec,
+    clock: Arc<dyn Clock>,
+}
+
+impl<T: HasChanDescriptions + DevWrite + ?Sized> SunFollow<T> {
+    pub fn new(output: Arc<Mutex<T>>, settings: SunFollowSpec) -> Self {
+        SunFollow { output, settings, clock: clock::real() }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn update(&self, utc: NaiveDateTime) -> Result<(), String> {
+        let val = self.settings.value_at(utc);
+        let mut output = self.output.lock().unwrap();
+
+        let mut frame = Frame::empty();
+        for chan in output.chan_descriptions() {
+            let is_tagged = self.settings.tags.is_empty() ||
+                chan.config.tags.iter()
+                .any(|tag| self.settings.tags.iter().any(|t| t == tag.name()));
+            if is_tagged {
+                frame.set(chan.chan_id, val);
+            }
+        }
+        output.set_frame(&frame)
+    }
+}
+
+impl<T: HasChanDescriptions + DevWrite + ?Sized> Runner for SunFollow<T> {
+    fn run(
+        self_lock: Arc<Mutex<SunFollow<T>>>,
+        stop: mpsc::Receiver<TaskMsg>,
+    ) -> Result<(), String> {
+        let clock = self_lock.lock().unwrap().clock.clone();
+        loop {
+            if let Err(e) = self_lock.lock().unwrap().update(clock.utc_now()) {
+                eprintln!("sun follow: {}", e);
+            }
+
+            // the sun is slow
+            match clock.recv_timeout(&stop, Duration::from_secs(10)) {
+                Ok(TaskMsg::Stop) => return Ok(()),
+                Ok(_) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::dev::DevRead;
+    use crate::test_dev::TestDev;
+
+    const LONDON: Location = Location { lat: 51.5074, lon: -0.1278 };
+    const NEW_YORK: Location = Location { lat: 40.7128, lon: -74.0060 };
+    const TROMSO: Location = Location { lat: 69.6496, lon: 18.9560 };
+
+    fn assert_close(actual: Option<NaiveDateTime>, expected_utc: &str) {
+        let expected =
+            NaiveDateTime::parse_from_str(expected_utc, "%Y-%m-%d %H:%M")
+            .unwrap();
+        let actual = actual.unwrap();
+        let diff = (actual - expected).num_seconds().abs();
+        assert!(diff <= 3 * 60,
+                "expected {} got {} ({}s off)", expected, actual, diff);
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    // almanac values are in local time, converted to UTC here
+
+    #[test]
+    fn test_london_summer_solstice() {
+        let day = date("2026-06-21");
+        // 04:43 and 21:21 BST
+        assert_close(sun_event(day, &LONDON, SunEvent::Sunrise),
+                     "2026-06-21 03:43");
+        assert_close(sun_event(day, &LONDON, SunEvent::Sunset),
+                     "2026-06-21 20:21");
+        // civil twilight 03:56 - 22:08 BST
+        assert_close(sun_event(day, &LONDON, SunEvent::Dawn),
+                     "2026-06-21 02:56");
+        assert_close(sun_event(day, &LONDON, SunEvent::Dusk),
+                     "2026-06-21 21:08");
+    }
+
+    #[test]
+    fn test_new_york_winter_solstice() {
+        let day = date("2026-12-21");
+        // 07:16 and 16:32 EST
+        assert_close(sun_event(day, &NEW_YORK, SunEvent::Sunrise),
+                     "2026-12-21 12:16");
+        assert_close(sun_event(day, &NEW_YORK, SunEvent::Sunset),
+                     "2026-12-21 21:32");
+    }
+
+    #[test]
+    fn test_polar_night() {
+        assert_eq!(sun_event(date("2026-12-21"), &TROMSO, SunEvent::Sunrise),
+                   None);
+        // but there is some twilight
+        assert!(sun_event(date("2026-12-21"), &TROMSO, SunEvent::Dawn)
+                .is_some());
+    }
+
+    #[test]
+    fn test_elevation() {
+        let sunrise = sun_event(date("2026-06-21"), &LONDON,
+                                SunEvent::Sunrise).unwrap();
+        assert!((elevation(sunrise, &LONDON) - -0.833).abs() < 0.3);
+
+        // solar noon at the summer solstice: 90 - lat + tilt
+        let noon = NaiveDateTime::parse_from_str("2026-06-21 12:02",
+                                                 "%Y-%m-%d %H:%M").unwrap();
+        assert!((elevation(noon, &LONDON) - 61.93).abs() < 0.3);
+    }
+
+    #[test]
+    fn test_sun_follow_value() {
+        let spec = SunFollowSpec {
+            location: LONDON,
+            tags: vec![],
+            day: 0.0,
+            night: 1.0,
+        };
+        let at = |s: &str| NaiveDateTime::parse_from_str(
+            s, "%Y-%m-%d %H:%M").unwrap();
+
+        assert_eq!(spec.value_at(at("2026-06-21 00:00")), 1.0);
+        assert_eq!(spec.value_at(at("2026-06-21 12:00")), 0.0);
+        let at_sunrise = spec.value_at(at("2026-06-21 03:43"));
+        assert!(at_sunrise > 0.0 && at_sunrise < 1.0);
+    }
+
+    #[test]
+    fn test_sun_follow_runner() {
+        let spec = SunFollowSpec {
+            location: LONDON,
+            tags: vec![],
+            day: 0.25,
+            night: 1.0,
+        };
+        let run = |local: &str| {
+            // BST, so it's noon and midnight in UTC
+            let clock = VirtualClock::new()
+                .with_utc_offset(chrono::Duration::hours(1))
+                .stop_after(Duration::from_secs(1));
+            clock.set_local_time(NaiveDateTime::parse_from_str(
+                    local, "%Y-%m-%d %H:%M").unwrap());
+            let dev = Arc::new(Mutex::new(TestDev::new(false)));
+            let sun = SunFollow::new(dev.clone(), spec.clone())
+                .with_clock(Arc::new(clock));
+            let (_stop, receiver) = mpsc::channel();
+            SunFollow::run(Arc::new(Mutex::new(sun)), receiver).unwrap();
+            let val = dev.lock().unwrap().get_f32(0).unwrap();
+            val
+        };
+
+        assert_eq!(0.25, run("2026-06-21 13:00"));
+        assert_eq!(1.0, run("2026-06-21 01:00"));
+    }
+}
@@ -0,0 +1,652 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::audio::{AudioInput, AudioSpec};
+use crate::clock::{self, Clock};
+use crate::runner::Runner;
+use crate::task::{Task, TaskMsg};
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_BPM: f32 = 120.0;
+
+/// Tempos from taps and onsets are doubled or halved into this range
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 200.0;
+
+/// Taps further apart than this start over on a downbeat
+const TAP_TIMEOUT: Duration = Duration::from_secs(2);
+const TAPS_KEPT: usize = 8;
+
+const MIDI_CLOCK: u8 = 0xf8;
+const MIDI_START: u8 = 0xfa;
+const MIDI_CLOCKS_PER_BEAT: u32 = 24;
+
+/// How often a MIDI clock waiting for bytes checks if it's stopped
+const MIDI_POLL: Duration = Duration::from_millis(100);
+
+/// Hops of audio an onset is compared against
+const ONSET_HISTORY: usize = 32;
+/// Louder than the recent average by this much is an onset
+const ONSET_THRESHOLD: f32 = 1.8;
+/// Quieter hops are never onsets
+const ONSET_MIN_ENERGY: f32 = 0.01;
+/// Faster than `MAX_BPM`, so we don't trigger twice on the same hit
+const ONSET_MIN_GAP: Duration = Duration::from_millis(200);
+
+/// Samples per hop for beat tracking, short so onsets land close to the hit
+const ONSET_HOP: usize = 256;
+
+/// Recent beat times, their median interval is the tempo
+#[derive(Debug, Default)]
+struct Taps {
+    times: VecDeque<Instant>,
+}
+
+impl Taps {
+    /// Whether it started over and the tempo once there's enough taps
+    fn tap(&mut self, at: Instant) -> (bool, Option<Duration>) {
+        let restart = match self.times.back() {
+            Some(last) => at.saturating_duration_since(*last) > TAP_TIMEOUT,
+            None => true,
+        };
+        if restart {
+            self.times.clear();
+        }
+        self.times.push_back(at);
+        if self.times.len() > TAPS_KEPT {
+            self.times.pop_front();
+        }
+
+        let mut intervals: Vec<f64> = self.times.iter()
+            .zip(self.times.iter().skip(1))
+            .map(|(a, b)| (*b - *a).as_secs_f64())
+            .collect();
+        if intervals.is_empty() {
+            return (restart, None);
+        }
+        intervals.sort_by(|a, b| a.total_cmp(b));
+        let median = intervals[intervals.len() / 2];
+        if median <= 0.0 {
+            return (restart, None);
+        }
+
+        let mut bpm = 60.0 / median;
+        while bpm < MIN_BPM as f64 {
+            bpm *= 2.0;
+        }
+        while bpm > MAX_BPM as f64 {
+            bpm /= 2.0;
+        }
+        (restart, Some(Duration::from_secs_f64(60.0 / bpm)))
+    }
+}
+
+/// Where the beat is, everything following it shares one
+#[derive(Debug)]
+pub struct Tempo {
+    /// One beat
+    period: Duration,
+    /// A bar starts here
+    anchor: Instant,
+    pub beats_per_bar: u32,
+    taps: Taps,
+    clock: Arc<dyn Clock>,
+}
+
+pub type SharedTempo = Arc<Mutex<Tempo>>;
+
+impl Tempo {
+    pub fn new(bpm: f32) -> Self {
+        let clock = clock::real();
+        Tempo {
+            period: Duration::from_secs_f32(60.0 / bpm.max(1.0)),
+            anchor: clock.now(),
+            beats_per_bar: 4,
+            taps: Taps::default(),
+            clock,
+        }
+    }
+
+    /// Starts a bar at the time of `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.anchor = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    pub fn bpm(&self) -> f32 {
+        60.0 / self.period.as_secs_f32()
+    }
+
+    /// Changes the tempo without jumping in the beat
+    pub fn set_bpm(&mut self, bpm: f32) {
+        let now = self.clock.now();
+        let phase = self.beats_at(now).rem_euclid(1.0);
+        self.period = Duration::from_secs_f32(60.0 / bpm.max(1.0));
+        self.anchor = now.checked_sub(self.period.mul_f64(phase))
+            .unwrap_or(now);
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn bar(&self) -> Duration {
+        self.period * self.beats_per_bar.max(1)
+    }
+
+    /// Beats since a bar has started, counting on from there
+    pub fn beats_at(&self, t: Instant) -> f64 {
+        let period = self.period.as_secs_f64();
+        match t.checked_duration_since(self.anchor) {
+            Some(since) => since.as_secs_f64() / period,
+            None => -(self.anchor - t).as_secs_f64() / period,
+        }
+    }
+
+    pub fn beats(&self) -> f64 {
+        self.beats_at(self.clock.now())
+    }
+
+    pub fn bars(&self) -> f64 {
+        self.beats() / self.beats_per_bar.max(1) as f64
+    }
+
+    /// 0 on the beat to 1 just before the next one
+    pub fn phase(&self) -> f32 {
+        self.beats().rem_euclid(1.0) as f32
+    }
+
+    /// A beat is at `at`, with the tempo if we know it, the beats keep
+    /// counting unless it's a `downbeat`
+    pub fn beat(&mut self, at: Instant, period: Option<Duration>,
+                downbeat: bool) {
+        let beats = self.beats_at(at).round();
+        if let Some(period) = period {
+            self.period = period;
+        }
+
+        let since_anchor = self.period.mul_f64(beats.abs());
+        self.anchor = match (downbeat, beats >= 0.0) {
+            (true, _) => Some(at),
+            (false, true) => at.checked_sub(since_anchor),
+            (false, false) => at.checked_add(since_anchor),
+        }.unwrap_or(at);
+    }
+
+    /// Tap tempo, the first tap after a pause is a downbeat
+    pub fn tap_at(&mut self, at: Instant) {
+        let (restart, period) = self.taps.tap(at);
+        self.beat(at, period, restart);
+    }
+
+    pub fn tap(&mut self) {
+        self.tap_at(self.clock.now());
+    }
+}
+
+/// The beat everything follows unless it's given another one
+pub fn shared() -> SharedTempo {
+    static TEMPO: OnceLock<SharedTempo> = OnceLock::new();
+    TEMPO.get_or_init(|| Arc::new(Mutex::new(Tempo::new(DEFAULT_BPM))))
+        .clone()
+}
+
+/// Locks an effect to every `every` beats of `tempo`
+#[derive(Debug, Clone)]
+pub struct BeatSync {
+    pub tempo: SharedTempo,
+    pub every: f64,
+}
+
+impl BeatSync {
+    /// Follows the shared tempo
+    pub fn shared(every: f64) -> Self {
+        BeatSync { tempo: shared(), every }
+    }
+
+    /// 0 to 1 over every `every` beats
+    pub fn phase(&self) -> f64 {
+        let beats = self.tempo.lock().unwrap().beats();
+        (beats / self.every.max(f64::EPSILON)).rem_euclid(1.0)
+    }
+}
+
+/// Finds beats in the energy of consecutive hops of audio
+#[derive(Debug, Default)]
+pub struct OnsetDetector {
+    history: VecDeque<f32>,
+    prev: f32,
+    last: Option<Instant>,
+}
+
+impl OnsetDetector {
+    /// `energy` of the hop ending at `at`, true if a hit starts there
+    pub fn push(&mut self, energy: f32, at: Instant) -> bool {
+        let average = match self.history.len() {
+            0 => 0.0,
+            len => self.history.iter().sum::<f32>() / len as f32,
+        };
+        let onset = energy > ONSET_MIN_ENERGY
+            && energy > average * ONSET_THRESHOLD
+            && energy > self.prev
+            && self.last.is_none_or(|last|
+                                    at.saturating_duration_since(last)
+                                    >= ONSET_MIN_GAP);
+
+        self.history.push_back(energy);
+        if self.history.len() > ONSET_HISTORY {
+            self.history.pop_front();
+        }
+        self.prev = energy;
+        if onset {
+            self.last = Some(at);
+        }
+        onset
+    }
+}
+
+/// Taps the tempo on every onset in the audio
+pub struct BeatTracker {
+    input: Box<dyn AudioInput>,
+    detector: OnsetDetector,
+    tempo: SharedTempo,
+    clock: Arc<dyn Clock>,
+}
+
+impl BeatTracker {
+    pub fn new(input: Box<dyn AudioInput>, tempo: SharedTempo) -> Self {
+        BeatTracker {
+            input,
+            detector: OnsetDetector::default(),
+            tempo,
+            clock: clock::real(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// False when the input is over
+    fn step(&mut self, buf: &mut [f32]) -> Result<bool, String> {
+        let num = self.input.read(buf)?;
+        if num == 0 {
+            return Ok(false);
+        }
+
+        let energy = (buf[..num].iter().map(|s| s * s).sum::<f32>()
+                      / num as f32).sqrt();
+        let now = self.clock.now();
+        if self.detector.push(energy, now) {
+            self.tempo.lock().unwrap().tap_at(now);
+        }
+        Ok(true)
+    }
+}
+
+impl Runner for BeatTracker {
+    fn run(
+        self_lock: Arc<Mutex<BeatTracker>>,
+        stop: mpsc::Receiver<TaskMsg>,
+    ) -> Result<(), String> {
+        let (clock, wait) = {
+            let tracker = self_lock.lock().unwrap();
+            let wait = match tracker.input.is_live() {
+                true => Duration::ZERO,
+                false => Duration::from_secs_f64(
+                    ONSET_HOP as f64 / tracker.input.sample_rate() as f64),
+            };
+            (tracker.clock.clone(), wait)
+        };
+
+        let mut buf = vec![0.0; ONSET_HOP];
+        loop {
+            if !self_lock.lock().unwrap().step(&mut buf)? {
+                return Ok(());
+            }
+
+            match clock.recv_timeout(&stop, wait) {
+                Ok(TaskMsg::Stop) => return Ok(()),
+                Ok(TaskMsg::Pause) | Ok(TaskMsg::Ping) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Follows MIDI clock from a raw MIDI device, i.e. /dev/snd/midiC1D0
+pub struct MidiClock<R> {
+    /// Taken by the reading thread when running
+    reader: Option<R>,
+    tempo: SharedTempo,
+    clock: Arc<dyn Clock>,
+    /// Clock ticks since start
+    ticks: u32,
+    /// The last beat's worth of ticks
+    tick_times: VecDeque<Instant>,
+}
+
+impl<R: Read> MidiClock<R> {
+    pub fn new(reader: R, tempo: SharedTempo) -> Self {
+        MidiClock {
+            reader: Some(reader),
+            tempo,
+            clock: clock::real(),
+            ticks: 0,
+            tick_times: VecDeque::new(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Realtime bytes can come in the middle of other messages, everything
+    /// but clock and start is ignored
+    pub fn feed(&mut self, byte: u8, at: Instant) {
+        match byte {
+            MIDI_START => {
+                // the next tick is the first beat of a bar
+                self.ticks = 0;
+            },
+            MIDI_CLOCK => {
+                self.tick_times.push_back(at);
+                if self.tick_times.len() > MIDI_CLOCKS_PER_BEAT as usize + 1 {
+                    self.tick_times.pop_front();
+                }
+
+                if self.ticks.is_multiple_of(MIDI_CLOCKS_PER_BEAT) {
+                    let mut tempo = self.tempo.lock().unwrap();
+                    let per_bar = MIDI_CLOCKS_PER_BEAT
+                        * tempo.beats_per_bar.max(1);
+                    tempo.beat(at, self.period(),
+                               self.ticks.is_multiple_of(per_bar));
+                }
+                self.ticks = self.ticks.wrapping_add(1);
+            },
+            _ => {},
+        }
+    }
+
+    /// A beat from the average tick, once there's a beat's worth of them
+    fn period(&self) -> Option<Duration> {
+        if self.tick_times.len() <= MIDI_CLOCKS_PER_BEAT as usize {
+            return None;
+        }
+        let first = self.tick_times.front()?;
+        let last = self.tick_times.back()?;
+        Some(*last - *first)
+    }
+}
+
+/// Reads bytes from `reader` with the time they came in, until the device
+/// is over or nobody is listening anymore
+fn read_midi<R: Read>(
+    mut reader: R,
+    clock: Arc<dyn Clock>,
+    bytes: mpsc::Sender<Result<(u8, Instant), String>>,
+) {
+    let mut byte = [0u8; 1];
+    loop {
+        let read = match reader.read(&mut byte) {
+            Ok(0) => return,
+            Ok(_) => Ok((byte[0], clock.now())),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => Err(format!("reading midi: {:?}", e)),
+        };
+        let failed = read.is_err();
+        if bytes.send(read).is_err() || failed {
+            return;
+        }
+    }
+}
+
+impl<R: 'static + Read + Send> Runner for MidiClock<R> {
+    /// Reads on its own thread so an idle device doesn't keep us from
+    /// stopping, that thread is gone after the next byte
+    fn run(
+        self_lock: Arc<Mutex<MidiClock<R>>>,
+        stop: mpsc::Receiver<TaskMsg>,
+    ) -> Result<(), String> {
+        let (clock, reader) = {
+            let mut midi = self_lock.lock().unwrap();
+            (midi.clock.clone(), midi.reader.take())
+        };
+        let reader = reader.ok_or("MIDI clock is already running")?;
+
+        let (bytes_tx, bytes) = mpsc::channel();
+        {
+            let clock = clock.clone();
+            thread::spawn(move || read_midi(reader, clock, bytes_tx));
+        }
+
+        loop {
+            match bytes.recv_timeout(MIDI_POLL) {
+                Ok(Ok((byte, at))) => self_lock.lock().unwrap().feed(byte, at),
+                Ok(Err(e)) => return Err(e),
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                // the device is over
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+
+            match stop.try_recv() {
+                Ok(TaskMsg::Stop) => return Ok(()),
+                Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+                _ => {},
+            }
+        }
+    }
+}
+
+/// ```yaml
+/// tempo:
+///   bpm: 128
+///   midi: /dev/snd/midiC1D0       # follow MIDI clock
+///   audio: { alsa: { } }          # or onsets in the audio
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TempoConfig {
+    pub bpm: f32,
+    pub beats_per_bar: u32,
+    pub midi: Option<PathBuf>,
+    pub audio: Option<AudioSpec>,
+}
+
+impl Default for TempoConfig {
+    fn default() -> Self {
+        TempoConfig {
+            bpm: DEFAULT_BPM,
+            beats_per_bar: 4,
+            midi: None,
+            audio: None,
+        }
+    }
+}
+
+impl TempoConfig {
+    /// Sets up the shared tempo and starts following the sources
+    pub fn start(&self) -> Result<Vec<Task>, String> {
+        {
+            let tempo = shared();
+            let mut tempo = tempo.lock().unwrap();
+            tempo.beats_per_bar = self.beats_per_bar.max(1);
+            tempo.set_bpm(self.bpm);
+        }
+
+        let mut tasks = vec![];
+        if let Some(path) = &self.midi {
+            let file = File::open(path)
+                .map_err(|e| format!("opening {:?}: {:?}", path, e))?;
+            let midi = Arc::new(Mutex::new(MidiClock::new(file, shared())));
+            let (tx, rx) = mpsc::channel::<TaskMsg>();
+            tasks.push(Task {
+                name: "MIDI clock".to_string(),
+                chan: tx,
+                join_handle: thread::spawn(move || Runner::run(midi, rx)),
+            });
+        }
+        if let Some(audio) = &self.audio {
+            let tracker = Arc::new(Mutex::new(
+                    BeatTracker::new(audio.open()?, shared())));
+            let (tx, rx) = mpsc::channel::<TaskMsg>();
+            tasks.push(Task {
+                name: "Beat tracker".to_string(),
+                chan: tx,
+                join_handle: thread::spawn(move || Runner::run(tracker, rx)),
+            });
+        }
+        Ok(tasks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use crate::audio::wav;
+    use crate::clock::VirtualClock;
+    use std::io::Cursor;
+
+    fn virtual_tempo(bpm: f32) -> (SharedTempo, VirtualClock) {
+        let clock = VirtualClock::new();
+        let tempo = Tempo::new(bpm).with_clock(Arc::new(clock.clone()));
+        (Arc::new(Mutex::new(tempo)), clock)
+    }
+
+    fn assert_near(expected: f64, actual: f64) {
+        assert!((expected - actual).abs() < 0.01,
+                "expected {} got {}", expected, actual);
+    }
+
+    #[test]
+    fn test_taps_and_midi() {
+        let (tempo, clock) = virtual_tempo(DEFAULT_BPM);
+        let start = clock.now();
+        // 100 BPM with a sloppy tap
+        for ms in [0, 600, 1220, 1800, 2400] {
+            tempo.lock().unwrap()
+                .tap_at(start + Duration::from_millis(ms));
+        }
+        clock.step(Duration::from_millis(2700));
+        let tempo_now = tempo.lock().unwrap();
+        assert_near(100.0, tempo_now.bpm() as f64);
+        assert_near(4.5, tempo_now.beats());
+        assert_near(0.5, tempo_now.phase() as f64);
+        drop(tempo_now);
+
+        // too slow, so it's folded into double time
+        let mut taps = Taps::default();
+        taps.tap(start);
+        let (restart, period) = taps.tap(start + Duration::from_millis(1200));
+        assert!(!restart);
+        assert_near(0.6, period.unwrap().as_secs_f64());
+
+        // taps after a while start a new bar
+        tempo.lock().unwrap().tap_at(start + Duration::from_secs(10));
+        clock.step(Duration::from_millis(7300));
+        assert_near(0.0, tempo.lock().unwrap().beats());
+
+        // 24 ticks a beat at 150 BPM, with a note on in the middle
+        let (tempo, clock) = virtual_tempo(DEFAULT_BPM);
+        let mut midi = MidiClock::new(io::empty(), tempo.clone());
+        let tick = Duration::from_secs_f64(60.0 / 150.0 / 24.0);
+        midi.feed(MIDI_START, clock.now());
+        for i in 0..24 * 6 {
+            if i == 30 {
+                for byte in [0x90, 60, MIDI_CLOCK, 100] {
+                    midi.feed(byte, clock.now());
+                }
+                clock.step(tick);
+                continue;
+            }
+            midi.feed(MIDI_CLOCK, clock.now());
+            clock.step(tick);
+        }
+        let tempo = tempo.lock().unwrap();
+        assert_near(150.0, tempo.bpm() as f64);
+        // counting from the second bar
+        assert_near(2.0, tempo.beats());
+        assert_near(0.5, tempo.bars());
+    }
+
+    /// Blocks until a byte comes in, like an idle MIDI device
+    struct Idle(mpsc::Receiver<u8>);
+
+    impl Read for Idle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.recv() {
+                Ok(byte) => {
+                    buf[0] = byte;
+                    Ok(1)
+                },
+                Err(_) => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_midi_stops_when_idle() {
+        let (bytes, idle) = mpsc::channel();
+        let (tempo, clock) = virtual_tempo(DEFAULT_BPM);
+        let midi = MidiClock::new(Idle(idle), tempo)
+            .with_clock(Arc::new(clock));
+        let (tx, rx) = mpsc::channel();
+        let join_handle = thread::spawn(
+            move || Runner::run(Arc::new(Mutex::new(midi)), rx));
+
+        bytes.send(MIDI_START).unwrap();
+        tx.send(TaskMsg::Stop).unwrap();
+        assert_eq!(Ok(()), join_handle.join().unwrap());
+        drop(bytes);
+    }
+
+    #[test]
+    fn test_click_track() {
+        // clicks at 128 BPM over quiet noise
+        let rate = 32000;
+        let beat = 60.0 / 128.0;
+        let mut data = vec![];
+        for i in 0..rate * 8 {
+            let secs = i as f32 / rate as f32;
+            let since_click = secs % beat;
+            let noise = ((i * 7919) % 13) as f32 / 13.0 - 0.5;
+            let val = match since_click < 0.02 {
+                true => 0.8 * (secs * 2000.0 * std::f32::consts::TAU).sin(),
+                false => 0.01 * noise,
+            };
+            data.extend(((val * 32767.0) as i16).to_le_bytes());
+        }
+        let mut bytes = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        for field in [16u32, 1 | (1 << 16), rate, rate * 2, 2 | (16 << 16)] {
+            bytes.extend(field.to_le_bytes());
+        }
+        bytes.extend(b"data");
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(data);
+
+        let (tempo, clock) = virtual_tempo(DEFAULT_BPM);
+        let input = wav(Cursor::new(bytes)).unwrap();
+        let tracker = BeatTracker::new(Box::new(input), tempo.clone())
+            .with_clock(Arc::new(clock.clone()));
+        let (_stop, receiver) = mpsc::channel();
+        BeatTracker::run(Arc::new(Mutex::new(tracker)), receiver).unwrap();
+
+        let tempo = tempo.lock().unwrap();
+        assert!((tempo.bpm() - 128.0).abs() < 2.0, "{} BPM", tempo.bpm());
+        // the last click was at 17 beats, 8 seconds is 17.07 beats
+        let beats = tempo.beats();
+        assert!((beats - 17.07).abs() < 0.1, "{} beats", beats);
+    }
+}
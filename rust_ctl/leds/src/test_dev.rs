@@ -1,5 +1,13 @@
 use std::fmt;
 
+use proto::v1::ChanId;
+
+use crate::chan::ChanConfig;
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+#[cfg(test)]
+use crate::cuboid::Cuboid;
+#[cfg(test)]
+use crate::tag::Tag;
 use crate::frame::Frame;
 use crate::dev::{Dev, DevNumChans, DevRead, DevWrite};
 
@@ -9,6 +17,9 @@ pub struct TestDev {
 
     /// Frame counter for printing if enabled, `None` means disabled
     print_frame_num: Option<usize>,
+
+    /// Every frame we've been sent, `None` means we don't keep them
+    history: Option<Vec<Frame<f32>>>,
 }
 
 impl TestDev {
@@ -19,8 +30,31 @@ impl TestDev {
             None
         };
 
-        TestDev { frame: Frame::new(3), print_frame_num }
+        TestDev { frame: Frame::new(3), print_frame_num, history: None }
+    }
+
+    /// Keeps every frame it's sent, for snapshot tests
+    #[cfg(test)]
+    pub fn recording() -> Self {
+        TestDev { history: Some(vec![]), ..TestDev::new(false) }
     }
+
+    #[cfg(test)]
+    pub fn history(&self) -> &[Frame<f32>] {
+        self.history.as_deref().unwrap_or(&[])
+    }
+}
+
+/// Chan `id` for effect tests, at `index` `id` of its dev
+#[cfg(test)]
+pub fn chan(id: u16, tags: &[&str], cuboid: Option<Cuboid>)
+        -> ChanDescription {
+    ChanDescription::new(id, format!("chan {}", id), ChanConfig {
+        index: id,
+        tags: tags.iter().map(Tag::new).collect(),
+        cuboid,
+        ..ChanConfig::default()
+    })
 }
 
 impl fmt::Display for TestDev {
@@ -52,6 +86,9 @@ impl DevRead for TestDev {
 impl DevWrite for TestDev {
     fn set_frame(&mut self, frame: &Frame<f32>) -> Result<(), String> {
         let res = self.frame.merge_frame(frame);
+        if let Some(history) = &mut self.history {
+            history.push(frame.clone());
+        }
 
         if let Some(frame_num) = &mut self.print_frame_num {
             println!(" -- Frame {:7} -------------", *frame_num);
@@ -63,4 +100,20 @@ impl DevWrite for TestDev {
     }
 }
 
+impl HasChanDescriptions for TestDev {
+    fn chans(&self) -> Vec<(ChanId, String)> {
+        (0..self.num_chans())
+            .map(|cid| (ChanId(cid), format!("Chan {cid} \"{self}\"")))
+            .collect()
+    }
+
+    fn chan_descriptions(&self) -> Vec<ChanDescription> {
+        (0..self.num_chans())
+            .map(|index| ChanDescription::new(
+                index, format!("test {index}"),
+                ChanConfig { index, ..ChanConfig::default() }))
+            .collect()
+    }
+}
+
 impl Dev for TestDev {}
@@ -0,0 +1,535 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::chan_description::{ChanDescription, HasChanDescriptions};
+use crate::clock::{self, Clock};
+use crate::dev::DevWrite;
+use crate::easing::Easing;
+use crate::effect::DEFAULT_FRAME_DURATION;
+use crate::expr::Selector;
+use crate::frame::Frame;
+use crate::runner::Runner;
+use crate::task::TaskMsg;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A show as keyframes, YAML or JSON
+///
+/// ```yaml
+/// markers: { intro: 0, drop: 8 }
+/// # play intro to drop twice, `times` left out loops forever
+/// loops:
+///   - { from: intro, to: drop, times: 2 }
+/// # start over at the end
+/// looped: true
+/// tracks:
+///   - chans: window
+///     easing: EaseInOut
+///     keys:
+///       - { at: 0, value: 0 }
+///       - { at: 2, value: 1, easing: Cubic }
+///       - { at: drop, value: 0.2 }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TimelineSpec {
+    /// How long we show each frame, i.e. 1/FPS
+    #[serde(skip, default = "TimelineSpec::default_frame_duration")]
+    pub frame_duration: Duration,
+
+    /// Named times for keys, loops and seeking
+    #[serde(default)]
+    pub markers: BTreeMap<String, f32>,
+
+    #[serde(default)]
+    pub loops: Vec<LoopSpec>,
+
+    /// Start from the beginning after the end
+    #[serde(default)]
+    pub looped: bool,
+
+    /// Seconds, the last key, loop or marker by default
+    #[serde(default)]
+    pub duration: Option<f32>,
+
+    /// Later tracks override earlier ones for the same chans
+    pub tracks: Vec<TrackSpec>,
+}
+
+impl TimelineSpec {
+    fn default_frame_duration() -> Duration {
+        DEFAULT_FRAME_DURATION
+    }
+}
+
+/// Seconds or a marker
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Time {
+    Secs(f32),
+    Marker(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LoopSpec {
+    pub from: Time,
+    pub to: Time,
+    /// How many times to play it, forever if not specified
+    #[serde(default)]
+    pub times: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TrackSpec {
+    /// A tag, a chan id or all
+    #[serde(default = "TrackSpec::all")]
+    pub chans: String,
+
+    /// How keys are reached unless they say otherwise
+    #[serde(default)]
+    pub easing: Easing,
+
+    pub keys: Vec<KeySpec>,
+}
+
+impl TrackSpec {
+    fn all() -> String {
+        "all".to_string()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KeySpec {
+    pub at: Time,
+    pub value: f32,
+    /// How we get here from the previous key
+    #[serde(default)]
+    pub easing: Option<Easing>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Key {
+    at: f32,
+    value: f32,
+    easing: Easing,
+}
+
+#[derive(Clone, Debug)]
+struct Track {
+    chans: Selector,
+    keys: Vec<Key>,
+}
+
+impl Track {
+    fn value_at(&self, pos: f32) -> f32 {
+        let next = self.keys.partition_point(|key| key.at <= pos);
+        match (next.checked_sub(1).map(|i| &self.keys[i]), self.keys.get(next)) {
+            (Some(prev), Some(next)) => {
+                let progress = (pos - prev.at) / (next.at - prev.at);
+                let eased = next.easing.apply(progress as f64) as f32;
+                prev.value + (next.value - prev.value) * eased
+            },
+            (Some(key), None) | (None, Some(key)) => key.value,
+            (None, None) => unreachable!("tracks have keys"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Loop {
+    from: f32,
+    to: f32,
+    times: Option<u32>,
+}
+
+/// Tracks of keys ready to be played
+#[derive(Clone, Debug)]
+pub struct Timeline {
+    /// How long we show each frame, i.e. 1/FPS
+    pub frame_duration: Duration,
+    tracks: Vec<Track>,
+    /// By `from`, they don't overlap
+    loops: Vec<Loop>,
+    /// By time
+    markers: Vec<(String, f32)>,
+    looped: bool,
+    end: f32,
+}
+
+impl Timeline {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let spec: TimelineSpec = serde_yaml::from_str(src)
+            .map_err(|e| e.to_string())?;
+        Timeline::compile(&spec)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Timeline::parse(&src).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn compile(spec: &TimelineSpec) -> Result<Self, String> {
+        for (name, at) in spec.markers.iter() {
+            if !at.is_finite() || *at < 0.0 {
+                return Err(format!("marker {} should be at 0 or later", name));
+            }
+        }
+        let time = |time: &Time| match time {
+            Time::Secs(secs) if secs.is_finite() && *secs >= 0.0 => Ok(*secs),
+            Time::Secs(secs) =>
+                Err(format!("{} should be 0 or later", secs)),
+            Time::Marker(name) => spec.markers.get(name).copied()
+                .ok_or_else(|| format!("marker '{}' does not exist", name)),
+        };
+
+        let tracks = spec.tracks.iter().enumerate().map(|(i, track)| {
+            let err = |e: String| format!("track {} ({}): {}",
+                                          i + 1, track.chans, e);
+            let mut keys = track.keys.iter().map(|key| {
+                if !(0.0..=1.0).contains(&key.value) {
+                    return Err(format!("value {} should be between 0 and 1",
+                                       key.value));
+                }
+                Ok(Key {
+                    at: time(&key.at)?,
+                    value: key.value,
+                    easing: key.easing.unwrap_or(track.easing),
+                })
+            }).collect::<Result<Vec<Key>, String>>().map_err(err)?;
+            if keys.is_empty() {
+                return Err(err("needs at least one key".to_string()));
+            }
+            keys.sort_by(|a, b| a.at.total_cmp(&b.at));
+            Ok(Track { chans: Selector::parse(&track.chans), keys })
+        }).collect::<Result<Vec<Track>, String>>()?;
+
+        let mut loops = spec.loops.iter().map(|l| {
+            let (from, to) = (time(&l.from)?, time(&l.to)?);
+            if to <= from {
+                return Err(format!("loop from {} to {} should end after \
+                                    it starts", from, to));
+            }
+            if l.times == Some(0) {
+                return Err("loops are played at least once".to_string());
+            }
+            Ok(Loop { from, to, times: l.times })
+        }).collect::<Result<Vec<Loop>, String>>()?;
+        loops.sort_by(|a, b| a.from.total_cmp(&b.from));
+        if loops.windows(2).any(|w| w[1].from < w[0].to) {
+            return Err("loops should not overlap".to_string());
+        }
+
+        let mut markers: Vec<(String, f32)> = spec.markers.iter()
+            .map(|(name, at)| (name.clone(), *at))
+            .collect();
+        markers.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let last = tracks.iter().flat_map(|t| t.keys.iter().map(|k| k.at))
+            .chain(loops.iter().map(|l| l.to))
+            .chain(markers.iter().map(|m| m.1))
+            .fold(0.0, f32::max);
+        let end = match spec.duration {
+            Some(secs) if secs.is_finite() && secs > 0.0 => secs,
+            Some(secs) => return Err(format!("duration {} should be more \
+                                              than 0", secs)),
+            None => last,
+        };
+
+        Ok(Timeline {
+            frame_duration: spec.frame_duration,
+            tracks,
+            loops,
+            markers,
+            looped: spec.looped,
+            end,
+        })
+    }
+
+    /// Where the marker `name` is
+    pub fn marker(&self, name: &str) -> Option<f32> {
+        self.markers.iter().find(|(n, _)| n == name).map(|(_, at)| *at)
+    }
+
+    pub fn end(&self) -> f32 {
+        self.end
+    }
+
+    /// Values of the chans with tracks `pos` seconds in
+    pub fn render(&self, pos: f32, chans: &[ChanDescription]) -> Frame<f32> {
+        let mut frame = Frame::empty();
+        for track in self.tracks.iter() {
+            let val = track.value_at(pos);
+            for chan in chans.iter().filter(|chan| track.chans.matches(chan)) {
+                frame.set(chan.chan_id, val);
+            }
+        }
+        frame
+    }
+}
+
+/// Plays a `Timeline` on `output`, `Pause` freezes and resumes it
+pub struct Player<T: ?Sized> {
+    output: Arc<Mutex<T>>,
+    timeline: Timeline,
+    pub frame_duration: Duration,
+    clock: Arc<dyn Clock>,
+
+    /// Seconds into the timeline
+    pos: f32,
+    /// Plays left per loop, None is forever
+    remaining: Vec<Option<u32>>,
+    last: Option<Instant>,
+    paused: bool,
+    done: bool,
+}
+
+impl<T: HasChanDescriptions + DevWrite + ?Sized> Player<T> {
+    pub fn new(output: Arc<Mutex<T>>, timeline: Timeline) -> Self {
+        let mut player = Player {
+            output,
+            frame_duration: timeline.frame_duration,
+            timeline,
+            clock: clock::real(),
+            pos: 0.0,
+            remaining: vec![],
+            last: None,
+            paused: false,
+            done: false,
+        };
+        player.reset_loops(0.0);
+        player
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Seconds into the timeline
+    pub fn position(&self) -> f32 {
+        self.pos
+    }
+
+    /// Goes to a marker or a number of seconds, loops after it start over
+    pub fn seek(&mut self, to: &str) -> Result<(), String> {
+        let to = to.trim();
+        let pos = match self.timeline.marker(to) {
+            Some(pos) => pos,
+            None => to.parse::<f32>().ok().filter(|pos| pos.is_finite())
+                .ok_or_else(|| format!("'{}' is neither a marker nor a \
+                                        number of seconds", to))?,
+        };
+        self.pos = pos.clamp(0.0, self.timeline.end);
+        self.reset_loops(self.pos);
+        self.done = false;
+        Ok(())
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    fn finished(&self) -> bool {
+        self.done
+    }
+
+    fn reset_loops(&mut self, pos: f32) {
+        let loops = &self.timeline.loops;
+        self.remaining.resize(loops.len(), None);
+        for (remaining, l) in self.remaining.iter_mut().zip(loops.iter()) {
+            if l.to > pos {
+                *remaining = l.times.map(|n| n - 1);
+            }
+        }
+    }
+
+    /// Moves on by `dt` seconds going round loops, the stretches of the
+    /// timeline played on the way
+    fn advance(&mut self, dt: f32) -> Vec<(f32, f32)> {
+        let mut played = vec![];
+        let mut from = self.pos;
+        let mut pos = self.pos + dt;
+        for (l, remaining) in self.timeline.loops.iter()
+                .zip(self.remaining.iter_mut()) {
+            if from < l.from || from >= l.to {
+                continue;
+            }
+            while pos >= l.to && *remaining != Some(0) {
+                played.push((from, l.to));
+                from = l.from;
+                pos -= l.to - l.from;
+                if let Some(n) = remaining {
+                    *n -= 1;
+                }
+            }
+        }
+
+        if pos >= self.timeline.end {
+            played.push((from, self.timeline.end));
+            if self.timeline.looped && self.timeline.end > 0.0 {
+                pos = (pos - self.timeline.end) % self.timeline.end;
+                from = 0.0;
+                self.reset_loops(0.0);
+            } else {
+                pos = self.timeline.end;
+                from = pos;
+                self.done = true;
+            }
+        }
+        played.push((from, pos));
+        self.pos = pos;
+        played
+    }
+
+    fn tick(&mut self, now: Instant) -> Result<(), String> {
+        let dt = match (self.last, self.paused) {
+            (Some(last), false) => (now - last).as_secs_f32(),
+            _ => 0.0,
+        };
+        let first = self.last.is_none();
+        self.last = Some(now);
+
+        for (i, (from, to)) in self.advance(dt).into_iter().enumerate() {
+            // going round a loop plays its start again
+            let from_start = first || i > 0;
+            for (name, at) in self.timeline.markers.iter() {
+                if (*at > from || (from_start && *at == from)) && *at <= to {
+                    println!("marker {} at {:.2}s", name, at);
+                }
+            }
+        }
+
+        let mut output = self.output.lock()
+            .map_err(|e| format!("write lock: {:?}", e))?;
+        let frame = self.timeline.render(self.pos, &output.chan_descriptions());
+        if frame.iter_some().next().is_some() {
+            output.set_frame(&frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: HasChanDescriptions + DevWrite + ?Sized> Runner for Player<T> {
+    fn run(
+        self_lock: Arc<Mutex<Player<T>>>,
+        stop: mpsc::Receiver<TaskMsg>,
+    ) -> Result<(), String> {
+        let (frame_duration, clock) = {
+            let player = self_lock.lock().unwrap();
+            (player.frame_duration, player.clock.clone())
+        };
+
+        loop {
+            {
+                let mut player = self_lock.lock().unwrap();
+                if let Err(e) = player.tick(clock.now()) {
+                    eprintln!("timeline err: {}", e);
+                }
+                if player.finished() {
+                    return Ok(());
+                }
+            }
+
+            match clock.recv_timeout(&stop, frame_duration) {
+                Ok(TaskMsg::Stop) => return Ok(()),
+                Ok(TaskMsg::Pause) => self_lock.lock().unwrap().toggle_pause(),
+                Ok(TaskMsg::Ping) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::dev::DevRead;
+    use crate::test_dev::TestDev;
+
+    const SHOW: &str = "
+markers: { intro: 1, drop: 3 }
+loops:
+  - { from: intro, to: drop, times: 2 }
+tracks:
+  - keys:
+      - { at: 0, value: 0 }
+      - { at: 2, value: 1 }
+      - { at: drop, value: 0.5, easing: EaseIn }
+  - chans: 2
+    keys: [{ at: 0, value: 0.25 }]
+";
+
+    #[test]
+    fn test_parse() {
+        let timeline = Timeline::parse(SHOW).unwrap();
+        assert_eq!(3.0, timeline.end());
+        assert_eq!(Some(1.0), timeline.marker("intro"));
+
+        let chans = TestDev::new(false).chan_descriptions();
+        let frame = timeline.render(1.0, &chans);
+        assert_eq!(Some(0.5), frame.get(0));
+        assert_eq!(Some(0.25), frame.get(2));
+        // eased in, slow at the start
+        let val = timeline.render(2.5, &chans).get(1).unwrap();
+        assert!(val > 0.75 && val < 0.95, "{}", val);
+        assert_eq!(Some(0.5), timeline.render(10.0, &chans).get(0));
+
+        // JSON is YAML too
+        assert!(Timeline::parse(r#"{"tracks": [{"keys": [{"at": 0,
+            "value": 1}]}]}"#).is_ok());
+        assert!(Timeline::parse("tracks: [{ keys: [{ at: nope, value: 1 }] }]")
+                .unwrap_err().contains("marker 'nope'"));
+        assert!(Timeline::parse("tracks: [{ keys: [{ at: 0, value: 2 }] }]")
+                .is_err());
+        assert!(Timeline::parse("tracks: [{ keys: [] }]").is_err());
+        Timeline::load("../timelines/demo.yaml").unwrap();
+        assert!(Timeline::parse("
+loops: [{ from: 2, to: 1 }]
+tracks: [{ keys: [{ at: 0, value: 1 }] }]").is_err());
+    }
+
+    #[test]
+    fn test_player() {
+        let dev = Arc::new(Mutex::new(TestDev::new(false)));
+        let clock = VirtualClock::new();
+        let mut player = Player::new(dev.clone(), Timeline::parse(SHOW).unwrap())
+            .with_clock(Arc::new(clock.clone()));
+        assert_eq!(DEFAULT_FRAME_DURATION, player.frame_duration);
+        let step = |player: &mut Player<TestDev>, secs: f32| {
+            clock.step(Duration::from_secs_f32(secs));
+            player.tick(clock.now()).unwrap();
+            player.position()
+        };
+
+        assert_eq!(0.0, step(&mut player, 0.0));
+        assert_eq!(1.0, step(&mut player, 1.0));
+        assert_eq!(0.5, dev.lock().unwrap().get_f32(0).unwrap());
+
+        // the loop plays intro to drop twice
+        assert_eq!(2.5, step(&mut player, 1.5));
+        assert!((1.5 - step(&mut player, 1.0)).abs() < 1e-5);
+        assert!((2.5 - step(&mut player, 1.0)).abs() < 1e-5);
+        assert_eq!(3.0, step(&mut player, 1.0));
+        assert!(player.finished());
+
+        // paused and seeked
+        player.seek("intro").unwrap();
+        player.toggle_pause();
+        assert_eq!(1.0, step(&mut player, 1.0));
+        player.seek("2").unwrap();
+        assert_eq!(2.0, step(&mut player, 1.0));
+        assert_eq!(1.0, dev.lock().unwrap().get_f32(0).unwrap());
+        player.toggle_pause();
+        assert_eq!(2.5, step(&mut player, 0.5));
+        assert!(!player.finished());
+        assert!(player.seek("nope").is_err());
+    }
+}
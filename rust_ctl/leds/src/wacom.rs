@@ -1,5 +1,6 @@
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
 
 // packet format
 // 1b ??
@@ -21,48 +22,58 @@ use std::io::Read;
 //   6 down btn
 //   7 is touching
 
-#[repr(C, packed)]
-#[derive(Default)]
+pub const PACKET_LEN: usize = 10;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct WacomPacket {
-    unknown1: u8,
     pub flags: u8,
     pub x: u16,
     pub y: u16,
     pub pressure: u8,
     pub is_touching: u8,
     pub distance: u8,
-    unknown2: u8,
 }
 
-pub struct Wacom<'a> {
-    #[allow(dead_code)]
-    filename: &'a str,
-    file: File,
+impl WacomPacket {
+    pub fn parse(buf: &[u8; PACKET_LEN]) -> Self {
+        WacomPacket {
+            flags: buf[1],
+            x: u16::from_le_bytes([buf[2], buf[3]]),
+            y: u16::from_le_bytes([buf[4], buf[5]]),
+            pressure: buf[6],
+            is_touching: buf[7],
+            distance: buf[8],
+        }
+    }
 }
 
-impl<'a> Wacom<'a> {
-    pub fn new(filename: &'a str) -> Result<Self, String> {
-        let file = match File::open(filename) {
-            Ok(f) => f,
-            Err(e) => return Err(format!("{:?}", e)),
-        };
+/// Reads packets from a hidraw device, i.e. `/dev/hidraw8`
+pub struct Wacom<R: Read> {
+    file: R,
+}
 
-        Ok(Wacom { filename, file })
+impl Wacom<File> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let file = File::open(&path)
+            .map_err(|e| format!("opening {:?}: {:?}", path.as_ref(), e))?;
+        Ok(Wacom { file })
     }
+}
 
-    pub fn read(&mut self, out: &mut WacomPacket) -> Result<(), String> {
-        let buf: &mut [u8] =
-            unsafe { std::slice::from_raw_parts_mut(std::mem::transmute(out), 10) };
-        let len = match self.file.read(buf) {
-            Ok(l) => l,
-            Err(e) => return Err(format!("{:?}", e)),
-        };
+impl<R: Read> Wacom<R> {
+    pub fn new(file: R) -> Self {
+        Wacom { file }
+    }
 
-        if len != 10 {
-            return Err(format!("invalid length {} instead of 10", len));
+    pub fn read(&mut self) -> Result<WacomPacket, String> {
+        let mut buf = [0u8; PACKET_LEN];
+        let len = self.file.read(&mut buf).map_err(|e| format!("{:?}", e))?;
+        if len != PACKET_LEN {
+            return Err(format!("invalid length {} instead of {}",
+                               len, PACKET_LEN));
         }
 
-        Ok(())
+        Ok(WacomPacket::parse(&buf))
     }
 }
 